@@ -1,8 +1,12 @@
 //! File for managing borders in the game
+use gb_emu::graphics;
 use macroquad::texture::Texture2D;
 
 use crate::common::{Rect, Renderer};
 
+/// The number of pieces in a border texture, including the unused one after the bottom-right piece
+pub const NUM_PIECES: usize = 7;
+
 /// A struct for managing a border
 pub struct BorderRenderer {
     texture: Texture2D,
@@ -23,6 +27,10 @@ impl BorderRenderer {
         BorderRenderer { texture, piece_size, scale }
     }
 
+    pub fn texture(&self) -> Texture2D {
+        self.texture
+    }
+
     fn scaled_size(&self) -> i32 {
         self.piece_size * self.scale
     }
@@ -84,3 +92,49 @@ impl BorderRenderer {
         renderer.copy(self.texture, Some(src_rect), Some(dst_rect));
     }
 }
+
+/// Whether a border texture extracted from the ROM has something drawn in every piece. Blank pieces
+/// mean that the border offsets do not match the loaded ROM.
+pub fn is_drawn(data: &[u8], piece_size: usize) -> bool {
+    const BYTES_PER_PIXEL: usize = 4;
+
+    let row_len = NUM_PIECES * piece_size * BYTES_PER_PIXEL;
+    (0..NUM_PIECES - 1).all(|piece| {
+        let mut pixels = (0..piece_size).flat_map(|y| {
+            let start = y * row_len + piece * piece_size * BYTES_PER_PIXEL;
+            data[start..start + piece_size * BYTES_PER_PIXEL].chunks(BYTES_PER_PIXEL)
+        });
+        let first = pixels.next();
+        pixels.any(|pixel| Some(pixel) != first)
+    })
+}
+
+/// The bundled border, a plain two pixel line in the same layout as the border in the ROM. This is
+/// used instead when the ROM's border can not be found.
+pub fn fallback_texture(piece_size: usize) -> Vec<u8> {
+    let (white, black) = (graphics::GB_COLOR_TABLE[0], graphics::GB_COLOR_TABLE[3]);
+
+    // The line runs through the middle of each piece, and corners join the lines that meet there
+    let line = piece_size / 2 - 1..piece_size / 2 + 1;
+    let mut data = vec![];
+    for y in 0..piece_size {
+        for piece in 0..NUM_PIECES {
+            for x in 0..piece_size {
+                let (horizontal, vertical) = (line.contains(&y), line.contains(&x));
+                let (right, left) = (x >= line.start, x < line.end);
+                let (down, up) = (y >= line.start, y < line.end);
+                let drawn = match piece {
+                    0 => (horizontal && right) || (vertical && down),
+                    1 => horizontal,
+                    2 => (horizontal && left) || (vertical && down),
+                    3 => vertical,
+                    4 => (horizontal && right) || (vertical && up),
+                    5 => (horizontal && left) || (vertical && up),
+                    _ => false,
+                };
+                data.extend(if drawn { black } else { white });
+            }
+        }
+    }
+    data
+}
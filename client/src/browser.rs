@@ -0,0 +1,180 @@
+//! Screen listing servers to pick from, shown before connecting when the client is run with `--lan`
+//! (servers found on the local network) or `--browse` (servers listed by a master server)
+//!
+//! Like the setup, this is drawn with macroquad's built-in font since no ROM has been read yet.
+use std::{io, net::SocketAddr, thread};
+
+use crossbeam_channel::{Receiver, TryRecvError};
+use macroquad::{
+    color::{Color, BLACK, DARKGRAY, RED, WHITE},
+    input::{is_key_pressed, KeyCode},
+    text::draw_text,
+    window::{clear_background, next_frame},
+};
+use network_common::{
+    discovery::{Search, ServerInfo},
+    master,
+};
+
+const FONT_SIZE: f32 = 24.0;
+const LINE_HEIGHT: f32 = 32.0;
+const MARGIN: f32 = 32.0;
+
+/// The most servers listed at once
+const MAX_SHOWN: usize = 10;
+
+/// Where to list servers from
+pub enum Listing {
+    Lan,
+    /// The address of a master server
+    Master(String),
+}
+
+type Fetch = Receiver<io::Result<Vec<(SocketAddr, ServerInfo)>>>;
+
+enum Source {
+    Lan(Search),
+    /// The master server, and the list being fetched from it
+    Master(String, Option<Fetch>),
+}
+
+impl Source {
+    fn title(&self) -> String {
+        match self {
+            Source::Lan(_) => "SERVERS ON THIS NETWORK".to_string(),
+            Source::Master(master, _) => format!("SERVERS LISTED BY {}", master),
+        }
+    }
+}
+
+/// List servers until the player picks one, returning its address. Returns `None` if the player
+/// cancels, or if the search could not be started.
+pub async fn choose_server(listing: Listing) -> Option<String> {
+    let mut source = match listing {
+        Listing::Lan => match Search::new() {
+            Ok(search) => Source::Lan(search),
+            Err(e) => {
+                println!("Failed to search the local network: {}", e);
+                return None;
+            }
+        },
+        Listing::Master(master) => {
+            let fetch = fetch(&master);
+            Source::Master(master, Some(fetch))
+        }
+    };
+
+    let mut servers: Vec<(SocketAddr, ServerInfo)> = vec![];
+    let mut selection: usize = 0;
+    let mut message: Option<String> = None;
+    loop {
+        match &mut source {
+            Source::Lan(search) => {
+                for (addr, info) in search.poll() {
+                    match servers.iter_mut().find(|(known, _)| *known == addr) {
+                        Some(server) => server.1 = info,
+                        None => servers.push((addr, info)),
+                    }
+                }
+            }
+            Source::Master(_, pending) => {
+                let result = match pending.as_ref().map(|fetch| fetch.try_recv()) {
+                    Some(Ok(result)) => Some(result),
+                    Some(Err(TryRecvError::Empty)) | None => None,
+                    Some(Err(TryRecvError::Disconnected)) => {
+                        Some(Err(io::Error::other("the list could not be fetched")))
+                    }
+                };
+                if let Some(result) = result {
+                    *pending = None;
+                    match result {
+                        Ok(list) => {
+                            if list.is_empty() {
+                                message = Some("No servers are listed".into());
+                            }
+                            servers = list;
+                        }
+                        Err(e) => message = Some(format!("Failed to fetch the list: {}", e)),
+                    }
+                    selection = selection.min(servers.len().saturating_sub(1));
+                }
+            }
+        }
+
+        if is_key_pressed(KeyCode::Escape) {
+            return None;
+        }
+        if is_key_pressed(KeyCode::Up) {
+            selection = selection.saturating_sub(1);
+        }
+        if is_key_pressed(KeyCode::Down) && selection + 1 < servers.len().min(MAX_SHOWN) {
+            selection += 1;
+        }
+        if is_key_pressed(KeyCode::R) {
+            if let Source::Master(master, pending @ None) = &mut source {
+                *pending = Some(fetch(master));
+                message = None;
+            }
+        }
+        if is_key_pressed(KeyCode::Enter) {
+            match servers.get(selection) {
+                Some((addr, info)) if info.is_compatible() => return Some(addr.to_string()),
+                Some(_) => message = Some("This server runs a different version".into()),
+                None => {}
+            }
+        }
+
+        draw(&source, &servers, selection, message.as_deref());
+        next_frame().await
+    }
+}
+
+/// Fetch the list from a master server on a background thread
+fn fetch(master: &str) -> Fetch {
+    let (sender, receiver) = crossbeam_channel::bounded(1);
+    let master = master.to_string();
+    thread::spawn(move || {
+        let _ = sender.send(master::fetch(&master));
+    });
+    receiver
+}
+
+fn draw(
+    source: &Source,
+    servers: &[(SocketAddr, ServerInfo)],
+    selection: usize,
+    message: Option<&str>,
+) {
+    clear_background(WHITE);
+
+    let mut y = MARGIN + LINE_HEIGHT;
+    let mut line = |text: &str, color: Color| {
+        draw_text(text, MARGIN, y, FONT_SIZE, color);
+        y += LINE_HEIGHT;
+    };
+
+    line(&source.title(), DARKGRAY);
+    line("", BLACK);
+    match source {
+        Source::Lan(_) if servers.is_empty() => line("Searching...", DARKGRAY),
+        Source::Master(_, Some(_)) => line("Fetching the list...", DARKGRAY),
+        _ => {}
+    }
+    for (i, (addr, info)) in servers.iter().take(MAX_SHOWN).enumerate() {
+        let cursor = if i == selection { ">" } else { " " };
+        let players = if info.players == 1 { "player" } else { "players" };
+        let text = format!("{} {}  ({} {}, {})", cursor, info.name, info.players, players, addr);
+        line(&text, if info.is_compatible() { BLACK } else { DARKGRAY });
+    }
+
+    if let Some(message) = message {
+        line(message, RED);
+    }
+    line("", BLACK);
+    match source {
+        Source::Lan(_) => line("ENTER: join    ESCAPE: use the default server", DARKGRAY),
+        Source::Master(..) => {
+            line("ENTER: join    R: refresh    ESCAPE: use the default server", DARKGRAY)
+        }
+    }
+}
@@ -1,6 +1,14 @@
-use std::mem;
+use std::{
+    cell::{Cell, RefCell},
+    mem,
+};
 
 use interface::text;
+use macroquad::{
+    camera::{set_camera, set_default_camera, Camera2D},
+    prelude::{clear_background, vec2, Color, BLACK, BLANK},
+    texture::{render_target, FilterMode, RenderTarget},
+};
 
 use crate::{
     border::BorderRenderer,
@@ -8,42 +16,78 @@ use crate::{
     font::{draw_text, Font},
 };
 
+/// Whether a message sent by the local player has reached the server
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum Delivery {
+    Delivered,
+    Pending,
+    Failed,
+}
+
 struct Message {
     user_name: Vec<u8>,
     data: Vec<u8>,
+    delivery: Delivery,
 }
 
 pub struct ChatBox<'a> {
     pub message_ready: bool,
     pub message_buffer: String,
     messages: Vec<Message>,
+    /// The number of messages received since the chat box was last opened
+    unread: usize,
+    /// A line of status information displayed at the top of the chat box
+    status: Vec<u8>,
 
     font: &'a Font,
     border: &'a BorderRenderer,
+    /// Filled in behind the messages, so that the gaps between lines match the font's background
+    background: Color,
 
     outer_rect: Rect,
     inner_rect: Rect,
+
+    /// The chat box as it was last drawn, which is only redrawn when what it shows changes
+    cache: RenderTarget,
+    /// Whether the messages or status have changed since the cache was drawn
+    dirty: Cell<bool>,
+    /// The typed text that the cache was drawn with, since the message buffer is edited directly
+    drawn_buffer: RefCell<String>,
 }
 
 impl<'a> ChatBox<'a> {
-    pub fn new(font: &'a Font, border: &'a BorderRenderer, rect: Rect) -> ChatBox<'a> {
+    pub fn new(
+        font: &'a Font,
+        border: &'a BorderRenderer,
+        rect: Rect,
+        high_contrast: bool,
+    ) -> ChatBox<'a> {
         let inner_rect = Rect::new(
             rect.x + font.char_width(),
             rect.y + font.line_height(),
             rect.width - 2 * font.char_width(),
             rect.height - 2 * font.line_height(),
         );
+        let cache = render_target(rect.width as u32, rect.height as u32);
+        cache.texture.set_filter(FilterMode::Nearest);
 
         ChatBox {
             message_ready: false,
             message_buffer: String::new(),
             messages: Vec::new(),
+            unread: 0,
+            status: Vec::new(),
 
             font,
             border,
+            background: if high_contrast { BLACK } else { BLANK },
 
             outer_rect: rect,
             inner_rect,
+
+            cache,
+            dirty: Cell::new(true),
+            drawn_buffer: RefCell::new(String::new()),
         }
     }
 
@@ -53,28 +97,112 @@ impl<'a> ChatBox<'a> {
     }
 
     pub fn add_message(&mut self, user_name: Vec<u8>, msg: Vec<u8>) {
-        self.messages.push(Message { user_name, data: msg });
+        self.messages.push(Message { user_name, data: msg, delivery: Delivery::Delivered });
+        self.unread += 1;
+        self.dirty.set(true);
+    }
+
+    /// Add a message sent by the local player that is waiting to be delivered, returning an index
+    /// that can be used to update its delivery status
+    pub fn add_pending_message(&mut self, user_name: Vec<u8>, msg: Vec<u8>) -> usize {
+        self.messages.push(Message { user_name, data: msg, delivery: Delivery::Pending });
+        self.dirty.set(true);
+        self.messages.len() - 1
+    }
+
+    pub fn set_delivery(&mut self, index: usize, delivery: Delivery) {
+        if let Some(message) = self.messages.get_mut(index) {
+            message.delivery = delivery;
+            self.dirty.set(true);
+        }
+    }
+
+    /// Add a message from the client itself, rather than from another player
+    pub fn add_notice(&mut self, msg: &str) {
+        self.add_message(vec![], text::Encoder::new(msg).collect());
+    }
+
+    pub fn unread(&self) -> usize {
+        self.unread
+    }
+
+    pub fn mark_read(&mut self) {
+        self.unread = 0;
     }
 
-    /// Draws the chat box to the screen.
-    /// TODO: Cache the render result
+    /// Sets the status line, which is done every frame but rarely changes it
+    pub fn set_status(&mut self, status: &str) {
+        let status: Vec<u8> = text::Encoder::new(status).collect();
+        if status != self.status {
+            self.status = status;
+            self.dirty.set(true);
+        }
+    }
+
+    /// Redraw the chat box on the next frame, e.g. after the font texture has been reloaded
+    pub fn invalidate(&self) {
+        self.dirty.set(true);
+    }
+
+    /// Draws the chat box to the screen, redrawing the cached copy of it first if it has changed
     pub fn draw(&self, renderer: &mut Renderer) {
+        if self.dirty.replace(false) || *self.drawn_buffer.borrow() != self.message_buffer {
+            self.drawn_buffer.replace(self.message_buffer.clone());
+
+            let rect = self.outer_rect;
+            set_camera(&Camera2D {
+                target: vec2(
+                    rect.x as f32 + rect.width as f32 / 2.0,
+                    rect.y as f32 + rect.height as f32 / 2.0,
+                ),
+                // Render targets are flipped vertically compared to the screen
+                zoom: vec2(2.0 / rect.width as f32, 2.0 / rect.height as f32),
+                render_target: Some(self.cache),
+                ..Default::default()
+            });
+            clear_background(self.background);
+            self.draw_contents(renderer);
+            set_default_camera();
+        }
+
+        renderer.copy(self.cache.texture, None, Some(self.outer_rect));
+    }
+
+    /// Draws the messages and border, in screen coordinates
+    fn draw_contents(&self, renderer: &mut Renderer) {
         let mut y = self.inner_rect.y;
         let msg_padding = self.font.char_width() / 2;
 
+        // Draw the status line
+        y += draw_text(renderer, self.font, &self.status, &self.inner_rect);
+        y += self.font.line_height();
+
         // Draw the text that the player is currently typing
         let encoded_buffer: Vec<_> = text::Encoder::new(&self.message_buffer).collect();
-        y += draw_text(renderer, &self.font, &encoded_buffer, &self.inner_rect);
+        y += draw_text(
+            renderer,
+            &self.font,
+            &encoded_buffer,
+            &Rect::new(self.inner_rect.x, y, self.inner_rect.width, self.inner_rect.height),
+        );
         y += self.font.line_height();
 
         // Draw the rest of the chat messages
         for message in self.messages.iter().rev() {
-            y += draw_text(
-                renderer,
-                &self.font,
-                &message.user_name,
-                &Rect::new(self.inner_rect.x, y, self.inner_rect.width, self.inner_rect.height),
-            );
+            if !message.user_name.is_empty() {
+                let mut user_name = message.user_name.clone();
+                match message.delivery {
+                    Delivery::Delivered => {}
+                    Delivery::Pending => user_name.extend(text::Encoder::new(" ...")),
+                    Delivery::Failed => user_name.extend(text::Encoder::new(" FAILED")),
+                }
+                y += draw_text(
+                    renderer,
+                    &self.font,
+                    &user_name,
+                    &Rect::new(self.inner_rect.x, y, self.inner_rect.width, self.inner_rect.height),
+                );
+            }
 
             y += draw_text(
                 renderer,
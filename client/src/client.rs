@@ -1,18 +1,31 @@
-use std::{error::Error, time::Instant};
+use std::{cell::RefCell, error::Error, thread, time::Instant};
 
-use gb_emu::{emulator::Emulator, graphics, mmu::Memory};
+use crossbeam_channel::Receiver;
+
+use gb_emu::{emulator::Emulator, graphics, joypad, mmu::Memory};
 
 use interface::{self, extract};
 use macroquad::{
     miniquad::EventHandler,
-    prelude::utils,
-    texture::{FilterMode, Texture2D},
+    prelude::{utils, KeyCode},
+    texture::{FilterMode, Image, Texture2D},
     window::{next_frame, request_new_screen_size},
 };
+use network_common::{game::GameInfo, transport::ClientOptions};
 
-use crate::{border::BorderRenderer, common::Renderer, font::Font, game::Game, net::ClientManager};
+use crate::{
+    border::{self, BorderRenderer},
+    common::Renderer,
+    config::{self, ChatFont, Config, PanelPlacement, WindowLayout},
+    font::{Font, TextColors, HIGH_CONTRAST_COLORS, STANDARD_COLORS},
+    game::{Game, GameState},
+    gamepad::Gamepads,
+    net::ClientManager,
+    usage,
+    window::{self, WindowTitle},
+};
 
-const EMU_SCALE: u32 = 3;
+pub const EMU_SCALE: u32 = 3;
 pub const EMU_WIDTH: u32 = graphics::WIDTH as u32 * EMU_SCALE;
 pub const EMU_HEIGHT: u32 = graphics::HEIGHT as u32 * EMU_SCALE;
 
@@ -22,7 +35,51 @@ pub const MENU_HEIGHT: u32 = EMU_HEIGHT / 2;
 pub const CHAT_WIDTH: u32 = 208;
 pub const CHAT_SCALE: u32 = 1;
 
-impl<'a> EventHandler for Game<'a> {
+/// The height of the area below the game's screen that detached panels are placed in
+pub const PANEL_HEIGHT: u32 = EMU_HEIGHT / 2;
+
+/// The width of the chat when it is docked, which grows with the size of its font
+pub fn chat_width(chat_font: &ChatFont) -> u32 {
+    CHAT_WIDTH * chat_font.scale()
+}
+
+/// The size of the area used by a single local player
+pub fn viewport_size(layout: &WindowLayout, chat_font: &ChatFont) -> (u32, u32) {
+    let width = match layout.chat {
+        PanelPlacement::Docked => EMU_WIDTH + chat_width(chat_font),
+        PanelPlacement::Detached => EMU_WIDTH,
+    };
+    let height = if layout.has_detached() { EMU_HEIGHT + PANEL_HEIGHT } else { EMU_HEIGHT };
+    (width, height)
+}
+
+/// A player sharing this window, with their own emulator and server connection
+pub struct LocalSession {
+    pub client_manager: ClientManager,
+    pub emulator: Box<Emulator>,
+}
+
+/// The games of all players sharing this window. Keyboard focus (for chat and menus) belongs to a
+/// single game at a time, and is switched with the Tab key, while joypad keys are sent to whichever
+/// player they are bound to. While the focused game is in its chat box or a menu, it has the whole
+/// keyboard, so that typing does not also press the other players' buttons.
+struct LocalPlayers<'a> {
+    games: Vec<Game<'a>>,
+    focus: usize,
+}
+
+impl<'a> LocalPlayers<'a> {
+    fn exit_requested(&self) -> bool {
+        self.games.iter().any(|game| game.exit_requested)
+    }
+
+    /// Whether the focused game is taking text or menu input, rather than playing
+    fn focus_is_typing(&self) -> bool {
+        self.games[self.focus].game_state != GameState::Emulator
+    }
+}
+
+impl<'a> EventHandler for LocalPlayers<'a> {
     fn update(&mut self, _ctx: &mut macroquad::miniquad::Context) {}
     fn draw(&mut self, _ctx: &mut macroquad::miniquad::Context) {}
 
@@ -33,7 +90,7 @@ impl<'a> EventHandler for Game<'a> {
         _keymods: macroquad::miniquad::KeyMods,
         _repeat: bool,
     ) {
-        self.text_input(character.to_string())
+        self.games[self.focus].text_input(character.to_string())
     }
 
     fn key_down_event(
@@ -43,7 +100,22 @@ impl<'a> EventHandler for Game<'a> {
         _keymods: macroquad::miniquad::KeyMods,
         _repeat: bool,
     ) {
-        self.key_down(keycode);
+        if keycode == KeyCode::Tab && self.games.len() > 1 {
+            return;
+        }
+        if self.focus_is_typing() {
+            self.games[self.focus].key_down(keycode);
+            return;
+        }
+
+        for (i, game) in self.games.iter_mut().enumerate() {
+            if i == self.focus {
+                game.key_down(keycode);
+            }
+            else {
+                game.joypad_input(keycode, joypad::State::Pressed);
+            }
+        }
     }
 
     fn key_up_event(
@@ -52,54 +124,124 @@ impl<'a> EventHandler for Game<'a> {
         keycode: macroquad::prelude::KeyCode,
         _keymods: macroquad::miniquad::KeyMods,
     ) {
-        self.key_up(keycode)
+        if keycode == KeyCode::Tab && self.games.len() > 1 {
+            self.focus = (self.focus + 1) % self.games.len();
+            return;
+        }
+
+        for (i, game) in self.games.iter_mut().enumerate() {
+            if i == self.focus {
+                game.key_up(keycode);
+            }
+            else {
+                game.joypad_input(keycode, joypad::State::Released);
+            }
+        }
     }
 
     fn quit_requested_event(&mut self, _ctx: &mut macroquad::miniquad::Context) {
         eprintln!("exit requested");
-        self.exit_requested = true;
+        for game in &mut self.games {
+            game.exit_requested = true;
+        }
     }
 }
 
 pub async fn run(
-    mut client_manager: ClientManager,
-    emulator: Box<Emulator>,
+    sessions: Vec<LocalSession>,
+    config: Config,
+    options: ClientOptions,
 ) -> Result<(), Box<dyn Error>> {
-    request_new_screen_size(EMU_WIDTH as f32, EMU_HEIGHT as f32);
+    let (width, height) = viewport_size(&config.layout, &config.chat_font);
+    request_new_screen_size((width * sessions.len() as u32) as f32, height as f32);
 
     let mut renderer = Renderer;
-    let font_data = load_font(&renderer, &emulator.mem);
-    let border_renderer = load_border_renderer(&renderer, &emulator.mem);
+    let font_data = load_font(&renderer, &sessions[0].emulator.mem, CHAT_SCALE, STANDARD_COLORS);
+    let chat_colors =
+        if config.chat_font.high_contrast { HIGH_CONTRAST_COLORS } else { STANDARD_COLORS };
+    let chat_font =
+        load_font(&renderer, &sessions[0].emulator.mem, config.chat_font.scale(), chat_colors);
+    let border_renderer = load_border_renderer(&renderer, &sessions[0].emulator.mem);
+    let config = RefCell::new(config);
+
+    let mut client_managers = vec![];
+    let mut players = LocalPlayers { games: vec![], focus: 0 };
+    for (i, session) in sessions.into_iter().enumerate() {
+        let server_address = session.client_manager.server_name();
+        let mut game = Game::new(
+            session.emulator,
+            &config,
+            i,
+            server_address,
+            &font_data,
+            &chat_font,
+            &border_renderer,
+        );
+        let settings = config.borrow().settings_for(server_address);
+        game.apply_server_settings(settings);
 
-    let mut game = Game::new(emulator, &font_data, &border_renderer);
+        players.games.push(game);
+        client_managers.push(session.client_manager);
+    }
+    let mut joins: Vec<Option<Join>> = client_managers.iter().map(|_| None).collect();
 
     let mut prev_time = Instant::now();
     let mut frame_time = 0;
 
+    let mut gamepads = Gamepads::new();
+    let mut window_title = WindowTitle::default();
     let events_subscriber = utils::register_input_subscriber();
-    while !game.exit_requested {
-        utils::repeat_all_miniquad_input(&mut game, events_subscriber);
+    while !players.exit_requested() {
+        utils::repeat_all_miniquad_input(&mut players, events_subscriber);
+        for (pad, action, state) in gamepads.poll() {
+            let num_games = players.games.len();
+            players.games[pad % num_games].gamepad_input(action, state);
+        }
 
-        game.render(&mut renderer);
+        for (i, ((game, client_manager), join)) in
+            players.games.iter_mut().zip(&mut client_managers).zip(&mut joins).enumerate()
+        {
+            game.update_touch_input();
+            game.render(&mut renderer);
 
-        client_manager.update_player(&game.player_data);
-        client_manager.send_update(&mut game).unwrap();
-        client_manager.recv_update(&mut game).unwrap();
+            if let Some(player_data) = game.take_player_update() {
+                client_manager.update_player(&player_data);
+            }
+            client_manager.send_update(game).unwrap();
+            client_manager.recv_update(game).unwrap();
+            let status =
+                game.update_status(client_manager.server_name(), client_manager.is_connected());
+            // The window shows the status of the player with keyboard focus
+            if i == players.focus {
+                window_title.set(format!("Pikemon - {}", status));
+            }
+            if std::mem::take(&mut game.attention_requested) {
+                window::request_attention();
+            }
+
+            if let Some(address) = game.switch_server.take() {
+                *join = Some(start_join(address, &options, game));
+            }
+            if let Some(joined) = join.as_ref().and_then(|join| join.try_recv().ok()) {
+                *join = None;
+                switch_server(game, client_manager, joined);
+            }
+        }
 
         let current_time = Instant::now();
         frame_time += (current_time - prev_time).as_nanos() as u64;
         prev_time = current_time;
 
-        if !game.fast_mode {
-            const TARGET_TIME_STEP: u64 = 16666667;
-            while frame_time >= TARGET_TIME_STEP {
-                frame_time -= TARGET_TIME_STEP;
-                game.update();
-            }
-            // thread::sleep(Duration::new(0, (TARGET_TIME_STEP - frame_time) as u32));
+        const TARGET_TIME_STEP: u64 = 16666667;
+        let mut num_frames = 0;
+        while frame_time >= TARGET_TIME_STEP {
+            frame_time -= TARGET_TIME_STEP;
+            num_frames += 1;
         }
-        else {
-            for _ in 0..10 {
+
+        for game in &mut players.games {
+            let num_frames = if game.fast_mode { 10 } else { num_frames };
+            for _ in 0..num_frames {
                 game.update();
             }
         }
@@ -107,50 +249,133 @@ pub async fn run(
         next_frame().await
     }
 
+    // A crash is reported by the first game to leave, since it is not tied to either player
+    let share_usage_stats = config.borrow().share_usage_stats;
+    let mut last_crash = if share_usage_stats { usage::take_last_crash() } else { None };
+    for (game, client_manager) in players.games.iter().zip(&mut client_managers) {
+        if share_usage_stats {
+            client_manager.send_usage_report(game.usage.report(last_crash.take()));
+        }
+        client_manager.disconnect();
+    }
     Ok(())
 }
 
-fn load_font(_renderer: &Renderer, mem: &Memory) -> Font {
-    const BLACK: [u8; 4] = [0, 0, 0, 255];
-    const WHITE: [u8; 4] = [255, 255, 255, 255];
+/// A server being joined in the background, so that the game keeps running while it answers
+type Join = Receiver<Result<ClientManager, String>>;
 
-    const FONT_TEX_WIDTH: usize = 8 * 16 * 8;
-    const FONT_TEX_HEIGHT: usize = 8;
+fn start_join(address: String, options: &ClientOptions, game: &Game) -> Join {
+    let options = options.clone();
+    let compression = game.config.borrow().network.compression();
+    let conditions = game.config.borrow().network.simulate;
+    let login = game.config.borrow().saved_login(&address);
+    let game_info = GameInfo::from_rom(&game.emulator.mem.cart.rom[0]);
+    let (sender, receiver) = crossbeam_channel::bounded(1);
+    thread::spawn(move || {
+        let joined =
+            ClientManager::join(&address, &options, compression, game_info, login, conditions);
+        let _ = sender.send(joined);
+    });
+    receiver
+}
 
-    // Extract the font data from the game
-    let data = extract::extract_texture(
-        mem,
-        interface::offsets::FONT_BANK,
-        interface::offsets::FONT_ADDR,
-        FONT_TEX_WIDTH,
-        FONT_TEX_HEIGHT,
-        extract::TextureFormat::Bpp1,
-        &[BLACK, WHITE],
-    );
+/// Leave the current server for one that has just been joined, or stay if it could not be joined
+fn switch_server(
+    game: &mut Game,
+    client_manager: &mut ClientManager,
+    joined: Result<ClientManager, String>,
+) {
+    match joined {
+        Ok(new_manager) => {
+            client_manager.disconnect();
+            *client_manager = new_manager;
+            let address = client_manager.server_name().to_string();
+            {
+                let mut config = game.config.borrow_mut();
+                if let Some(account) = client_manager.take_new_account() {
+                    config.save_account(&address, account);
+                    config::save_config(&config);
+                }
+                else if config.add_server(&address) {
+                    config::save_config(&config);
+                }
+            }
+            game.server_changed(&address);
+        }
+        Err(e) => {
+            println!("{}", e);
+            game.chat_box.add_notice("Could not join the server");
+        }
+    }
+}
+
+const FONT_TEX_WIDTH: usize = 8 * 16 * 8;
+const FONT_TEX_HEIGHT: usize = 8;
+
+const BORDER_PIECE_SIZE: usize = 8;
+const BORDER_TEX_WIDTH: usize = BORDER_PIECE_SIZE * border::NUM_PIECES;
+const BORDER_TEX_HEIGHT: usize = BORDER_PIECE_SIZE;
 
-    // Build a texture from the extracted data
+fn load_font(_renderer: &Renderer, mem: &Memory, scale: u32, colors: TextColors) -> Font {
+    // Build a texture from the font data in the game
+    let data = extract_font(mem, colors);
     let texture = Texture2D::from_rgba8(FONT_TEX_WIDTH as u16, FONT_TEX_HEIGHT as u16, &data);
     texture.set_filter(FilterMode::Nearest);
-    Font::new(texture, 8, 8, CHAT_SCALE as i32)
+    Font::new(texture, 8, 8, scale as i32, colors)
 }
 
 fn load_border_renderer(_renderer: &Renderer, mem: &Memory) -> BorderRenderer {
-    const BORDER_TEX_WIDTH: usize = 8 * 7;
-    const BORDER_TEX_HEIGHT: usize = 8;
+    // Build a texture from the border data in the game
+    let data = extract_border(mem);
+    let texture = Texture2D::from_rgba8(BORDER_TEX_WIDTH as u16, BORDER_TEX_HEIGHT as u16, &data);
+    texture.set_filter(FilterMode::Nearest);
+    BorderRenderer::new(texture, BORDER_PIECE_SIZE as i32, CHAT_SCALE as i32)
+}
+
+/// Re-extract the font and border textures, after a different cart has been loaded
+pub fn reload_textures(fonts: &[&Font], border_renderer: &BorderRenderer, mem: &Memory) {
+    for font in fonts {
+        font.texture().update(&Image {
+            bytes: extract_font(mem, font.colors()),
+            width: FONT_TEX_WIDTH as u16,
+            height: FONT_TEX_HEIGHT as u16,
+        });
+    }
+    border_renderer.texture().update(&Image {
+        bytes: extract_border(mem),
+        width: BORDER_TEX_WIDTH as u16,
+        height: BORDER_TEX_HEIGHT as u16,
+    });
+}
 
-    // Extract the border data from the game
+fn extract_font(mem: &Memory, colors: TextColors) -> Vec<u8> {
+    extract::extract_texture(
+        mem,
+        interface::offsets::FONT_BANK.get(),
+        interface::offsets::FONT_ADDR.get(),
+        FONT_TEX_WIDTH,
+        FONT_TEX_HEIGHT,
+        extract::TextureFormat::Bpp1,
+        &colors,
+    )
+}
+
+/// The dialog border tiles from the game, or the bundled border if the ROM does not have them where
+/// the offsets say. Tiles outside of the ROM are extracted as blank, which is not drawn.
+fn extract_border(mem: &Memory) -> Vec<u8> {
     let data = extract::extract_texture(
         mem,
-        interface::offsets::FONT_BANK,
-        interface::offsets::BORDER_ADDR,
+        interface::offsets::BORDER_BANK.get(),
+        interface::offsets::BORDER_ADDR.get(),
         BORDER_TEX_WIDTH,
         BORDER_TEX_HEIGHT,
         extract::TextureFormat::Bpp2,
         graphics::GB_COLOR_TABLE,
     );
+    if border::is_drawn(&data, BORDER_PIECE_SIZE) {
+        return data;
+    }
 
-    // Build a texture from the extracted data
-    let texture = Texture2D::from_rgba8(BORDER_TEX_WIDTH as u16, BORDER_TEX_HEIGHT as u16, &data);
-    texture.set_filter(FilterMode::Nearest);
-    BorderRenderer::new(texture, 8, CHAT_SCALE as i32)
+    println!("Border tiles not found in the ROM, using the bundled border");
+    border::fallback_texture(BORDER_PIECE_SIZE)
 }
@@ -15,6 +15,10 @@ impl Rect {
     pub fn new(x: i32, y: i32, width: i32, height: i32) -> Rect {
         Rect { x, y, width, height }
     }
+
+    pub fn contains(&self, x: i32, y: i32) -> bool {
+        x >= self.x && x < self.x + self.width && y >= self.y && y < self.y + self.height
+    }
 }
 
 pub struct Renderer;
@@ -0,0 +1,577 @@
+//! Persistent client settings
+use std::{
+    collections::{HashMap, HashSet},
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use interface::{relay::ItemRule, values::PlayerSprite, visibility::Visibility, PlayerId};
+use network_common::{
+    account::{Login, LoginKind},
+    codec::Compression,
+    simulator::NetworkConditions,
+    MAX_UPDATE_RATE,
+};
+
+use crate::{
+    handshake,
+    input::{KeyBindings, KeyboardLayout},
+    paths,
+};
+
+pub const CONFIG_FILE: &str = "pikemon.json";
+
+/// The maximum number of players that can share a single window
+pub const MAX_LOCAL_PLAYERS: usize = 2;
+
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// The ROM to load, chosen during setup
+    pub rom_path: Option<String>,
+    /// Where save files are kept. They are kept next to the ROM if this is not set.
+    pub save_dir: Option<String>,
+    /// The server to join when no address is given on the command line
+    pub default_server: Option<String>,
+    pub keyboard_layout: KeyboardLayout,
+    /// Bindings for the joypad and hotkeys
+    pub joypad: KeyBindings,
+    /// Bindings used by the second player when running with split input
+    pub second_joypad: KeyBindings,
+    /// Settings used for servers that do not override them
+    pub server_settings: ServerSettings,
+    /// Servers that have been connected to, along with any settings specific to that server
+    pub servers: Vec<ServerEntry>,
+    pub video: VideoSettings,
+    pub network: NetworkSettings,
+    /// Where the chat and spectator panels are placed in the window
+    pub layout: WindowLayout,
+    /// How text in the chat is drawn
+    pub chat_font: ChatFont,
+    /// Show an on-screen joypad that can be used with a touch screen or mouse
+    pub touch_joypad: bool,
+    /// Where to download updated game definitions from
+    pub definitions_url: Option<String>,
+    /// How battles requested by the local player are run
+    pub battle_mode: BattleMode,
+    /// Whether items can be used in relayed battles requested by the local player
+    pub item_rule: ItemRule,
+    /// Announce to the server when the local player enters the Hall of Fame
+    pub share_hall_of_fame: bool,
+    /// Announce rare wild encounters to nearby players, and let them watch the battle
+    pub share_encounters: bool,
+    /// Which players can watch the local player's battles
+    pub spectators: SpectatorRule,
+    /// Show desktop notifications for battle requests and players coming online while away
+    pub notifications: bool,
+    /// Pause the game while the local player is away, letting other players know
+    pub pause_when_away: bool,
+    /// Keep the local player idle while they are away, answering battle requests and trade offers
+    /// with `away_message` instead of accepting them
+    pub away_autopilot: bool,
+    pub away_message: String,
+    /// Only share the town or route the local player is in, and mask their name in the chat and
+    /// player list
+    pub privacy_mode: bool,
+    /// Which other players are shown and can be bumped into. Servers may hide more players.
+    pub visibility: Visibility,
+    /// Send anonymous statistics to the server when leaving: how long the session lasted, how often
+    /// each feature was used and where the client last crashed
+    pub share_usage_stats: bool,
+}
+
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct VideoSettings {
+    /// Dampen rapid changes in brightness, such as battle flashes
+    pub reduce_flashing: bool,
+    pub filter: DisplayFilter,
+    /// Show afterimages where other players have recently been
+    pub player_trails: bool,
+    /// The most other players to draw at once, nearest first. All players on screen are drawn if
+    /// this is not set.
+    pub max_drawn_players: Option<usize>,
+    /// Animate other players walking between their movement updates
+    pub smooth_walking: bool,
+    /// Draw other players gliding between the positions received for them, a moment behind, so
+    /// that they do not jump when updates arrive late
+    pub interpolation: bool,
+}
+
+impl Default for VideoSettings {
+    fn default() -> VideoSettings {
+        VideoSettings {
+            reduce_flashing: false,
+            filter: DisplayFilter::None,
+            player_trails: false,
+            max_drawn_players: None,
+            smooth_walking: true,
+            interpolation: true,
+        }
+    }
+}
+
+/// How often to communicate with the server. Lower rates use less data, but make movement less
+/// smooth. Servers may require rates above a minimum.
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct NetworkSettings {
+    /// The most times per second that the local player's movement and other changes are sent.
+    /// Changes in between are combined into the newest state.
+    pub movement_rate: u32,
+    /// How many times per second to check for updates from the server
+    pub receive_rate: u32,
+    /// Compress large packets, if the server supports it
+    pub compression: bool,
+    /// A PEM certificate to trust when connecting with `--tls`, for servers that use a self-signed
+    /// certificate
+    pub tls_certificate: Option<String>,
+    /// A SOCKS5 proxy to connect to servers through, e.g. `127.0.0.1:1080`
+    pub proxy: Option<String>,
+    /// How long to wait for an opponent to answer a battle request before giving up, in seconds
+    pub battle_request_timeout: u64,
+    /// The master server to list servers from when running with `--browse`
+    pub master_server: Option<String>,
+    /// Ask servers to only send other players' positions, a few times a second, for very slow
+    /// connections. Chat and watching battles are turned off.
+    pub lite_mode: bool,
+    /// Latency, jitter and packet loss to add to the connection, for testing how the game copes
+    /// with a poor connection. The default adds nothing.
+    pub simulate: NetworkConditions,
+}
+
+impl NetworkSettings {
+    /// The compression to offer servers when joining them
+    pub fn compression(&self) -> Compression {
+        if self.compression {
+            Compression::Deflate
+        }
+        else {
+            Compression::None
+        }
+    }
+}
+
+impl Default for NetworkSettings {
+    fn default() -> NetworkSettings {
+        NetworkSettings {
+            movement_rate: MAX_UPDATE_RATE,
+            receive_rate: MAX_UPDATE_RATE,
+            compression: true,
+            tls_certificate: None,
+            proxy: None,
+            battle_request_timeout: handshake::DEFAULT_RESPONSE_TIMEOUT.as_secs(),
+            master_server: None,
+            lite_mode: false,
+            simulate: NetworkConditions::default(),
+        }
+    }
+}
+
+/// Where a panel is placed in the window
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum PanelPlacement {
+    /// Next to, or on top of, the game's screen
+    #[default]
+    Docked,
+    /// In its own area below the game's screen, which streaming software can capture separately
+    Detached,
+}
+
+impl PanelPlacement {
+    pub fn name(self) -> &'static str {
+        match self {
+            PanelPlacement::Docked => "DOCKED",
+            PanelPlacement::Detached => "DETACHED",
+        }
+    }
+
+    pub fn next(self) -> PanelPlacement {
+        match self {
+            PanelPlacement::Docked => PanelPlacement::Detached,
+            PanelPlacement::Detached => PanelPlacement::Docked,
+        }
+    }
+}
+
+/// The placement of each panel. The window is laid out when the game starts, so changes take
+/// effect the next time it is started.
+#[derive(Copy, Clone, Default, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct WindowLayout {
+    /// Docked to the right of the game's screen
+    pub chat: PanelPlacement,
+    /// Docked over the top of the game's screen while watching a battle
+    pub spectator: PanelPlacement,
+}
+
+impl WindowLayout {
+    pub fn has_detached(&self) -> bool {
+        self.chat == PanelPlacement::Detached || self.spectator == PanelPlacement::Detached
+    }
+}
+
+/// The size and colors of the chat's text, separate from the size of the game's screen. Like the
+/// layout, changes take effect the next time the game is started.
+#[derive(Copy, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct ChatFont {
+    /// How many times larger than the game's own font to draw text, from 1 to `MAX_CHAT_SCALE`
+    pub scale: u32,
+    /// Draw yellow text on a black background
+    pub high_contrast: bool,
+}
+
+pub const MAX_CHAT_SCALE: u32 = 3;
+
+impl ChatFont {
+    pub fn scale(&self) -> u32 {
+        self.scale.clamp(1, MAX_CHAT_SCALE)
+    }
+
+    pub fn next_scale(&self) -> u32 {
+        self.scale() % MAX_CHAT_SCALE + 1
+    }
+}
+
+impl Default for ChatFont {
+    fn default() -> ChatFont {
+        ChatFont { scale: 1, high_contrast: false }
+    }
+}
+
+/// Filters that emulate the look of the original hardware's screen
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum DisplayFilter {
+    #[default]
+    None,
+    /// Blend each frame with the previous one, like the slow response time of the original LCD
+    Ghosting,
+    /// Darken the gap between each row of pixels
+    Scanlines,
+    /// Darken the gaps between each row and column of pixels
+    LcdGrid,
+}
+
+impl DisplayFilter {
+    pub const ALL: [DisplayFilter; 4] = [
+        DisplayFilter::None,
+        DisplayFilter::Ghosting,
+        DisplayFilter::Scanlines,
+        DisplayFilter::LcdGrid,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            DisplayFilter::None => "NONE",
+            DisplayFilter::Ghosting => "GHOSTING",
+            DisplayFilter::Scanlines => "SCANLINES",
+            DisplayFilter::LcdGrid => "LCD GRID",
+        }
+    }
+
+    pub fn next(self) -> DisplayFilter {
+        let index = DisplayFilter::ALL.iter().position(|&f| f == self).unwrap_or(0);
+        DisplayFilter::ALL[(index + 1) % DisplayFilter::ALL.len()]
+    }
+}
+
+/// How battles against other players are run
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum BattleMode {
+    /// Battle a copy of the opponent's party, with moves chosen by the game
+    #[default]
+    Local,
+    /// Both players battle each other's party, with each move chosen by the other player
+    Relay,
+}
+
+impl BattleMode {
+    pub fn name(self) -> &'static str {
+        match self {
+            BattleMode::Local => "LOCAL",
+            BattleMode::Relay => "RELAY",
+        }
+    }
+
+    pub fn next(self) -> BattleMode {
+        match self {
+            BattleMode::Local => BattleMode::Relay,
+            BattleMode::Relay => BattleMode::Local,
+        }
+    }
+}
+
+/// Which players are allowed to watch the local player's battles
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum SpectatorRule {
+    #[default]
+    Everyone,
+    /// Only players that the local player has marked as friends
+    Friends,
+    Nobody,
+}
+
+impl SpectatorRule {
+    pub fn name(self) -> &'static str {
+        match self {
+            SpectatorRule::Everyone => "EVERYONE",
+            SpectatorRule::Friends => "FRIENDS",
+            SpectatorRule::Nobody => "NOBODY",
+        }
+    }
+
+    pub fn next(self) -> SpectatorRule {
+        match self {
+            SpectatorRule::Everyone => SpectatorRule::Friends,
+            SpectatorRule::Friends => SpectatorRule::Nobody,
+            SpectatorRule::Nobody => SpectatorRule::Everyone,
+        }
+    }
+
+    pub fn allows(self, is_friend: bool) -> bool {
+        match self {
+            SpectatorRule::Everyone => true,
+            SpectatorRule::Friends => is_friend,
+            SpectatorRule::Nobody => false,
+        }
+    }
+}
+
+/// Settings that may be overridden for individual servers
+#[derive(Clone, Default, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct ServerSettings {
+    /// The sprite other players see for the local player
+    pub sprite: PlayerSprite,
+    /// Words that are masked out of chat messages from other players
+    pub chat_filter: Vec<String>,
+    /// Turn down battle requests and trade offers from other players without showing them
+    pub auto_decline: bool,
+}
+
+impl ServerSettings {
+    /// Masks any filtered words in a chat message
+    pub fn filter_chat(&self, msg: &str) -> String {
+        let mut filtered = msg.to_string();
+        for word in self.chat_filter.iter().filter(|word| !word.is_empty()) {
+            let word = word.to_ascii_lowercase();
+            let mask = "*".repeat(word.chars().count());
+
+            let mut search_start = 0;
+            while let Some(offset) = filtered[search_start..].to_ascii_lowercase().find(&word) {
+                let start = search_start + offset;
+                filtered.replace_range(start..start + word.len(), &mask);
+                search_start = start + mask.len();
+            }
+        }
+        filtered
+    }
+}
+
+#[derive(Clone, Default, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct SettingsOverrides {
+    pub sprite: Option<PlayerSprite>,
+    pub chat_filter: Option<Vec<String>>,
+    pub auto_decline: Option<bool>,
+}
+
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+pub struct ServerEntry {
+    pub address: String,
+    #[serde(default)]
+    pub overrides: SettingsOverrides,
+    /// Nicknames and notes for the players on this server. Player ids are assigned by the server,
+    /// so these are kept separately for each server.
+    #[serde(default)]
+    pub player_notes: HashMap<PlayerId, PlayerNote>,
+    /// The account to log in to on this server, if the player has logged in to one before
+    #[serde(default)]
+    pub account: Option<SavedAccount>,
+}
+
+/// An account that the player has logged in to, kept so that they stay logged in without giving
+/// their password again
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+pub struct SavedAccount {
+    pub name: String,
+    /// Given by the server after logging in with the password, to be used in its place
+    pub token: String,
+}
+
+/// A private nickname and note that the local player has attached to another player
+#[derive(Clone, Default, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct PlayerNote {
+    /// Shown in place of the player's name
+    pub nickname: String,
+    pub note: String,
+    /// Friends are still shown when only friends are visible
+    pub friend: bool,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            rom_path: None,
+            save_dir: None,
+            default_server: None,
+            keyboard_layout: KeyboardLayout::default(),
+            joypad: KeyBindings::default(),
+            second_joypad: KeyBindings::player_two(),
+            server_settings: ServerSettings::default(),
+            servers: Vec::new(),
+            video: VideoSettings::default(),
+            network: NetworkSettings::default(),
+            layout: WindowLayout::default(),
+            chat_font: ChatFont::default(),
+            touch_joypad: false,
+            definitions_url: None,
+            battle_mode: BattleMode::default(),
+            item_rule: ItemRule::default(),
+            share_hall_of_fame: false,
+            share_encounters: false,
+            spectators: SpectatorRule::default(),
+            notifications: true,
+            pause_when_away: true,
+            away_autopilot: false,
+            away_message: "I'm away from the game right now".to_string(),
+            privacy_mode: false,
+            visibility: Visibility::default(),
+            share_usage_stats: false,
+        }
+    }
+}
+
+impl Config {
+    /// Loads the config file, falling back to the default settings if it is missing or invalid
+    pub fn load(path: &Path) -> Config {
+        match fs::read(path) {
+            Ok(data) => match serde_json::from_slice(&data) {
+                Ok(config) => config,
+                Err(e) => {
+                    println!("Invalid config file ({}), using default settings", e);
+                    Config::default()
+                }
+            },
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Config::default(),
+            Err(e) => {
+                println!("Error reading config file ({}), using default settings", e);
+                Config::default()
+            }
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let data = serde_json::to_vec_pretty(self).map_err(io::Error::from)?;
+        fs::write(path, data)
+    }
+
+    /// Join relative paths to the ROM, save folder and certificate onto `base`, so that they no
+    /// longer depend on the working directory. Returns true if any were changed.
+    pub fn make_paths_absolute(&mut self, base: &Path) -> bool {
+        let mut changed = false;
+        let fields = [&mut self.rom_path, &mut self.save_dir, &mut self.network.tls_certificate];
+        for path in fields.into_iter().flatten() {
+            if Path::new(path.as_str()).is_relative() {
+                *path = base.join(&*path).to_string_lossy().into_owned();
+                changed = true;
+            }
+        }
+        changed
+    }
+
+    /// Get the settings to use for a server, applying any overrides from its saved entry
+    pub fn settings_for(&self, address: &str) -> ServerSettings {
+        let mut settings = self.server_settings.clone();
+        if let Some(entry) = self.servers.iter().find(|entry| entry.address == address) {
+            let overrides = entry.overrides.clone();
+            settings.sprite = overrides.sprite.unwrap_or(settings.sprite);
+            settings.chat_filter = overrides.chat_filter.unwrap_or(settings.chat_filter);
+            settings.auto_decline = overrides.auto_decline.unwrap_or(settings.auto_decline);
+        }
+        settings
+    }
+
+    /// Add a server to the list of saved servers, returning false if it was already saved
+    pub fn add_server(&mut self, address: &str) -> bool {
+        if self.servers.iter().any(|entry| entry.address == address) {
+            return false;
+        }
+        self.servers.push(ServerEntry {
+            address: address.to_string(),
+            overrides: SettingsOverrides::default(),
+            player_notes: HashMap::new(),
+            account: None,
+        });
+        true
+    }
+
+    /// The login for the account saved for a server, if there is one
+    pub fn saved_login(&self, address: &str) -> Option<Login> {
+        let entry = self.servers.iter().find(|entry| entry.address == address)?;
+        let account = entry.account.clone()?;
+        Some(Login { kind: LoginKind::Token, name: account.name, secret: account.token })
+    }
+
+    /// Remember the account logged in to on a server, replacing any account saved for it before
+    pub fn save_account(&mut self, address: &str, account: SavedAccount) {
+        self.add_server(address);
+        let entry = self.servers.iter_mut().find(|entry| entry.address == address).unwrap();
+        entry.account = Some(account);
+    }
+
+    /// Get the nickname and note attached to a player on a server
+    pub fn player_note(&self, address: &str, id: PlayerId) -> Option<&PlayerNote> {
+        let entry = self.servers.iter().find(|entry| entry.address == address)?;
+        entry.player_notes.get(&id)
+    }
+
+    /// Attach a nickname and note to a player on a server, removing them if both are empty and the
+    /// player is not a friend
+    pub fn set_player_note(&mut self, address: &str, id: PlayerId, note: PlayerNote) {
+        self.add_server(address);
+        let entry = self.servers.iter_mut().find(|entry| entry.address == address).unwrap();
+        if note.nickname.is_empty() && note.note.is_empty() && !note.friend {
+            entry.player_notes.remove(&id);
+        }
+        else {
+            entry.player_notes.insert(id, note);
+        }
+    }
+
+    /// Get the players on a server that the local player has marked as friends
+    pub fn friends(&self, address: &str) -> HashSet<PlayerId> {
+        let entry = self.servers.iter().find(|entry| entry.address == address);
+        let notes = entry.into_iter().flat_map(|entry| &entry.player_notes);
+        notes.filter(|(_, note)| note.friend).map(|(&id, _)| id).collect()
+    }
+
+    /// Get the key bindings of a local player
+    pub fn bindings(&self, local_player: usize) -> &KeyBindings {
+        match local_player {
+            0 => &self.joypad,
+            _ => &self.second_joypad,
+        }
+    }
+
+    pub fn bindings_mut(&mut self, local_player: usize) -> &mut KeyBindings {
+        match local_player {
+            0 => &mut self.joypad,
+            _ => &mut self.second_joypad,
+        }
+    }
+}
+
+/// The config file, in the client's config directory
+pub fn config_path() -> PathBuf {
+    paths::config_dir().join(CONFIG_FILE)
+}
+
+/// Save the config to the default location, logging any errors
+pub fn save_config(config: &Config) {
+    if let Err(e) = config.save(&config_path()) {
+        println!("Failed to save config: {}", e);
+    }
+}
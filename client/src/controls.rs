@@ -0,0 +1,182 @@
+//! Menu for rebinding the joypad controls and hotkeys
+use macroquad::prelude::KeyCode;
+
+use crate::{
+    border::BorderRenderer,
+    common::{Rect, Renderer},
+    config::{self, Config},
+    font::Font,
+    input::{self, Action, Button},
+    menu::ItemBox,
+};
+
+/// What each line of the menu does when selected
+#[derive(Copy, Clone)]
+enum Row {
+    Layout,
+    Page,
+    Binding(Action),
+    Back,
+}
+
+pub struct ControlsMenu<'a> {
+    item_box: ItemBox<'a>,
+    rows: Vec<Row>,
+    local_player: usize,
+    /// Whether the hotkeys are shown instead of the joypad buttons, which do not fit on the screen
+    /// at the same time
+    show_hotkeys: bool,
+    /// The action that is waiting for a new key to be pressed
+    awaiting: Option<Action>,
+    /// The last key that could not be bound, as (action, key, the action already using the key)
+    conflict: Option<(Action, KeyCode, Action)>,
+}
+
+impl<'a> ControlsMenu<'a> {
+    pub fn new(
+        config: &Config,
+        local_player: usize,
+        font: &'a Font,
+        border: &'a BorderRenderer,
+        rect: Rect,
+    ) -> ControlsMenu<'a> {
+        let mut controls_menu = ControlsMenu {
+            item_box: ItemBox::new(vec![], font, border, rect),
+            rows: vec![],
+            local_player,
+            show_hotkeys: false,
+            awaiting: None,
+            conflict: None,
+        };
+        controls_menu.refresh(config);
+        controls_menu
+    }
+
+    /// Rebuild the displayed items from the current config. Keys are shown using the label they
+    /// have on the configured keyboard layout.
+    fn refresh(&mut self, config: &Config) {
+        let mut rows = vec![Row::Layout, Row::Page];
+        if self.show_hotkeys {
+            rows.extend(Action::HOTKEYS.iter().map(|&action| Row::Binding(action)));
+        }
+        else {
+            rows.extend(Button::ALL.iter().map(|&button| Row::Binding(Action::Joypad(button))));
+        }
+        rows.push(Row::Back);
+
+        let items = rows.iter().map(|&row| self.row_text(row, config)).collect();
+        self.rows = rows;
+        self.item_box.set_items(items);
+    }
+
+    fn row_text(&self, row: Row, config: &Config) -> String {
+        let layout = config.keyboard_layout;
+        let bindings = config.bindings(self.local_player);
+        match row {
+            Row::Layout => format!("LAYOUT {}", layout.name()),
+            Row::Page => format!("SHOW {}", if self.show_hotkeys { "HOTKEYS" } else { "JOYPAD" }),
+            Row::Binding(action) => {
+                let key = match (self.awaiting, self.conflict) {
+                    (Some(awaiting), _) if awaiting == action => "...".to_string(),
+                    (_, Some((failed, key, used_by))) if failed == action => {
+                        format!("{} USED BY {}", input::key_label(key), used_by.name())
+                    }
+                    _ => bindings.label(action, layout),
+                };
+                format!("{:<7}{}", action.name(), key)
+            }
+            Row::Back => "BACK".to_string(),
+        }
+    }
+
+    pub fn draw(&self, renderer: &mut Renderer) {
+        self.item_box.draw(renderer);
+    }
+
+    pub fn key_down(&mut self, keycode: KeyCode) {
+        if self.awaiting.is_some() {
+            return;
+        }
+
+        match keycode {
+            KeyCode::Up => self.item_box.move_up(),
+            KeyCode::Down => self.item_box.move_down(),
+            _ => {}
+        }
+    }
+
+    /// Handles a key release, returning true if the menu should be closed
+    pub fn key_up(&mut self, keycode: KeyCode, config: &mut Config) -> bool {
+        self.conflict = None;
+        if let Some(action) = self.awaiting.take() {
+            if keycode != KeyCode::Escape && input::is_bindable(keycode) {
+                self.bind(action, keycode, config);
+            }
+            self.refresh(config);
+            return false;
+        }
+
+        match keycode {
+            KeyCode::Escape => true,
+            KeyCode::Enter => self.select(config),
+            _ => {
+                self.refresh(config);
+                false
+            }
+        }
+    }
+
+    /// Handles a button on a controller, returning true if the menu should be closed. Controllers
+    /// can move around the menu, but only keys can be bound, so releasing a button while a key is
+    /// awaited stops waiting for it.
+    pub fn pad_input(&mut self, action: Action, pressed: bool, config: &mut Config) -> bool {
+        self.conflict = None;
+        if self.awaiting.is_some() {
+            if !pressed {
+                self.awaiting = None;
+                self.refresh(config);
+            }
+            return false;
+        }
+
+        match action {
+            Action::Joypad(Button::Up) if pressed => self.item_box.move_up(),
+            Action::Joypad(Button::Down) if pressed => self.item_box.move_down(),
+            Action::Joypad(Button::A | Button::Start) if !pressed => return self.select(config),
+            Action::Joypad(Button::B) | Action::Menu if !pressed => return true,
+            _ => {}
+        }
+        self.refresh(config);
+        false
+    }
+
+    /// Act on the selected row, returning true if the menu should be closed
+    fn select(&mut self, config: &mut Config) -> bool {
+        match self.rows[self.item_box.selection()] {
+            Row::Layout => {
+                config.keyboard_layout = config.keyboard_layout.next();
+                config::save_config(config);
+            }
+            Row::Page => self.show_hotkeys = !self.show_hotkeys,
+            Row::Binding(action) => self.awaiting = Some(action),
+            Row::Back => return true,
+        }
+        self.refresh(config);
+        false
+    }
+
+    /// Bind a key to an action, unless another action already uses it
+    fn bind(&mut self, action: Action, keycode: KeyCode, config: &mut Config) {
+        let physical_key = config.keyboard_layout.to_physical(keycode);
+        let bindings = config.bindings_mut(self.local_player);
+        match bindings.action_for(physical_key) {
+            Some(used_by) if used_by != action => {
+                self.conflict = Some((action, keycode, used_by));
+            }
+            _ => {
+                bindings.set(action, physical_key);
+                config::save_config(config);
+            }
+        }
+    }
+}
@@ -0,0 +1,153 @@
+//! Crash reports, written when the client panics so that players can attach them to an issue
+//!
+//! A report has the client's version, the hash of the ROM, the emulator's registers as of the last
+//! frame and the last network events sent or received, along with the panic message and a
+//! backtrace. Unlike the crash locations in usage statistics, reports are never sent anywhere. The
+//! next time the client starts, it shows where the last report was saved.
+use std::{
+    backtrace::Backtrace,
+    fmt::Write,
+    fs, panic,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use gb_emu::cpu::Cpu;
+use macroquad::{
+    color::{DARKGRAY, WHITE},
+    input::{is_key_pressed, KeyCode},
+    text::draw_text,
+    window::{clear_background, next_frame},
+};
+use network_common::{game, shared, stats::Traffic, version::PROTOCOL_VERSION};
+
+use crate::paths;
+
+/// The reports' directory, in the client's data directory
+pub const REPORT_DIR: &str = "crash-reports";
+
+/// Where the path of the last report is kept in `REPORT_DIR` until it has been shown
+const LAST_REPORT_FILE: &str = "last.txt";
+
+const FONT_SIZE: f32 = 24.0;
+const LINE_HEIGHT: f32 = 32.0;
+const MARGIN: f32 = 32.0;
+
+struct Context {
+    rom_hash: String,
+    /// The program counter, stack pointer and accumulator
+    registers: Option<(u16, u16, u8)>,
+    traffic: Option<Traffic>,
+}
+
+static CONTEXT: Mutex<Context> =
+    Mutex::new(Context { rom_hash: String::new(), registers: None, traffic: None });
+
+fn update(f: impl FnOnce(&mut Context)) {
+    if let Ok(mut context) = CONTEXT.lock() {
+        f(&mut context);
+    }
+}
+
+/// Note the ROM being played, whenever one is loaded
+pub fn set_rom(rom: &[u8]) {
+    let rom_hash = game::rom_hash(rom);
+    update(|context| context.rom_hash = rom_hash);
+}
+
+/// Note the connection whose events are included in reports, whenever the client joins a server
+pub fn set_traffic(traffic: Traffic) {
+    update(|context| context.traffic = Some(traffic));
+}
+
+/// Note the emulator's registers, once a frame
+pub fn record_registers(cpu: &Cpu) {
+    update(|context| context.registers = Some((cpu.pc, cpu.sp, cpu.a)));
+}
+
+/// Write a report whenever the client panics, as well as printing the panic as usual
+pub fn write_reports() {
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        match write_report(&info.to_string()) {
+            Ok(path) => {
+                eprintln!("A crash report was saved to {}", path.display());
+                let last_report = report_dir().join(LAST_REPORT_FILE);
+                let _ = fs::write(last_report, path.to_string_lossy().as_bytes());
+            }
+            Err(e) => eprintln!("Failed to write a crash report: {}", e),
+        }
+        default_hook(info);
+    }));
+}
+
+fn write_report(message: &str) -> std::io::Result<PathBuf> {
+    let mut report = String::new();
+    let _ = writeln!(report, "Pikemon client {}", env!("CARGO_PKG_VERSION"));
+    let _ = writeln!(report, "Protocol version: {}", PROTOCOL_VERSION);
+    let _ = writeln!(report, "Panic: {}", message);
+
+    // The context is left out if the panic happened while it was being updated
+    if let Ok(context) = CONTEXT.try_lock() {
+        let _ = writeln!(report, "ROM SHA-256: {}", context.rom_hash);
+        match context.registers {
+            Some((pc, sp, a)) => {
+                let _ = writeln!(report, "Registers: PC {:04X} SP {:04X} A {:02X}", pc, sp, a);
+            }
+            None => {
+                let _ = writeln!(report, "Registers: the emulator had not started");
+            }
+        }
+        let events = context.traffic.as_ref().map(Traffic::recent_events).unwrap_or_default();
+        let _ = writeln!(report, "\nLast {} network events, newest last:", events.len());
+        for event in events {
+            let direction = if event.sent { "sent" } else { "received" };
+            let age = event.at.elapsed().as_secs_f32();
+            let _ = writeln!(report, "  {:>8.3}s ago  {:<8}  {}", age, direction, event.name);
+        }
+    }
+
+    let _ = writeln!(report, "\nBacktrace:\n{}", Backtrace::force_capture());
+
+    fs::create_dir_all(report_dir())?;
+    let path = report_dir().join(format!("crash-{}.txt", shared::now_secs()));
+    fs::write(&path, report)?;
+    Ok(path)
+}
+
+fn report_dir() -> PathBuf {
+    paths::data_dir().join(REPORT_DIR)
+}
+
+/// The path of the report written when the client last crashed, if it has not been shown yet
+pub fn take_last_report() -> Option<PathBuf> {
+    let last_report = report_dir().join(LAST_REPORT_FILE);
+    let path = fs::read_to_string(&last_report).ok()?;
+    let _ = fs::remove_file(last_report);
+    Some(PathBuf::from(path))
+}
+
+/// Tell the player where the report of the last crash was saved, until they press a key. Like the
+/// setup, this is drawn with macroquad's built-in font since no ROM has been read yet.
+pub async fn show_report(path: &Path) {
+    let lines = [
+        "Pikemon closed unexpectedly last time.".to_string(),
+        "A crash report was saved to:".to_string(),
+        format!("  {}", path.display()),
+        "Please attach it when reporting the problem.".to_string(),
+        String::new(),
+        "Press ENTER to continue".to_string(),
+    ];
+    loop {
+        if is_key_pressed(KeyCode::Enter) || is_key_pressed(KeyCode::Escape) {
+            return;
+        }
+
+        clear_background(WHITE);
+        for (i, line) in lines.iter().enumerate() {
+            let y = MARGIN + (i + 1) as f32 * LINE_HEIGHT;
+            draw_text(line, MARGIN, y, FONT_SIZE, DARKGRAY);
+        }
+        next_frame().await
+    }
+}
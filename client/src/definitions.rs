@@ -0,0 +1,99 @@
+//! Updating the game definitions (the offsets used to interface with the game) from a URL
+//!
+//! Developers working out the offsets for new hooks can run the client with
+//! `--dev-definitions=PATH`, and press `RELOAD_KEY` during the game to apply that file again after
+//! editing it. The offsets are replaced without restarting the emulator, and offsets removed from
+//! the file go back to their built-in values.
+use std::{
+    fs,
+    io::{self, Read},
+    path::{Path, PathBuf},
+    thread,
+    time::Duration,
+};
+
+use crossbeam_channel::Receiver;
+use interface::definitions::Definitions;
+use macroquad::prelude::KeyCode;
+
+use crate::paths;
+
+/// Where the most recently downloaded definitions are stored in the data directory, so that they
+/// are used on startup
+pub const DEFINITIONS_FILE: &str = "definitions.json";
+
+/// The maximum size of a definitions file that will be downloaded
+const MAX_DEFINITIONS_SIZE: u64 = 1024 * 1024;
+
+const DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Reloads the definitions file given with `--dev-definitions`
+pub const RELOAD_KEY: KeyCode = KeyCode::F5;
+
+/// The definitions file that developers are editing, if one was given
+pub fn dev_path() -> Option<PathBuf> {
+    std::env::args().find_map(|arg| arg.strip_prefix("--dev-definitions=").map(PathBuf::from))
+}
+
+/// Read a definitions file, then start using it
+pub fn load_file(path: &Path, rom: &[u8]) -> Result<(), String> {
+    let data = fs::read(path).map_err(|e| format!("error reading {}: {}", path.display(), e))?;
+    apply(&parse(&data)?, rom)
+}
+
+pub fn saved_path() -> PathBuf {
+    paths::data_dir().join(DEFINITIONS_FILE)
+}
+
+/// Use the definitions saved by a previous update, if there are any
+pub fn load_saved(rom: &[u8]) {
+    let path = saved_path();
+    let data = match fs::read(&path) {
+        Ok(data) => data,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return,
+        Err(e) => {
+            println!("Error reading saved definitions: {}", e);
+            return;
+        }
+    };
+
+    match parse(&data).and_then(|definitions| apply(&definitions, rom)) {
+        Ok(()) => println!("Loaded definitions from {}", path.display()),
+        Err(e) => println!("Saved definitions were not loaded: {}", e),
+    }
+}
+
+pub fn parse(data: &[u8]) -> Result<Definitions, String> {
+    serde_json::from_slice(data).map_err(|e| format!("invalid definitions file: {}", e))
+}
+
+/// Check that definitions are valid for a ROM, then start using them
+pub fn apply(definitions: &Definitions, rom: &[u8]) -> Result<(), String> {
+    definitions.validate(rom).map_err(|e| e.to_string())?;
+    definitions.apply();
+    Ok(())
+}
+
+/// Download a definitions file on a background thread
+pub fn download(url: String) -> Receiver<Result<Vec<u8>, String>> {
+    let (sender, receiver) = crossbeam_channel::bounded(1);
+    thread::spawn(move || {
+        let _ = sender.send(fetch(&url));
+    });
+    receiver
+}
+
+fn fetch(url: &str) -> Result<Vec<u8>, String> {
+    if !url.starts_with("https://") {
+        return Err("definitions must be downloaded over HTTPS".into());
+    }
+
+    let response = ureq::get(url).timeout(DOWNLOAD_TIMEOUT).call().map_err(|e| e.to_string())?;
+    let mut data = vec![];
+    response
+        .into_reader()
+        .take(MAX_DEFINITIONS_SIZE)
+        .read_to_end(&mut data)
+        .map_err(|e| e.to_string())?;
+    Ok(data)
+}
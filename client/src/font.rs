@@ -1,18 +1,48 @@
-use interface::text::special;
-use macroquad::texture::Texture2D;
+use interface::text::{self, special};
+use macroquad::{
+    prelude::{draw_rectangle, WHITE},
+    texture::Texture2D,
+};
 
-use crate::common::{Rect, Renderer};
+use crate::{
+    border::BorderRenderer,
+    common::{Rect, Renderer},
+};
+
+/// The colors of a font's characters, and of the background behind them
+pub type TextColors = [[u8; 4]; 2];
+
+pub const STANDARD_COLORS: TextColors = [[0, 0, 0, 255], [255, 255, 255, 255]];
+
+/// Yellow on black, for players who find the standard colors hard to read
+pub const HIGH_CONTRAST_COLORS: TextColors = [[255, 255, 0, 255], [0, 0, 0, 255]];
 
 pub struct Font {
     texture: Texture2D,
     char_height: i32,
     char_width: i32,
     scale: i32,
+    colors: TextColors,
 }
 
 impl Font {
-    pub fn new(texture: Texture2D, char_height: i32, char_width: i32, scale: i32) -> Font {
-        Font { texture, char_height, char_width, scale }
+    pub fn new(
+        texture: Texture2D,
+        char_height: i32,
+        char_width: i32,
+        scale: i32,
+        colors: TextColors,
+    ) -> Font {
+        Font { texture, char_height, char_width, scale, colors }
+    }
+
+    pub fn texture(&self) -> Texture2D {
+        self.texture
+    }
+
+    /// The colors that the texture was extracted with
+    pub fn colors(&self) -> TextColors {
+        self.colors
     }
 
     pub fn line_height(&self) -> i32 {
@@ -70,3 +100,22 @@ pub fn draw_text(renderer: &mut Renderer, font: &Font, text: &[u8], target: &Rec
     // Return the height of the text drawn
     y - target.y + font.line_height()
 }
+
+/// Draw a single line of text in a bordered box, with its top left corner at (x, y)
+pub fn draw_label(
+    renderer: &mut Renderer,
+    font: &Font,
+    border: &BorderRenderer,
+    label: &str,
+    x: i32,
+    y: i32,
+) {
+    let width = (label.chars().count() as i32 + 2) * font.char_width();
+    let height = 3 * font.line_height();
+    draw_rectangle(x as f32, y as f32, width as f32, height as f32, WHITE);
+    border.draw_box(renderer, Rect::new(x, y, width, height));
+
+    let encoded: Vec<u8> = text::Encoder::new(label).collect();
+    let target = Rect::new(x + font.char_width(), y + font.line_height(), width, height);
+    draw_text(renderer, font, &encoded, &target);
+}
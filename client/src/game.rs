@@ -1,33 +1,83 @@
-use std::{cell::RefCell, mem};
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    fs, mem,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
 
+use crossbeam_channel::{Receiver, TryRecvError};
 use gb_emu::{cpu::Cpu, emulator::Emulator, graphics, joypad, mmu::Memory};
 
 use interface::{
     self,
-    data::{PlayerData, SpriteData},
-    extract, hacks,
-    values::Direction,
-    InterfaceData, InterfaceState,
+    data::{MovementData, PlayerData},
+    extract,
+    facade::{self, Facade},
+    text,
+    visibility::Visibility,
+    watch::Changes,
+    InterfaceData, PlayerId,
 };
 use macroquad::{
     prelude::{KeyCode, WHITE},
     texture::{render_target, FilterMode, Image, Texture2D},
 };
+use network_common::Feature;
 
 use crate::{
     border::BorderRenderer,
     chat::ChatBox,
     client,
     common::{Rect, Renderer},
-    font::Font,
+    config::{Config, PanelPlacement, PlayerNote, ServerSettings},
+    controls::ControlsMenu,
+    crash, definitions,
+    font::{self, Font},
+    help::{self, ConnectionInfo, HelpView},
+    input::{Action, Button},
+    interpolation::Interpolation,
+    keyboard::{Key, OnScreenKeyboard},
     menu::ItemBox,
+    options::OptionsMenu,
+    players::{PlayerEntry, PlayerList},
+    replays::{Recorder, ReplayMenu},
+    roms::{RomMenu, RomMenuAction},
+    safari::SafariEvent,
+    save::{self, LocalSaveWrapper, SaveProblem},
+    servers::{ServerMenu, ServerMenuAction},
+    shop::Shop,
+    spectate::{self, Spectating},
+    touch::VirtualJoypad,
+    trade::TradeBoard,
+    traffic::TrafficView,
+    trails::Trails,
+    usage::Usage,
+    video::{self, FrameFilter},
+    world::WorldEffects,
 };
 
+/// How long the player must go without any input to be considered away from the game
+const AWAY_TIME: Duration = Duration::from_secs(60);
+
+/// Shown in place of the local player's name in privacy mode
+const MASKED_NAME: &str = "YOU";
+
 #[derive(PartialEq, Eq)]
 pub enum GameState {
     Emulator,
     ChatBox,
     Menu,
+    Controls,
+    Options,
+    Roms,
+    Servers,
+    Replays,
+    Players,
+    Trades,
+    Shop,
+    Traffic,
+    Help,
 }
 
 pub struct Game<'a> {
@@ -35,33 +85,128 @@ pub struct Game<'a> {
     pub screen: Image,
     pub screen_texture: Texture2D,
     pub font: &'a Font,
+    /// The font used by the chat box, which can be larger or have different colors
+    pub chat_font: &'a Font,
     pub border_renderer: &'a BorderRenderer,
 
     pub game_state: GameState,
-    pub interface_data: RefCell<InterfaceData>,
+    pub interface: RefCell<Facade>,
     pub chat_box: ChatBox<'a>,
+    pub keyboard: OnScreenKeyboard<'a>,
     pub menu: ItemBox<'a>,
+    pub controls_menu: ControlsMenu<'a>,
+    pub options_menu: OptionsMenu<'a>,
+    pub rom_menu: RomMenu<'a>,
+    pub server_menu: ServerMenu<'a>,
+    pub replay_menu: ReplayMenu<'a>,
+    pub player_list: PlayerList<'a>,
+    pub trade_board: TradeBoard<'a>,
+    pub shop: Shop<'a>,
+    pub traffic_view: TrafficView<'a>,
+    pub help_view: HelpView<'a>,
+    /// A download of updated game definitions that is in progress
+    pub definitions_download: Option<Receiver<Result<Vec<u8>, String>>>,
+    /// The definitions file that can be reloaded while the game is running, for development
+    pub dev_definitions: Option<PathBuf>,
+    pub frame_filter: FrameFilter,
+    pub trails: Trails,
+    pub interpolation: Interpolation,
+    pub touch_joypad: VirtualJoypad,
+    pub config: &'a RefCell<Config>,
+    /// The index of this game when multiple players share the same window
+    pub local_player: usize,
+    /// The area of the window that this game is drawn to
+    pub viewport: Rect,
+    /// Where the battle being watched is shown, which is over the game's screen unless the
+    /// spectator panel is detached
+    spectator_rect: Rect,
+    pub server_settings: ServerSettings,
     pub player_data: PlayerData,
+    /// What has changed in the game since the parts of the client that react to it last looked.
+    /// Each part clears the changes it handles, so nothing is read from the game while it is idle.
+    pub changes: Changes,
+    /// The privacy mode that the player's data was last shared with, or None if it needs to be
+    /// shared again because something that the game's memory does not show has changed
+    shared_privacy: Option<bool>,
+    /// The number of times other players have entered the Hall of Fame, for players that shared it
+    pub hall_of_fame: HashMap<PlayerId, u8>,
+    /// Other players whose game is paused because they have left it
+    pub paused_players: HashSet<PlayerId>,
+    /// The round trip time to the server, once it has been measured
+    pub latency: Option<Duration>,
+    pub spectating: Spectating,
+    pub recorder: Recorder,
+    pub safari: SafariEvent,
+    pub world: WorldEffects,
     pub fast_mode: bool,
+    /// A quick chat phrase that the player wants to send to their opponent
+    pub quick_chat: Option<u8>,
+    /// Whether the player has chosen to forfeit their relayed battle from the menu
+    pub forfeit_requested: bool,
     pub exit_requested: bool,
+    /// How often features have been used this session, for players that share usage statistics
+    pub usage: Usage,
+    /// When the player last pressed a key or button
+    pub last_input: Instant,
+    /// The address of the server this game is connected to
+    pub server_address: String,
+    /// A server that the player has chosen to switch to, which is joined outside of the game
+    pub switch_server: Option<String>,
+    /// Whether another player has asked for something, so the window should ask for attention
+    pub attention_requested: bool,
 }
 
 impl<'a> Game<'a> {
     pub fn new(
         emulator: Box<Emulator>,
+        config: &'a RefCell<Config>,
+        local_player: usize,
+        server_address: &str,
         font: &'a Font,
+        chat_font: &'a Font,
         border_renderer: &'a BorderRenderer,
     ) -> Game<'a> {
         let player_data = PlayerData::new(&emulator.mem);
 
-        let chat_box_rect = Rect::new(
-            client::EMU_WIDTH as i32,
-            0,
-            client::CHAT_WIDTH as i32,
+        // Local players are laid out side by side, each with their own chat box
+        let (layout, chat_font_settings) = {
+            let config = config.borrow();
+            (config.layout, config.chat_font)
+        };
+        let (width, height) = client::viewport_size(&layout, &chat_font_settings);
+        let viewport =
+            Rect::new(local_player as i32 * width as i32, 0, width as i32, height as i32);
+        let screen_rect =
+            Rect::new(viewport.x, 0, client::EMU_WIDTH as i32, client::EMU_HEIGHT as i32);
+
+        // Detached panels share the area below the game's screen, with the spectator view on top
+        let mut panel = Rect::new(
+            viewport.x,
             client::EMU_HEIGHT as i32,
+            client::EMU_WIDTH as i32,
+            client::PANEL_HEIGHT as i32,
         );
+        let spectator_rect = match layout.spectator {
+            PanelPlacement::Docked => screen_rect,
+            PanelPlacement::Detached => {
+                let height = spectate::VIEW_LINES * font.line_height();
+                let rect = Rect::new(panel.x, panel.y, panel.width, height);
+                panel.y += height;
+                panel.height -= height;
+                rect
+            }
+        };
+        let chat_box_rect = match layout.chat {
+            PanelPlacement::Docked => Rect::new(
+                viewport.x + client::EMU_WIDTH as i32,
+                0,
+                client::chat_width(&chat_font_settings) as i32,
+                client::EMU_HEIGHT as i32,
+            ),
+            PanelPlacement::Detached => panel,
+        };
         let menu_rect = Rect::new(
-            ((client::EMU_WIDTH - client::MENU_WIDTH) / 2) as i32,
+            viewport.x + ((client::EMU_WIDTH - client::MENU_WIDTH) / 2) as i32,
             ((client::EMU_HEIGHT - client::MENU_HEIGHT) / 2) as i32,
             client::MENU_WIDTH as i32,
             client::MENU_HEIGHT as i32,
@@ -75,86 +220,326 @@ impl<'a> Game<'a> {
             screen: Image::gen_image_color(graphics::WIDTH as u16, graphics::HEIGHT as u16, WHITE),
             screen_texture,
             font,
+            chat_font,
             border_renderer,
 
             game_state: GameState::Emulator,
-            interface_data: RefCell::new(InterfaceData::new()),
-            chat_box: ChatBox::new(font, border_renderer, chat_box_rect),
+            interface: RefCell::new(Facade::new()),
+            chat_box: ChatBox::new(
+                chat_font,
+                border_renderer,
+                chat_box_rect,
+                chat_font_settings.high_contrast,
+            ),
+            keyboard: OnScreenKeyboard::new(font, border_renderer, chat_box_rect),
             menu: ItemBox::new(
-                vec!["CONNECT".to_string(), "SHOW PLAYERS".to_string(), "EXIT".to_string()],
+                vec![
+                    "CONNECT".to_string(),
+                    "SHOW PLAYERS".to_string(),
+                    "NETWORK STATS".to_string(),
+                    "WATCH BATTLE".to_string(),
+                    "REPLAYS".to_string(),
+                    "FORFEIT BATTLE".to_string(),
+                    "SAFARI EVENT".to_string(),
+                    "TRADE BOARD".to_string(),
+                    "SERVER SHOP".to_string(),
+                    "CONTROLS".to_string(),
+                    "OPTIONS".to_string(),
+                    "SWAP GAME".to_string(),
+                    "UPDATE DEFINITIONS".to_string(),
+                    "EXIT".to_string(),
+                ],
+                font,
+                border_renderer,
+                menu_rect,
+            ),
+            controls_menu: ControlsMenu::new(
+                &config.borrow(),
+                local_player,
                 font,
                 border_renderer,
                 menu_rect,
             ),
+            options_menu: OptionsMenu::new(&config.borrow(), font, border_renderer, menu_rect),
+            rom_menu: RomMenu::new(font, border_renderer, menu_rect),
+            server_menu: ServerMenu::new(font, border_renderer, menu_rect),
+            replay_menu: ReplayMenu::new(font, border_renderer, menu_rect),
+            player_list: PlayerList::new(font, border_renderer, menu_rect),
+            trade_board: TradeBoard::new(font, border_renderer, menu_rect),
+            shop: Shop::new(font, border_renderer, menu_rect),
+            traffic_view: TrafficView::new(font, border_renderer, menu_rect),
+            help_view: HelpView::new(font, border_renderer, menu_rect),
+            definitions_download: None,
+            dev_definitions: definitions::dev_path(),
+            frame_filter: FrameFilter::new(),
+            trails: Trails::new(),
+            interpolation: Interpolation::new(),
+            touch_joypad: VirtualJoypad::new(),
+            config,
+            local_player,
+            viewport,
+            spectator_rect,
+            server_settings: ServerSettings::default(),
             player_data,
+            changes: Changes::ALL,
+            shared_privacy: None,
+            hall_of_fame: HashMap::new(),
+            paused_players: HashSet::new(),
+            latency: None,
+            spectating: Spectating::new(),
+            recorder: Recorder::new(),
+            safari: SafariEvent::new(),
+            world: WorldEffects::new(),
             fast_mode: false,
+            quick_chat: None,
+            forfeit_requested: false,
             exit_requested: false,
+            usage: Usage::new(),
+            last_input: Instant::now(),
+            server_address: server_address.to_string(),
+            switch_server: None,
+            attention_requested: false,
         }
     }
 
     pub fn update(&mut self) {
-        if self.interface_data.borrow().state == InterfaceState::Normal {
+        self.check_definitions_download();
+        if self.on_autopilot() {
+            self.fast_mode = false;
+            for button in Button::ALL {
+                self.set_button(button, joypad::State::Released);
+            }
+        }
+        if let Some(notice) = self.world.update() {
+            self.chat_box.add_notice(notice);
+        }
+        self.update_visibility();
+
+        if !self.interface.borrow().is_waiting() && !self.is_paused() {
             // Individually borrow elements of self that we need so that we pass Rust's borrow
             // checker. (Hopefully we won't need to do this in the future)
-            let interface_data = &mut self.interface_data;
+            let interface = &self.interface;
             let player_data = &mut self.player_data;
+            let changes = &mut self.changes;
             let screen = &mut self.screen;
             let emulator = &mut self.emulator;
+            let frame_filter = &mut self.frame_filter;
+            let trails = &mut self.trails;
+            let interpolation = &self.interpolation;
+            let config = self.config;
 
             // After each tick we run all the hacks on the game. Most of the hacks do not actually
             // do anything for most of the cycles but wait for the program to reach a certain point.
             let on_tick = |cpu: &mut Cpu, mem: &mut Memory| {
-                let interface_data = &mut interface_data.borrow_mut();
-                hacks::sprite_check(cpu, mem, interface_data);
-                hacks::display_text(cpu, mem, interface_data);
-                hacks::sprite_update_tracker(cpu, mem, interface_data);
+                interface.borrow_mut().on_tick(cpu, mem);
             };
 
             // On each vblank we draw other players to the screen and copy the internal framebuffer
             // to a texture. It is important do this during the vblank period to ensure that we
             // don't get partially redrawn lines affecting the result.
-            let on_vblank = |_: &mut Cpu, mem: &mut Memory| {
-                let new_player_data = PlayerData {
-                    name: extract::player_name(mem),
-                    sprite: mem::replace(&mut player_data.sprite, vec![]),
-                    movement_data: extract::movement_data(mem),
-                };
-                *player_data = new_player_data;
+            let on_vblank = |cpu: &mut Cpu, mem: &mut Memory| {
+                crash::record_registers(cpu);
+                let frame_changes = interface.borrow_mut().update_local_player(mem, player_data);
+                *changes = changes.union(frame_changes);
+                let interface = &interface.borrow();
 
-                let interface_data = &interface_data.borrow();
+                let interface_data = &interface.data;
+                let show_trails = config.borrow().video.player_trails;
+                if show_trails {
+                    trails.update(&interface_data.players);
+                }
+                else {
+                    trails.clear();
+                }
                 if interface_data.sprites_enabled() {
-                    draw_other_players(interface_data, player_data, mem);
+                    let config = config.borrow();
+                    let drawn = DrawnPlayers {
+                        trails,
+                        interpolation: config.video.interpolation.then_some(interpolation),
+                        max: config.video.max_drawn_players,
+                    };
+                    draw_other_players(interface_data, &drawn, player_data, mem);
                 }
 
                 screen.bytes.copy_from_slice(&mem.gpu.framebuffer);
+                frame_filter.apply(&config.borrow().video, &mut screen.bytes);
                 self.screen_texture.update(&screen);
             };
 
             emulator.frame(on_tick, on_vblank);
+            self.record_battle();
+        }
+        if self.game_state == GameState::Replays {
+            self.replay_menu.update();
         }
     }
 
+    /// Add the current state of the local player's battle to its replay
+    fn record_battle(&mut self) {
+        let opponent = {
+            let interface = self.interface.borrow();
+            let relay = interface.data.battle_relay.as_ref();
+            let opponent = relay.and_then(|relay| interface.data.players.get(&relay.opponent));
+            opponent.map(|player| text::decode(&player.name))
+        };
+        self.recorder.update(&self.emulator.mem, opponent);
+    }
+
+    /// Apply the local player's visibility rule and friends, which may have changed in the options
+    /// menu or from a chat command
+    fn update_visibility(&self) {
+        let config = self.config.borrow();
+        let mut interface = self.interface.borrow_mut();
+        let visibility = &mut interface.data.visibility;
+        visibility.local = config.visibility;
+        visibility.friends = config.friends(&self.server_address);
+    }
+
+    /// The area of the window that the emulator's screen is drawn to
+    fn screen_rect(&self) -> Rect {
+        Rect::new(
+            self.viewport.x,
+            self.viewport.y,
+            client::EMU_WIDTH as i32,
+            client::EMU_HEIGHT as i32,
+        )
+    }
+
     pub fn render(&self, renderer: &mut Renderer) {
-        renderer.copy(
-            self.screen_texture,
-            None,
-            Some(Rect::new(0, 0, client::EMU_WIDTH as i32, client::EMU_HEIGHT as i32)),
+        let screen_rect = self.screen_rect();
+        renderer.copy(self.screen_texture, None, Some(screen_rect));
+
+        let config = self.config.borrow();
+        self.world.draw_tint(screen_rect, self.player_data.movement_data.map_id);
+        video::draw_overlay(&config.video, screen_rect, client::EMU_SCALE as i32);
+        self.spectating.draw(
+            renderer,
+            self.font,
+            self.border_renderer,
+            &self.emulator.mem,
+            self.spectator_rect,
         );
+        self.spectating.draw_watchers(renderer, self.font, self.border_renderer, screen_rect);
+        self.safari.draw(renderer, self.font, self.border_renderer, screen_rect);
+        self.world.draw(renderer, self.font, self.border_renderer, screen_rect);
+        if matches!(&self.interface.borrow().data.battle_relay, Some(relay) if relay.paused) {
+            let label = "WAITING FOR OPPONENT";
+            let x = screen_rect.x
+                + (screen_rect.width - label.len() as i32 * self.font.char_width()) / 2;
+            let y = screen_rect.y + screen_rect.height / 2;
+            font::draw_label(renderer, self.font, self.border_renderer, label, x, y);
+        }
+        else if self.is_paused() {
+            let label = "PAUSED";
+            let x = screen_rect.x
+                + (screen_rect.width - label.len() as i32 * self.font.char_width()) / 2;
+            let y = screen_rect.y + screen_rect.height / 2;
+            font::draw_label(renderer, self.font, self.border_renderer, label, x, y);
+        }
+        if config.touch_joypad && self.game_state == GameState::Emulator {
+            self.touch_joypad.draw(renderer, self.font, screen_rect);
+        }
         self.chat_box.draw(renderer);
 
-        if self.game_state == GameState::Menu {
-            self.menu.draw(renderer);
+        match self.game_state {
+            GameState::ChatBox if self.keyboard.visible => self.keyboard.draw(renderer),
+            GameState::Menu => self.menu.draw(renderer),
+            GameState::Controls => self.controls_menu.draw(renderer),
+            GameState::Options => self.options_menu.draw(renderer),
+            GameState::Roms => self.rom_menu.draw(renderer),
+            GameState::Servers => self.server_menu.draw(renderer),
+            GameState::Replays => self.replay_menu.draw(renderer, &self.emulator.mem),
+            GameState::Players => self.player_list.draw(renderer),
+            GameState::Trades => self.trade_board.draw(renderer),
+            GameState::Shop => self.shop.draw(renderer),
+            GameState::Traffic => self.traffic_view.draw(renderer),
+            GameState::Help => self.help_view.draw(renderer),
+            _ => {}
+        }
+    }
+
+    /// Update the joypad from the on-screen buttons, if they are enabled
+    pub fn update_touch_input(&mut self) {
+        let enabled = self.config.borrow().touch_joypad;
+        let changes = if enabled && self.game_state == GameState::Emulator {
+            self.touch_joypad.update(self.screen_rect())
+        }
+        else {
+            self.touch_joypad.release_all()
+        };
+
+        for (button, state) in changes {
+            self.set_button(button, state);
+        }
+    }
+
+    /// Whether the player has not used the game for a while. The window system does not report when
+    /// the window is minimized, so this is used to decide when to show desktop notifications.
+    pub fn is_away(&self) -> bool {
+        self.last_input.elapsed() > AWAY_TIME
+    }
+
+    /// Whether the emulator is paused because the player is away. The game keeps running during a
+    /// relayed battle, since the opponent is waiting for it. Any input resumes it immediately.
+    pub fn is_paused(&self) -> bool {
+        self.config.borrow().pause_when_away
+            && self.is_away()
+            && self.interface.borrow().data.battle_relay.is_none()
+    }
+
+    /// Whether the autopilot is keeping the player idle while they are away. Requests from other
+    /// players are answered with the away message, and the joypad is held released so that a key
+    /// that got stuck down does not walk the player into wild battles or trainers.
+    pub fn on_autopilot(&self) -> bool {
+        self.config.borrow().away_autopilot && self.is_away()
+    }
+
+    /// Whether requests from other players are answered with a message instead of being shown,
+    /// because the player is on autopilot or turns them down on this server
+    pub fn declines_requests(&self) -> bool {
+        self.server_settings.auto_decline || self.on_autopilot()
+    }
+
+    /// The local player's data as other players see it. In privacy mode only the town or route the
+    /// player is in is shared, and not their position in it.
+    pub fn shared_player_data(&self) -> PlayerData {
+        let mut player_data = self.player_data.clone();
+        if self.config.borrow().privacy_mode {
+            player_data.movement_data = MovementData::hidden(extract::region(&self.emulator.mem));
+        }
+        player_data
+    }
+
+    /// The local player's data as other players see it, if it has changed since it was last taken
+    pub fn take_player_update(&mut self) -> Option<PlayerData> {
+        let privacy_mode = self.config.borrow().privacy_mode;
+        let changed = mem::take(&mut self.changes.position) | mem::take(&mut self.changes.name);
+        if !changed && self.shared_privacy == Some(privacy_mode) {
+            return None;
+        }
+        self.shared_privacy = Some(privacy_mode);
+        Some(self.shared_player_data())
+    }
+
+    /// The local player's name as shown in the chat and player list, which is masked in privacy
+    /// mode so that it does not appear on stream or in screenshots
+    pub fn own_name(&self) -> Vec<u8> {
+        if self.config.borrow().privacy_mode {
+            text::Encoder::new(MASKED_NAME).collect()
+        }
+        else {
+            self.player_data.name.clone()
         }
     }
 
     pub fn key_down(&mut self, keycode: KeyCode) {
+        self.last_input = Instant::now();
         match self.game_state {
-            GameState::Emulator => {
-                self.write_to_joypad(keycode, joypad::State::Pressed);
-                if keycode == KeyCode::Space {
-                    self.fast_mode = true;
-                }
-            }
+            GameState::Emulator => match self.action_for(keycode) {
+                Some(Action::Joypad(button)) => self.set_button(button, joypad::State::Pressed),
+                Some(Action::FastForward) => self.fast_mode = true,
+                _ => {}
+            },
 
             GameState::ChatBox => match keycode {
                 // TODO: Possible handle other editing
@@ -169,139 +554,574 @@ impl<'a> Game<'a> {
                 KeyCode::Down => self.menu.move_down(),
                 _ => {}
             },
+
+            GameState::Controls => self.controls_menu.key_down(keycode),
+            GameState::Options => self.options_menu.key_down(keycode),
+            GameState::Roms => self.rom_menu.key_down(keycode),
+            GameState::Servers => self.server_menu.key_down(keycode),
+            GameState::Replays => self.replay_menu.key_down(keycode),
+            GameState::Players => self.player_list.key_down(keycode),
+            GameState::Trades => self.trade_board.key_down(keycode, &self.emulator.mem),
+            GameState::Shop => self.shop.key_down(keycode, &self.emulator.mem),
+            GameState::Traffic => self.traffic_view.key_down(keycode),
+            GameState::Help => self.help_view.key_down(keycode),
         }
     }
 
     pub fn key_up(&mut self, keycode: KeyCode) {
+        self.last_input = Instant::now();
         match self.game_state {
-            GameState::Emulator => {
-                self.write_to_joypad(keycode, joypad::State::Released);
-                if keycode == KeyCode::Space {
-                    self.fast_mode = false;
+            GameState::Emulator => match self.action_for(keycode) {
+                Some(Action::Joypad(button)) => self.set_button(button, joypad::State::Released),
+                Some(Action::FastForward) => self.fast_mode = false,
+                Some(Action::Chat) => self.open_chat(false),
+                Some(Action::Menu) => self.game_state = GameState::Menu,
+                // Sent without leaving the game, so the battle can carry on
+                Some(Action::QuickChat(phrase)) => self.quick_chat = Some(phrase),
+                None if keycode == definitions::RELOAD_KEY => self.reload_definitions(),
+                None if keycode == help::HELP_KEY => self.open_help(),
+                None => {}
+            },
+
+            GameState::ChatBox => match keycode {
+                KeyCode::Enter => self.close_chat(true),
+                KeyCode::Escape => self.close_chat(false),
+                _ => {}
+            },
+
+            GameState::Menu => match keycode {
+                KeyCode::Escape => {
+                    self.game_state = GameState::Emulator;
                 }
-                else if keycode == KeyCode::T {
-                    self.game_state = GameState::ChatBox;
-                    // sdl_keyboard::start_text_input();
+                KeyCode::Enter => match self.menu.selected_item() {
+                    // The other server would not know about a battle started on this one
+                    "CONNECT" if extract::in_battle(&self.emulator.mem) => {
+                        self.chat_box.add_notice("Finish the battle before switching servers");
+                        self.game_state = GameState::Emulator;
+                    }
+                    "CONNECT" => {
+                        self.server_menu.refresh(&self.config.borrow(), &self.server_address);
+                        self.game_state = GameState::Servers;
+                    }
+                    "SHOW PLAYERS" => {
+                        self.usage.record(Feature::PlayerList);
+                        let interface = self.interface.borrow();
+                        let config = self.config.borrow();
+                        let masked_name =
+                            PlayerNote { nickname: MASKED_NAME.to_string(), ..Default::default() };
+                        let local_player = PlayerEntry {
+                            player: &self.player_data,
+                            id: None,
+                            hall_of_fame: extract::hall_of_fame_count(&self.emulator.mem),
+                            paused: false,
+                            latency: self.latency,
+                            note: config.privacy_mode.then_some(&masked_name),
+                        };
+                        let players =
+                            interface.data.players.iter().map(|(&id, player)| PlayerEntry {
+                                player,
+                                id: Some(id),
+                                hall_of_fame: self.hall_of_fame.get(&id).copied().unwrap_or(0),
+                                paused: self.paused_players.contains(&id),
+                                latency: None,
+                                note: config.player_note(&self.server_address, id),
+                            });
+                        self.player_list.refresh(std::iter::once(local_player).chain(players));
+                        self.game_state = GameState::Players;
+                    }
+                    // The statistics are filled in by the client manager while this is open
+                    "NETWORK STATS" => {
+                        self.usage.record(Feature::NetworkStats);
+                        self.game_state = GameState::Traffic;
+                    }
+                    "WATCH BATTLE" => {
+                        self.usage.record(Feature::WatchBattle);
+                        self.spectating.toggle_watching();
+                        if self.spectating.watching.is_some() {
+                            self.chat_box.add_notice("Type /b MESSAGE to chat about the battle");
+                        }
+                        self.game_state = GameState::Emulator;
+                    }
+                    "REPLAYS" => {
+                        self.replay_menu.refresh();
+                        self.game_state = GameState::Replays;
+                    }
+                    "FORFEIT BATTLE" => {
+                        self.forfeit_requested = true;
+                        self.game_state = GameState::Emulator;
+                    }
+                    "SAFARI EVENT" => {
+                        self.usage.record(Feature::SafariEvent);
+                        self.safari.start_requested = true;
+                        self.game_state = GameState::Emulator;
+                    }
+                    "TRADE BOARD" => {
+                        self.usage.record(Feature::TradeBoard);
+                        self.trade_board.open(&self.emulator.mem);
+                        self.game_state = GameState::Trades;
+                    }
+                    "SERVER SHOP" => {
+                        self.usage.record(Feature::ServerShop);
+                        if self.shop.is_open() {
+                            self.shop.open(&self.emulator.mem);
+                            self.game_state = GameState::Shop;
+                        }
+                        else {
+                            self.chat_box.add_notice("This server does not have a shop");
+                            self.game_state = GameState::Emulator;
+                        }
+                    }
+                    "CONTROLS" => self.game_state = GameState::Controls,
+                    "OPTIONS" => self.game_state = GameState::Options,
+                    "SWAP GAME" => {
+                        self.usage.record(Feature::SwapGame);
+                        self.rom_menu.refresh();
+                        self.game_state = GameState::Roms;
+                    }
+                    "UPDATE DEFINITIONS" => {
+                        self.usage.record(Feature::UpdateDefinitions);
+                        self.update_definitions();
+                        self.game_state = GameState::Emulator;
+                    }
+                    _ => {}
+                },
+                _ => {}
+            },
+
+            GameState::Controls => {
+                if self.controls_menu.key_up(keycode, &mut self.config.borrow_mut()) {
+                    self.game_state = GameState::Menu;
                 }
-                else if keycode == KeyCode::Escape {
+            }
+
+            GameState::Options => {
+                if self.options_menu.key_up(keycode, &mut self.config.borrow_mut()) {
                     self.game_state = GameState::Menu;
                 }
             }
 
-            GameState::ChatBox => {
-                match keycode {
-                    KeyCode::Enter => {
-                        self.chat_box.message_ready = true;
-                        self.game_state = GameState::Emulator;
-                        // sdl_keyboard::stop_text_input();
-                    }
+            GameState::Players => {
+                if self.player_list.key_up(keycode) {
+                    self.game_state = GameState::Menu;
+                }
+            }
 
-                    KeyCode::Escape => {
-                        self.game_state = GameState::Emulator;
-                        // sdl_keyboard::stop_text_input();
-                    }
+            GameState::Replays => {
+                if self.replay_menu.key_up(keycode, &self.emulator.mem) {
+                    self.game_state = GameState::Menu;
+                }
+            }
 
-                    _ => {}
+            GameState::Trades => {
+                if self.trade_board.key_up(keycode, &self.emulator.mem) {
+                    self.game_state = GameState::Menu;
                 }
             }
 
-            GameState::Menu => match keycode {
-                KeyCode::Escape => {
+            GameState::Shop => {
+                if self.shop.key_up(keycode, &mut self.emulator.mem) {
+                    self.game_state = GameState::Menu;
+                }
+            }
+
+            GameState::Traffic => {
+                if self.traffic_view.key_up(keycode) {
+                    self.game_state = GameState::Menu;
+                }
+            }
+
+            GameState::Help => {
+                if self.help_view.key_up(keycode) {
                     self.game_state = GameState::Emulator;
                 }
+            }
+
+            GameState::Roms => match self.rom_menu.key_up(keycode) {
+                RomMenuAction::None => {}
+                RomMenuAction::Close => self.game_state = GameState::Menu,
+                RomMenuAction::Load(rom_path) => match self.swap_cart(&rom_path) {
+                    Ok(()) => self.game_state = GameState::Emulator,
+                    Err(e) => println!("{}", e),
+                },
+            },
+
+            GameState::Servers => match self.server_menu.key_up(keycode) {
+                ServerMenuAction::None => {}
+                ServerMenuAction::Close => self.game_state = GameState::Menu,
+                ServerMenuAction::Connect(address) => {
+                    self.chat_box.add_notice(&format!("Connecting to {}", address));
+                    self.switch_server = Some(address);
+                    self.game_state = GameState::Emulator;
+                }
+            },
+        }
+    }
+
+    /// Handle input from a controller
+    pub fn gamepad_input(&mut self, action: Action, state: joypad::State) {
+        self.last_input = Instant::now();
+        let pressed = state == joypad::State::Pressed;
+        match self.game_state {
+            GameState::Emulator => match action {
+                Action::Joypad(button) => self.set_button(button, state),
+                Action::FastForward => self.fast_mode = pressed,
+                Action::Chat if !pressed => self.open_chat(true),
+                Action::Menu if !pressed => self.game_state = GameState::Menu,
                 _ => {}
             },
+
+            GameState::ChatBox => match action {
+                _ if !pressed => {}
+                Action::Joypad(Button::A) => match self.keyboard.selected() {
+                    Key::Char(char_) => self.chat_box.message_buffer.push(char_),
+                    Key::Space => self.chat_box.message_buffer.push(' '),
+                    Key::Delete => {
+                        self.chat_box.message_buffer.pop();
+                    }
+                    Key::Send => self.close_chat(true),
+                },
+                Action::Joypad(Button::B) => {
+                    self.chat_box.message_buffer.pop();
+                }
+                Action::Joypad(Button::Start) => self.close_chat(true),
+                Action::Joypad(direction) => self.keyboard.move_cursor(direction),
+                Action::Chat | Action::Menu => self.close_chat(false),
+                Action::FastForward | Action::QuickChat(_) => {}
+            },
+
+            // Controller buttons are passed on as they are here, so that they are never bound as keys
+            GameState::Controls => {
+                let config = &mut self.config.borrow_mut();
+                if self.controls_menu.pad_input(action, pressed, config) {
+                    self.game_state = GameState::Menu;
+                }
+            }
+
+            // The other menus only use keys to move around, so controller input is translated into
+            // the equivalent keys
+            GameState::Menu
+            | GameState::Options
+            | GameState::Roms
+            | GameState::Servers
+            | GameState::Replays
+            | GameState::Players
+            | GameState::Trades
+            | GameState::Shop
+            | GameState::Traffic
+            | GameState::Help => {
+                let keycode = match action {
+                    Action::Joypad(Button::Up) => KeyCode::Up,
+                    Action::Joypad(Button::Down) => KeyCode::Down,
+                    Action::Joypad(Button::Left) => KeyCode::Left,
+                    Action::Joypad(Button::Right) => KeyCode::Right,
+                    Action::Joypad(Button::A) | Action::Joypad(Button::Start) => KeyCode::Enter,
+                    Action::Joypad(Button::B) | Action::Menu => KeyCode::Escape,
+                    _ => return,
+                };
+
+                if pressed {
+                    self.key_down(keycode);
+                }
+                else {
+                    self.key_up(keycode);
+                }
+            }
+        }
+    }
+
+    /// Open the chat box, showing the on-screen keyboard if the player is using a controller
+    fn open_chat(&mut self, show_keyboard: bool) {
+        self.game_state = GameState::ChatBox;
+        self.keyboard.visible = show_keyboard;
+        self.chat_box.mark_read();
+    }
+
+    fn close_chat(&mut self, send: bool) {
+        self.chat_box.message_ready = send;
+        self.chat_box.mark_read();
+        self.keyboard.visible = false;
+        self.game_state = GameState::Emulator;
+    }
+
+    /// Replace the running game with a different ROM and its save file, staying connected to the
+    /// same server
+    pub fn swap_cart(&mut self, rom_path: &Path) -> Result<(), String> {
+        let rom = fs::read(rom_path)
+            .map_err(|e| format!("Error opening '{}': {}", rom_path.display(), e))?;
+        let generation = match interface::rom::generation(&rom) {
+            Some(generation) => generation,
+            None => return Err(format!("'{}' is not a supported game", rom_path.display())),
+        };
+        // The server checked the game when this player joined, and only starts battles between
+        // games of the same generation
+        let current = interface::rom::generation(&self.emulator.mem.cart.rom[0]);
+        if current.is_some_and(|current| current != generation) {
+            return Err(format!("'{}' can not be played on this server", rom_path.display()));
+        }
+        // This replaces the definitions for the previous game
+        interface::offsets::set_generation(generation);
+        definitions::load_saved(&rom);
+
+        let mut emulator = Box::new(Emulator::new());
+        let save_dir = self.config.borrow().save_dir.clone();
+        let save_path = save::save_path(rom_path, save_dir.as_deref(), self.local_player);
+        let save_format = interface::rom::save_format(&rom);
+        let save_file = Box::new(LocalSaveWrapper::new(save_path, save_format));
+        // Choosing what to do with a save that does not fit is only offered at startup
+        if let Some(SaveProblem::WrongSize(_)) = save_file.check() {
+            let problem = "has a save file that does not fit it, restart to choose what to do";
+            return Err(format!("'{}' {}", rom_path.display(), problem));
+        }
+        emulator.load_cart(&rom, Some(save_file));
+        emulator.start();
+        self.emulator = emulator;
+        crash::set_rom(&rom);
+
+        // The interface state is tied to the previous emulator, but the other players are not
+        let mut interface = Facade::new();
+        interface.data.players = mem::take(&mut self.interface.borrow_mut().data.players);
+        self.interface = RefCell::new(interface);
+
+        self.player_data = PlayerData::new(&self.emulator.mem);
+        self.apply_server_settings(self.server_settings.clone());
+        self.frame_filter = FrameFilter::new();
+        client::reload_textures(
+            &[self.font, self.chat_font],
+            self.border_renderer,
+            &self.emulator.mem,
+        );
+        self.chat_box.invalidate();
+
+        println!("Loaded {}", rom_path.display());
+        Ok(())
+    }
+
+    /// Start downloading updated game definitions from the configured URL
+    fn update_definitions(&mut self) {
+        if self.definitions_download.is_some() {
+            return;
+        }
+
+        match self.config.borrow().definitions_url.clone() {
+            Some(url) => {
+                self.chat_box.add_notice("Updating definitions...");
+                self.definitions_download = Some(definitions::download(url));
+            }
+            None => self.chat_box.add_notice("No definitions URL set"),
+        }
+    }
+
+    /// Apply downloaded game definitions once they are ready
+    fn check_definitions_download(&mut self) {
+        let result = match self.definitions_download.as_ref().map(|download| download.try_recv()) {
+            Some(Ok(result)) => result,
+            Some(Err(TryRecvError::Empty)) | None => return,
+            Some(Err(TryRecvError::Disconnected)) => Err("download failed".to_string()),
+        };
+        self.definitions_download = None;
+
+        let rom = self.emulator.mem.cart.rom.concat();
+        let result = result.and_then(|data| {
+            let new_definitions = definitions::parse(&data)?;
+            definitions::apply(&new_definitions, &rom)?;
+            fs::write(definitions::saved_path(), &data).map_err(|e| e.to_string())
+        });
+
+        match result {
+            Ok(()) => {
+                self.definitions_changed();
+                self.chat_box.add_notice("Definitions updated");
+            }
+            Err(e) => {
+                println!("Failed to update definitions: {}", e);
+                self.chat_box.add_notice("Definitions update failed");
+            }
         }
     }
 
+    /// Apply the definitions file given for development again, after it has been edited
+    fn reload_definitions(&mut self) {
+        let path = match &self.dev_definitions {
+            Some(path) => path,
+            None => return,
+        };
+        let rom = self.emulator.mem.cart.rom.concat();
+        match definitions::load_file(path, &rom) {
+            Ok(()) => {
+                println!("Reloaded definitions from {}", path.display());
+                self.definitions_changed();
+                self.chat_box.add_notice("Definitions reloaded");
+            }
+            Err(e) => {
+                println!("Failed to reload definitions: {}", e);
+                self.chat_box.add_notice("Definitions reload failed");
+            }
+        }
+    }
+
+    /// Extract the data that is read from the ROM again, since it may have moved
+    fn definitions_changed(&mut self) {
+        self.apply_server_settings(self.server_settings.clone());
+        client::reload_textures(
+            &[self.font, self.chat_font],
+            self.border_renderer,
+            &self.emulator.mem,
+        );
+        self.chat_box.invalidate();
+    }
+
+    /// Forget everything learned from the previous server once another has been joined. The
+    /// emulator and the local player's save are kept as they are.
+    pub fn server_changed(&mut self, server_address: &str) {
+        {
+            let mut interface = self.interface.borrow_mut();
+            interface.data.players.clear();
+            interface.data.visibility.server = Visibility::Everyone;
+        }
+        self.hall_of_fame.clear();
+        self.paused_players.clear();
+        self.latency = None;
+        self.spectating = Spectating::new();
+        self.safari = SafariEvent::new();
+        self.world = WorldEffects::new();
+        self.interpolation = Interpolation::new();
+        self.trails.clear();
+        self.shop.set_items(vec![]);
+        // The new connection has not seen any of the game yet
+        self.changes = Changes::ALL;
+
+        self.server_address = server_address.to_string();
+        let settings = self.config.borrow().settings_for(server_address);
+        self.apply_server_settings(settings);
+        self.chat_box.add_notice(&format!("Connected to {}", server_address));
+    }
+
+    fn open_help(&mut self) {
+        let connection = ConnectionInfo {
+            server_address: &self.server_address,
+            num_players: self.interface.borrow().data.players.len() + 1,
+            latency: self.latency,
+        };
+        self.help_view.refresh(&self.config.borrow(), self.local_player, connection);
+        self.game_state = GameState::Help;
+    }
+
+    /// Apply the settings for the server that this game is connected to
+    pub fn apply_server_settings(&mut self, settings: ServerSettings) {
+        self.player_data.sprite =
+            self.interface.borrow().player_sprite(&self.emulator.mem, settings.sprite);
+        self.server_settings = settings;
+        self.shared_privacy = None;
+    }
+
+    /// Update the status line shown above the chat with the current server, number of players and
+    /// the number of unread messages, or whether the connection to the server was lost.
+    pub fn update_status(&mut self, server_name: &str, connected: bool) -> String {
+        let num_players = self.interface.borrow().data.players.len() + 1;
+        let status = match (connected, self.chat_box.unread()) {
+            (false, _) => format!("{} OFFLINE", server_name),
+            (true, 0) => format!("{} {}P", server_name, num_players),
+            (true, unread) => format!("{} {}P {} NEW", server_name, num_players, unread),
+        };
+        self.chat_box.set_status(&status);
+        status
+    }
+
     pub fn text_input(&mut self, text: String) {
         if self.game_state == GameState::ChatBox {
             self.chat_box.message_buffer.push_str(&text);
         }
     }
 
-    fn write_to_joypad(&mut self, keycode: KeyCode, state: joypad::State) {
-        let joypad = &mut self.emulator.mem.joypad;
-        // TODO: Add custom key bindings
-        match keycode {
-            KeyCode::Up => joypad.up = state,
-            KeyCode::Down => joypad.down = state,
-            KeyCode::Left => joypad.left = state,
-            KeyCode::Right => joypad.right = state,
-
-            KeyCode::Z => joypad.a = state,
-            KeyCode::X => joypad.b = state,
-            KeyCode::Enter => joypad.start = state,
-            KeyCode::RightShift => joypad.select = state,
-
-            _ => {}
+    /// Forward a key to the joypad without handling any of the UI keys. Used for local players that
+    /// do not currently have focus.
+    pub fn joypad_input(&mut self, keycode: KeyCode, state: joypad::State) {
+        self.last_input = Instant::now();
+        if self.game_state != GameState::Emulator {
+            return;
+        }
+        if let Some(Action::Joypad(button)) = self.action_for(keycode) {
+            self.set_button(button, state);
         }
     }
-}
 
-fn draw_other_players(interface_data: &InterfaceData, self_data: &PlayerData, mem: &mut Memory) {
-    for player in interface_data.players.values() {
-        if player.is_visible_to(self_data) {
-            let (x, y) = get_player_draw_position(self_data, player);
-            let (index, flags) = get_sprite_index_and_flags(player);
-            let sprite_data =
-                SpriteData { x: x as isize, y: y as isize, index: index as usize, flags };
-            interface::render_sprite(mem, &player.sprite, &sprite_data);
-        }
+    /// The action that a key is bound to for this player
+    fn action_for(&self, keycode: KeyCode) -> Option<Action> {
+        let config = self.config.borrow();
+        let physical_key = config.keyboard_layout.to_physical(keycode);
+        config.bindings(self.local_player).action_for(physical_key)
     }
-}
 
-/// Get the screen coordinates of where to draw a target player adjusted relative to the local
-/// player's screen
-fn get_player_draw_position(self_player: &PlayerData, other_player: &PlayerData) -> (i32, i32) {
-    let base_x = (graphics::WIDTH as i32) / 2 - 16;
-    let base_y = (graphics::HEIGHT as i32) / 2 - 12;
+    fn set_button(&mut self, button: Button, state: joypad::State) {
+        let joypad = &mut self.emulator.mem.joypad;
+        match button {
+            Button::Up => joypad.up = state,
+            Button::Down => joypad.down = state,
+            Button::Left => joypad.left = state,
+            Button::Right => joypad.right = state,
 
-    let (self_x, self_y) = get_player_position(self_player);
-    let (other_x, other_y) = get_player_position(other_player);
+            Button::A => joypad.a = state,
+            Button::B => joypad.b = state,
+            Button::Start => joypad.start = state,
+            Button::Select => joypad.select = state,
+        }
+    }
+}
 
-    (other_x - self_x + base_x, other_y - self_y + base_y)
+/// How the other players are drawn
+struct DrawnPlayers<'a> {
+    trails: &'a Trails,
+    /// Smooths out the movement of other players, if it is enabled
+    interpolation: Option<&'a Interpolation>,
+    /// The most players to draw, nearest first
+    max: Option<usize>,
 }
 
-fn get_player_position(player: &PlayerData) -> (i32, i32) {
-    let x = player.movement_data.map_x as i32 * 16;
-    let y = player.movement_data.map_y as i32 * 16;
+/// Draw the other players that are on screen. If a maximum is set, only that many of the players
+/// nearest to the local player are drawn.
+fn draw_other_players(
+    interface_data: &InterfaceData,
+    drawn: &DrawnPlayers,
+    self_data: &PlayerData,
+    mem: &mut Memory,
+) {
+    let self_position = facade::player_position(&self_data.movement_data);
+    let map_id = self_data.movement_data.map_id;
+    let mut nearby: Vec<_> = interface_data
+        .players
+        .iter()
+        .filter(|&(&id, player)| interface_data.visibility.is_visible(id, player, map_id))
+        .filter_map(|(&id, player)| {
+            let (x, y) = match drawn.interpolation {
+                Some(interpolation) => interpolation.position(id, &player.movement_data),
+                None => facade::player_position(&player.movement_data),
+            };
+            let (dx, dy) = (x - self_position.0, y - self_position.1);
+            is_near_screen(dx, dy).then(|| (id, player, (x, y), dx * dx + dy * dy))
+        })
+        .collect();
+
+    if let Some(max_players) = drawn.max {
+        nearby.sort_unstable_by_key(|&(id, _, _, distance)| (distance, id));
+        nearby.truncate(max_players);
+    }
 
-    // Determine the offset of the player between tiles:
-    // When a player begins walking, the walk counter is set to 8. For each step the walk counter
-    // decreases by one, and the player is moved by two pixels, until the walk counter is 0. When
-    // we reach this point, the players map coordinate updated.
-    let ticks = player.movement_data.walk_counter;
-    let offset = if ticks == 0 { 0 } else { (8 - ticks) * 2 } as i32;
+    for (id, player, position, _) in nearby {
+        for trail in drawn.trails.get(id) {
+            let sprite_data = facade::player_sprite_data(&self_data.movement_data, trail);
+            interface::render_afterimage(mem, &player.sprite, &sprite_data);
+        }
 
-    match player.movement_data.direction {
-        Direction::Down => (x, y + offset),
-        Direction::Up => (x, y - offset),
-        Direction::Left => (x - offset, y),
-        Direction::Right => (x + offset, y),
+        let sprite_data =
+            facade::sprite_data_at(&self_data.movement_data, &player.movement_data, position);
+        interface::render_sprite(mem, &player.sprite, &sprite_data);
     }
 }
 
-fn get_sprite_index_and_flags(player: &PlayerData) -> (isize, u8) {
-    // Determine the base sprite index and flags that need to be set based on the direction the
-    // player is currently facing.
-    let (mut index, mut flags) = match player.movement_data.direction {
-        Direction::Down => (0, 0x00),
-        Direction::Up => (1, 0x00),
-        Direction::Left => (2, 0x00),
-        Direction::Right => (2, 0x20),
-    };
-
-    // Set the flag that indicates background data may be drawn on top. I'm not sure if this is
-    // strictly necessary, however it seems to be set by most sprites.
-    flags |= 0x80;
-
-    // Change the frame which is displayed based on
-    index += match (player.movement_data.walk_counter / 4) & 1 {
-        0 => 0,
-        1 => 3,
-        _ => unreachable!(),
-    };
-
-    (index, flags)
+/// Check whether a player at an offset (in pixels) from the local player could appear on the
+/// screen. This allows a few tiles of leeway for the player's trail.
+fn is_near_screen(dx: i32, dy: i32) -> bool {
+    const MARGIN: i32 = 4 * 16;
+    dx.abs() <= graphics::WIDTH as i32 / 2 + MARGIN
+        && dy.abs() <= graphics::HEIGHT as i32 / 2 + MARGIN
 }
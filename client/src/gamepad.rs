@@ -0,0 +1,130 @@
+//! Controller support
+//!
+//! Controllers can be used both to play and to navigate every menu, with chat messages typed using
+//! the on-screen keyboard. Each controller is assigned to a local player based on the order that
+//! the controllers were connected in.
+use std::collections::HashMap;
+
+use gb_emu::joypad;
+use gilrs::{Axis, EventType, Gilrs};
+
+use crate::input::{Action, Button};
+
+/// How far a stick needs to be pushed before it is treated as a D-pad press
+const STICK_THRESHOLD: f32 = 0.5;
+
+pub struct Gamepads {
+    gilrs: Option<Gilrs>,
+    /// The (horizontal, vertical) directions currently held with the left stick of each controller
+    stick_directions: HashMap<usize, (Option<Button>, Option<Button>)>,
+}
+
+impl Gamepads {
+    pub fn new() -> Gamepads {
+        let gilrs = match Gilrs::new() {
+            Ok(gilrs) => Some(gilrs),
+            Err(e) => {
+                println!("Controller support is unavailable: {}", e);
+                None
+            }
+        };
+        Gamepads { gilrs, stick_directions: HashMap::new() }
+    }
+
+    /// Gets all controller input since the last poll, as (controller, action, state) tuples
+    pub fn poll(&mut self) -> Vec<(usize, Action, joypad::State)> {
+        let gilrs = match &mut self.gilrs {
+            Some(gilrs) => gilrs,
+            None => return vec![],
+        };
+
+        let mut actions = vec![];
+        while let Some(event) = gilrs.next_event() {
+            let pad = usize::from(event.id);
+            match event.event {
+                EventType::ButtonPressed(button, _) => {
+                    if let Some(action) = map_button(button) {
+                        actions.push((pad, action, joypad::State::Pressed));
+                    }
+                }
+
+                EventType::ButtonReleased(button, _) => {
+                    if let Some(action) = map_button(button) {
+                        actions.push((pad, action, joypad::State::Released));
+                    }
+                }
+
+                EventType::AxisChanged(axis, value, _) => {
+                    let directions = self.stick_directions.entry(pad).or_insert((None, None));
+                    let (held, direction) = match axis {
+                        Axis::LeftStickX => {
+                            (&mut directions.0, axis_direction(value, Button::Left, Button::Right))
+                        }
+                        // The Y axis points upwards
+                        Axis::LeftStickY => {
+                            (&mut directions.1, axis_direction(value, Button::Down, Button::Up))
+                        }
+                        _ => continue,
+                    };
+
+                    if *held != direction {
+                        if let Some(button) = held.take() {
+                            actions.push((pad, Action::Joypad(button), joypad::State::Released));
+                        }
+                        if let Some(button) = direction {
+                            actions.push((pad, Action::Joypad(button), joypad::State::Pressed));
+                        }
+                        *held = direction;
+                    }
+                }
+
+                EventType::Disconnected => {
+                    if let Some((x, y)) = self.stick_directions.remove(&pad) {
+                        for button in x.into_iter().chain(y) {
+                            actions.push((pad, Action::Joypad(button), joypad::State::Released));
+                        }
+                    }
+                }
+
+                _ => {}
+            }
+        }
+
+        actions
+    }
+}
+
+fn map_button(button: gilrs::Button) -> Option<Action> {
+    use gilrs::Button as Pad;
+
+    let action = match button {
+        Pad::DPadUp => Action::Joypad(Button::Up),
+        Pad::DPadDown => Action::Joypad(Button::Down),
+        Pad::DPadLeft => Action::Joypad(Button::Left),
+        Pad::DPadRight => Action::Joypad(Button::Right),
+
+        Pad::South => Action::Joypad(Button::A),
+        Pad::East => Action::Joypad(Button::B),
+        Pad::Start => Action::Joypad(Button::Start),
+        Pad::Select => Action::Joypad(Button::Select),
+
+        Pad::North => Action::Chat,
+        Pad::West => Action::Menu,
+        Pad::RightTrigger | Pad::RightTrigger2 => Action::FastForward,
+
+        _ => return None,
+    };
+    Some(action)
+}
+
+fn axis_direction(value: f32, negative: Button, positive: Button) -> Option<Button> {
+    if value <= -STICK_THRESHOLD {
+        Some(negative)
+    }
+    else if value >= STICK_THRESHOLD {
+        Some(positive)
+    }
+    else {
+        None
+    }
+}
@@ -0,0 +1,226 @@
+//! The states of a battle request between two players
+//!
+//! The player requesting a battle commits to a secret seed, asks for the opponent's party, and
+//! reveals the seed once the party has arrived. Each side keeps track of where it is in this
+//! exchange, so that messages that arrive twice, late, or after the other side has given up are
+//! recognised instead of leaving the game waiting on a battle that will never start.
+use std::{
+    collections::HashMap,
+    mem,
+    time::{Duration, Instant},
+};
+
+use interface::{relay::ItemRule, PlayerId};
+use network_common::seed::{self, Seed, SeedCommitment};
+
+/// How long to wait for an opponent to respond to a battle request before giving up, unless the
+/// config sets another time
+pub const DEFAULT_RESPONSE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long to wait for a response before sending a battle request again
+const RETRANSMIT_INTERVAL: Duration = Duration::from_secs(3);
+
+/// How long to remember a battle request that has been answered, so that the same answer can be
+/// sent if the request is repeated
+const ANSWER_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A battle that the local player has requested
+#[derive(Copy, Clone)]
+pub struct Request {
+    pub opponent: PlayerId,
+    /// The local player's secret seed, revealed once the opponent's party has arrived
+    pub secret: Seed,
+    /// The opponent's contribution to the battle's seed, once it has arrived
+    pub opponent_seed: Option<Seed>,
+    /// The rules of the battle, if it is a relayed battle
+    pub relay: Option<ItemRule>,
+    started_at: Instant,
+    sent_at: Instant,
+}
+
+/// The local player's side of a battle request
+pub enum Outgoing {
+    Idle,
+    /// Waiting for the opponent's party
+    Requested(Request),
+}
+
+impl Outgoing {
+    /// The player that a battle has been requested with, if any
+    pub fn opponent(&self) -> Option<PlayerId> {
+        match self {
+            Outgoing::Idle => None,
+            Outgoing::Requested(request) => Some(request.opponent),
+        }
+    }
+
+    pub fn is_idle(&self) -> bool {
+        matches!(self, Outgoing::Idle)
+    }
+
+    /// Start requesting a battle, returning the previous request if it had not finished
+    pub fn start(&mut self, opponent: PlayerId, relay: Option<ItemRule>) -> Option<Request> {
+        let now = Instant::now();
+        let request = Request {
+            opponent,
+            secret: seed::generate(),
+            opponent_seed: None,
+            relay,
+            started_at: now,
+            sent_at: now,
+        };
+        self.replace(Outgoing::Requested(request))
+    }
+
+    /// The request that is waiting for a response
+    pub fn request(&self) -> Option<&Request> {
+        match self {
+            Outgoing::Idle => None,
+            Outgoing::Requested(request) => Some(request),
+        }
+    }
+
+    /// Store the opponent's seed. Only the first seed is kept, so a repeated message can not
+    /// change the battle's seed.
+    pub fn receive_seed(&mut self, from: PlayerId, opponent_seed: Seed) {
+        if let Outgoing::Requested(request) = self {
+            if request.opponent == from && request.opponent_seed.is_none() {
+                request.opponent_seed = Some(opponent_seed);
+            }
+        }
+    }
+
+    /// Finish the request once a party arrives, if it came from the player that was asked
+    pub fn finish(&mut self, from: PlayerId) -> Option<Request> {
+        match self.opponent() {
+            Some(opponent) if opponent == from => self.cancel(),
+            _ => None,
+        }
+    }
+
+    /// Give up on the request, returning it if there was one
+    pub fn cancel(&mut self) -> Option<Request> {
+        self.replace(Outgoing::Idle)
+    }
+
+    /// Get the request if the opponent has not responded to it for a while, so that it can be sent
+    /// again
+    pub fn retransmit(&mut self) -> Option<Request> {
+        match self {
+            Outgoing::Requested(request) if request.sent_at.elapsed() >= RETRANSMIT_INTERVAL => {
+                request.sent_at = Instant::now();
+                Some(*request)
+            }
+            _ => None,
+        }
+    }
+
+    /// Whether the opponent has not responded within `timeout` of the request starting
+    pub fn timed_out(&self, timeout: Duration) -> bool {
+        match self {
+            Outgoing::Idle => false,
+            Outgoing::Requested(request) => request.started_at.elapsed() > timeout,
+        }
+    }
+
+    fn replace(&mut self, state: Outgoing) -> Option<Request> {
+        match mem::replace(self, state) {
+            Outgoing::Idle => None,
+            Outgoing::Requested(request) => Some(request),
+        }
+    }
+}
+
+/// A battle request made by another player
+enum Incoming {
+    /// The player has committed to a seed, and their request should follow
+    Committed { commitment: SeedCommitment, at: Instant },
+    /// The local player has sent its party, along with its own seed if the player committed to one
+    Answered { commitment: Option<SeedCommitment>, seed: Option<Seed>, at: Instant },
+}
+
+/// How to respond to a battle request
+pub struct Answer {
+    /// The local player's contribution to the battle's seed
+    pub seed: Option<Seed>,
+    /// Whether the request has already been answered, and this is a repeat of the same request
+    pub repeated: bool,
+}
+
+/// The result of checking a seed revealed by a player that requested a battle
+pub enum Reveal {
+    /// The seed does not match the player's commitment, or they never committed to one
+    Invalid,
+    /// The seed is valid, and this is the local player's seed that it is combined with
+    Valid(Option<Seed>),
+}
+
+/// The local player's side of battle requests made by other players
+#[derive(Default)]
+pub struct IncomingRequests {
+    requests: HashMap<PlayerId, Incoming>,
+}
+
+impl IncomingRequests {
+    pub fn new() -> IncomingRequests {
+        IncomingRequests::default()
+    }
+
+    /// Store a player's seed commitment. A commitment to the same seed as a request that has
+    /// already been answered is part of a repeated request, so the answer is kept.
+    pub fn commit(&mut self, from: PlayerId, commitment: SeedCommitment) {
+        let repeated = matches!(
+            self.requests.get(&from),
+            Some(Incoming::Answered { commitment: Some(answered), .. }) if *answered == commitment
+        );
+        if !repeated {
+            self.requests.insert(from, Incoming::Committed { commitment, at: Instant::now() });
+        }
+    }
+
+    /// Answer a player's battle request, with the same seed as before if it has been answered
+    pub fn answer(&mut self, from: PlayerId) -> Answer {
+        let commitment = match self.requests.get_mut(&from) {
+            Some(Incoming::Answered { seed, at, .. }) => {
+                *at = Instant::now();
+                return Answer { seed: *seed, repeated: true };
+            }
+            Some(Incoming::Committed { commitment, .. }) => Some(*commitment),
+            None => None,
+        };
+
+        // Only contribute to the battle's seed if the player committed to one
+        let seed = commitment.map(|_| seed::generate());
+        self.requests.insert(from, Incoming::Answered { commitment, seed, at: Instant::now() });
+        Answer { seed, repeated: false }
+    }
+
+    /// Check the secret seed revealed by a player, which finishes their request
+    pub fn reveal(&mut self, from: PlayerId, secret: &Seed) -> Reveal {
+        match self.requests.remove(&from) {
+            Some(Incoming::Answered { commitment: Some(commitment), seed, .. })
+                if seed::verify(&commitment, secret) =>
+            {
+                Reveal::Valid(seed)
+            }
+            Some(Incoming::Committed { commitment, .. }) if seed::verify(&commitment, secret) => {
+                Reveal::Valid(None)
+            }
+            _ => Reveal::Invalid,
+        }
+    }
+
+    /// Forget a player's request, after they have given up on it or left
+    pub fn remove(&mut self, from: PlayerId) {
+        self.requests.remove(&from);
+    }
+
+    /// Forget requests that were never finished
+    pub fn prune(&mut self) {
+        self.requests.retain(|_, request| match request {
+            Incoming::Committed { at, .. } | Incoming::Answered { at, .. } => {
+                at.elapsed() < ANSWER_TIMEOUT
+            }
+        });
+    }
+}
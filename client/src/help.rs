@@ -0,0 +1,80 @@
+//! Help screen, opened with `HELP_KEY`, listing the current key bindings, the chat commands and the
+//! server the game is connected to
+//!
+//! The bindings are read from the same table that handles key presses, so the screen always shows
+//! the keys that are actually bound.
+use std::time::Duration;
+
+use macroquad::prelude::KeyCode;
+
+use crate::{
+    border::BorderRenderer,
+    common::{Rect, Renderer},
+    config::Config,
+    font::Font,
+    input::{self, Action},
+    menu::ItemBox,
+    net, notes, spectate,
+};
+
+/// Opens and closes the help screen. It is not one of the bindable keys, so it can not be taken by
+/// another action.
+pub const HELP_KEY: KeyCode = KeyCode::F1;
+
+/// Where the game is connected, as shown at the bottom of the help screen
+pub struct ConnectionInfo<'a> {
+    pub server_address: &'a str,
+    pub num_players: usize,
+    pub latency: Option<Duration>,
+}
+
+pub struct HelpView<'a> {
+    item_box: ItemBox<'a>,
+}
+
+impl<'a> HelpView<'a> {
+    pub fn new(font: &'a Font, border: &'a BorderRenderer, rect: Rect) -> HelpView<'a> {
+        HelpView { item_box: ItemBox::new(vec!["BACK".to_string()], font, border, rect) }
+    }
+
+    /// Rebuild the lines from the current bindings and connection
+    pub fn refresh(&mut self, config: &Config, local_player: usize, connection: ConnectionInfo) {
+        let layout = config.keyboard_layout;
+        let bindings = config.bindings(local_player);
+        let mut items: Vec<String> = Action::all()
+            .map(|action| format!("{:<7}{}", action.name(), bindings.label(action, layout)))
+            .collect();
+        items.push(format!("{:<7}{}", "HELP", input::key_label(HELP_KEY)));
+
+        items.push(format!("{} TEXT", spectate::BATTLE_CHAT_COMMAND));
+        items.push(net::FORFEIT_COMMAND.to_string());
+        items.extend(notes::COMMANDS.iter().map(|usage| usage.to_string()));
+
+        items.push(connection.server_address.to_string());
+        items.push(format!("{} PLAYERS", connection.num_players));
+        match connection.latency {
+            Some(latency) => items.push(format!("PING {}MS", latency.as_millis())),
+            None => items.push("PING ?".to_string()),
+        }
+
+        items.push("BACK".to_string());
+        self.item_box.set_items(items.into_iter().map(|item| item.to_uppercase()).collect());
+    }
+
+    pub fn draw(&self, renderer: &mut Renderer) {
+        self.item_box.draw(renderer);
+    }
+
+    pub fn key_down(&mut self, keycode: KeyCode) {
+        match keycode {
+            KeyCode::Up => self.item_box.move_up(),
+            KeyCode::Down => self.item_box.move_down(),
+            _ => {}
+        }
+    }
+
+    /// Handles a key release, returning true if the screen should be closed
+    pub fn key_up(&mut self, keycode: KeyCode) -> bool {
+        matches!(keycode, KeyCode::Escape | KeyCode::Enter | HELP_KEY)
+    }
+}
@@ -0,0 +1,388 @@
+//! Keyboard handling and key bindings
+//!
+//! Joypad buttons and the client's own hotkeys (fast forward, chat, menu and quick chat) are bound
+//! in the same table, so that a key can only ever do one thing.
+//!
+//! Bindings are stored by the *physical* position of a key, using the name that key has on a US
+//! QWERTY keyboard. The windowing backend only reports keys after they have been translated by the
+//! active keyboard layout, so every key event is first mapped back to its physical position using
+//! the configured `KeyboardLayout`. This keeps the default bindings (Z/X for A/B) under the same
+//! fingers regardless of whether the player uses QWERTY, AZERTY or Dvorak.
+use macroquad::prelude::KeyCode;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Button {
+    Up,
+    Down,
+    Left,
+    Right,
+    A,
+    B,
+    Start,
+    Select,
+}
+
+impl Button {
+    pub const ALL: [Button; 8] = [
+        Button::Up,
+        Button::Down,
+        Button::Left,
+        Button::Right,
+        Button::A,
+        Button::B,
+        Button::Start,
+        Button::Select,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Button::Up => "UP",
+            Button::Down => "DOWN",
+            Button::Left => "LEFT",
+            Button::Right => "RIGHT",
+            Button::A => "A",
+            Button::B => "B",
+            Button::Start => "START",
+            Button::Select => "SELECT",
+        }
+    }
+}
+
+/// Anything that can be bound to a key or triggered from a controller
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Action {
+    Joypad(Button),
+    /// Opens the chat, or closes it without sending the message
+    Chat,
+    /// Opens the menu, or closes the current menu
+    Menu,
+    FastForward,
+    /// Sends one of the `QUICK_CHAT_PHRASES` to the opponent of a relayed battle
+    QuickChat(u8),
+}
+
+impl Action {
+    /// The actions that are handled by the client instead of the game
+    pub const HOTKEYS: [Action; 7] = [
+        Action::FastForward,
+        Action::Chat,
+        Action::Menu,
+        Action::QuickChat(0),
+        Action::QuickChat(1),
+        Action::QuickChat(2),
+        Action::QuickChat(3),
+    ];
+
+    pub fn all() -> impl Iterator<Item = Action> {
+        Button::ALL.iter().map(|&button| Action::Joypad(button)).chain(Action::HOTKEYS)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Action::Joypad(button) => button.name(),
+            Action::Chat => "CHAT",
+            Action::Menu => "MENU",
+            Action::FastForward => "TURBO",
+            Action::QuickChat(0) => "QUICK 1",
+            Action::QuickChat(1) => "QUICK 2",
+            Action::QuickChat(2) => "QUICK 3",
+            Action::QuickChat(_) => "QUICK 4",
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum KeyboardLayout {
+    #[default]
+    Qwerty,
+    Azerty,
+    Dvorak,
+}
+
+/// (physical key, key reported by the layout) pairs for the keys that differ from QWERTY
+const AZERTY_KEYS: &[(KeyCode, KeyCode)] = &[
+    (KeyCode::Q, KeyCode::A),
+    (KeyCode::W, KeyCode::Z),
+    (KeyCode::A, KeyCode::Q),
+    (KeyCode::Z, KeyCode::W),
+    (KeyCode::Semicolon, KeyCode::M),
+    (KeyCode::M, KeyCode::Comma),
+    (KeyCode::Comma, KeyCode::Semicolon),
+];
+
+const DVORAK_KEYS: &[(KeyCode, KeyCode)] = &[
+    (KeyCode::Q, KeyCode::Apostrophe),
+    (KeyCode::W, KeyCode::Comma),
+    (KeyCode::E, KeyCode::Period),
+    (KeyCode::R, KeyCode::P),
+    (KeyCode::T, KeyCode::Y),
+    (KeyCode::Y, KeyCode::F),
+    (KeyCode::U, KeyCode::G),
+    (KeyCode::I, KeyCode::C),
+    (KeyCode::O, KeyCode::R),
+    (KeyCode::P, KeyCode::L),
+    (KeyCode::LeftBracket, KeyCode::Slash),
+    (KeyCode::RightBracket, KeyCode::Equal),
+    (KeyCode::S, KeyCode::O),
+    (KeyCode::D, KeyCode::E),
+    (KeyCode::F, KeyCode::U),
+    (KeyCode::G, KeyCode::I),
+    (KeyCode::H, KeyCode::D),
+    (KeyCode::J, KeyCode::H),
+    (KeyCode::K, KeyCode::T),
+    (KeyCode::L, KeyCode::N),
+    (KeyCode::Semicolon, KeyCode::S),
+    (KeyCode::Apostrophe, KeyCode::Minus),
+    (KeyCode::Z, KeyCode::Semicolon),
+    (KeyCode::X, KeyCode::Q),
+    (KeyCode::C, KeyCode::J),
+    (KeyCode::V, KeyCode::K),
+    (KeyCode::B, KeyCode::X),
+    (KeyCode::N, KeyCode::B),
+    (KeyCode::Comma, KeyCode::W),
+    (KeyCode::Period, KeyCode::V),
+    (KeyCode::Slash, KeyCode::Z),
+    (KeyCode::Minus, KeyCode::LeftBracket),
+    (KeyCode::Equal, KeyCode::RightBracket),
+];
+
+impl KeyboardLayout {
+    pub const ALL: [KeyboardLayout; 3] =
+        [KeyboardLayout::Qwerty, KeyboardLayout::Azerty, KeyboardLayout::Dvorak];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            KeyboardLayout::Qwerty => "QWERTY",
+            KeyboardLayout::Azerty => "AZERTY",
+            KeyboardLayout::Dvorak => "DVORAK",
+        }
+    }
+
+    /// Returns the next layout, used for cycling through layouts in the controls menu
+    pub fn next(self) -> KeyboardLayout {
+        let index = KeyboardLayout::ALL.iter().position(|&l| l == self).unwrap_or(0);
+        KeyboardLayout::ALL[(index + 1) % KeyboardLayout::ALL.len()]
+    }
+
+    fn remapped_keys(self) -> &'static [(KeyCode, KeyCode)] {
+        match self {
+            KeyboardLayout::Qwerty => &[],
+            KeyboardLayout::Azerty => AZERTY_KEYS,
+            KeyboardLayout::Dvorak => DVORAK_KEYS,
+        }
+    }
+
+    /// Converts a key reported by the windowing backend into its physical position
+    pub fn to_physical(self, keycode: KeyCode) -> KeyCode {
+        self.remapped_keys().iter().find(|&&(_, key)| key == keycode).map_or(keycode, |&(p, _)| p)
+    }
+
+    /// Converts a physical key position into the key that this layout has printed on it
+    pub fn key_at(self, physical: KeyCode) -> KeyCode {
+        self.remapped_keys().iter().find(|&&(p, _)| p == physical).map_or(physical, |&(_, k)| k)
+    }
+}
+
+/// Key bindings for the joypad and hotkeys, stored as physical key positions
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct KeyBindings {
+    #[serde(with = "key_name")]
+    pub up: KeyCode,
+    #[serde(with = "key_name")]
+    pub down: KeyCode,
+    #[serde(with = "key_name")]
+    pub left: KeyCode,
+    #[serde(with = "key_name")]
+    pub right: KeyCode,
+    #[serde(with = "key_name")]
+    pub a: KeyCode,
+    #[serde(with = "key_name")]
+    pub b: KeyCode,
+    #[serde(with = "key_name")]
+    pub start: KeyCode,
+    #[serde(with = "key_name")]
+    pub select: KeyCode,
+    // Hotkeys were added after the joypad bindings, so older config files do not have them
+    #[serde(with = "key_name", default = "default_fast_forward")]
+    pub fast_forward: KeyCode,
+    #[serde(with = "key_name", default = "default_chat")]
+    pub chat: KeyCode,
+    #[serde(with = "key_name", default = "default_menu")]
+    pub menu: KeyCode,
+    #[serde(with = "key_name", default = "default_quick_chat_1")]
+    pub quick_chat_1: KeyCode,
+    #[serde(with = "key_name", default = "default_quick_chat_2")]
+    pub quick_chat_2: KeyCode,
+    #[serde(with = "key_name", default = "default_quick_chat_3")]
+    pub quick_chat_3: KeyCode,
+    #[serde(with = "key_name", default = "default_quick_chat_4")]
+    pub quick_chat_4: KeyCode,
+}
+
+fn default_fast_forward() -> KeyCode {
+    KeyCode::Space
+}
+
+fn default_chat() -> KeyCode {
+    KeyCode::T
+}
+
+fn default_menu() -> KeyCode {
+    KeyCode::Escape
+}
+
+fn default_quick_chat_1() -> KeyCode {
+    KeyCode::Key1
+}
+
+fn default_quick_chat_2() -> KeyCode {
+    KeyCode::Key2
+}
+
+fn default_quick_chat_3() -> KeyCode {
+    KeyCode::Key3
+}
+
+fn default_quick_chat_4() -> KeyCode {
+    KeyCode::Key4
+}
+
+impl Default for KeyBindings {
+    fn default() -> KeyBindings {
+        KeyBindings {
+            up: KeyCode::Up,
+            down: KeyCode::Down,
+            left: KeyCode::Left,
+            right: KeyCode::Right,
+            a: KeyCode::Z,
+            b: KeyCode::X,
+            start: KeyCode::Enter,
+            select: KeyCode::RightShift,
+            fast_forward: default_fast_forward(),
+            chat: default_chat(),
+            menu: default_menu(),
+            quick_chat_1: default_quick_chat_1(),
+            quick_chat_2: default_quick_chat_2(),
+            quick_chat_3: default_quick_chat_3(),
+            quick_chat_4: default_quick_chat_4(),
+        }
+    }
+}
+
+impl KeyBindings {
+    /// Default bindings for the second local player, chosen so that their joypad does not overlap
+    /// with the first player's bindings. Hotkeys only apply to the player with keyboard focus, so
+    /// both players share the same ones.
+    pub fn player_two() -> KeyBindings {
+        KeyBindings {
+            up: KeyCode::W,
+            down: KeyCode::S,
+            left: KeyCode::A,
+            right: KeyCode::D,
+            a: KeyCode::G,
+            b: KeyCode::H,
+            start: KeyCode::Y,
+            select: KeyCode::U,
+            ..KeyBindings::default()
+        }
+    }
+
+    pub fn get(&self, action: Action) -> KeyCode {
+        match action {
+            Action::Joypad(Button::Up) => self.up,
+            Action::Joypad(Button::Down) => self.down,
+            Action::Joypad(Button::Left) => self.left,
+            Action::Joypad(Button::Right) => self.right,
+            Action::Joypad(Button::A) => self.a,
+            Action::Joypad(Button::B) => self.b,
+            Action::Joypad(Button::Start) => self.start,
+            Action::Joypad(Button::Select) => self.select,
+            Action::FastForward => self.fast_forward,
+            Action::Chat => self.chat,
+            Action::Menu => self.menu,
+            Action::QuickChat(0) => self.quick_chat_1,
+            Action::QuickChat(1) => self.quick_chat_2,
+            Action::QuickChat(2) => self.quick_chat_3,
+            Action::QuickChat(_) => self.quick_chat_4,
+        }
+    }
+
+    pub fn set(&mut self, action: Action, physical: KeyCode) {
+        let slot = match action {
+            Action::Joypad(Button::Up) => &mut self.up,
+            Action::Joypad(Button::Down) => &mut self.down,
+            Action::Joypad(Button::Left) => &mut self.left,
+            Action::Joypad(Button::Right) => &mut self.right,
+            Action::Joypad(Button::A) => &mut self.a,
+            Action::Joypad(Button::B) => &mut self.b,
+            Action::Joypad(Button::Start) => &mut self.start,
+            Action::Joypad(Button::Select) => &mut self.select,
+            Action::FastForward => &mut self.fast_forward,
+            Action::Chat => &mut self.chat,
+            Action::Menu => &mut self.menu,
+            Action::QuickChat(0) => &mut self.quick_chat_1,
+            Action::QuickChat(1) => &mut self.quick_chat_2,
+            Action::QuickChat(2) => &mut self.quick_chat_3,
+            Action::QuickChat(_) => &mut self.quick_chat_4,
+        };
+        *slot = physical;
+    }
+
+    /// The key bound to an action, labelled as it is on the given keyboard layout
+    pub fn label(&self, action: Action, layout: KeyboardLayout) -> String {
+        key_label(layout.key_at(self.get(action)))
+    }
+
+    /// Find the action bound to a physical key
+    pub fn action_for(&self, physical: KeyCode) -> Option<Action> {
+        Action::all().find(|&action| self.get(action) == physical)
+    }
+}
+
+/// Returns the text used to display a key in the UI
+pub fn key_label(keycode: KeyCode) -> String {
+    format!("{:?}", keycode).to_uppercase()
+}
+
+/// Keys that can be bound and stored in the config file
+#[rustfmt::skip]
+const BINDABLE_KEYS: &[KeyCode] = &[
+    KeyCode::A, KeyCode::B, KeyCode::C, KeyCode::D, KeyCode::E, KeyCode::F, KeyCode::G,
+    KeyCode::H, KeyCode::I, KeyCode::J, KeyCode::K, KeyCode::L, KeyCode::M, KeyCode::N,
+    KeyCode::O, KeyCode::P, KeyCode::Q, KeyCode::R, KeyCode::S, KeyCode::T, KeyCode::U,
+    KeyCode::V, KeyCode::W, KeyCode::X, KeyCode::Y, KeyCode::Z,
+    KeyCode::Key0, KeyCode::Key1, KeyCode::Key2, KeyCode::Key3, KeyCode::Key4,
+    KeyCode::Key5, KeyCode::Key6, KeyCode::Key7, KeyCode::Key8, KeyCode::Key9,
+    KeyCode::Up, KeyCode::Down, KeyCode::Left, KeyCode::Right,
+    KeyCode::Space, KeyCode::Enter, KeyCode::Backspace, KeyCode::Tab, KeyCode::Escape,
+    KeyCode::LeftShift, KeyCode::RightShift, KeyCode::LeftControl, KeyCode::RightControl,
+    KeyCode::LeftAlt, KeyCode::RightAlt,
+    KeyCode::Apostrophe, KeyCode::Comma, KeyCode::Minus, KeyCode::Period, KeyCode::Slash,
+    KeyCode::Semicolon, KeyCode::Equal, KeyCode::LeftBracket, KeyCode::RightBracket,
+    KeyCode::Backslash,
+];
+
+pub fn is_bindable(keycode: KeyCode) -> bool {
+    BINDABLE_KEYS.contains(&keycode)
+}
+
+/// (De)serializes key codes using their names, since `KeyCode` does not implement serde's traits
+pub mod key_name {
+    use macroquad::prelude::KeyCode;
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    use super::BINDABLE_KEYS;
+
+    pub fn serialize<S: Serializer>(keycode: &KeyCode, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&format!("{:?}", keycode))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<KeyCode, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        BINDABLE_KEYS
+            .iter()
+            .copied()
+            .find(|key| format!("{:?}", key) == name)
+            .ok_or_else(|| D::Error::custom(format!("unknown key: {}", name)))
+    }
+}
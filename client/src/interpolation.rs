@@ -0,0 +1,110 @@
+//! Drawing other players between the positions received for them, so that they glide instead of
+//! jumping when updates arrive late or bunched together
+//!
+//! The last two positions received for each player are kept with the time they arrived. Players
+//! are drawn one update behind, moving from the older position to the newer one over the time that
+//! passed between them. If no new update arrives in time, the player keeps moving the same way for
+//! a moment (at most one tile) before stopping, which hides short gaps in the packets. Positions
+//! that are far apart, like after a warp, are not smoothed.
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use interface::{data::MovementData, facade, PlayerId};
+
+/// The shortest and longest time taken to move between two positions. Updates that arrive almost
+/// together are still spread out, and players do not crawl after a long pause.
+const MIN_INTERVAL: Duration = Duration::from_millis(16);
+const MAX_INTERVAL: Duration = Duration::from_millis(500);
+
+/// How long a player keeps moving after reaching their newest position without a new update
+const MAX_EXTRAPOLATION: Duration = Duration::from_millis(250);
+
+/// Positions further apart than this (in pixels) are drawn straight away
+const MAX_SMOOTHED_DISTANCE: i32 = 4 * 16;
+
+/// The furthest a player is moved past their newest position (in pixels)
+const MAX_EXTRAPOLATED_DISTANCE: i32 = 16;
+
+#[derive(Copy, Clone)]
+struct Sample {
+    map_id: u8,
+    position: (i32, i32),
+    received_at: Instant,
+}
+
+#[derive(Default)]
+struct Track {
+    previous: Option<Sample>,
+    latest: Option<Sample>,
+}
+
+pub struct Interpolation {
+    tracks: HashMap<PlayerId, Track>,
+}
+
+impl Interpolation {
+    pub fn new() -> Interpolation {
+        Interpolation { tracks: HashMap::new() }
+    }
+
+    /// Record a position received for a player
+    pub fn receive(&mut self, id: PlayerId, movement_data: &MovementData) {
+        let track = self.tracks.entry(id).or_default();
+        if movement_data.is_hidden() {
+            *track = Track::default();
+            return;
+        }
+
+        let sample = Sample {
+            map_id: movement_data.map_id,
+            position: facade::player_position(movement_data),
+            received_at: Instant::now(),
+        };
+        // Start again after a warp, rather than sliding across the map
+        track.previous = match track.latest {
+            Some(latest) if latest.map_id == sample.map_id => {
+                let (dx, dy) = offset(latest.position, sample.position);
+                (dx.abs() + dy.abs() <= MAX_SMOOTHED_DISTANCE).then_some(latest)
+            }
+            _ => None,
+        };
+        track.latest = Some(sample);
+    }
+
+    pub fn remove(&mut self, id: PlayerId) {
+        self.tracks.remove(&id);
+    }
+
+    /// The position on their map to draw a player at, in pixels. Players without two recent
+    /// positions on their current map are drawn where their movement data puts them.
+    pub fn position(&self, id: PlayerId, movement_data: &MovementData) -> (i32, i32) {
+        let current = facade::player_position(movement_data);
+        let (previous, latest) = match self.tracks.get(&id) {
+            Some(Track { previous: Some(previous), latest: Some(latest) }) => (previous, latest),
+            _ => return current,
+        };
+        if latest.map_id != movement_data.map_id {
+            return current;
+        }
+
+        let interval = latest.received_at.saturating_duration_since(previous.received_at);
+        let interval = interval.clamp(MIN_INTERVAL, MAX_INTERVAL);
+        let elapsed = Instant::now().saturating_duration_since(latest.received_at);
+        let max_elapsed = interval + MAX_EXTRAPOLATION.min(interval);
+        let progress = elapsed.min(max_elapsed).as_secs_f32() / interval.as_secs_f32();
+
+        // Past the newest position, players only move on a little way
+        let moved = |delta: i32| {
+            let reach = delta.abs() + MAX_EXTRAPOLATED_DISTANCE;
+            ((delta as f32 * progress).round() as i32).clamp(-reach, reach)
+        };
+        let (dx, dy) = offset(previous.position, latest.position);
+        (previous.position.0 + moved(dx), previous.position.1 + moved(dy))
+    }
+}
+
+fn offset(from: (i32, i32), to: (i32, i32)) -> (i32, i32) {
+    (to.0 - from.0, to.1 - from.1)
+}
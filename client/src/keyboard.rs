@@ -0,0 +1,134 @@
+//! On-screen keyboard, used to type chat messages with a controller
+use interface::text;
+use macroquad::{color::WHITE, shapes::draw_rectangle};
+
+use crate::{
+    border::BorderRenderer,
+    common::{Rect, Renderer},
+    font::{draw_text, Font},
+    input::Button,
+};
+
+const CHAR_ROWS: [&str; 4] = ["1234567890", "ABCDEFGHIJ", "KLMNOPQRST", "UVWXYZ.,!?"];
+const SPECIAL_ROW: [(Key, &str); 3] =
+    [(Key::Space, "SPACE"), (Key::Delete, "DEL"), (Key::Send, "SEND")];
+
+/// The number of rows, including the row of special keys
+const NUM_ROWS: usize = CHAR_ROWS.len() + 1;
+
+/// The width of the keyboard in characters, excluding the border
+const WIDTH_CHARS: i32 = 20;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Key {
+    Char(char),
+    Space,
+    Delete,
+    Send,
+}
+
+pub struct OnScreenKeyboard<'a> {
+    pub visible: bool,
+    row: usize,
+    column: usize,
+
+    font: &'a Font,
+    border: &'a BorderRenderer,
+    outer_rect: Rect,
+    inner_rect: Rect,
+}
+
+impl<'a> OnScreenKeyboard<'a> {
+    /// Create a new keyboard, placed along the bottom of `area`
+    pub fn new(font: &'a Font, border: &'a BorderRenderer, area: Rect) -> OnScreenKeyboard<'a> {
+        let width = (WIDTH_CHARS + 2) * font.char_width();
+        let height = (2 * NUM_ROWS as i32 + 1) * font.line_height();
+        let outer_rect = Rect::new(
+            area.x + (area.width - width) / 2,
+            area.y + area.height - height - font.line_height(),
+            width,
+            height,
+        );
+        let inner_rect = Rect::new(
+            outer_rect.x + font.char_width(),
+            outer_rect.y + font.line_height(),
+            outer_rect.width - 2 * font.char_width(),
+            outer_rect.height - 2 * font.line_height(),
+        );
+
+        OnScreenKeyboard { visible: false, row: 0, column: 0, font, border, outer_rect, inner_rect }
+    }
+
+    fn row_len(row: usize) -> usize {
+        match CHAR_ROWS.get(row) {
+            Some(chars) => chars.len(),
+            None => SPECIAL_ROW.len(),
+        }
+    }
+
+    /// Move the cursor in the direction of a D-pad button
+    pub fn move_cursor(&mut self, direction: Button) {
+        let len = Self::row_len(self.row);
+        match direction {
+            Button::Left => self.column = (self.column + len - 1) % len,
+            Button::Right => self.column = (self.column + 1) % len,
+            Button::Up | Button::Down => {
+                let new_row = match direction {
+                    Button::Up => (self.row + NUM_ROWS - 1) % NUM_ROWS,
+                    _ => (self.row + 1) % NUM_ROWS,
+                };
+                // Keep the cursor at roughly the same horizontal position between rows of
+                // different lengths
+                self.column = self.column * Self::row_len(new_row) / len;
+                self.row = new_row;
+            }
+            _ => {}
+        }
+    }
+
+    pub fn selected(&self) -> Key {
+        match CHAR_ROWS.get(self.row) {
+            Some(chars) => Key::Char(chars.as_bytes()[self.column] as char),
+            None => SPECIAL_ROW[self.column].0,
+        }
+    }
+
+    pub fn draw(&self, renderer: &mut Renderer) {
+        let (char_width, line_height) = (self.font.char_width(), self.font.line_height());
+        draw_rectangle(
+            self.outer_rect.x as f32,
+            self.outer_rect.y as f32,
+            self.outer_rect.width as f32,
+            self.outer_rect.height as f32,
+            WHITE,
+        );
+
+        let cursor = text::encode_char('>') as i32 - 0x80;
+        for row in 0..NUM_ROWS {
+            let y = self.inner_rect.y + 2 * row as i32 * line_height;
+            let labels: Vec<String> = match CHAR_ROWS.get(row) {
+                Some(chars) => chars.chars().map(String::from).collect(),
+                None => SPECIAL_ROW.iter().map(|(_, label)| label.to_string()).collect(),
+            };
+
+            // Keys are spread evenly across the row, with space for the cursor before each key
+            let key_width = WIDTH_CHARS * char_width / labels.len() as i32;
+            for (column, label) in labels.iter().enumerate() {
+                let x = self.inner_rect.x + column as i32 * key_width;
+                if row == self.row && column == self.column {
+                    self.font.draw_char(renderer, cursor, x, y);
+                }
+
+                let encoded: Vec<u8> = text::Encoder::new(label).collect();
+                draw_text(
+                    renderer,
+                    self.font,
+                    &encoded,
+                    &Rect::new(x + char_width, y, key_width - char_width, line_height),
+                );
+            }
+        }
+
+        self.border.draw_box(renderer, self.outer_rect);
+    }
+}
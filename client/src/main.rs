@@ -1,56 +1,216 @@
-use std::{fs::File, io::prelude::*, net::TcpStream, path::Path};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
 
 use gb_emu::emulator::Emulator;
+use macroquad::window::Conf;
+use network_common::{
+    account::{Login, LoginKind},
+    game::GameInfo,
+    tls,
+    transport::ClientOptions,
+};
 
 use crate::{
-    net::{ClientManager, NetworkManager},
+    browser::Listing,
+    client::LocalSession,
+    config::{Config, MAX_LOCAL_PLAYERS},
+    net::ClientManager,
     save::LocalSaveWrapper,
 };
 
 mod border;
+mod browser;
 mod chat;
 mod client;
 mod common;
+mod config;
+mod controls;
+mod crash;
+mod definitions;
 mod font;
 mod game;
+mod gamepad;
+mod handshake;
+mod help;
+mod input;
+mod interpolation;
+mod keyboard;
 mod menu;
+mod options;
 mod net;
+mod notes;
+mod notify;
+mod paths;
+mod players;
+mod replays;
+mod roms;
+mod safari;
 mod save;
+mod servers;
+mod setup;
+mod shop;
+mod spectate;
+mod touch;
+mod trade;
+mod traffic;
+mod trails;
+mod usage;
+mod video;
+mod walking;
+mod window;
+mod world;
+
+const DEFAULT_ROM_PATH: &str = "Pokemon Red.gb";
+
+/// The environment variable that the password for `--login` and `--register` is read from
+const PASSWORD_VAR: &str = "PIKEMON_PASSWORD";
+
+/// The address of the server to connect to. Uses the server chosen during setup if there was no
+/// address specified, or localhost if there is no saved server.
+fn server_address(config: &Config) -> String {
+    match std::env::args().skip(1).find(|arg| !arg.starts_with("--")) {
+        Some(address) => address,
+        None => config.default_server.clone().unwrap_or_else(|| "localhost".into()),
+    }
+}
+
+fn window_conf() -> Conf {
+    // This runs before `main`, so files left in the working directory by older versions are moved
+    // here, before the config is read
+    paths::migrate();
+
+    // The title is kept up to date with the status of the game once it is running (see `window.rs`)
+    let config = Config::load(&config::config_path());
+    Conf { window_title: format!("Pikemon - {}", server_address(&config)), ..Default::default() }
+}
 
-#[macroquad::main("Pikemon")]
+#[macroquad::main(window_conf)]
 async fn main() {
-    let socket = match std::env::args().nth(1) {
-        Some(ip_addr) => TcpStream::connect((&*ip_addr, 8080)).unwrap(),
-        // Assume localhost if there was no argument specified
-        None => TcpStream::connect(("localhost", 8080)).unwrap(),
+    let args: Vec<_> = std::env::args().skip(1).collect();
+    usage::record_crashes();
+    crash::write_reports();
+    if let Some(path) = crash::take_last_report() {
+        crash::show_report(&path).await;
+    }
+
+    // Walk new players through choosing a ROM and server the first time the client is run, or
+    // whenever it is run with `--setup`
+    let mut config = Config::load(&config::config_path());
+    if !config::config_path().exists() || args.iter().any(|arg| arg == "--setup") {
+        config = setup::run(config).await;
+        config::save_config(&config);
+    }
+
+    // Running with `--lan` lists the servers on the local network to pick from, instead of joining
+    // the default server. `--browse=ADDRESS` lists the servers on a master server instead, and
+    // `--browse` uses the master server in the config file.
+    let master = args.iter().find_map(|arg| match arg.as_str() {
+        "--browse" => config.network.master_server.clone(),
+        _ => arg.strip_prefix("--browse=").map(str::to_string),
+    });
+    let listing = match master {
+        _ if args.iter().any(|arg| arg == "--lan") => Some(Listing::Lan),
+        Some(master) => Some(Listing::Master(master)),
+        None if args.iter().any(|arg| arg == "--browse") => {
+            println!("No master server set, use --browse=ADDRESS");
+            None
+        }
+        None => None,
+    };
+    let chosen_server = match listing {
+        Some(listing) => browser::choose_server(listing).await,
+        None => None,
     };
+    let ip_addr = chosen_server.unwrap_or_else(|| server_address(&config));
 
-    let (local_update_sender, local_update_receiver) = crossbeam_channel::unbounded();
-    let (global_update_sender, global_update_receiver) = crossbeam_channel::unbounded();
+    // Running with `--split` allows two players to share a single window, each with their own
+    // emulator and connection to the server.
+    let num_players = if args.iter().any(|arg| arg == "--split") { MAX_LOCAL_PLAYERS } else { 1 };
 
-    let network_manager = NetworkManager { socket, local_update_receiver, global_update_sender };
-    let id = net::handle_network(network_manager).unwrap();
+    // Owned, since the config is changed while the players are set up
+    let rom_path = PathBuf::from(config.rom_path.as_deref().unwrap_or(DEFAULT_ROM_PATH));
+    let cart = match fs::read(&rom_path) {
+        Ok(data) => data,
+        Err(e) => panic!("Error opening '{}': {}", rom_path.display(), e),
+    };
+    crash::set_rom(&cart);
+    match interface::rom::generation(&cart) {
+        Some(generation) => interface::offsets::set_generation(generation),
+        None => println!("Warning: '{}' is not a supported game", rom_path.display()),
+    }
+    definitions::load_saved(&cart);
+    if let Some(path) = definitions::dev_path() {
+        match definitions::load_file(&path, &cart) {
+            Ok(()) => println!("Loaded definitions from {}", path.display()),
+            Err(e) => println!("Definitions were not loaded: {}", e),
+        }
+    }
 
-    let mut emulator = Box::new(Emulator::new());
+    // Running with `--tls` encrypts the connection, checking the server's certificate, and
+    // `--websocket` connects to servers that use WebSocket framing
+    let tls = args.iter().any(|arg| arg == "--tls").then(|| {
+        let trusted = config.network.tls_certificate.as_deref().map(Path::new);
+        tls::client_config(trusted).unwrap_or_else(|e| panic!("Failed to set up TLS: {}", e))
+    });
+    let websocket = args.iter().any(|arg| arg == "--websocket");
+    // `--proxy=ADDRESS` connects through a SOCKS5 proxy, in place of any proxy in the config file
+    let proxy = args.iter().find_map(|arg| arg.strip_prefix("--proxy=")).map(str::to_string);
+    let options = ClientOptions { tls, websocket, proxy: proxy.or(config.network.proxy.clone()) };
 
-    let cart = {
-        let mut data = vec![];
-        let mut f = match File::open("Pokemon Red.gb") {
-            Ok(f) => f,
-            Err(e) => panic!("Error opening 'Pokemon Red.gb': {}", e),
-        };
-        f.read_to_end(&mut data).unwrap();
-        data
+    // `--login=NAME` logs in to an account, and `--register=NAME` creates one, with the password
+    // taken from `PASSWORD_VAR` so that it is not left in the shell's history. The server gives back
+    // a token that is saved for it, so the first player stays logged in from then on.
+    let account_name = |prefix: &str| args.iter().find_map(|arg| arg.strip_prefix(prefix));
+    let login = match (account_name("--login="), account_name("--register=")) {
+        (Some(name), _) => Some((LoginKind::Password, name)),
+        (None, Some(name)) => Some((LoginKind::Register, name)),
+        (None, None) => None,
     };
-    let save_path = Path::new("Pokemon Red.sav");
+    let login = login.map(|(kind, name)| {
+        let secret = std::env::var(PASSWORD_VAR)
+            .unwrap_or_else(|_| panic!("Set {} to the account's password", PASSWORD_VAR));
+        Login { kind, name: name.to_string(), secret }
+    });
+    let mut login = login.or_else(|| config.saved_login(&ip_addr));
 
-    let save_file = Box::new(LocalSaveWrapper { path: save_path });
-    emulator.load_cart(&cart, Some(save_file));
-    emulator.start();
+    let mut sessions = vec![];
+    for local_player in 0..num_players {
+        // A missing or mismatched save is sorted out before joining, so the server is not kept
+        // waiting
+        let save_path = save::save_path(&rom_path, config.save_dir.as_deref(), local_player);
+        let save_format = interface::rom::save_format(&cart);
+        let save_file = Box::new(LocalSaveWrapper::new(save_path, save_format));
+        if !save::check_on_startup(&save_file).await {
+            return;
+        }
 
-    let client_manager = ClientManager::new(id, local_update_sender, global_update_receiver);
+        let compression = config.network.compression();
+        let game_info = GameInfo::from_rom(&cart);
+        let conditions = config.network.simulate;
+        let login = login.take();
+        let joined =
+            ClientManager::join(&ip_addr, &options, compression, game_info, login, conditions);
+        let mut client_manager = joined.unwrap_or_else(|e| panic!("{}", e));
+        if let Some(account) = client_manager.take_new_account() {
+            config.save_account(&ip_addr, account);
+            config::save_config(&config);
+        }
+
+        let mut emulator = Box::new(Emulator::new());
+        emulator.load_cart(&cart, Some(save_file));
+        emulator.start();
+
+        sessions.push(LocalSession { client_manager, emulator });
+    }
+
+    // Remember the server so that settings can be overridden for it
+    if config.add_server(&ip_addr) {
+        config::save_config(&config);
+    }
 
-    if let Err(e) = client::run(client_manager, emulator).await {
+    if let Err(e) = client::run(sessions, config, options).await {
         println!("Pikemon encountered an error and was forced to close. ({})", e);
     }
 }
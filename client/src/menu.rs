@@ -66,6 +66,22 @@ impl<'a> ItemBox<'a> {
         self.border.draw_box(renderer, self.outer_rect);
     }
 
+    pub fn selection(&self) -> usize {
+        self.selection
+    }
+
+    pub fn selected_item(&self) -> &str {
+        &self.items[self.selection]
+    }
+
+    /// Replace the items in the box, keeping the current selection if it is still valid
+    pub fn set_items(&mut self, items: Vec<String>) {
+        self.items = items;
+        if self.selection >= self.items.len() {
+            self.selection = 0;
+        }
+    }
+
     pub fn move_down(&mut self) {
         self.selection += 1;
         if self.selection == self.items.len() {
@@ -1,212 +1,1340 @@
 use std::{
-    io::{prelude::*, BufReader},
+    collections::{HashMap, VecDeque},
     mem,
-    net::TcpStream,
-    thread,
+    time::{Duration, Instant},
 };
 
-use crossbeam_channel::{Receiver, Sender};
+use crossbeam_channel::{Receiver, TryRecvError};
 use interface::{
     self,
-    data::{MovementData, PlayerData},
-    extract, text, InterfaceState, NetworkRequest,
+    data::{BattleData, MovementData, PlayerData},
+    extract, maps,
+    relay::{ItemRule, Loser},
+    text, values, InterfaceData, InterfaceState, NetworkRequest,
 };
 use network_common::{
+    account::Login,
+    client::{self, Latency, NetworkManager},
+    codec::{Compression, MAX_CHAT_LENGTH},
     error::{NetworkError, NetworkResult},
-    NetworkEvent, PlayerId,
+    game::GameInfo,
+    outbox::{Outbox, CHANNEL_CAPACITY},
+    poll, seed,
+    sequence::{LatestUpdates, SequenceCounter},
+    simulator::NetworkConditions,
+    stats::Traffic,
+    transport::{self, ClientOptions},
+    ChatId, Feature, NetworkEvent, PlayerId, RateLimits, SafariStatus, UsageReport,
+    LITE_UPDATE_RATE, MAX_UPDATE_RATE, QUICK_CHAT_PHRASES,
 };
 
-use crate::game::Game;
+use crate::{
+    chat::{ChatBox, Delivery},
+    config::{BattleMode, SavedAccount},
+    crash,
+    game::{Game, GameState},
+    handshake::{IncomingRequests, Outgoing, Request, Reveal},
+    notes, notify,
+    spectate::{self, SpectateRequest},
+    trade::{self, TradeRequest},
+    walking::Walking,
+};
 
-pub struct NetworkManager {
-    pub socket: TcpStream,
-    pub local_update_receiver: Receiver<NetworkEvent>,
-    pub global_update_sender: Sender<NetworkEvent>,
-}
+/// How long to wait for the server to confirm that a chat message was delivered before sending it
+/// again
+const CHAT_ACK_TIMEOUT: Duration = Duration::from_secs(5);
 
-pub fn handle_network(network_manager: NetworkManager) -> NetworkResult<PlayerId> {
-    let mut receiver_socket = BufReader::new(network_manager.socket.try_clone()?);
-    let mut data_buffer = String::new();
+/// How many times to send a chat message before giving up on it
+const CHAT_SEND_ATTEMPTS: u32 = 3;
 
-    receiver_socket.read_line(&mut data_buffer)?;
-    let player_id = match serde_json::from_str(&data_buffer) {
-        Ok(NetworkEvent::PlayerJoin(id)) => id,
-        _ => return Err(NetworkError::DecodeError),
-    };
-    data_buffer.clear();
+/// Shown after the name of a player whose message was sent by their autopilot
+const AWAY_TAG: &str = " (AWAY)";
 
-    let global_update_sender = network_manager.global_update_sender;
-    thread::spawn(move || {
-        loop {
-            match receiver_socket.read_line(&mut data_buffer) {
-                Ok(_) => {
-                    let packet = serde_json::from_str(&data_buffer).unwrap();
-                    // TODO: better error handling
-                    let _ = global_update_sender.send(packet);
-                }
+/// Sent in place of the away message on servers where the player turns down every request
+const DECLINED_MESSAGE: &str = "Not taking battles or trades on this server";
 
-                Err(e) => {
-                    println!("Disconnected from server: {}", e);
-                    break;
-                }
-            }
-            data_buffer.clear();
+/// A chat message that the server has not confirmed yet
+struct PendingChat {
+    id: ChatId,
+    msg: String,
+    /// The battle that the message was sent to, if it is a battle chat message
+    battle: Option<PlayerId>,
+    /// The message's position in the chat box, for updating its delivery status
+    index: usize,
+    sent_at: Instant,
+    attempts: u32,
+}
+
+impl PendingChat {
+    fn event(&self, id: PlayerId) -> NetworkEvent {
+        match self.battle {
+            Some(battle) => NetworkEvent::BattleChat(battle, id, self.id, self.msg.clone()),
+            None => NetworkEvent::Chat(id, self.id, self.msg.clone()),
         }
-    });
+    }
+}
 
-    let local_update_receiver = network_manager.local_update_receiver;
-    let mut sender_socket = network_manager.socket;
-    thread::spawn(move || {
-        loop {
-            let packet = serde_json::to_vec(&local_update_receiver.recv().unwrap()).unwrap();
+/// How often to let the opponent know that we are still connected during a relayed battle
+const BATTLE_KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(1);
 
-            // TODO: better error handling
-            let _ = sender_socket.write(&packet);
-            let _ = sender_socket.write(b"\n");
-        }
-    });
+/// How long the opponent can be silent before a relayed battle is paused
+const BATTLE_PAUSE_THRESHOLD: Duration = Duration::from_secs(3);
 
-    Ok(player_id)
-}
+/// How long a relayed battle can stay paused before the opponent is considered to have left
+const BATTLE_PAUSE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The chat command that concedes a relayed battle
+pub const FORFEIT_COMMAND: &str = "/forfeit";
+
+/// How long updates can go unread before the state of other players is requested again
+const RESYNC_AFTER_STALL: Duration = Duration::from_secs(2);
 
 pub struct ClientManager {
     id: PlayerId,
+    server_name: String,
+    /// An account that was just logged in to with its password, which should be saved so that the
+    /// player stays logged in next time
+    new_account: Option<SavedAccount>,
     last_state: Option<PlayerData>,
     full_update: Option<PlayerData>,
     movement_update: Option<MovementData>,
-    update_sender: Sender<NetworkEvent>,
+    /// When the local player's state was last sent, whether as a full or a movement update
+    state_sent_at: Option<Instant>,
+    /// When updates were last received from the server
+    received_at: Option<Instant>,
+    rate_limits: RateLimits,
+    /// The battle that this player has requested
+    outgoing: Outgoing,
+    /// Battles that other players have requested with this player
+    incoming: IncomingRequests,
+    /// The rules of relayed battles requested by other players
+    relay_rules: HashMap<PlayerId, ItemRule>,
+    /// The parties of players that have requested relayed battles, which are only started once
+    /// their battle request is answered
+    relay_data: HashMap<PlayerId, BattleData>,
+    /// The last number of Hall of Fame entries read from the game
+    hall_of_fame: Option<u8>,
+    /// When a keep alive message was last sent to the opponent of a relayed battle
+    keep_alive_sent: Option<Instant>,
+    /// Chat messages that are waiting to be confirmed by the server, oldest first
+    pending_chat: VecDeque<PendingChat>,
+    /// The id of the next chat message that the player sends
+    next_chat_id: ChatId,
+    /// Whether the connection to the server is still open
+    connected: bool,
+    /// Whether the server has been told that the local player's game is paused
+    paused: bool,
+    /// Whether the server has been asked for lite mode, following the config
+    lite_requested: bool,
+    /// Whether the server has put this client in lite mode, whether it was asked to or not
+    lite_mode: bool,
+    /// Numbers the local player's updates
+    update_seq: SequenceCounter,
+    /// The newest update applied from each other player, so that late movement is dropped
+    latest_updates: LatestUpdates,
+    /// Moves other players along between their movement updates
+    walking: Walking,
+    /// The round trip time to the server, measured by the network threads
+    latency: Latency,
+    /// The data sent and received by the network threads
+    traffic: Traffic,
+    /// Events for the network thread, which are held while its channel is full
+    update_sender: Outbox<poll::Sender<NetworkEvent>>,
     update_receiver: Receiver<NetworkEvent>,
 }
 
 impl ClientManager {
     pub fn new(
         id: PlayerId,
-        update_sender: Sender<NetworkEvent>,
+        server_name: String,
+        latency: Latency,
+        traffic: Traffic,
+        update_sender: poll::Sender<NetworkEvent>,
         update_receiver: Receiver<NetworkEvent>,
     ) -> ClientManager {
         ClientManager {
             id,
+            server_name,
+            new_account: None,
             last_state: None,
             full_update: None,
             movement_update: None,
-            update_sender,
+            state_sent_at: None,
+            received_at: None,
+            rate_limits: RateLimits { min_movement_rate: 1, min_receive_rate: 1 },
+            outgoing: Outgoing::Idle,
+            incoming: IncomingRequests::new(),
+            relay_rules: HashMap::new(),
+            relay_data: HashMap::new(),
+            hall_of_fame: None,
+            keep_alive_sent: None,
+            pending_chat: VecDeque::new(),
+            next_chat_id: 0,
+            connected: true,
+            paused: false,
+            lite_requested: false,
+            lite_mode: false,
+            update_seq: SequenceCounter::default(),
+            latest_updates: LatestUpdates::default(),
+            walking: Walking::new(),
+            latency,
+            traffic,
+            update_sender: Outbox::new(update_sender),
             update_receiver,
         }
     }
 
+    /// Connect to a server and join it as a new player, logged in to an account if `login` is given.
+    /// This blocks until the server has answered. Once joined, the connection is passed through the
+    /// network simulator unless `conditions` are perfect.
+    pub fn join(
+        address: &str,
+        options: &ClientOptions,
+        compression: Compression,
+        game: GameInfo,
+        login: Option<Login>,
+        conditions: NetworkConditions,
+    ) -> Result<ClientManager, String> {
+        let socket = transport::connect(address, options)
+            .map_err(|e| format!("Failed to connect to '{}': {}", address, e))?;
+
+        let (local_update_sender, local_update_receiver) = poll::bounded(CHANNEL_CAPACITY)
+            .map_err(|e| format!("Failed to create a channel: {}", e))?;
+        let (global_update_sender, global_update_receiver) =
+            crossbeam_channel::bounded(CHANNEL_CAPACITY);
+
+        let latency = Latency::default();
+        let traffic = Traffic::default();
+        let network_manager = NetworkManager {
+            socket,
+            local_update_receiver,
+            global_update_sender,
+            compression,
+            game,
+            login: login.clone(),
+            latency: latency.clone(),
+            traffic: traffic.clone(),
+            simulate: conditions,
+        };
+        let joined = client::handle_network(network_manager)
+            .map_err(|e| format!("Failed to join '{}': {}", address, e))?;
+        crash::set_traffic(traffic.clone());
+
+        let mut client_manager = ClientManager::new(
+            joined.player_id,
+            address.to_string(),
+            latency,
+            traffic,
+            local_update_sender,
+            global_update_receiver,
+        );
+        client_manager.new_account =
+            login.zip(joined.token).map(|(login, token)| SavedAccount { name: login.name, token });
+        Ok(client_manager)
+    }
+
+    /// Take the account that was just logged in to, if it needs to be saved
+    pub fn take_new_account(&mut self) -> Option<SavedAccount> {
+        self.new_account.take()
+    }
+
+    pub fn server_name(&self) -> &str {
+        &self.server_name
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.connected
+    }
+
+    /// Send anonymous statistics about the session to the server
+    pub fn send_usage_report(&mut self, report: UsageReport) {
+        if self.connected && self.update_sender.send(NetworkEvent::UsageReport(report)).is_err() {
+            println!("Failed to send the usage report");
+        }
+    }
+
+    /// Let the server know that the player is leaving, so other players see them go straight away
+    pub fn disconnect(&mut self) {
+        if self.connected {
+            client::disconnect(self.id, &mut self.update_sender, &self.update_receiver);
+            self.connected = false;
+        }
+    }
+
     pub fn update_player(&mut self, new_data: &PlayerData) {
-        if let Some(ref last_state) = self.last_state {
-            if last_state.movement_data != new_data.movement_data {
+        if self.last_state.as_ref() == Some(new_data) {
+            return;
+        }
+
+        // Full updates include the player's sprite, so movement is sent on its own unless something
+        // else about the player has changed
+        match self.last_state {
+            Some(ref last_state)
+                if last_state.name == new_data.name && last_state.sprite == new_data.sprite =>
+            {
                 self.movement_update = Some(new_data.movement_data);
             }
+            // Any movement that is still waiting is out of date, and the full update carries the
+            // newest movement anyway
+            _ => {
+                self.full_update = Some(new_data.clone());
+                self.movement_update = None;
+            }
         }
-        if self.last_state.as_ref() != Some(new_data) {
-            self.last_state = Some(new_data.clone());
-            self.full_update = Some(new_data.clone());
-        }
+        self.last_state = Some(new_data.clone());
     }
 
     pub fn send_update(&mut self, game: &mut Game) -> NetworkResult<()> {
-        if self.movement_update.is_some() {
-            let update_data = mem::replace(&mut self.movement_update, None).unwrap();
-            self.update_sender
-                .send(NetworkEvent::MovementUpdate(self.id, update_data))
-                .map_err(|_| NetworkError::SendError)?;
-        }
+        // Events held back while the network thread was busy go before any new ones
+        self.update_sender.flush().map_err(|_| NetworkError::SendError)?;
 
-        if self.full_update.is_some() {
-            let update_data = mem::replace(&mut self.full_update, None).unwrap();
-            self.update_sender
-                .send(NetworkEvent::FullUpdate(self.id, update_data))
-                .map_err(|_| NetworkError::SendError)?;
+        // Changes that are not sent yet are kept and replaced by newer ones, so the game can change
+        // every frame without sending more than `movement_rate` updates a second
+        let movement_rate = game.config.borrow().network.movement_rate;
+        let interval = if self.lite_mode {
+            update_interval(LITE_UPDATE_RATE, 1)
+        }
+        else {
+            update_interval(movement_rate, self.rate_limits.min_movement_rate)
+        };
+        let pending = self.full_update.is_some() || self.movement_update.is_some();
+        if pending && is_due(self.state_sent_at, interval) {
+            self.state_sent_at = Some(Instant::now());
+            let event = match self.full_update.take() {
+                // Movement that changed after the full update is newer than the movement in it
+                Some(mut update_data) => {
+                    if let Some(movement_data) = self.movement_update.take() {
+                        update_data.movement_data = movement_data;
+                    }
+                    NetworkEvent::FullUpdate(self.id, self.update_seq.advance(), update_data)
+                }
+                None => {
+                    let movement_data = self.movement_update.take().unwrap();
+                    NetworkEvent::MovementUpdate(self.id, self.update_seq.advance(), movement_data)
+                }
+            };
+            self.update_sender.send(event).map_err(|_| NetworkError::SendError)?;
         }
 
         if game.chat_box.message_ready {
             self.send_message(game)?;
         }
+        if let Some(phrase) = game.quick_chat.take() {
+            self.send_quick_chat(game, phrase)?;
+        }
+        if mem::take(&mut game.forfeit_requested) {
+            self.forfeit_battle(game)?;
+        }
+        self.retry_chat(game)?;
+
+        self.check_hall_of_fame(game)?;
+        self.update_paused(game)?;
+        self.update_lite_mode(game)?;
+        self.update_spectating(game)?;
+        self.update_safari(game)?;
+        self.update_trades(game)?;
+        self.report_purchases(game)?;
 
-        match game.interface_data.borrow().network_request {
+        self.update_relay(game)?;
+
+        let network_request = game.interface.borrow_mut().take_network_request();
+        match network_request {
             NetworkRequest::None => {}
             NetworkRequest::Battle(id) => {
                 println!("Requesting battle");
-                self.update_sender
-                    .send(NetworkEvent::BattleDataRequest(id, self.id))
-                    .map_err(|_| NetworkError::SendError)?;
+                game.usage.record(Feature::BattleRequest);
+                let config = game.config.borrow();
+                let relay = match config.battle_mode {
+                    BattleMode::Local => None,
+                    BattleMode::Relay => Some(config.item_rule),
+                };
+                // The game waits for each battle to start before it can request another, but make
+                // sure that an unfinished request is not left open with the previous opponent
+                if let Some(previous) = self.outgoing.start(id, relay) {
+                    self.update_sender
+                        .send(NetworkEvent::BattleRequestCancel(previous.opponent, self.id))
+                        .map_err(|_| NetworkError::SendError)?;
+                }
+                let relay_data =
+                    relay.map(|_| game.interface.borrow().battle_data(&game.emulator.mem));
+                let request = *self.outgoing.request().unwrap();
+                self.send_battle_request(&request, relay_data)?;
             }
         }
 
-        game.interface_data.borrow_mut().network_request = NetworkRequest::None;
+        if let Some(request) = self.outgoing.retransmit() {
+            println!("Sending battle request again");
+            self.send_battle_request(&request, None)?;
+        }
+        Ok(())
+    }
+
+    /// Send the messages that make up a battle request. The relayed battle data is only sent with
+    /// the first request, since it starts the battle for the opponent. Everything else can be sent
+    /// again: the opponent answers a repeated request with the same seed.
+    fn send_battle_request(
+        &mut self,
+        request: &Request,
+        relay_data: Option<BattleData>,
+    ) -> NetworkResult<()> {
+        let opponent = request.opponent;
+        let commitment = seed::commitment(&request.secret);
+        self.update_sender
+            .send(NetworkEvent::BattleSeedCommit(opponent, self.id, commitment))
+            .map_err(|_| NetworkError::SendError)?;
+        if let Some(item_rule) = request.relay {
+            self.update_sender
+                .send(NetworkEvent::BattleRules(opponent, self.id, item_rule))
+                .map_err(|_| NetworkError::SendError)?;
+        }
+        if let Some(data) = relay_data {
+            self.update_sender
+                .send(NetworkEvent::RelayBattleData(opponent, self.id, data))
+                .map_err(|_| NetworkError::SendError)?;
+        }
+        self.update_sender
+            .send(NetworkEvent::BattleDataRequest(opponent, self.id))
+            .map_err(|_| NetworkError::SendError)?;
         Ok(())
     }
 
     pub fn recv_update(&mut self, game: &mut Game) -> NetworkResult<()> {
-        let interface_data = &mut game.interface_data.borrow_mut();
+        game.latency = self.latency.get();
+        if game.game_state == GameState::Traffic {
+            game.traffic_view.refresh(&self.traffic.stats());
+        }
+
+        // Other players keep walking every frame, however often updates are received
+        if game.config.borrow().video.smooth_walking {
+            self.walking.advance(&mut game.interface.borrow_mut().data.players);
+        }
+
+        let receive_rate = game.config.borrow().network.receive_rate;
+        let receive_interval = update_interval(receive_rate, self.rate_limits.min_receive_rate);
+        if !is_due(self.received_at, receive_interval) {
+            return Ok(());
+        }
+        // Movement sent over UDP may have been dropped while updates were not being read, so ask
+        // for the state of every player again
+        if matches!(self.received_at, Some(at) if at.elapsed() >= RESYNC_AFTER_STALL) {
+            println!("Requesting the state of other players after a stall");
+            self.update_sender
+                .send(NetworkEvent::ResyncRequest(self.id))
+                .map_err(|_| NetworkError::SendError)?;
+        }
+        self.received_at = Some(Instant::now());
+
+        let interface = &mut *game.interface.borrow_mut();
+        let mut resynced = VecDeque::new();
         loop {
-            match self.update_receiver.try_recv() {
-                Ok(NetworkEvent::FullUpdate(id, update_data)) => {
-                    interface_data.players.insert(id, update_data);
+            // Players in a snapshot are applied the same way as full updates
+            let event = match resynced.pop_front() {
+                Some((id, seq, update_data)) => Ok(NetworkEvent::FullUpdate(id, seq, update_data)),
+                None => self.update_receiver.try_recv(),
+            };
+            match event {
+                Ok(NetworkEvent::FullUpdate(id, seq, mut update_data)) => {
+                    // A late full update still has the player's newest name and sprite, but their
+                    // position is older than the movement that has already been applied
+                    if !self.latest_updates.accept(id, seq) {
+                        if let Some(player) = interface.data.players.get(&id) {
+                            update_data.movement_data = player.movement_data;
+                        }
+                    }
+                    else {
+                        game.interpolation.receive(id, &update_data.movement_data);
+                    }
+                    let name = text::decode(&update_data.name);
+                    if interface.data.players.insert(id, update_data).is_none() {
+                        notify_if_away(game, &format!("{} is online", name));
+                    }
                 }
 
-                Ok(NetworkEvent::MovementUpdate(id, update_data)) => {
-                    if let Some(player) = interface_data.players.get_mut(&id) {
-                        player.movement_data = update_data;
+                Ok(NetworkEvent::PlayerSnapshot(players)) => resynced.extend(players),
+
+                Ok(NetworkEvent::MovementUpdate(id, seq, update_data)) => {
+                    if !self.latest_updates.accept(id, seq) {
+                        continue;
+                    }
+                    game.interpolation.receive(id, &update_data);
+                    if let Some(player) = interface.data.players.get_mut(&id) {
+                        self.walking.receive(id, &mut player.movement_data, update_data);
                     }
                 }
 
                 Ok(NetworkEvent::PlayerQuit(id)) => {
                     println!("Player: {} quit.", id);
-                    interface_data.players.remove(&id);
+                    self.incoming.remove(id);
+                    self.relay_rules.remove(&id);
+                    self.relay_data.remove(&id);
+                    self.walking.remove(id);
+                    self.latest_updates.remove(id);
+                    game.interpolation.remove(id);
+                    game.spectating.watchers.remove(&id);
+                    game.paused_players.remove(&id);
+                    if matches!(game.spectating.watching, Some((watched, _)) if watched == id) {
+                        game.spectating.watching = None;
+                    }
+                    if self.outgoing.opponent() == Some(id) {
+                        let reason = "Battle cancelled, the opponent did not respond";
+                        self.cancel_battle(&mut interface.data, &mut game.chat_box, reason);
+                    }
+                    if matches!(&interface.data.battle_relay, Some(relay) if relay.opponent == id) {
+                        // Stop waiting for moves from the opponent, the game will choose the
+                        // enemy's moves for the rest of the battle.
+                        interface.data.battle_relay = None;
+                        interface.resume();
+                        game.chat_box.add_notice("The opponent left the battle");
+                    }
+                    interface.data.players.remove(&id);
+                }
+
+                // Nothing is started while the player is away or turning requests down, the
+                // requester is told why instead
+                Ok(NetworkEvent::BattleDataRequest(_, id)) if game.declines_requests() => {
+                    self.incoming.remove(id);
+                    self.relay_rules.remove(&id);
+                    self.relay_data.remove(&id);
+                    self.send_away_message(game, id)?;
                 }
 
                 Ok(NetworkEvent::BattleDataRequest(_, id)) => {
-                    println!("Responding to battle request");
-                    let data = extract::battle_data(&game.emulator.mem);
+                    let answer = self.incoming.answer(id);
+                    if answer.repeated {
+                        println!("Responding to repeated battle request");
+                    }
+                    else if let Some(player) = interface.data.players.get(&id) {
+                        println!("Responding to battle request");
+                        let notice = text::Encoder::new("wants to battle!").collect();
+                        game.chat_box.add_message(player.name.clone(), notice);
+                        let name = text::decode(&player.name);
+                        notify_if_away(game, &format!("{} wants to battle!", name));
+                        game.attention_requested = true;
+                    }
+                    if let Some(own_seed) = answer.seed {
+                        self.update_sender
+                            .send(NetworkEvent::BattleSeed(id, self.id, own_seed))
+                            .map_err(|_| NetworkError::SendError)?;
+                    }
+                    let data = interface.battle_data(&game.emulator.mem);
                     self.update_sender
-                        .send(NetworkEvent::BattleDataResponse(id, data))
+                        .send(NetworkEvent::BattleDataResponse(id, self.id, data))
                         .map_err(|_| NetworkError::SendError)?;
+
+                    // A relayed battle starts for this player once they have answered, the same
+                    // way as it does for the requester
+                    if let Some(battle_data) = self.relay_data.remove(&id) {
+                        if interface.data.battle_relay.is_some() {
+                            println!("Not starting a relayed battle with {}, already battling", id);
+                            continue;
+                        }
+                        let item_rule = self.relay_rules.remove(&id).unwrap_or_default();
+                        interface.start_battle(
+                            &mut game.emulator.mem,
+                            id,
+                            battle_data,
+                            Some(item_rule),
+                        );
+                    }
+                }
+
+                Ok(NetworkEvent::BattleRequestCancel(_, id)) => {
+                    println!("Player: {} cancelled their battle request", id);
+                    self.incoming.remove(id);
+                    self.relay_rules.remove(&id);
+                    self.relay_data.remove(&id);
+                }
+
+                Ok(NetworkEvent::BattleRequestRefused(_, id)) => {
+                    if self.outgoing.opponent() == Some(id) {
+                        let reason = "Please wait before asking this player to battle again";
+                        self.cancel_battle(&mut interface.data, &mut game.chat_box, reason);
+                    }
+                }
+
+                Ok(NetworkEvent::BattleDataResponse(_, id, battle_data)) => {
+                    // Responses can arrive after the request was cancelled, or more than once if
+                    // the request was sent again
+                    let request = match self.outgoing.finish(id) {
+                        Some(request) => request,
+                        None => {
+                            println!("Ignoring battle data from player: {}, not requested", id);
+                            continue;
+                        }
+                    };
+                    interface.resume();
+
+                    // Now that the opponent's seed has been received, reveal the secret seed so
+                    // that the opponent can check that the battle seed was not manipulated.
+                    match request.opponent_seed {
+                        Some(opponent_seed) => {
+                            let secret = request.secret;
+                            self.update_sender
+                                .send(NetworkEvent::BattleSeedReveal(id, self.id, secret))
+                                .map_err(|_| NetworkError::SendError)?;
+                            let rng_state = seed::combine(&secret, &opponent_seed);
+                            interface.set_rng_state(&mut game.emulator.mem, rng_state);
+                        }
+                        None => println!("No battle seed was agreed on"),
+                    }
+
+                    interface.start_battle(&mut game.emulator.mem, id, battle_data, request.relay);
+                }
+
+                // Kept until the battle request that follows it is answered
+                Ok(NetworkEvent::RelayBattleData(_, id, battle_data)) => {
+                    self.relay_data.insert(id, battle_data);
+                }
+
+                Ok(NetworkEvent::BattleKeepAlive(_, id)) => {
+                    if let Some(relay) = &mut interface.data.battle_relay {
+                        if relay.opponent == id {
+                            relay.last_heard = Instant::now();
+                        }
+                    }
+                }
+
+                Ok(NetworkEvent::BattleRules(_, id, item_rule)) => {
+                    self.relay_rules.insert(id, item_rule);
+                }
+
+                Ok(NetworkEvent::BattleItemUsed(_, id)) => {
+                    let item_rule = match &interface.data.battle_relay {
+                        Some(relay) if relay.opponent == id => relay.item_rule,
+                        _ => continue,
+                    };
+                    let name = match interface.data.players.get(&id) {
+                        Some(player) => player.name.clone(),
+                        None => text::Encoder::new("UNKNOWN").collect(),
+                    };
+                    let notice = match item_rule {
+                        ItemRule::Forfeit => "used an item and forfeited!",
+                        _ => "used an item!",
+                    };
+                    game.chat_box.add_message(name, text::Encoder::new(notice).collect());
+                    if item_rule == ItemRule::Forfeit {
+                        interface.forfeit_battle(&mut game.emulator.mem, Loser::Enemy);
+                    }
+                }
+
+                Ok(NetworkEvent::BattleForfeit(_, id)) => {
+                    if !matches!(&interface.data.battle_relay, Some(r) if r.opponent == id) {
+                        println!("Ignoring forfeit from player: {}", id);
+                        continue;
+                    }
+                    interface.forfeit_battle(&mut game.emulator.mem, Loser::Enemy);
+                    let name = self.player_name(game, &interface.data, id);
+                    let notice = text::Encoder::new("forfeited the battle!").collect();
+                    game.chat_box.add_message(name, notice);
+                }
+
+                Ok(NetworkEvent::BattleAction(_, id, action)) => {
+                    match &mut interface.data.battle_relay {
+                        Some(relay) if relay.opponent == id => {
+                            relay.last_heard = Instant::now();
+                            relay.incoming.push_back(action);
+                            if self.outgoing.is_idle() {
+                                interface.resume();
+                            }
+                        }
+                        _ => println!("Ignoring battle action from player: {}", id),
+                    }
+                }
+
+                Ok(NetworkEvent::BattleSeedCommit(_, id, commitment)) => {
+                    self.incoming.commit(id, commitment);
                 }
 
-                Ok(NetworkEvent::BattleDataResponse(_, battle_data)) => {
-                    interface_data.state = InterfaceState::Normal;
-                    let enemy_id = interface_data.last_interaction;
-                    if let Some(enemy) = interface_data.players.get(&enemy_id) {
-                        interface::set_battle(&mut game.emulator.mem, enemy, battle_data);
+                Ok(NetworkEvent::BattleSeed(_, id, opponent_seed)) => {
+                    self.outgoing.receive_seed(id, opponent_seed);
+                }
+
+                Ok(NetworkEvent::BattleSeedReveal(_, id, secret)) => {
+                    match self.incoming.reveal(id, &secret) {
+                        Reveal::Invalid => {
+                            println!("Player: {} revealed an invalid battle seed", id);
+                            if let Some(player) = interface.data.players.get(&id) {
+                                let notice =
+                                    text::Encoder::new("used an invalid battle seed!").collect();
+                                game.chat_box.add_message(player.name.clone(), notice);
+                            }
+                        }
+                        Reveal::Valid(Some(own_seed)) => {
+                            // Use the same RNG state as the opponent for a relayed battle
+                            if matches!(&interface.data.battle_relay, Some(r) if r.opponent == id) {
+                                let rng_state = seed::combine(&secret, &own_seed);
+                                interface.set_rng_state(&mut game.emulator.mem, rng_state);
+                            }
+                        }
+                        Reveal::Valid(None) => {}
+                    }
+                }
+
+                Ok(NetworkEvent::HallOfFame(id, teams)) => {
+                    // Players that entered the Hall of Fame before we joined are not announced
+                    if let Some(player) = interface.data.players.get(&id) {
+                        let notice = text::Encoder::new("entered the Hall of Fame!").collect();
+                        game.chat_box.add_message(player.name.clone(), notice);
+                    }
+                    game.hall_of_fame.insert(id, teams);
+                }
+
+                Ok(NetworkEvent::PlayerPaused(id, paused)) => {
+                    if paused {
+                        game.paused_players.insert(id);
+                    }
+                    else {
+                        game.paused_players.remove(&id);
+                    }
+                }
+
+                Ok(NetworkEvent::WildEncounter(id, species, level)) => {
+                    // Only announce encounters from players on the same map
+                    let local_map = game.player_data.movement_data.map_id;
+                    let player = match interface.data.players.get(&id) {
+                        Some(player) if player.movement_data.map_id == local_map => player,
+                        _ => continue,
+                    };
+                    let mut notice = text::Encoder::new("found a wild ").collect::<Vec<_>>();
+                    notice.extend(extract::species_name(&game.emulator.mem, species));
+                    notice.extend(text::Encoder::new(&format!(" L{}!", level)));
+                    game.chat_box.add_message(player.name.clone(), notice);
+                    game.chat_box.add_notice("Select WATCH BATTLE from the menu to watch");
+                    game.spectating.last_announcement = Some(id);
+                }
+
+                Ok(NetworkEvent::SpectateRequest(_, id)) => {
+                    let in_battle = extract::battle_snapshot(&game.emulator.mem).is_some();
+                    let allowed = {
+                        let config = game.config.borrow();
+                        let note = config.player_note(&self.server_name, id);
+                        let is_friend = note.is_some_and(|note| note.friend);
+                        config.share_encounters && config.spectators.allows(is_friend)
+                    };
+                    if in_battle && allowed {
+                        game.spectating.watchers.insert(id);
+                        // Make sure the new watcher is sent the current state of the battle
+                        game.spectating.last_snapshot = None;
+                    }
+                    else {
+                        self.update_sender
+                            .send(NetworkEvent::SpectateEnd(id, self.id))
+                            .map_err(|_| NetworkError::SendError)?;
+                    }
+                }
+
+                Ok(NetworkEvent::SpectateStop(_, id)) => {
+                    game.spectating.watchers.remove(&id);
+                }
+
+                Ok(NetworkEvent::BattleSnapshot(_, id, snapshot)) => {
+                    if let Some((watched, state)) = &mut game.spectating.watching {
+                        if *watched == id {
+                            *state = Some(snapshot);
+                        }
+                    }
+                }
+
+                Ok(NetworkEvent::SpectateEnd(_, id)) => {
+                    if matches!(game.spectating.watching, Some((watched, _)) if watched == id) {
+                        game.spectating.watching = None;
+                        game.chat_box.add_notice("The battle is over");
                     }
                 }
 
+                Ok(NetworkEvent::TradeBoard(listings)) => {
+                    game.trade_board.set_listings(listings, self.id, &game.emulator.mem);
+                }
+
+                Ok(NetworkEvent::TradeOffer(_, id, ..)) if game.declines_requests() => {
+                    self.send_away_message(game, id)?;
+                }
+
+                Ok(NetworkEvent::TradeOffer(_, id, species, level)) => {
+                    let listing = match game.trade_board.own_listing() {
+                        Some(listing) => *listing,
+                        None => continue,
+                    };
+                    let mem = &game.emulator.mem;
+                    let offer = format!(
+                        "offers {} L{} for your {}. Meet in the Cable Club to trade!",
+                        trade::species_name(mem, species),
+                        level,
+                        trade::species_name(mem, listing.species)
+                    );
+                    let player_name = self.player_name(game, &interface.data, id);
+                    let name = text::decode(&player_name);
+                    notify_if_away(game, &format!("{} wants to trade!", name));
+                    game.attention_requested = true;
+                    game.chat_box.add_message(player_name, text::Encoder::new(&offer).collect());
+                }
+
+                Ok(NetworkEvent::AwayMessage(_, id, msg)) => {
+                    if self.outgoing.opponent() == Some(id) {
+                        let reason = "Battle cancelled, the player is away";
+                        self.cancel_battle(&mut interface.data, &mut game.chat_box, reason);
+                    }
+                    let mut player_name = self.player_name(game, &interface.data, id);
+                    player_name.extend(text::Encoder::new(AWAY_TAG));
+                    game.chat_box.add_message(player_name, text::Encoder::new(&msg).collect());
+                }
+
+                Ok(NetworkEvent::ShopCatalog(items)) => game.shop.set_items(items),
+
+                Ok(NetworkEvent::DayCycle(cycle)) => game.world.set_day_cycle(cycle),
+
+                Ok(NetworkEvent::Visibility(rule)) => interface.data.visibility.server = rule,
+
+                Ok(NetworkEvent::Motd(motd)) => game.chat_box.add_notice(&motd),
+
+                Ok(NetworkEvent::SafariUpdate(status)) => {
+                    let was_running = game.safari.remaining().is_some();
+                    if status.remaining_secs == 0 {
+                        game.chat_box.add_notice("The Safari Zone event is over!");
+                        self.show_leaderboard(&status, &interface.data, &mut game.chat_box);
+                    }
+                    else if !was_running {
+                        let minutes = status.remaining_secs.div_ceil(60);
+                        game.chat_box.add_notice(&format!(
+                            "A Safari Zone event has started! Catch the most Pokemon in {} minutes",
+                            minutes
+                        ));
+                    }
+                    game.safari.update_status(status);
+                }
+
+                Ok(NetworkEvent::RateLimits(limits)) => {
+                    self.rate_limits = limits;
+                }
+
+                Ok(NetworkEvent::LiteMode(_, on)) if on != self.lite_mode => {
+                    self.lite_mode = on;
+                    if on {
+                        let reason = if self.lite_requested {
+                            "Lite mode is on"
+                        }
+                        else {
+                            "The connection is slow, switched to lite mode"
+                        };
+                        game.chat_box.add_notice(reason);
+                        game.chat_box.add_notice("Chat and watching battles are off");
+                        // The server no longer passes on battle snapshots to or from this client
+                        let spectating = &mut game.spectating;
+                        if let Some((id, _)) = spectating.watching.take() {
+                            self.update_sender
+                                .send(NetworkEvent::SpectateStop(id, self.id))
+                                .map_err(|_| NetworkError::SendError)?;
+                        }
+                        for id in spectating.watchers.drain() {
+                            self.update_sender
+                                .send(NetworkEvent::SpectateEnd(id, self.id))
+                                .map_err(|_| NetworkError::SendError)?;
+                        }
+                    }
+                    else {
+                        game.chat_box.add_notice("Lite mode is off");
+                        // Catch up on the movement that was left out while in lite mode
+                        self.update_sender
+                            .send(NetworkEvent::ResyncRequest(self.id))
+                            .map_err(|_| NetworkError::SendError)?;
+                    }
+                }
+
+                Ok(NetworkEvent::LiteMode(..)) => {}
+
+                Ok(NetworkEvent::SharedSeed(seed)) => {
+                    game.world.set_seed(seed);
+                }
+
                 Ok(NetworkEvent::UpdateRequest) => {
                     println!("Responding to update request");
-                    let update_data = game.player_data.clone();
+                    let update_data = game.shared_player_data();
+                    let seq = self.update_seq.advance();
                     self.update_sender
-                        .send(NetworkEvent::FullUpdate(self.id, update_data))
+                        .send(NetworkEvent::FullUpdate(self.id, seq, update_data))
                         .map_err(|_| NetworkError::SendError)?;
                 }
 
-                Ok(NetworkEvent::Chat(id, msg)) => {
-                    let player_name = match interface_data.players.get(&id) {
-                        Some(player) => player.name.clone(),
-                        None => text::Encoder::new("UNKNOWN").collect(),
-                    };
-                    game.chat_box.add_message(player_name, text::Encoder::new(&*msg).collect());
+                Ok(NetworkEvent::ChatAck(chat_id)) => {
+                    let position =
+                        self.pending_chat.iter().position(|pending| pending.id == chat_id);
+                    if let Some(pending) = position.and_then(|i| self.pending_chat.remove(i)) {
+                        game.chat_box.set_delivery(pending.index, Delivery::Delivered);
+                    }
+                }
+
+                Ok(NetworkEvent::Chat(id, _, msg)) => {
+                    let player_name = self.player_name(game, &interface.data, id);
+                    let msg = game.server_settings.filter_chat(&msg);
+                    game.chat_box.add_message(player_name, text::Encoder::new(&msg).collect());
+                }
+
+                Ok(NetworkEvent::BattleChat(_, id, _, msg)) => {
+                    let mut player_name = self.player_name(game, &interface.data, id);
+                    player_name.extend(text::Encoder::new(spectate::BATTLE_CHAT_TAG));
+                    let msg = game.server_settings.filter_chat(&msg);
+                    game.chat_box.add_message(player_name, text::Encoder::new(&msg).collect());
+                }
+
+                // Only shown from the current opponent, since nobody else is in the battle
+                Ok(NetworkEvent::BattleQuickChat(_, id, phrase)) => {
+                    let from_opponent =
+                        matches!(&interface.data.battle_relay, Some(relay) if relay.opponent == id);
+                    if !from_opponent {
+                        continue;
+                    }
+                    let player_name = self.player_name(game, &interface.data, id);
+                    let msg = QUICK_CHAT_PHRASES[phrase as usize];
+                    game.chat_box.add_message(player_name, text::Encoder::new(msg).collect());
+                }
+
+                Ok(event) => println!("Ignoring an unexpected {} from the server", event.name()),
+
+                // The network thread stops once the connection to the server has been lost
+                Err(TryRecvError::Disconnected) => {
+                    if self.connected {
+                        self.connection_lost(&mut interface.data, &mut game.chat_box);
+                        notify_if_away(game, "Connection to the server was lost");
+                    }
+                    break;
+                }
+
+                Err(TryRecvError::Empty) => break,
+            }
+        }
+
+        let timeout = game.config.borrow().network.battle_request_timeout;
+        if self.outgoing.timed_out(Duration::from_secs(timeout)) {
+            println!("Battle request timed out");
+            // Let the opponent know, in case their response is only delayed
+            if let Some(opponent) = self.outgoing.opponent() {
+                self.update_sender
+                    .send(NetworkEvent::BattleRequestCancel(opponent, self.id))
+                    .map_err(|_| NetworkError::SendError)?;
+            }
+            let reason = "Battle cancelled, the opponent did not respond";
+            self.cancel_battle(&mut interface.data, &mut game.chat_box, reason);
+        }
+        self.incoming.prune();
+
+        self.check_connection(&mut interface.data, &mut game.chat_box);
+
+        Ok(())
+    }
+
+    /// Announce when the local player enters the Hall of Fame, if they have chosen to share it
+    fn check_hall_of_fame(&mut self, game: &mut Game) -> NetworkResult<()> {
+        if !mem::take(&mut game.changes.hall_of_fame) {
+            return Ok(());
+        }
+        let teams = extract::hall_of_fame_count(&game.emulator.mem);
+        let last_teams = self.hall_of_fame.replace(teams);
+
+        // The count also changes when a save is loaded, so only count new entries that are made
+        // while the player is in the Hall of Fame.
+        let entered = matches!(last_teams, Some(last) if teams > last)
+            && game.player_data.movement_data.map_id == maps::HALL_OF_FAME;
+        if entered && game.config.borrow().share_hall_of_fame {
+            let user_name = game.own_name();
+            let notice = text::Encoder::new("entered the Hall of Fame!").collect();
+            game.chat_box.add_message(user_name, notice);
+            self.update_sender
+                .send(NetworkEvent::HallOfFame(self.id, teams))
+                .map_err(|_| NetworkError::SendError)?;
+        }
+        Ok(())
+    }
+
+    /// Let other players know when the local player's game is paused or resumed
+    fn update_paused(&mut self, game: &Game) -> NetworkResult<()> {
+        let paused = game.is_paused();
+        if paused != self.paused {
+            self.paused = paused;
+            self.update_sender
+                .send(NetworkEvent::PlayerPaused(self.id, paused))
+                .map_err(|_| NetworkError::SendError)?;
+        }
+        Ok(())
+    }
+
+    /// Ask the server to turn lite mode on or off whenever it is changed in the config
+    fn update_lite_mode(&mut self, game: &Game) -> NetworkResult<()> {
+        let lite_mode = game.config.borrow().network.lite_mode;
+        if lite_mode != self.lite_requested {
+            self.lite_requested = lite_mode;
+            self.update_sender
+                .send(NetworkEvent::LiteMode(self.id, lite_mode))
+                .map_err(|_| NetworkError::SendError)?;
+        }
+        Ok(())
+    }
+
+    /// Announce rare wild encounters, and keep players watching the local player's battle up to date
+    fn update_spectating(&mut self, game: &mut Game) -> NetworkResult<()> {
+        let spectating = &mut game.spectating;
+        match spectating.request.take() {
+            Some(SpectateRequest::Start(_)) if self.lite_mode => {
+                spectating.watching = None;
+                game.chat_box.add_notice("Battles can not be watched in lite mode");
+            }
+            Some(SpectateRequest::Start(id)) => self
+                .update_sender
+                .send(NetworkEvent::SpectateRequest(id, self.id))
+                .map_err(|_| NetworkError::SendError)?,
+            Some(SpectateRequest::Stop(id)) => self
+                .update_sender
+                .send(NetworkEvent::SpectateStop(id, self.id))
+                .map_err(|_| NetworkError::SendError)?,
+            None => {}
+        }
+
+        // Encounters can only start or end when the battle state changes
+        if mem::take(&mut game.changes.battle) {
+            match extract::wild_encounter(&game.emulator.mem) {
+                Some((species, level)) if !spectating.announced => {
+                    spectating.announced = true;
+                    let share = game.config.borrow().share_encounters;
+                    if values::RARE_SPECIES.contains(&species) && share {
+                        self.update_sender
+                            .send(NetworkEvent::WildEncounter(self.id, species, level))
+                            .map_err(|_| NetworkError::SendError)?;
+                    }
                 }
+                Some(_) => {}
+                None => spectating.announced = false,
+            }
+        }
+
+        if spectating.watchers.is_empty() {
+            return Ok(());
+        }
+
+        match extract::battle_snapshot(&game.emulator.mem) {
+            Some(snapshot) if spectating.last_snapshot != Some(snapshot) => {
+                spectating.last_snapshot = Some(snapshot);
+                for &id in &spectating.watchers {
+                    self.update_sender
+                        .send(NetworkEvent::BattleSnapshot(id, self.id, snapshot))
+                        .map_err(|_| NetworkError::SendError)?;
+                }
+            }
+            Some(_) => {}
+            None => {
+                spectating.last_snapshot = None;
+                for id in spectating.watchers.drain() {
+                    self.update_sender
+                        .send(NetworkEvent::SpectateEnd(id, self.id))
+                        .map_err(|_| NetworkError::SendError)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Send the actions chosen in a relayed battle, and enforce its rules on the local player
+    fn update_relay(&mut self, game: &mut Game) -> NetworkResult<()> {
+        let interface = &mut *game.interface.borrow_mut();
+        let relay = match &mut interface.data.battle_relay {
+            Some(relay) => relay,
+            None => return Ok(()),
+        };
+
+        for action in relay.outgoing.drain(..) {
+            self.update_sender
+                .send(NetworkEvent::BattleAction(relay.opponent, self.id, action))
+                .map_err(|_| NetworkError::SendError)?;
+        }
+
+        if is_due(self.keep_alive_sent, BATTLE_KEEP_ALIVE_INTERVAL) {
+            self.keep_alive_sent = Some(Instant::now());
+            self.update_sender
+                .send(NetworkEvent::BattleKeepAlive(relay.opponent, self.id))
+                .map_err(|_| NetworkError::SendError)?;
+        }
 
-                Ok(_) => unimplemented!(),
-                _ => break,
+        if relay.check_item_use(&game.emulator.mem) && relay.item_rule != ItemRule::Allow {
+            self.update_sender
+                .send(NetworkEvent::BattleItemUsed(relay.opponent, self.id))
+                .map_err(|_| NetworkError::SendError)?;
+            if relay.item_rule == ItemRule::Forfeit {
+                interface.forfeit_battle(&mut game.emulator.mem, Loser::Player);
+                game.chat_box.add_notice("You used an item, and forfeited the battle");
+            }
+            else {
+                game.chat_box.add_notice("Items are not allowed in this battle!");
             }
         }
+        Ok(())
+    }
+
+    /// Ask the server to start a Safari Zone event, and tell it about catches made during one
+    fn update_safari(&mut self, game: &mut Game) -> NetworkResult<()> {
+        if mem::take(&mut game.safari.start_requested) {
+            self.update_sender
+                .send(NetworkEvent::SafariStart(self.id))
+                .map_err(|_| NetworkError::SendError)?;
+        }
+
+        // Catches add to the party or the box, so they are only checked for once either changes
+        if !mem::take(&mut game.changes.party) {
+            return Ok(());
+        }
+        let map_id = game.player_data.movement_data.map_id;
+        if let Some(species) = game.safari.check_catch(&game.emulator.mem, map_id) {
+            self.update_sender
+                .send(NetworkEvent::SafariCatch(self.id, species))
+                .map_err(|_| NetworkError::SendError)?;
+        }
+        Ok(())
+    }
 
+    fn update_trades(&mut self, game: &mut Game) -> NetworkResult<()> {
+        let event = match game.trade_board.request.take() {
+            Some(TradeRequest::Refresh) => NetworkEvent::TradeBoardRequest(self.id),
+            Some(TradeRequest::Post { species, level, wanted }) => {
+                NetworkEvent::TradeListingPost(self.id, species, level, wanted)
+            }
+            Some(TradeRequest::Remove) => NetworkEvent::TradeListingRemove(self.id),
+            Some(TradeRequest::Offer { to, species, level }) => {
+                game.chat_box.add_notice(&format!("Sent your offer to #{}", to));
+                NetworkEvent::TradeOffer(to, self.id, species, level)
+            }
+            None => return Ok(()),
+        };
+        self.update_sender.send(event).map_err(|_| NetworkError::SendError)
+    }
+
+    /// Let the server know what was bought from its shop
+    fn report_purchases(&mut self, game: &mut Game) -> NetworkResult<()> {
+        for (item, quantity) in game.shop.purchases.drain(..) {
+            self.update_sender
+                .send(NetworkEvent::ShopPurchase(self.id, item, quantity))
+                .map_err(|_| NetworkError::SendError)?;
+        }
         Ok(())
     }
 
+    /// The name to show for a player in the chat
+    fn player_name(&self, game: &Game, interface_data: &InterfaceData, id: PlayerId) -> Vec<u8> {
+        match interface_data.players.get(&id) {
+            Some(player) => {
+                notes::display_name(&game.config.borrow(), &self.server_name, id, player)
+            }
+            None => text::Encoder::new("UNKNOWN").collect(),
+        }
+    }
+
+    fn show_leaderboard(
+        &self,
+        status: &SafariStatus,
+        interface_data: &InterfaceData,
+        chat_box: &mut ChatBox,
+    ) {
+        if status.leaderboard.is_empty() {
+            chat_box.add_notice("Nobody caught anything");
+        }
+        for (rank, &(id, catches)) in status.leaderboard.iter().enumerate() {
+            let name = match interface_data.players.get(&id) {
+                Some(player) => text::decode(&player.name),
+                None if id == self.id => "YOU".to_string(),
+                None => "UNKNOWN".to_string(),
+            };
+            chat_box.add_notice(&format!("{}. {} {}", rank + 1, name, catches));
+        }
+    }
+
+    /// Pause a relayed battle while the opponent is not responding, and end it if they do not come
+    /// back
+    fn check_connection(&mut self, interface_data: &mut InterfaceData, chat_box: &mut ChatBox) {
+        let relay = match &mut interface_data.battle_relay {
+            Some(relay) => relay,
+            None => return,
+        };
+
+        let silence = relay.last_heard.elapsed();
+        if silence > BATTLE_PAUSE_TIMEOUT {
+            println!("Relayed battle timed out");
+            interface_data.battle_relay = None;
+            interface_data.state = InterfaceState::Normal;
+            chat_box.add_notice("The opponent's connection was lost, you win the battle!");
+        }
+        else if silence > BATTLE_PAUSE_THRESHOLD {
+            relay.paused = true;
+        }
+        else if relay.paused {
+            relay.paused = false;
+            if self.outgoing.is_idle() {
+                interface_data.state = InterfaceState::Normal;
+            }
+            chat_box.add_notice("The opponent's connection has recovered");
+        }
+    }
+
+    /// Remove the other players, who can no longer be seen or battled, and let the player know
+    fn connection_lost(&mut self, interface_data: &mut InterfaceData, chat_box: &mut ChatBox) {
+        println!("Connection to the server was lost");
+        self.connected = false;
+        interface_data.players.clear();
+        let reason = "Battle cancelled, the connection to the server was lost";
+        self.cancel_battle(interface_data, chat_box, reason);
+        if interface_data.battle_relay.take().is_some() {
+            // The game chooses the enemy's moves for the rest of the battle
+            interface_data.state = InterfaceState::Normal;
+        }
+        chat_box.add_notice("Connection to the server was lost");
+    }
+
+    /// Answer a request from another player with the away message, while on autopilot, or with
+    /// `DECLINED_MESSAGE` on servers where requests are turned down
+    fn send_away_message(&mut self, game: &Game, to: PlayerId) -> NetworkResult<()> {
+        let msg = match game.server_settings.auto_decline {
+            true => DECLINED_MESSAGE.to_string(),
+            false => game.config.borrow().away_message.chars().take(MAX_CHAT_LENGTH).collect(),
+        };
+        self.update_sender
+            .send(NetworkEvent::AwayMessage(to, self.id, msg))
+            .map_err(|_| NetworkError::SendError)
+    }
+
+    /// Give up on the battle that this player requested, so that the game is not left waiting for
+    /// an opponent that has disconnected or for a request that the server refused.
+    fn cancel_battle(
+        &mut self,
+        interface_data: &mut InterfaceData,
+        chat_box: &mut ChatBox,
+        reason: &str,
+    ) {
+        if self.outgoing.cancel().is_some() {
+            interface_data.state = InterfaceState::Normal;
+            chat_box.add_notice(reason);
+        }
+    }
+
     pub fn send_message(&mut self, game: &mut Game) -> NetworkResult<()> {
         let msg = game.chat_box.get_message_buffer();
-        let user_name = game.player_data.name.clone();
+        if msg.trim() == FORFEIT_COMMAND {
+            return self.forfeit_battle(game);
+        }
+        let command = notes::run_command(&mut game.config.borrow_mut(), &self.server_name, &msg);
+        if let Some(notice) = command {
+            game.chat_box.add_notice(&notice);
+            return Ok(());
+        }
+        if self.lite_mode {
+            game.chat_box.add_notice("Chat is off in lite mode");
+            return Ok(());
+        }
+        let mut user_name = game.own_name();
+
+        let (msg, battle) = match spectate::battle_chat(&msg) {
+            Some(text) => match game.spectating.battle_channel(self.id) {
+                Some(battle) => {
+                    user_name.extend(text::Encoder::new(spectate::BATTLE_CHAT_TAG));
+                    (text.to_string(), Some(battle))
+                }
+                None => {
+                    game.chat_box.add_notice("You are not watching a battle, or being watched");
+                    return Ok(());
+                }
+            },
+            None => (msg, None),
+        };
+
+        game.usage.record(Feature::Chat);
+        let index =
+            game.chat_box.add_pending_message(user_name, text::Encoder::new(&msg).collect());
+        let id = self.next_chat_id;
+        self.next_chat_id = self.next_chat_id.wrapping_add(1);
+        let pending = PendingChat { id, msg, battle, index, sent_at: Instant::now(), attempts: 1 };
+        self.update_sender.send(pending.event(self.id)).map_err(|_| NetworkError::SendError)?;
+        self.pending_chat.push_back(pending);
+
+        Ok(())
+    }
+
+    /// Concede the relayed battle, and let the opponent know that they have won
+    fn forfeit_battle(&mut self, game: &mut Game) -> NetworkResult<()> {
+        game.usage.record(Feature::Forfeit);
+        let opponent =
+            game.interface.borrow_mut().forfeit_battle(&mut game.emulator.mem, Loser::Player);
+        match opponent {
+            Some(opponent) => {
+                game.chat_box.add_notice("You forfeited the battle");
+                self.update_sender
+                    .send(NetworkEvent::BattleForfeit(opponent, self.id))
+                    .map_err(|_| NetworkError::SendError)
+            }
+            None => {
+                game.chat_box.add_notice("You can only forfeit a relayed battle");
+                Ok(())
+            }
+        }
+    }
 
-        game.chat_box.add_message(user_name, text::Encoder::new(&msg).collect());
+    /// Send a quick chat phrase to the opponent of a relayed battle. The chat box is not opened, so
+    /// the player can keep choosing moves.
+    fn send_quick_chat(&mut self, game: &mut Game, phrase: u8) -> NetworkResult<()> {
+        let opponent = match &game.interface.borrow().data.battle_relay {
+            Some(relay) => relay.opponent,
+            None => {
+                game.chat_box.add_notice("Quick chat can only be used in a relayed battle");
+                return Ok(());
+            }
+        };
+        game.usage.record(Feature::QuickChat);
+        let msg = QUICK_CHAT_PHRASES[phrase as usize];
+        game.chat_box.add_message(game.own_name(), text::Encoder::new(msg).collect());
         self.update_sender
-            .send(NetworkEvent::Chat(self.id, msg))
-            .map_err(|_| NetworkError::SendError)?;
+            .send(NetworkEvent::BattleQuickChat(opponent, self.id, phrase))
+            .map_err(|_| NetworkError::SendError)
+    }
+
+    /// Send chat messages again if the server has not confirmed them, giving up after a few tries
+    fn retry_chat(&mut self, game: &mut Game) -> NetworkResult<()> {
+        for pending in &mut self.pending_chat {
+            if pending.sent_at.elapsed() < CHAT_ACK_TIMEOUT
+                || pending.attempts >= CHAT_SEND_ATTEMPTS
+            {
+                continue;
+            }
+            pending.sent_at = Instant::now();
+            pending.attempts += 1;
+            self.update_sender.send(pending.event(self.id)).map_err(|_| NetworkError::SendError)?;
+        }
 
+        let chat_box = &mut game.chat_box;
+        self.pending_chat.retain(|pending| {
+            let failed = pending.attempts >= CHAT_SEND_ATTEMPTS
+                && pending.sent_at.elapsed() >= CHAT_ACK_TIMEOUT;
+            if failed {
+                chat_box.set_delivery(pending.index, Delivery::Failed);
+            }
+            !failed
+        });
         Ok(())
     }
 }
+
+/// Show a desktop notification if the player is away from the game, and has them turned on
+fn notify_if_away(game: &Game, body: &str) {
+    if game.config.borrow().notifications && game.is_away() {
+        notify::show("Pikemon", body);
+    }
+}
+
+/// The time between updates for a configured rate, keeping it within the limits of the server
+fn update_interval(rate: u32, min_rate: u32) -> Duration {
+    match rate.clamp(min_rate, MAX_UPDATE_RATE) {
+        // Update every frame, rather than risk skipping frames that are slightly too short
+        MAX_UPDATE_RATE => Duration::ZERO,
+        rate => Duration::from_secs(1) / rate,
+    }
+}
+
+/// Check whether enough time has passed since something was last done to do it again
+fn is_due(last_time: Option<Instant>, interval: Duration) -> bool {
+    match last_time {
+        Some(time) => time.elapsed() >= interval,
+        None => true,
+    }
+}
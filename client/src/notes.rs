@@ -0,0 +1,60 @@
+//! Private nicknames and notes that players can attach to each other, to remember who is who, and
+//! the players they have marked as friends
+use interface::{
+    data::{PlayerData, MAX_NAME_LENGTH},
+    text, PlayerId,
+};
+
+use crate::config::{self, Config};
+
+/// The name to show for another player, which is the nickname given to them if there is one
+pub fn display_name(config: &Config, server: &str, id: PlayerId, player: &PlayerData) -> Vec<u8> {
+    match config.player_note(server, id) {
+        Some(note) if !note.nickname.is_empty() => text::Encoder::new(&note.nickname).collect(),
+        _ => player.name.clone(),
+    }
+}
+
+/// How each of the commands run by `run_command` is used, as shown on the help screen
+pub const COMMANDS: [&str; 3] = ["/nick ID NAME", "/note ID TEXT", "/friend ID"];
+
+/// Run a chat command that edits a player's nickname (`/nick ID NAME`) or note (`/note ID TEXT`).
+/// Leaving out the text clears it. `/friend ID` marks a player as a friend, or unmarks them if they
+/// already are one. Returns a notice to show to the player, or None if the message is not one of
+/// these commands.
+pub fn run_command(config: &mut Config, server: &str, msg: &str) -> Option<String> {
+    let (command, args) = msg.trim().split_once(' ').unwrap_or((msg.trim(), ""));
+    let usage = COMMANDS.iter().find(|usage| usage.split(' ').next() == Some(command))?;
+
+    let (id, value) = args.trim().split_once(' ').unwrap_or((args.trim(), ""));
+    let id: PlayerId = match id.trim_start_matches('#').parse() {
+        Ok(id) => id,
+        Err(_) => return Some(format!("Usage: {}", usage)),
+    };
+    let value = value.trim();
+
+    let mut note = config.player_note(server, id).cloned().unwrap_or_default();
+    if command == "/friend" {
+        note.friend = !note.friend;
+        let notice = match note.friend {
+            true => format!("#{} is now a friend", id),
+            false => format!("#{} is no longer a friend", id),
+        };
+        config.set_player_note(server, id, note);
+        config::save_config(config);
+        return Some(notice);
+    }
+    let field = if command == "/nick" {
+        note.nickname = value.chars().take(MAX_NAME_LENGTH).collect();
+        "nickname"
+    }
+    else {
+        note.note = value.to_string();
+        "note"
+    };
+    config.set_player_note(server, id, note);
+    config::save_config(config);
+
+    let action = if value.is_empty() { "Cleared" } else { "Saved" };
+    Some(format!("{} the {} for #{}", action, field, id))
+}
@@ -0,0 +1,63 @@
+//! Desktop notifications, to let players know about events in the game while they are away
+use std::{process::Command, thread};
+
+/// Show a desktop notification. This uses the notification tool that comes with each platform, and
+/// runs in the background. Failures are only logged, since notifications are not essential.
+pub fn show(title: &str, body: &str) {
+    let mut command = notification_command(title, body);
+    thread::spawn(move || {
+        if let Err(e) = command.status() {
+            println!("Failed to show notification: {}", e);
+        }
+    });
+}
+
+#[cfg(target_os = "macos")]
+fn notification_command(title: &str, body: &str) -> Command {
+    let quote = |text: &str| format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""));
+    let script = format!("display notification {} with title {}", quote(body), quote(title));
+
+    let mut command = Command::new("osascript");
+    command.args(["-e", &script]);
+    command
+}
+
+#[cfg(target_os = "windows")]
+fn notification_command(title: &str, body: &str) -> Command {
+    use std::os::windows::process::CommandExt;
+
+    /// Don't flash a console window while the notification is shown
+    const CREATE_NO_WINDOW: u32 = 0x08000000;
+
+    // Toasts must come from a registered application, so PowerShell's id is used
+    const APP_ID: &str =
+        r"{1AC14E77-02E7-4E5D-B744-2EB1AE5198B7}\WindowsPowerShell\v1.0\powershell.exe";
+
+    let quote = |text: &str| format!("'{}'", text.replace('\'', "''"));
+    let script = format!(
+        "[Windows.UI.Notifications.ToastNotificationManager, Windows.UI.Notifications, \
+            ContentType = WindowsRuntime] > $null
+        $kind = [Windows.UI.Notifications.ToastTemplateType]::ToastText02
+        $toast = [Windows.UI.Notifications.ToastNotificationManager]::GetTemplateContent($kind)
+        $text = $toast.GetElementsByTagName('text')
+        $text.Item(0).AppendChild($toast.CreateTextNode({})) > $null
+        $text.Item(1).AppendChild($toast.CreateTextNode({})) > $null
+        $notification = [Windows.UI.Notifications.ToastNotification]::new($toast)
+        [Windows.UI.Notifications.ToastNotificationManager]::CreateToastNotifier({}).Show($notification)",
+        quote(title),
+        quote(body),
+        quote(APP_ID),
+    );
+
+    let mut command = Command::new("powershell");
+    command.args(["-NoProfile", "-Command", &script]).creation_flags(CREATE_NO_WINDOW);
+    command
+}
+
+/// Other platforms use the freedesktop notification service
+#[cfg(not(any(target_os = "macos", target_os = "windows")))]
+fn notification_command(title: &str, body: &str) -> Command {
+    let mut command = Command::new("notify-send");
+    command.args(["--app-name=Pikemon", title, body]);
+    command
+}
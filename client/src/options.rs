@@ -0,0 +1,134 @@
+//! Menu for changing general settings
+use interface::relay::ItemRule;
+use macroquad::prelude::KeyCode;
+
+use crate::{
+    border::BorderRenderer,
+    common::{Rect, Renderer},
+    config::{self, Config},
+    font::Font,
+    menu::ItemBox,
+};
+
+pub struct OptionsMenu<'a> {
+    item_box: ItemBox<'a>,
+}
+
+impl<'a> OptionsMenu<'a> {
+    pub fn new(
+        config: &Config,
+        font: &'a Font,
+        border: &'a BorderRenderer,
+        rect: Rect,
+    ) -> OptionsMenu<'a> {
+        let mut options_menu = OptionsMenu { item_box: ItemBox::new(vec![], font, border, rect) };
+        options_menu.refresh(config);
+        options_menu
+    }
+
+    fn refresh(&mut self, config: &Config) {
+        let on_off = |value| if value { "ON" } else { "OFF" };
+        self.item_box.set_items(vec![
+            format!("REDUCE FLASHING {}", on_off(config.video.reduce_flashing)),
+            format!("FILTER {}", config.video.filter.name()),
+            format!("PLAYER TRAILS {}", on_off(config.video.player_trails)),
+            format!("PLAYER LIMIT {}", player_limit_name(config.video.max_drawn_players)),
+            format!("TOUCH JOYPAD {}", on_off(config.touch_joypad)),
+            format!("BATTLE MODE {}", config.battle_mode.name()),
+            format!("BATTLE ITEMS {}", item_rule_name(config.item_rule)),
+            format!("SHARE HALL OF FAME {}", on_off(config.share_hall_of_fame)),
+            format!("SHARE ENCOUNTERS {}", on_off(config.share_encounters)),
+            format!("NOTIFICATIONS {}", on_off(config.notifications)),
+            format!("PRIVACY MODE {}", on_off(config.privacy_mode)),
+            format!("AWAY AUTOPILOT {}", on_off(config.away_autopilot)),
+            format!("SHOW PLAYERS {}", config.visibility.name()),
+            format!("CHAT PANEL {}", config.layout.chat.name()),
+            format!("SPECTATOR PANEL {}", config.layout.spectator.name()),
+            format!("SHARE USAGE STATS {}", on_off(config.share_usage_stats)),
+            format!("CHAT FONT SIZE {}X", config.chat_font.scale()),
+            format!("HIGH CONTRAST CHAT {}", on_off(config.chat_font.high_contrast)),
+            format!("SPECTATORS {}", config.spectators.name()),
+            "BACK".to_string(),
+        ]);
+    }
+
+    pub fn draw(&self, renderer: &mut Renderer) {
+        self.item_box.draw(renderer);
+    }
+
+    pub fn key_down(&mut self, keycode: KeyCode) {
+        match keycode {
+            KeyCode::Up => self.item_box.move_up(),
+            KeyCode::Down => self.item_box.move_down(),
+            _ => {}
+        }
+    }
+
+    /// Handles a key release, returning true if the menu should be closed
+    pub fn key_up(&mut self, keycode: KeyCode, config: &mut Config) -> bool {
+        match keycode {
+            KeyCode::Escape => return true,
+            KeyCode::Enter => match self.item_box.selection() {
+                0 => config.video.reduce_flashing = !config.video.reduce_flashing,
+                1 => config.video.filter = config.video.filter.next(),
+                2 => config.video.player_trails = !config.video.player_trails,
+                3 => {
+                    config.video.max_drawn_players =
+                        next_player_limit(config.video.max_drawn_players)
+                }
+                4 => config.touch_joypad = !config.touch_joypad,
+                5 => config.battle_mode = config.battle_mode.next(),
+                6 => config.item_rule = next_item_rule(config.item_rule),
+                7 => config.share_hall_of_fame = !config.share_hall_of_fame,
+                8 => config.share_encounters = !config.share_encounters,
+                9 => config.notifications = !config.notifications,
+                10 => config.privacy_mode = !config.privacy_mode,
+                11 => config.away_autopilot = !config.away_autopilot,
+                12 => config.visibility = config.visibility.next(),
+                13 => config.layout.chat = config.layout.chat.next(),
+                14 => config.layout.spectator = config.layout.spectator.next(),
+                15 => config.share_usage_stats = !config.share_usage_stats,
+                16 => config.chat_font.scale = config.chat_font.next_scale(),
+                17 => config.chat_font.high_contrast = !config.chat_font.high_contrast,
+                18 => config.spectators = config.spectators.next(),
+                _ => return true,
+            },
+            _ => return false,
+        }
+
+        config::save_config(config);
+        self.refresh(config);
+        false
+    }
+}
+
+/// The choices for the most other players to draw at once
+const PLAYER_LIMITS: [Option<usize>; 4] = [None, Some(4), Some(8), Some(16)];
+
+fn player_limit_name(limit: Option<usize>) -> String {
+    match limit {
+        Some(limit) => limit.to_string(),
+        None => "OFF".to_string(),
+    }
+}
+
+fn next_player_limit(limit: Option<usize>) -> Option<usize> {
+    let index = PLAYER_LIMITS.iter().position(|&l| l == limit).unwrap_or(0);
+    PLAYER_LIMITS[(index + 1) % PLAYER_LIMITS.len()]
+}
+
+fn item_rule_name(item_rule: ItemRule) -> &'static str {
+    match item_rule {
+        ItemRule::Allow => "ALLOW",
+        ItemRule::Warn => "WARN",
+        ItemRule::Forfeit => "FORFEIT",
+    }
+}
+
+fn next_item_rule(item_rule: ItemRule) -> ItemRule {
+    match item_rule {
+        ItemRule::Allow => ItemRule::Warn,
+        ItemRule::Warn => ItemRule::Forfeit,
+        ItemRule::Forfeit => ItemRule::Allow,
+    }
+}
@@ -0,0 +1,131 @@
+//! Where the client keeps its files, so that it works the same however it is launched
+//!
+//! The config file is kept in the platform's config directory, and everything else that the client
+//! writes (downloaded definitions, crash reports and replays) in its data directory:
+//!
+//! - Linux and other Unix systems: `$XDG_CONFIG_HOME/pikemon` and `$XDG_DATA_HOME/pikemon`, which
+//!   default to `~/.config/pikemon` and `~/.local/share/pikemon`
+//! - Windows: `%APPDATA%\Pikemon` for both
+//! - macOS: `~/Library/Application Support/Pikemon` for both
+//!
+//! Setting `PIKEMON_HOME` keeps everything in that directory instead, e.g. `PIKEMON_HOME=.` to keep
+//! a portable copy in one folder. The working directory is used if the platform's directories can
+//! not be found. Older versions kept these files in the working directory, and `migrate` moves them
+//! from there.
+use std::{
+    env, fs, io,
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
+
+use crate::{
+    config::{self, Config},
+    crash, definitions, replays, usage,
+};
+
+struct Dirs {
+    config: PathBuf,
+    data: PathBuf,
+}
+
+fn dirs() -> &'static Dirs {
+    static DIRS: OnceLock<Dirs> = OnceLock::new();
+    DIRS.get_or_init(find_dirs)
+}
+
+fn find_dirs() -> Dirs {
+    let var = |name: &str| env::var_os(name).filter(|value| !value.is_empty()).map(PathBuf::from);
+    if let Some(dir) = var("PIKEMON_HOME") {
+        return Dirs { config: dir.clone(), data: dir };
+    }
+
+    let home = var("HOME");
+    let (config, data) = if cfg!(windows) {
+        let app_data = var("APPDATA").map(|dir| dir.join("Pikemon"));
+        (app_data.clone(), app_data)
+    }
+    else if cfg!(target_os = "macos") {
+        let support = home.map(|home| home.join("Library/Application Support/Pikemon"));
+        (support.clone(), support)
+    }
+    else {
+        let config = var("XDG_CONFIG_HOME").or_else(|| home.as_ref().map(|h| h.join(".config")));
+        let data = var("XDG_DATA_HOME").or_else(|| home.as_ref().map(|h| h.join(".local/share")));
+        (config.map(|dir| dir.join("pikemon")), data.map(|dir| dir.join("pikemon")))
+    };
+
+    let current = PathBuf::from(".");
+    Dirs { config: config.unwrap_or_else(|| current.clone()), data: data.unwrap_or(current) }
+}
+
+pub fn config_dir() -> &'static Path {
+    &dirs().config
+}
+
+pub fn data_dir() -> &'static Path {
+    &dirs().data
+}
+
+/// Create the client's directories, and move in the files that older versions left in the working
+/// directory. Files that are already in the new place are not replaced.
+pub fn migrate() {
+    for dir in [config_dir(), data_dir()] {
+        if let Err(e) = fs::create_dir_all(dir) {
+            println!("Failed to create {}: {}", dir.display(), e);
+        }
+    }
+
+    let legacy = [
+        (config::CONFIG_FILE, config_dir()),
+        (definitions::DEFINITIONS_FILE, data_dir()),
+        (crash::REPORT_DIR, data_dir()),
+        (replays::REPLAY_DIR, data_dir()),
+        (usage::CRASH_FILE, data_dir()),
+    ];
+    for (name, dir) in legacy {
+        let (from, to) = (Path::new(name), dir.join(name));
+        if !from.exists() || to.exists() || same_dir(dir, Path::new(".")) {
+            continue;
+        }
+        if let Err(e) = move_path(from, &to) {
+            println!("Failed to move {} to {}: {}", name, to.display(), e);
+            continue;
+        }
+        println!("Moved {} to {}", name, to.display());
+
+        // Paths in the old config were relative to the working directory, which is not always the
+        // same from now on
+        if name == config::CONFIG_FILE {
+            let mut config = Config::load(&to);
+            if let Ok(current) = env::current_dir() {
+                if config.make_paths_absolute(&current) {
+                    config::save_config(&config);
+                }
+            }
+        }
+    }
+}
+
+fn same_dir(a: &Path, b: &Path) -> bool {
+    matches!((fs::canonicalize(a), fs::canonicalize(b)), (Ok(a), Ok(b)) if a == b)
+}
+
+/// Move a file or directory, copying files if they can not simply be renamed, e.g. onto another
+/// drive
+fn move_path(from: &Path, to: &Path) -> io::Result<()> {
+    if fs::rename(from, to).is_ok() {
+        return Ok(());
+    }
+    if from.is_dir() {
+        fs::create_dir_all(to)?;
+        for entry in fs::read_dir(from)? {
+            let entry = entry?;
+            move_path(&entry.path(), &to.join(entry.file_name()))?;
+        }
+        fs::remove_dir(from)
+    }
+    else {
+        fs::copy(from, to)?;
+        fs::remove_file(from)
+    }
+}
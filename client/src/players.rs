@@ -0,0 +1,90 @@
+//! Menu listing the players connected to the server, and where they are
+use std::time::Duration;
+
+use interface::{data::PlayerData, maps, text, PlayerId};
+use macroquad::prelude::KeyCode;
+
+use crate::{
+    border::BorderRenderer,
+    common::{Rect, Renderer},
+    config::PlayerNote,
+    font::Font,
+    menu::ItemBox,
+};
+
+/// A player to show in the list
+pub struct PlayerEntry<'b> {
+    pub player: &'b PlayerData,
+    /// The id of the player, or None for the local player
+    pub id: Option<PlayerId>,
+    /// The number of times the player has entered the Hall of Fame, if they shared it
+    pub hall_of_fame: u8,
+    /// Whether the player's game is paused because they have left it
+    pub paused: bool,
+    /// The round trip time to the server, shown for the local player
+    pub latency: Option<Duration>,
+    pub note: Option<&'b PlayerNote>,
+}
+
+pub struct PlayerList<'a> {
+    item_box: ItemBox<'a>,
+}
+
+impl<'a> PlayerList<'a> {
+    pub fn new(font: &'a Font, border: &'a BorderRenderer, rect: Rect) -> PlayerList<'a> {
+        PlayerList { item_box: ItemBox::new(vec![], font, border, rect) }
+    }
+
+    /// Rebuild the list from the local player and the other players currently connected
+    pub fn refresh<'b>(&mut self, players: impl Iterator<Item = PlayerEntry<'b>>) {
+        let mut items: Vec<String> = players.map(|entry| player_entry(&entry)).collect();
+        items.push("BACK".to_string());
+        self.item_box.set_items(items);
+    }
+
+    pub fn draw(&self, renderer: &mut Renderer) {
+        self.item_box.draw(renderer);
+    }
+
+    pub fn key_down(&mut self, keycode: KeyCode) {
+        match keycode {
+            KeyCode::Up => self.item_box.move_up(),
+            KeyCode::Down => self.item_box.move_down(),
+            _ => {}
+        }
+    }
+
+    /// Handles a key release, returning true if the menu should be closed
+    pub fn key_up(&mut self, keycode: KeyCode) -> bool {
+        matches!(keycode, KeyCode::Escape | KeyCode::Enter)
+    }
+}
+
+fn player_entry(entry: &PlayerEntry) -> String {
+    let name = match entry.note {
+        Some(note) if !note.nickname.is_empty() => note.nickname.clone(),
+        _ => text::decode(&entry.player.name),
+    };
+    let location = maps::map_name(entry.player.movement_data.map_id).unwrap_or("Unknown");
+
+    let mut line = format!("{:<11} {}", name, location);
+    if entry.hall_of_fame > 0 {
+        line.push_str(" HOF");
+    }
+    if entry.paused {
+        line.push_str(" AWAY");
+    }
+    if let Some(latency) = entry.latency {
+        line.push_str(&format!(" {}MS", latency.as_millis()));
+    }
+    if entry.note.is_some_and(|note| note.friend) {
+        line.push_str(" FRIEND");
+    }
+    if let Some(id) = entry.id {
+        line.push_str(&format!(" #{}", id));
+    }
+    if let Some(note) = entry.note.filter(|note| !note.note.is_empty()) {
+        line.push_str(&format!(" - {}", note.note));
+    }
+    line
+}
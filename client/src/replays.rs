@@ -0,0 +1,411 @@
+//! Recordings of the local player's battles, and a menu for watching them again
+//!
+//! Each battle is saved to its own file in `REPLAY_DIR`, made from the Pokemon fighting whenever
+//! they changed, along with when the battle started, who it was against and how it ended. Only the
+//! most recent `MAX_REPLAYS` are kept.
+use std::{
+    ffi::OsStr,
+    fs,
+    path::{Path, PathBuf},
+    time::Instant,
+};
+
+use gb_emu::mmu::Memory;
+use interface::{
+    data::{BattleSnapshot, BattlerData},
+    extract, text,
+};
+use macroquad::prelude::KeyCode;
+use network_common::shared;
+
+use crate::{
+    border::BorderRenderer,
+    common::{Rect, Renderer},
+    font::{self, Font},
+    menu::ItemBox,
+    paths, spectate,
+};
+
+/// The replays' directory, in the client's data directory
+pub const REPLAY_DIR: &str = "replays";
+
+/// Where replays are exported to in `REPLAY_DIR`, as text that can be shared
+const EXPORT_DIR: &str = "exported";
+
+/// The most replays kept, after which the oldest are deleted
+const MAX_REPLAYS: usize = 50;
+
+/// The maximum number of replays listed, so that the list fits within the menu
+const MAX_LISTED: usize = 10;
+
+/// The speeds that replays can be played at
+const SPEEDS: [f32; 4] = [0.5, 1.0, 2.0, 4.0];
+
+#[derive(Copy, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum BattleResult {
+    Won,
+    Lost,
+    /// The battle ended without either Pokemon fainting, e.g. by running away
+    Ended,
+}
+
+impl BattleResult {
+    fn name(self) -> &'static str {
+        match self {
+            BattleResult::Won => "WON",
+            BattleResult::Lost => "LOST",
+            BattleResult::Ended => "ENDED",
+        }
+    }
+
+    /// How a battle ended, judged from the last Pokemon to fight
+    fn of(last: &BattleSnapshot) -> BattleResult {
+        if last.enemy.hp == 0 {
+            BattleResult::Won
+        }
+        else if last.player.hp == 0 {
+            BattleResult::Lost
+        }
+        else {
+            BattleResult::Ended
+        }
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct ReplayFrame {
+    /// The time since the start of the battle, in milliseconds
+    pub at: u32,
+    pub snapshot: BattleSnapshot,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct Replay {
+    /// When the battle started, in seconds since the Unix epoch
+    pub started_at: u64,
+    pub opponent: String,
+    pub result: BattleResult,
+    pub frames: Vec<ReplayFrame>,
+}
+
+impl Replay {
+    fn load(path: &Path) -> Result<Replay, String> {
+        let data =
+            fs::read(path).map_err(|e| format!("error reading {}: {}", path.display(), e))?;
+        serde_json::from_slice(&data)
+            .map_err(|e| format!("invalid replay {}: {}", path.display(), e))
+    }
+
+    fn save(&self) -> Result<(), String> {
+        fs::create_dir_all(replay_dir()).map_err(|e| e.to_string())?;
+        let data = serde_json::to_vec(self).map_err(|e| e.to_string())?;
+        let path = replay_dir().join(format!("{}.json", self.started_at));
+        fs::write(path, data).map_err(|e| e.to_string())
+    }
+
+    fn title(&self) -> String {
+        format!("{} {} {}", format_date(self.started_at), self.opponent, self.result.name())
+    }
+
+    /// The replay as lines of text, one for each change to the battle
+    fn export(&self, mem: &Memory) -> String {
+        let battler = |battler: &BattlerData| {
+            format!(
+                "{} L{} HP {}/{}",
+                text::decode(&extract::species_name(mem, battler.species)),
+                battler.level,
+                battler.hp,
+                battler.max_hp
+            )
+        };
+
+        let mut export = format!("{}\n", self.title());
+        for frame in &self.frames {
+            export.push_str(&format!(
+                "{:>6.1}s  {}  VS  {}\n",
+                frame.at as f32 / 1000.0,
+                battler(&frame.snapshot.player),
+                battler(&frame.snapshot.enemy)
+            ));
+        }
+        export
+    }
+}
+
+/// Records the local player's battles as they happen
+pub struct Recorder {
+    /// The battle being recorded, and when it started
+    current: Option<(Instant, Replay)>,
+}
+
+impl Recorder {
+    pub fn new() -> Recorder {
+        Recorder { current: None }
+    }
+
+    /// Record any change to the battle, saving the replay once the battle is over. `opponent` is
+    /// the name of the player being fought in a relayed battle.
+    pub fn update(&mut self, mem: &Memory, opponent: Option<String>) {
+        let snapshot = match extract::battle_snapshot(mem) {
+            // The Pokemon are not loaded until the battle has been introduced
+            Some(snapshot) if snapshot.enemy.max_hp != 0 => snapshot,
+            Some(_) => return,
+            None => {
+                if let Some((_, replay)) = self.current.take() {
+                    finish(replay);
+                }
+                return;
+            }
+        };
+
+        let (started, replay) = self.current.get_or_insert_with(|| {
+            let species = text::decode(&extract::species_name(mem, snapshot.enemy.species));
+            let replay = Replay {
+                started_at: shared::now_secs(),
+                opponent: species,
+                result: BattleResult::Ended,
+                frames: vec![],
+            };
+            (Instant::now(), replay)
+        });
+        if let Some(opponent) = opponent {
+            replay.opponent = opponent;
+        }
+        if replay.frames.last().map(|frame| frame.snapshot) != Some(snapshot) {
+            let at = started.elapsed().as_millis().try_into().unwrap_or(u32::MAX);
+            replay.frames.push(ReplayFrame { at, snapshot });
+        }
+    }
+}
+
+fn finish(mut replay: Replay) {
+    let last = match replay.frames.last() {
+        Some(frame) => frame.snapshot,
+        None => return,
+    };
+    replay.result = BattleResult::of(&last);
+    if let Err(e) = replay.save() {
+        println!("Error saving replay: {}", e);
+    }
+
+    for path in list_replays().iter().skip(MAX_REPLAYS) {
+        if let Err(e) = fs::remove_file(path) {
+            println!("Error deleting old replay {}: {}", path.display(), e);
+        }
+    }
+}
+
+fn replay_dir() -> PathBuf {
+    paths::data_dir().join(REPLAY_DIR)
+}
+
+/// The saved replays, newest first
+fn list_replays() -> Vec<PathBuf> {
+    let mut paths: Vec<PathBuf> = match fs::read_dir(replay_dir()) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension() == Some(OsStr::new("json")))
+            .collect(),
+        Err(_) => vec![],
+    };
+    paths.sort_by_key(|path| std::cmp::Reverse(replay_time(path)));
+    paths
+}
+
+fn replay_time(path: &Path) -> u64 {
+    path.file_stem().and_then(|stem| stem.to_str()).and_then(|stem| stem.parse().ok()).unwrap_or(0)
+}
+
+/// A date as YYYY-MM-DD, from seconds since the Unix epoch
+fn format_date(secs: u64) -> String {
+    // Converts days to a civil date, counting in 400 year eras that start on the 1st of March
+    let days = (secs / shared::SECONDS_PER_DAY) as i64 + 719468;
+    let era = days / 146097;
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!("{}-{:02}-{:02}", year, month, day)
+}
+
+struct Playback {
+    replay: Replay,
+    speed: usize,
+    /// How far into the battle the replay is, in milliseconds
+    position: f32,
+    last_update: Instant,
+}
+
+impl Playback {
+    fn current(&self) -> Option<&BattleSnapshot> {
+        let frames = self.replay.frames.iter().take_while(|frame| frame.at as f32 <= self.position);
+        frames.last().or(self.replay.frames.first()).map(|frame| &frame.snapshot)
+    }
+
+    fn finished(&self) -> bool {
+        self.replay.frames.iter().all(|frame| frame.at as f32 <= self.position)
+    }
+}
+
+enum View {
+    List,
+    /// Choosing what to do with the selected replay
+    Actions(PathBuf),
+    Playing(Playback),
+}
+
+pub struct ReplayMenu<'a> {
+    item_box: ItemBox<'a>,
+    action_box: ItemBox<'a>,
+    font: &'a Font,
+    border: &'a BorderRenderer,
+    rect: Rect,
+    replays: Vec<PathBuf>,
+    view: View,
+}
+
+impl<'a> ReplayMenu<'a> {
+    pub fn new(font: &'a Font, border: &'a BorderRenderer, rect: Rect) -> ReplayMenu<'a> {
+        let actions = ["PLAY", "DELETE", "EXPORT", "BACK"].map(String::from).to_vec();
+        ReplayMenu {
+            item_box: ItemBox::new(vec!["BACK".to_string()], font, border, rect),
+            action_box: ItemBox::new(actions, font, border, rect),
+            font,
+            border,
+            rect,
+            replays: vec![],
+            view: View::List,
+        }
+    }
+
+    /// Rebuild the list from the saved replays
+    pub fn refresh(&mut self) {
+        self.replays = list_replays();
+        self.replays.truncate(MAX_LISTED);
+
+        let mut items: Vec<String> = self
+            .replays
+            .iter()
+            .map(|path| match Replay::load(path) {
+                Ok(replay) => replay.title(),
+                Err(_) => format!("{} UNREADABLE", format_date(replay_time(path))),
+            })
+            .collect();
+        items.push("BACK".to_string());
+        self.item_box.set_items(items);
+        self.view = View::List;
+    }
+
+    /// Move the replay being played forward
+    pub fn update(&mut self) {
+        if let View::Playing(playback) = &mut self.view {
+            let elapsed = playback.last_update.elapsed().as_secs_f32() * 1000.0;
+            playback.position += elapsed * SPEEDS[playback.speed];
+            playback.last_update = Instant::now();
+        }
+    }
+
+    pub fn draw(&self, renderer: &mut Renderer, mem: &Memory) {
+        match &self.view {
+            View::List => self.item_box.draw(renderer),
+            View::Actions(_) => self.action_box.draw(renderer),
+            View::Playing(playback) => {
+                if let Some(snapshot) = playback.current() {
+                    spectate::draw_snapshot(
+                        renderer,
+                        self.font,
+                        self.border,
+                        mem,
+                        self.rect,
+                        snapshot,
+                    );
+                }
+                let status = if playback.finished() { "END" } else { "" };
+                let label = format!("REPLAY {}X {}", SPEEDS[playback.speed], status);
+                let y = self.rect.y + spectate::VIEW_LINES * self.font.line_height();
+                font::draw_label(renderer, self.font, self.border, label.trim(), self.rect.x, y);
+            }
+        }
+    }
+
+    pub fn key_down(&mut self, keycode: KeyCode) {
+        let item_box = match &mut self.view {
+            View::List => &mut self.item_box,
+            View::Actions(_) => &mut self.action_box,
+            View::Playing(_) => return,
+        };
+        match keycode {
+            KeyCode::Up => item_box.move_up(),
+            KeyCode::Down => item_box.move_down(),
+            _ => {}
+        }
+    }
+
+    /// Handle a key being released, returning true when the menu should be closed
+    pub fn key_up(&mut self, keycode: KeyCode, mem: &Memory) -> bool {
+        match &mut self.view {
+            View::List => match keycode {
+                KeyCode::Escape => return true,
+                KeyCode::Enter => match self.replays.get(self.item_box.selection()) {
+                    Some(path) => self.view = View::Actions(path.clone()),
+                    None => return true,
+                },
+                _ => {}
+            },
+
+            View::Actions(path) => match keycode {
+                KeyCode::Escape => self.view = View::List,
+                KeyCode::Enter => match self.action_box.selected_item() {
+                    "PLAY" => match Replay::load(path) {
+                        Ok(replay) => {
+                            self.view = View::Playing(Playback {
+                                replay,
+                                speed: 1,
+                                position: 0.0,
+                                last_update: Instant::now(),
+                            })
+                        }
+                        Err(e) => println!("{}", e),
+                    },
+                    "DELETE" => {
+                        if let Err(e) = fs::remove_file(path.as_path()) {
+                            println!("Error deleting replay {}: {}", path.display(), e);
+                        }
+                        self.refresh();
+                    }
+                    "EXPORT" => {
+                        if let Err(e) = export(path, mem) {
+                            println!("Error exporting replay {}: {}", path.display(), e);
+                        }
+                        self.view = View::List;
+                    }
+                    _ => self.view = View::List,
+                },
+                _ => {}
+            },
+
+            View::Playing(playback) => match keycode {
+                KeyCode::Escape => self.view = View::List,
+                KeyCode::Left => playback.speed = playback.speed.saturating_sub(1),
+                KeyCode::Right => playback.speed = (playback.speed + 1).min(SPEEDS.len() - 1),
+                // Start again from the beginning
+                KeyCode::Enter => playback.position = 0.0,
+                _ => {}
+            },
+        }
+        false
+    }
+}
+
+/// Write a replay as text to `EXPORT_DIR`, where it can be shared
+fn export(path: &Path, mem: &Memory) -> Result<(), String> {
+    let replay = Replay::load(path)?;
+    let export_dir = replay_dir().join(EXPORT_DIR);
+    fs::create_dir_all(&export_dir).map_err(|e| e.to_string())?;
+    let export_path = export_dir.join(format!("{}.txt", replay.started_at));
+    fs::write(export_path, replay.export(mem)).map_err(|e| e.to_string())
+}
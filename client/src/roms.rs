@@ -0,0 +1,81 @@
+//! Menu for switching to a different game without restarting the client
+use std::{ffi::OsStr, fs, path::PathBuf};
+
+use macroquad::prelude::KeyCode;
+
+use crate::{
+    border::BorderRenderer,
+    common::{Rect, Renderer},
+    font::Font,
+    menu::ItemBox,
+};
+
+/// The maximum number of ROMs listed, so that the list fits within the menu
+const MAX_ROMS: usize = 10;
+
+pub enum RomMenuAction {
+    None,
+    Close,
+    Load(PathBuf),
+}
+
+pub struct RomMenu<'a> {
+    item_box: ItemBox<'a>,
+    roms: Vec<PathBuf>,
+}
+
+impl<'a> RomMenu<'a> {
+    pub fn new(font: &'a Font, border: &'a BorderRenderer, rect: Rect) -> RomMenu<'a> {
+        RomMenu {
+            item_box: ItemBox::new(vec!["BACK".to_string()], font, border, rect),
+            roms: vec![],
+        }
+    }
+
+    /// Rebuild the list of ROMs from the files in the current directory
+    pub fn refresh(&mut self) {
+        self.roms = match fs::read_dir(".") {
+            Ok(entries) => entries
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|path| path.extension() == Some(OsStr::new("gb")))
+                .collect(),
+            Err(e) => {
+                println!("Error searching for ROMs: {}", e);
+                vec![]
+            }
+        };
+        self.roms.sort();
+        self.roms.truncate(MAX_ROMS);
+
+        let mut items: Vec<String> = self
+            .roms
+            .iter()
+            .map(|path| path.file_stem().unwrap_or_default().to_string_lossy().into_owned())
+            .collect();
+        items.push("BACK".to_string());
+        self.item_box.set_items(items);
+    }
+
+    pub fn draw(&self, renderer: &mut Renderer) {
+        self.item_box.draw(renderer);
+    }
+
+    pub fn key_down(&mut self, keycode: KeyCode) {
+        match keycode {
+            KeyCode::Up => self.item_box.move_up(),
+            KeyCode::Down => self.item_box.move_down(),
+            _ => {}
+        }
+    }
+
+    pub fn key_up(&mut self, keycode: KeyCode) -> RomMenuAction {
+        match keycode {
+            KeyCode::Escape => RomMenuAction::Close,
+            KeyCode::Enter => match self.roms.get(self.item_box.selection()) {
+                Some(path) => RomMenuAction::Load(path.clone()),
+                None => RomMenuAction::Close,
+            },
+            _ => RomMenuAction::None,
+        }
+    }
+}
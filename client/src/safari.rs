@@ -0,0 +1,81 @@
+//! Shared Safari Zone events, where players compete to catch the most Pokemon before the server's
+//! timer runs out
+use std::time::{Duration, Instant};
+
+use gb_emu::mmu::Memory;
+use interface::{extract, maps};
+use network_common::SafariStatus;
+
+use crate::{
+    border::BorderRenderer,
+    common::{Rect, Renderer},
+    font::{self, Font},
+};
+
+pub struct SafariEvent {
+    /// The latest status of the event, and when it was received
+    status: Option<(SafariStatus, Instant)>,
+    /// The number of Pokemon in the player's party and box when they were last checked
+    last_counts: Option<(usize, usize)>,
+    /// Whether the player has asked to start an event
+    pub start_requested: bool,
+}
+
+impl SafariEvent {
+    pub fn new() -> SafariEvent {
+        SafariEvent { status: None, last_counts: None, start_requested: false }
+    }
+
+    pub fn update_status(&mut self, status: SafariStatus) {
+        self.status = Some((status, Instant::now()));
+    }
+
+    /// The time left in the current event, or None if there is no event running
+    pub fn remaining(&self) -> Option<Duration> {
+        let (status, received_at) = self.status.as_ref()?;
+        Duration::from_secs(status.remaining_secs as u64)
+            .checked_sub(received_at.elapsed())
+            .filter(|remaining| !remaining.is_zero())
+    }
+
+    /// Check whether the player has caught a Pokemon in the Safari Zone during an event, returning
+    /// its species if they have
+    pub fn check_catch(&mut self, mem: &Memory, map_id: u8) -> Option<u8> {
+        let (party, pc_box) = (extract::party_species(mem), extract::box_species(mem));
+        let counts = (party.len(), pc_box.len());
+        let last_counts = self.last_counts.replace(counts)?;
+
+        if self.remaining().is_none() || !maps::SAFARI_ZONE.contains(&map_id) {
+            return None;
+        }
+
+        // Caught Pokemon are added to the party, or to the box once the party is full
+        if counts.0 > last_counts.0 {
+            party.last().copied()
+        }
+        else if counts.1 > last_counts.1 {
+            pc_box.last().copied()
+        }
+        else {
+            None
+        }
+    }
+
+    /// Draw the time left in the event in the corner of the screen
+    pub fn draw(
+        &self,
+        renderer: &mut Renderer,
+        font: &Font,
+        border: &BorderRenderer,
+        screen: Rect,
+    ) {
+        let remaining = match self.remaining() {
+            Some(remaining) => remaining.as_secs(),
+            None => return,
+        };
+
+        let label = format!("SAFARI {}:{:02}", remaining / 60, remaining % 60);
+        let y = screen.y + screen.height - 3 * font.line_height();
+        font::draw_label(renderer, font, border, &label, screen.x, y);
+    }
+}
@@ -1,39 +1,264 @@
 use std::{
     fs::{self, File},
     io::{self, prelude::*},
-    path::Path,
+    path::{Path, PathBuf},
 };
 
 use gb_emu::cart::SaveFile;
+use interface::rom::SaveFormat;
+use macroquad::{
+    color::{Color, BLACK, DARKGRAY, RED, WHITE},
+    input::{is_key_pressed, KeyCode},
+    text::draw_text,
+    window::{clear_background, next_frame},
+};
+use network_common::shared;
+
+const FONT_SIZE: f32 = 24.0;
+const LINE_HEIGHT: f32 = 32.0;
+const MARGIN: f32 = 32.0;
+
+/// Get the save file for a ROM, in the save folder if one is set or otherwise next to the ROM. Each
+/// local player uses their own save file.
+pub fn save_path(rom_path: &Path, save_dir: Option<&str>, local_player: usize) -> PathBuf {
+    let stem = rom_path.file_stem().unwrap_or_default().to_string_lossy();
+    let file_name = match local_player {
+        0 => format!("{}.sav", stem),
+        n => format!("{} ({}).sav", stem, n + 1),
+    };
+    match save_dir {
+        Some(dir) => Path::new(dir).join(file_name),
+        None => rom_path.with_file_name(file_name),
+    }
+}
+
+/// The sizes of the clock state that emulators add after the RAM of carts with a real time clock,
+/// with a 4 or 8 byte timestamp
+const RTC_FOOTER_SIZES: &[usize] = &[44, 48];
+
+/// Why a save file can not be used as it is
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SaveProblem {
+    /// There is no save file
+    Missing,
+    /// The save file has this many bytes, which is not the size of the cart's RAM
+    WrongSize(usize),
+}
 
-pub struct LocalSaveWrapper<'a> {
-    pub path: &'a Path,
+/// What to do about a save file that can not be used as it is
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum SaveChoice {
+    NewSave,
+    RestoreBackup,
+    Quit,
 }
 
-impl<'a> SaveFile for LocalSaveWrapper<'a> {
+impl SaveChoice {
+    fn label(&self) -> &'static str {
+        match self {
+            SaveChoice::NewSave => "Start a new game",
+            SaveChoice::RestoreBackup => "Restore the backup of the last save",
+            SaveChoice::Quit => "Quit",
+        }
+    }
+}
+
+pub struct LocalSaveWrapper {
+    pub path: PathBuf,
+    pub format: SaveFormat,
+    /// The clock state that followed the RAM in the save file. This is written back unchanged, so
+    /// that the clock is not lost when the save is used in another emulator.
+    rtc_footer: Vec<u8>,
+}
+
+impl LocalSaveWrapper {
+    pub fn new(path: PathBuf, format: SaveFormat) -> LocalSaveWrapper {
+        LocalSaveWrapper { path, format, rtc_footer: vec![] }
+    }
+
+    /// The previous save, which is kept each time the game saves
+    pub fn backup_path(&self) -> PathBuf {
+        self.path.with_extension("sav.bak")
+    }
+
+    /// Whether the save file is missing or does not fit the cart. Carts without RAM never have a
+    /// problem.
+    pub fn check(&self) -> Option<SaveProblem> {
+        if self.format.ram_size == 0 {
+            return None;
+        }
+        match fs::metadata(&self.path) {
+            Ok(metadata) if self.fits(metadata.len() as usize) => None,
+            Ok(metadata) => Some(SaveProblem::WrongSize(metadata.len() as usize)),
+            Err(_) => Some(SaveProblem::Missing),
+        }
+    }
+
+    /// Whether a file of `size` bytes is a save for the cart, with or without its clock state
+    fn fits(&self, size: usize) -> bool {
+        let ram_size = self.format.ram_size;
+        size == ram_size
+            || (self.format.rtc && RTC_FOOTER_SIZES.iter().any(|footer| size == ram_size + footer))
+    }
+
+    fn has_backup(&self) -> bool {
+        fs::metadata(self.backup_path()).is_ok_and(|metadata| self.fits(metadata.len() as usize))
+    }
+
+    /// Move a save file that does not fit the cart out of the way, so that it is not overwritten.
+    /// It is kept next to the save with the time it was moved, e.g.
+    /// `Pokemon Red.1700000000.sav.mismatched`, so that earlier ones are kept as well.
+    fn set_aside(&self) -> io::Result<()> {
+        let extension = format!("{}.sav.mismatched", shared::now_secs());
+        match fs::rename(&self.path, self.path.with_extension(extension)) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    /// Start from an empty save, keeping any save file that is already there
+    pub fn start_new(&self) -> io::Result<()> {
+        self.set_aside()
+    }
+
+    /// Replace the save file with the backup of the previous save
+    pub fn restore_backup(&self) -> io::Result<()> {
+        self.set_aside()?;
+        fs::copy(self.backup_path(), &self.path)?;
+        Ok(())
+    }
+}
+
+/// Check the save file before the game starts. If it is missing or does not fit the cart, the player
+/// is asked whether to start a new game or restore the backup, and nothing is asked of new players
+/// that have neither a save nor a backup. Returns false if the player chose to quit. Like the
+/// setup, this is drawn with macroquad's built-in font since the game's font has not been loaded.
+///
+/// Saves are not offered from servers. The same save is used on every server, and servers are run
+/// by anyone, so they are neither trusted with a player's save nor expected to keep one.
+pub async fn check_on_startup(save_file: &LocalSaveWrapper) -> bool {
+    let problem = match save_file.check() {
+        Some(problem) => problem,
+        None => return true,
+    };
+    let has_backup = save_file.has_backup();
+    if problem == SaveProblem::Missing && !has_backup {
+        println!("No save file at {}, starting a new game", save_file.path.display());
+        return true;
+    }
+
+    let mut lines = match problem {
+        SaveProblem::Missing => vec!["The save file is missing:".to_string()],
+        SaveProblem::WrongSize(size) => vec![
+            "The save file does not match this game:".to_string(),
+            format!("  {} bytes, but the game saves {}", size, save_file.format.ram_size),
+        ],
+    };
+    lines.insert(1, format!("  {}", save_file.path.display()));
+    if let SaveProblem::WrongSize(_) = problem {
+        lines.push("It will be kept with the extension .sav.mismatched.".to_string());
+    }
+    let mut choices = vec![SaveChoice::NewSave];
+    if has_backup {
+        choices.insert(0, SaveChoice::RestoreBackup);
+    }
+    choices.push(SaveChoice::Quit);
+
+    let mut selected: usize = 0;
+    let mut message = None;
+    loop {
+        if is_key_pressed(KeyCode::Up) {
+            selected = selected.saturating_sub(1);
+        }
+        if is_key_pressed(KeyCode::Down) {
+            selected = (selected + 1).min(choices.len() - 1);
+        }
+        if is_key_pressed(KeyCode::Escape) {
+            return false;
+        }
+        if is_key_pressed(KeyCode::Enter) {
+            let result = match choices[selected] {
+                SaveChoice::NewSave => save_file.start_new(),
+                SaveChoice::RestoreBackup => save_file.restore_backup(),
+                SaveChoice::Quit => return false,
+            };
+            match result {
+                Ok(()) => return true,
+                Err(e) => message = Some(format!("Failed: {}", e)),
+            }
+        }
+
+        clear_background(WHITE);
+        let mut y = MARGIN + LINE_HEIGHT;
+        let mut line = |text: &str, color: Color| {
+            draw_text(text, MARGIN, y, FONT_SIZE, color);
+            y += LINE_HEIGHT;
+        };
+        for text in &lines {
+            line(text, DARKGRAY);
+        }
+        line("", BLACK);
+        for (i, choice) in choices.iter().enumerate() {
+            let cursor = if i == selected { ">" } else { " " };
+            line(&format!("{} {}", cursor, choice.label()), BLACK);
+        }
+        if let Some(message) = &message {
+            line(message, RED);
+        }
+        line("", BLACK);
+        line("ENTER: choose    ESCAPE: quit", DARKGRAY);
+        next_frame().await
+    }
+}
+
+impl SaveFile for LocalSaveWrapper {
     fn load(&mut self, data: &mut [u8]) {
-        if let Ok(_) = File::open(&self.path).map(|mut f| f.read(data)) {
-            println!("Loaded {}", self.path.display());
+        let file = match fs::read(&self.path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return,
+            Err(e) => {
+                println!("Error reading save file {}: {}", self.path.display(), e);
+                return;
+            }
+        };
+
+        let (ram, footer) = file.split_at(file.len().min(data.len()));
+        data[..ram.len()].copy_from_slice(ram);
+        if data.len() < self.format.ram_size {
+            println!(
+                "Only {} of {} bytes of the save can be used",
+                data.len(),
+                self.format.ram_size
+            );
+        }
+        if self.format.rtc && RTC_FOOTER_SIZES.contains(&footer.len()) {
+            self.rtc_footer = footer.to_vec();
+        }
+        else if !footer.is_empty() {
+            println!("Ignoring {} extra bytes at the end of the save file", footer.len());
         }
+        println!("Loaded {}", self.path.display());
     }
 
     fn save(&mut self, data: &[u8]) {
         // First create a temporary file and write to that, to ensure that if an error occurs, the
         // old file is not lost.
         let tmp_path = self.path.with_extension("sav.tmp");
-        if let Err(e) = File::create(&tmp_path).map(|mut f| f.write_all(data)) {
+        let written = File::create(&tmp_path)
+            .and_then(|mut f| f.write_all(data).and_then(|_| f.write_all(&self.rtc_footer)));
+        if let Err(e) = written {
             println!("An error occured when writing the save file: {}", e);
             return;
         }
 
-        // At this stage the new save file has been successfully written, so we can safely remove
-        // the old file if it exists.
-        match fs::remove_file(&self.path) {
+        // At this stage the new save file has been successfully written, so the old file can be
+        // kept as the backup if it exists.
+        match fs::rename(&self.path, self.backup_path()) {
             Ok(_) => {}
             Err(ref e) if e.kind() == io::ErrorKind::NotFound => {}
             Err(e) => {
                 println!(
-                    "Error removing old save file ({}), current save has been written to: {}",
+                    "Error backing up old save file ({}), current save has been written to: {}",
                     e,
                     tmp_path.display()
                 );
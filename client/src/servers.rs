@@ -0,0 +1,71 @@
+//! Menu for switching to a different server without restarting the client
+use macroquad::prelude::KeyCode;
+
+use crate::{
+    border::BorderRenderer,
+    common::{Rect, Renderer},
+    config::Config,
+    font::Font,
+    menu::ItemBox,
+};
+
+/// The maximum number of servers listed, so that the list fits within the menu
+const MAX_SERVERS: usize = 10;
+
+pub enum ServerMenuAction {
+    None,
+    Close,
+    Connect(String),
+}
+
+pub struct ServerMenu<'a> {
+    item_box: ItemBox<'a>,
+    servers: Vec<String>,
+}
+
+impl<'a> ServerMenu<'a> {
+    pub fn new(font: &'a Font, border: &'a BorderRenderer, rect: Rect) -> ServerMenu<'a> {
+        ServerMenu {
+            item_box: ItemBox::new(vec!["BACK".to_string()], font, border, rect),
+            servers: vec![],
+        }
+    }
+
+    /// Rebuild the list from the servers that have been connected to, leaving out the current one
+    pub fn refresh(&mut self, config: &Config, current: &str) {
+        self.servers = config
+            .servers
+            .iter()
+            .map(|entry| entry.address.clone())
+            .filter(|address| address != current)
+            .take(MAX_SERVERS)
+            .collect();
+
+        let mut items = self.servers.clone();
+        items.push("BACK".to_string());
+        self.item_box.set_items(items);
+    }
+
+    pub fn draw(&self, renderer: &mut Renderer) {
+        self.item_box.draw(renderer);
+    }
+
+    pub fn key_down(&mut self, keycode: KeyCode) {
+        match keycode {
+            KeyCode::Up => self.item_box.move_up(),
+            KeyCode::Down => self.item_box.move_down(),
+            _ => {}
+        }
+    }
+
+    pub fn key_up(&mut self, keycode: KeyCode) -> ServerMenuAction {
+        match keycode {
+            KeyCode::Escape => ServerMenuAction::Close,
+            KeyCode::Enter => match self.servers.get(self.item_box.selection()) {
+                Some(address) => ServerMenuAction::Connect(address.clone()),
+                None => ServerMenuAction::Close,
+            },
+            _ => ServerMenuAction::None,
+        }
+    }
+}
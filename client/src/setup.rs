@@ -0,0 +1,238 @@
+//! Guided setup shown the first time the client is run
+//!
+//! Walks the player through choosing a ROM, where to keep save files, their sprite, keyboard layout
+//! and server, then writes the answers to the config file so that later launches need no arguments.
+//! The game's font is read from the ROM, so this is drawn with macroquad's built-in font instead.
+use std::{ffi::OsStr, fs, path::Path};
+
+use interface::values::PlayerSprite;
+use macroquad::{
+    color::{Color, BLACK, DARKGRAY, RED, WHITE},
+    input::{get_char_pressed, is_key_pressed, KeyCode},
+    text::draw_text,
+    window::{clear_background, next_frame},
+};
+use network_common::transport;
+
+use crate::{config::Config, input::KeyboardLayout};
+
+const FONT_SIZE: f32 = 24.0;
+const LINE_HEIGHT: f32 = 32.0;
+const MARGIN: f32 = 32.0;
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum Step {
+    Rom,
+    SaveDir,
+    Sprite,
+    Controls,
+    Server,
+}
+
+impl Step {
+    const ALL: [Step; 5] = [Step::Rom, Step::SaveDir, Step::Sprite, Step::Controls, Step::Server];
+
+    fn title(self) -> &'static str {
+        match self {
+            Step::Rom => "Where is your Pokemon Red ROM?",
+            Step::SaveDir => "Where should save files be kept?",
+            Step::Sprite => "How should other players see you?",
+            Step::Controls => "Which keyboard layout do you use?",
+            Step::Server => "Which server should be joined by default?",
+        }
+    }
+
+    fn help(self) -> &'static [&'static str] {
+        match self {
+            Step::Rom => &["Type the path to the ROM file."],
+            Step::SaveDir => {
+                &["Type a folder, or leave this empty to keep", "saves next to the ROM."]
+            }
+            Step::Sprite => &["Press LEFT or RIGHT to change your sprite."],
+            Step::Controls => &[
+                "Press LEFT or RIGHT to change the layout.",
+                "A and B are on the keys where Z and X are on a",
+                "QWERTY keyboard. Change them from CONTROLS in the menu.",
+            ],
+            Step::Server => {
+                &["Type the server's address. It can still be", "changed when launching."]
+            }
+        }
+    }
+}
+
+struct Setup {
+    step: usize,
+    rom_path: String,
+    save_dir: String,
+    sprite: PlayerSprite,
+    layout: KeyboardLayout,
+    server: String,
+    /// A problem with the current answer
+    message: Option<String>,
+    /// Whether the player was warned that the ROM is not supported, and can press Enter again to
+    /// use it anyway
+    rom_warned: bool,
+}
+
+/// Run the setup until the player has answered every step, returning the updated config
+pub async fn run(mut config: Config) -> Config {
+    let mut setup = Setup {
+        step: 0,
+        rom_path: config.rom_path.clone().unwrap_or_else(find_rom),
+        save_dir: config.save_dir.clone().unwrap_or_default(),
+        sprite: config.server_settings.sprite,
+        layout: config.keyboard_layout,
+        server: config.default_server.clone().unwrap_or_else(|| "localhost".to_string()),
+        message: None,
+        rom_warned: false,
+    };
+
+    while setup.step < Step::ALL.len() {
+        setup.update();
+        setup.draw();
+        next_frame().await
+    }
+
+    config.rom_path = Some(setup.rom_path);
+    config.save_dir = Some(setup.save_dir).filter(|dir| !dir.is_empty());
+    config.server_settings.sprite = setup.sprite;
+    config.keyboard_layout = setup.layout;
+    config.default_server = Some(setup.server);
+    // The client is not always started from the same directory
+    if let Ok(current) = std::env::current_dir() {
+        config.make_paths_absolute(&current);
+    }
+    config
+}
+
+/// The first ROM in the current directory, as a starting point for the path
+fn find_rom() -> String {
+    let mut roms: Vec<_> = fs::read_dir(".")
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension() == Some(OsStr::new("gb")))
+        .collect();
+    roms.sort();
+    roms.first().map_or_else(
+        || crate::DEFAULT_ROM_PATH.to_string(),
+        |path| path.to_string_lossy().into_owned(),
+    )
+}
+
+impl Setup {
+    fn current(&self) -> Step {
+        Step::ALL[self.step]
+    }
+
+    fn text_field(&mut self) -> Option<&mut String> {
+        match self.current() {
+            Step::Rom => Some(&mut self.rom_path),
+            Step::SaveDir => Some(&mut self.save_dir),
+            Step::Server => Some(&mut self.server),
+            Step::Sprite | Step::Controls => None,
+        }
+    }
+
+    fn update(&mut self) {
+        let mut edited = false;
+        while let Some(c) = get_char_pressed() {
+            if let Some(field) = self.text_field() {
+                if !c.is_control() {
+                    field.push(c);
+                    edited = true;
+                }
+            }
+        }
+        if is_key_pressed(KeyCode::Backspace) {
+            if let Some(field) = self.text_field() {
+                edited |= field.pop().is_some();
+            }
+        }
+        if edited {
+            self.rom_warned = false;
+        }
+
+        if is_key_pressed(KeyCode::Left) || is_key_pressed(KeyCode::Right) {
+            match self.current() {
+                Step::Sprite => self.sprite = self.sprite.next(),
+                Step::Controls => self.layout = self.layout.next(),
+                _ => {}
+            }
+        }
+
+        if is_key_pressed(KeyCode::Escape) && self.step > 0 {
+            self.step -= 1;
+            self.message = None;
+        }
+
+        if is_key_pressed(KeyCode::Enter) {
+            match self.check() {
+                Ok(()) => {
+                    self.step += 1;
+                    self.message = None;
+                }
+                Err(message) => self.message = Some(message),
+            }
+        }
+    }
+
+    /// Check the answer to the current step
+    fn check(&mut self) -> Result<(), String> {
+        match self.current() {
+            Step::Rom => {
+                let path = Path::new(&self.rom_path);
+                let rom = fs::read(path).map_err(|e| format!("Could not open the ROM: {}", e))?;
+                if !interface::rom::is_supported(&rom) && !self.rom_warned {
+                    self.rom_warned = true;
+                    return Err("This is not a supported game, press ENTER to use it anyway".into());
+                }
+                Ok(())
+            }
+            Step::SaveDir if !self.save_dir.is_empty() => fs::create_dir_all(&self.save_dir)
+                .map_err(|e| format!("Could not create the folder: {}", e)),
+            Step::Server if self.server.trim().is_empty() => {
+                Err("Enter the address of a server".to_string())
+            }
+            Step::Server => transport::parse_address(&self.server)
+                .map(|_| ())
+                .map_err(|e| format!("Invalid address: {}", e)),
+            _ => Ok(()),
+        }
+    }
+
+    fn draw(&self) {
+        clear_background(WHITE);
+        let step = self.current();
+
+        let mut y = MARGIN + LINE_HEIGHT;
+        let mut line = |text: &str, color: Color| {
+            draw_text(text, MARGIN, y, FONT_SIZE, color);
+            y += LINE_HEIGHT;
+        };
+
+        line(&format!("PIKEMON SETUP ({} of {})", self.step + 1, Step::ALL.len()), DARKGRAY);
+        line("", BLACK);
+        line(step.title(), BLACK);
+        for help in step.help() {
+            line(help, DARKGRAY);
+        }
+        line("", BLACK);
+
+        let answer = match step {
+            Step::Rom => format!("> {}_", self.rom_path),
+            Step::SaveDir => format!("> {}_", self.save_dir),
+            Step::Server => format!("> {}_", self.server),
+            Step::Sprite => format!("< {} >", self.sprite.name()),
+            Step::Controls => format!("< {} >", self.layout.name()),
+        };
+        line(&answer, BLACK);
+
+        if let Some(message) = &self.message {
+            line(message, RED);
+        }
+        line("", BLACK);
+        line("ENTER: next    ESCAPE: back", DARKGRAY);
+    }
+}
@@ -0,0 +1,153 @@
+//! The server's shop, where players spend the money they have in the game on items chosen by the
+//! server. Purchases are paid for and added to the bag by writing to the game's memory.
+use gb_emu::mmu::Memory;
+use interface::{data::MAX_ITEM_QUANTITY, extract, text};
+use macroquad::prelude::KeyCode;
+use network_common::ShopItem;
+
+use crate::{
+    border::BorderRenderer,
+    common::{Rect, Renderer},
+    font::Font,
+    menu::ItemBox,
+};
+
+/// What each line of the shop does when selected
+#[derive(Copy, Clone)]
+enum Row {
+    Money,
+    Quantity,
+    Item(ShopItem),
+    Back,
+}
+
+pub struct Shop<'a> {
+    item_box: ItemBox<'a>,
+    rows: Vec<Row>,
+    /// The items sold by the server, or none if it does not have a shop
+    items: Vec<ShopItem>,
+    /// How many of an item to buy at once
+    quantity: u8,
+    /// The result of the last purchase, shown in place of the player's money
+    message: Option<String>,
+    /// Purchases that are waiting to be reported to the server, as (item, quantity)
+    pub purchases: Vec<(u8, u8)>,
+}
+
+impl<'a> Shop<'a> {
+    pub fn new(font: &'a Font, border: &'a BorderRenderer, rect: Rect) -> Shop<'a> {
+        Shop {
+            item_box: ItemBox::new(vec![], font, border, rect),
+            rows: vec![],
+            items: vec![],
+            quantity: 1,
+            message: None,
+            purchases: vec![],
+        }
+    }
+
+    pub fn set_items(&mut self, items: Vec<ShopItem>) {
+        self.items = items;
+    }
+
+    pub fn is_open(&self) -> bool {
+        !self.items.is_empty()
+    }
+
+    pub fn open(&mut self, mem: &Memory) {
+        self.message = None;
+        self.refresh(mem);
+    }
+
+    fn refresh(&mut self, mem: &Memory) {
+        let mut rows = vec![Row::Money, Row::Quantity];
+        rows.extend(self.items.iter().map(|&item| Row::Item(item)));
+        rows.push(Row::Back);
+
+        let items = rows.iter().map(|&row| self.row_text(row, mem)).collect();
+        self.rows = rows;
+        self.item_box.set_items(items);
+    }
+
+    fn row_text(&self, row: Row, mem: &Memory) -> String {
+        match row {
+            Row::Money => match &self.message {
+                Some(message) => message.clone(),
+                None => format!("MONEY {}", extract::money(mem)),
+            },
+            Row::Quantity => format!("BUY {} AT A TIME", self.quantity),
+            Row::Item(item) => format!("{} {}", item_name(mem, item.item), item.price),
+            Row::Back => "BACK".to_string(),
+        }
+    }
+
+    pub fn draw(&self, renderer: &mut Renderer) {
+        self.item_box.draw(renderer);
+    }
+
+    pub fn key_down(&mut self, keycode: KeyCode, mem: &Memory) {
+        match keycode {
+            KeyCode::Up => self.item_box.move_up(),
+            KeyCode::Down => self.item_box.move_down(),
+            KeyCode::Left => self.step(-1, mem),
+            KeyCode::Right => self.step(1, mem),
+            _ => {}
+        }
+    }
+
+    /// Change the quantity to buy, if it is selected
+    fn step(&mut self, step: i32, mem: &Memory) {
+        if let Row::Quantity = self.rows[self.item_box.selection()] {
+            let quantity = self.quantity as i32 - 1 + step;
+            self.quantity = (quantity.rem_euclid(MAX_ITEM_QUANTITY as i32) + 1) as u8;
+            self.refresh(mem);
+        }
+    }
+
+    /// Handles a key release, returning true if the shop should be closed
+    pub fn key_up(&mut self, keycode: KeyCode, mem: &mut Memory) -> bool {
+        match keycode {
+            KeyCode::Escape => return true,
+            KeyCode::Enter => {}
+            _ => return false,
+        }
+
+        match self.rows[self.item_box.selection()] {
+            Row::Money => {}
+            Row::Quantity => self.step(1, mem),
+            Row::Item(item) => {
+                let message = match self.buy(mem, item) {
+                    Ok(()) => format!("BOUGHT {} {}", self.quantity, item_name(mem, item.item)),
+                    Err(e) => e.to_string(),
+                };
+                self.message = Some(message);
+            }
+            Row::Back => return true,
+        }
+        self.refresh(mem);
+        false
+    }
+
+    /// Pay for the selected quantity of an item and put it in the player's bag
+    fn buy(&mut self, mem: &mut Memory, item: ShopItem) -> Result<(), &'static str> {
+        // The bag is also used by the battle, and items can not be added to it then
+        if extract::in_battle(mem) {
+            return Err("NOT DURING A BATTLE");
+        }
+        let money = extract::money(mem);
+        let cost = item.price * self.quantity as u32;
+        if cost > money {
+            return Err("NOT ENOUGH MONEY");
+        }
+        if !interface::add_bag_item(mem, item.item, self.quantity) {
+            return Err("NO ROOM IN THE BAG");
+        }
+        interface::set_money(mem, money - cost);
+        self.purchases.push((item.item, self.quantity));
+        Ok(())
+    }
+}
+
+fn item_name(mem: &Memory, item: u8) -> String {
+    text::decode(&extract::item_name(mem, item))
+}
@@ -0,0 +1,143 @@
+//! Watching the battles of other players
+use std::collections::HashSet;
+
+use gb_emu::mmu::Memory;
+use interface::{
+    data::{BattleSnapshot, BattlerData},
+    extract, text, PlayerId,
+};
+use macroquad::prelude::{draw_rectangle, WHITE};
+
+use crate::{
+    border::BorderRenderer,
+    common::{Rect, Renderer},
+    font::{self, Font},
+};
+
+/// The chat command for sending a message to everyone in the battle being watched or played
+pub const BATTLE_CHAT_COMMAND: &str = "/b";
+
+/// Added to the names of players in battle chat messages
+pub const BATTLE_CHAT_TAG: &str = " (BATTLE)";
+
+/// The height of the view of a battle being watched, in lines of text
+pub const VIEW_LINES: i32 = 4;
+
+/// A change to the battle being watched, to be sent by the network client
+#[derive(Copy, Clone)]
+pub enum SpectateRequest {
+    Start(PlayerId),
+    Stop(PlayerId),
+}
+
+#[derive(Default)]
+pub struct Spectating {
+    /// Players that are watching the local player's battle
+    pub watchers: HashSet<PlayerId>,
+    /// The player whose battle is being watched, and the latest state of that battle
+    pub watching: Option<(PlayerId, Option<BattleSnapshot>)>,
+    /// The last nearby player to announce a rare encounter
+    pub last_announcement: Option<PlayerId>,
+    /// A request that is waiting to be sent
+    pub request: Option<SpectateRequest>,
+    /// The last state of the local player's battle that was sent to watchers
+    pub last_snapshot: Option<BattleSnapshot>,
+    /// Whether the local player's current encounter has been announced
+    pub announced: bool,
+}
+
+impl Spectating {
+    pub fn new() -> Spectating {
+        Spectating::default()
+    }
+
+    /// Start watching the battle of the last player to announce an encounter, or stop watching if a
+    /// battle is already being watched
+    pub fn toggle_watching(&mut self) {
+        match self.watching.take() {
+            Some((id, _)) => self.request = Some(SpectateRequest::Stop(id)),
+            None => {
+                if let Some(id) = self.last_announcement {
+                    self.watching = Some((id, None));
+                    self.request = Some(SpectateRequest::Start(id));
+                }
+            }
+        }
+    }
+
+    /// The battle that the local player is in, either watching it or being watched, identified by
+    /// the player whose battle it is
+    pub fn battle_channel(&self, local_id: PlayerId) -> Option<PlayerId> {
+        match self.watching {
+            Some((id, _)) => Some(id),
+            None => (!self.watchers.is_empty()).then_some(local_id),
+        }
+    }
+
+    /// Show how many players are watching the local player's battle, in the bottom right corner of
+    /// the screen
+    pub fn draw_watchers(
+        &self,
+        renderer: &mut Renderer,
+        font: &Font,
+        border: &BorderRenderer,
+        screen: Rect,
+    ) {
+        if self.watchers.is_empty() {
+            return;
+        }
+        let label = format!("WATCHED BY {}", self.watchers.len());
+        let width = (label.len() as i32 + 2) * font.char_width();
+        let x = screen.x + screen.width - width;
+        let y = screen.y + screen.height - 3 * font.line_height();
+        font::draw_label(renderer, font, border, &label, x, y);
+    }
+
+    /// Draw the state of the watched battle over the top of the screen
+    pub fn draw(
+        &self,
+        renderer: &mut Renderer,
+        font: &Font,
+        border: &BorderRenderer,
+        mem: &Memory,
+        screen: Rect,
+    ) {
+        if let Some((_, Some(snapshot))) = &self.watching {
+            draw_snapshot(renderer, font, border, mem, screen, snapshot);
+        }
+    }
+}
+
+/// Draw the Pokemon fighting in a battle in a box at the top of `screen`
+pub fn draw_snapshot(
+    renderer: &mut Renderer,
+    font: &Font,
+    border: &BorderRenderer,
+    mem: &Memory,
+    screen: Rect,
+    snapshot: &BattleSnapshot,
+) {
+    let height = VIEW_LINES * font.line_height();
+    draw_rectangle(screen.x as f32, screen.y as f32, screen.width as f32, height as f32, WHITE);
+    border.draw_box(renderer, Rect::new(screen.x, screen.y, screen.width, height));
+
+    let lines = [battler_line(mem, &snapshot.enemy), battler_line(mem, &snapshot.player)];
+    for (i, line) in lines.iter().enumerate() {
+        let y = screen.y + (i as i32 + 1) * font.line_height();
+        let target = Rect::new(screen.x + font.char_width(), y, screen.width, font.line_height());
+        font::draw_text(renderer, font, line, &target);
+    }
+}
+
+/// The text of a battle chat message (`/b TEXT`), or None if the message is not one
+pub fn battle_chat(msg: &str) -> Option<&str> {
+    let (command, text) = msg.trim().split_once(' ')?;
+    (command == BATTLE_CHAT_COMMAND).then(|| text.trim())
+}
+
+fn battler_line(mem: &Memory, battler: &BattlerData) -> Vec<u8> {
+    let mut line = extract::species_name(mem, battler.species);
+    let stats = format!(" L{} HP {}/{}", battler.level, battler.hp, battler.max_hp);
+    line.extend(text::Encoder::new(&stats));
+    line
+}
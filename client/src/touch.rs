@@ -0,0 +1,150 @@
+//! On-screen joypad for touch screens
+//!
+//! Touches over the emulator's screen are mapped to joypad buttons, which are drawn as a
+//! translucent overlay. Left mouse clicks are treated as touches as well, since some devices only
+//! report touches as mouse events.
+use gb_emu::joypad;
+use interface::text;
+use macroquad::{
+    color::Color,
+    input::{is_mouse_button_down, mouse_position, touches, MouseButton, TouchPhase},
+    shapes::draw_rectangle,
+};
+
+use crate::{
+    common::{Rect, Renderer},
+    font::{draw_text, Font},
+    input::Button,
+};
+
+const BUTTON_COLOR: Color = Color { r: 1.0, g: 1.0, b: 1.0, a: 0.25 };
+const PRESSED_COLOR: Color = Color { r: 1.0, g: 1.0, b: 1.0, a: 0.5 };
+
+/// The size of each arm of the D-pad, and of the A and B buttons
+const BUTTON_SIZE: i32 = 48;
+
+/// The margin between the buttons and the edge of the screen
+const MARGIN: i32 = 16;
+
+pub struct VirtualJoypad {
+    pressed: Vec<Button>,
+}
+
+impl VirtualJoypad {
+    pub fn new() -> VirtualJoypad {
+        VirtualJoypad { pressed: vec![] }
+    }
+
+    /// Checks which buttons are being touched, returning the buttons that have changed state
+    pub fn update(&mut self, screen: Rect) -> Vec<(Button, joypad::State)> {
+        let layout = button_layout(screen);
+
+        let mut pressed = vec![];
+        for (x, y) in touch_points() {
+            for &(button, rect) in &layout {
+                if rect.contains(x, y) && !pressed.contains(&button) {
+                    pressed.push(button);
+                }
+            }
+        }
+
+        let mut changes = vec![];
+        for button in Button::ALL {
+            match (self.pressed.contains(&button), pressed.contains(&button)) {
+                (false, true) => changes.push((button, joypad::State::Pressed)),
+                (true, false) => changes.push((button, joypad::State::Released)),
+                _ => {}
+            }
+        }
+
+        self.pressed = pressed;
+        changes
+    }
+
+    /// Releases all buttons, returning the buttons that were pressed
+    pub fn release_all(&mut self) -> Vec<(Button, joypad::State)> {
+        self.pressed.drain(..).map(|button| (button, joypad::State::Released)).collect()
+    }
+
+    pub fn draw(&self, renderer: &mut Renderer, font: &Font, screen: Rect) {
+        for (button, rect) in button_layout(screen) {
+            let color = if self.pressed.contains(&button) { PRESSED_COLOR } else { BUTTON_COLOR };
+            draw_rectangle(
+                rect.x as f32,
+                rect.y as f32,
+                rect.width as f32,
+                rect.height as f32,
+                color,
+            );
+
+            let label = match button {
+                Button::A | Button::B | Button::Start | Button::Select => button.name(),
+                Button::Up | Button::Down | Button::Left | Button::Right => continue,
+            };
+            let label_width = label.len() as i32 * font.char_width();
+            let label_rect = Rect::new(
+                rect.x + (rect.width - label_width) / 2,
+                rect.y + (rect.height - font.line_height()) / 2,
+                label_width,
+                font.line_height(),
+            );
+            let encoded: Vec<u8> = text::Encoder::new(label).collect();
+            draw_text(renderer, font, &encoded, &label_rect);
+        }
+    }
+}
+
+/// Gets the area covered by each button when drawn over the given screen
+fn button_layout(screen: Rect) -> [(Button, Rect); 8] {
+    let size = BUTTON_SIZE;
+    let bottom = screen.y + screen.height - MARGIN;
+
+    // The D-pad is in the bottom left corner
+    let (dpad_x, dpad_y) = (screen.x + MARGIN, bottom - 3 * size);
+
+    // A and B are in the bottom right corner, with A placed slightly higher than B
+    let a_x = screen.x + screen.width - MARGIN - size;
+    let b_x = a_x - size - MARGIN;
+
+    // Start and Select are small buttons centered along the bottom of the screen
+    let (small_width, small_height) = (size + MARGIN, size / 2);
+    let center = screen.x + screen.width / 2;
+
+    [
+        (Button::Up, Rect::new(dpad_x + size, dpad_y, size, size)),
+        (Button::Down, Rect::new(dpad_x + size, dpad_y + 2 * size, size, size)),
+        (Button::Left, Rect::new(dpad_x, dpad_y + size, size, size)),
+        (Button::Right, Rect::new(dpad_x + 2 * size, dpad_y + size, size, size)),
+        (Button::A, Rect::new(a_x, bottom - 2 * size, size, size)),
+        (Button::B, Rect::new(b_x, bottom - size - size / 2, size, size)),
+        (
+            Button::Start,
+            Rect::new(center + MARGIN / 2, bottom - small_height, small_width, small_height),
+        ),
+        (
+            Button::Select,
+            Rect::new(
+                center - MARGIN / 2 - small_width,
+                bottom - small_height,
+                small_width,
+                small_height,
+            ),
+        ),
+    ]
+}
+
+/// Gets the positions of all active touches, including the mouse if the left button is held
+fn touch_points() -> Vec<(i32, i32)> {
+    let mut points: Vec<(i32, i32)> = touches()
+        .iter()
+        .filter(|touch| !matches!(touch.phase, TouchPhase::Ended | TouchPhase::Cancelled))
+        .map(|touch| (touch.position.x as i32, touch.position.y as i32))
+        .collect();
+
+    if is_mouse_button_down(MouseButton::Left) {
+        let (x, y) = mouse_position();
+        points.push((x as i32, y as i32));
+    }
+
+    points
+}
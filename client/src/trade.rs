@@ -0,0 +1,277 @@
+//! The trade board, where players list a Pokemon from their party along with the species they want
+//! for it, and other players browse the listings and offer one of their own Pokemon for them. The
+//! board only helps players find each other, the trade itself is made in the game's Cable Club.
+use gb_emu::mmu::Memory;
+use interface::{data::MAX_SPECIES_ID, extract, text, PlayerId};
+use macroquad::prelude::KeyCode;
+use network_common::TradeListing;
+
+use crate::{
+    border::BorderRenderer,
+    common::{Rect, Renderer},
+    font::Font,
+    menu::ItemBox,
+};
+
+/// The number of listings shown on each page of the board
+const LISTINGS_PER_PAGE: usize = 5;
+
+/// A change to the board, to be sent by the network client
+#[derive(Copy, Clone)]
+pub enum TradeRequest {
+    Refresh,
+    Post { species: u8, level: u8, wanted: u8 },
+    Remove,
+    Offer { to: PlayerId, species: u8, level: u8 },
+}
+
+/// Which listings are shown on the board
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum TradeFilter {
+    All,
+    /// Listings that want a species in the local player's party
+    WantsParty,
+    /// The local player's own listing
+    Own,
+}
+
+impl TradeFilter {
+    const ALL: [TradeFilter; 3] = [TradeFilter::All, TradeFilter::WantsParty, TradeFilter::Own];
+
+    fn name(self) -> &'static str {
+        match self {
+            TradeFilter::All => "ALL",
+            TradeFilter::WantsParty => "WANTS YOURS",
+            TradeFilter::Own => "YOURS",
+        }
+    }
+
+    fn next(self) -> TradeFilter {
+        let index = TradeFilter::ALL.iter().position(|&filter| filter == self).unwrap();
+        TradeFilter::ALL[(index + 1) % TradeFilter::ALL.len()]
+    }
+}
+
+/// What each line of the board does when selected
+#[derive(Copy, Clone)]
+enum Row {
+    Filter,
+    Page,
+    Listing(TradeListing),
+    /// The Pokemon from the local player's party to list or offer
+    Slot,
+    Wanted,
+    Post,
+    Remove,
+    Back,
+}
+
+pub struct TradeBoard<'a> {
+    item_box: ItemBox<'a>,
+    rows: Vec<Row>,
+    listings: Vec<TradeListing>,
+    local_id: Option<PlayerId>,
+    filter: TradeFilter,
+    page: usize,
+    /// The species and level of each Pokemon in the local player's party
+    party: Vec<(u8, u8)>,
+    /// The position in the party of the Pokemon to list or offer
+    slot: usize,
+    /// The species wanted in return for the local player's listing, or 0 for any
+    wanted: u8,
+    /// A request that is waiting to be sent
+    pub request: Option<TradeRequest>,
+}
+
+impl<'a> TradeBoard<'a> {
+    pub fn new(font: &'a Font, border: &'a BorderRenderer, rect: Rect) -> TradeBoard<'a> {
+        TradeBoard {
+            item_box: ItemBox::new(vec![], font, border, rect),
+            rows: vec![],
+            listings: vec![],
+            local_id: None,
+            filter: TradeFilter::All,
+            page: 0,
+            party: vec![],
+            slot: 0,
+            wanted: 0,
+            request: None,
+        }
+    }
+
+    /// Open the board, asking the server for the latest listings
+    pub fn open(&mut self, mem: &Memory) {
+        self.request = Some(TradeRequest::Refresh);
+        self.refresh(mem);
+    }
+
+    pub fn set_listings(&mut self, listings: Vec<TradeListing>, local_id: PlayerId, mem: &Memory) {
+        self.listings = listings;
+        self.local_id = Some(local_id);
+        self.refresh(mem);
+    }
+
+    /// The listing made by the local player, if they have one
+    pub fn own_listing(&self) -> Option<&TradeListing> {
+        self.listings.iter().find(|listing| Some(listing.player) == self.local_id)
+    }
+
+    fn filtered(&self) -> Vec<TradeListing> {
+        let party_species: Vec<u8> = self.party.iter().map(|&(species, _)| species).collect();
+        let own = |listing: &TradeListing| Some(listing.player) == self.local_id;
+        self.listings
+            .iter()
+            .filter(|listing| match self.filter {
+                TradeFilter::All => true,
+                TradeFilter::WantsParty => {
+                    !own(listing)
+                        && (listing.wanted == 0 || party_species.contains(&listing.wanted))
+                }
+                TradeFilter::Own => own(listing),
+            })
+            .copied()
+            .collect()
+    }
+
+    fn pages(&self) -> usize {
+        self.filtered().len().div_ceil(LISTINGS_PER_PAGE).max(1)
+    }
+
+    /// Rebuild the lines of the board from the listings and the local player's party
+    fn refresh(&mut self, mem: &Memory) {
+        self.party = extract::party_levels(mem);
+        if self.slot >= self.party.len() {
+            self.slot = 0;
+        }
+        self.page = self.page.min(self.pages() - 1);
+
+        let mut rows = vec![Row::Filter, Row::Page];
+        let listings = self.filtered();
+        let page = listings.iter().skip(self.page * LISTINGS_PER_PAGE).take(LISTINGS_PER_PAGE);
+        rows.extend(page.map(|&listing| Row::Listing(listing)));
+        rows.extend([Row::Slot, Row::Wanted, Row::Post]);
+        if self.own_listing().is_some() {
+            rows.push(Row::Remove);
+        }
+        rows.push(Row::Back);
+
+        let items = rows.iter().map(|&row| self.row_text(row, mem)).collect();
+        self.rows = rows;
+        self.item_box.set_items(items);
+    }
+
+    fn row_text(&self, row: Row, mem: &Memory) -> String {
+        match row {
+            Row::Filter => format!("SHOW {}", self.filter.name()),
+            Row::Page => format!("PAGE {}/{}", self.page + 1, self.pages()),
+            Row::Listing(listing) => {
+                let mut line = format!(
+                    "{} L{} FOR {}",
+                    species_name(mem, listing.species),
+                    listing.level,
+                    species_name(mem, listing.wanted)
+                );
+                if Some(listing.player) != self.local_id {
+                    line.push_str(&format!(" #{}", listing.player));
+                }
+                line
+            }
+            Row::Slot => match self.party.get(self.slot) {
+                Some(&(species, level)) => {
+                    format!("YOURS {} L{}", species_name(mem, species), level)
+                }
+                None => "YOURS NONE".to_string(),
+            },
+            Row::Wanted => format!("WANT {}", species_name(mem, self.wanted)),
+            Row::Post => "LIST YOURS".to_string(),
+            Row::Remove => "REMOVE LISTING".to_string(),
+            Row::Back => "BACK".to_string(),
+        }
+    }
+
+    pub fn draw(&self, renderer: &mut Renderer) {
+        self.item_box.draw(renderer);
+    }
+
+    pub fn key_down(&mut self, keycode: KeyCode, mem: &Memory) {
+        match keycode {
+            KeyCode::Up => self.item_box.move_up(),
+            KeyCode::Down => self.item_box.move_down(),
+            KeyCode::Left => self.step(-1, mem),
+            KeyCode::Right => self.step(1, mem),
+            _ => {}
+        }
+    }
+
+    /// Change the value on the selected line, for lines that have one
+    fn step(&mut self, step: isize, mem: &Memory) {
+        match self.rows[self.item_box.selection()] {
+            Row::Page => {
+                let pages = self.pages() as isize;
+                self.page = (self.page as isize + step).rem_euclid(pages) as usize;
+            }
+            Row::Slot if !self.party.is_empty() => {
+                let len = self.party.len() as isize;
+                self.slot = (self.slot as isize + step).rem_euclid(len) as usize;
+            }
+            Row::Wanted => self.wanted = next_wanted(mem, self.wanted, step),
+            _ => return,
+        }
+        self.refresh(mem);
+    }
+
+    /// Handles a key release, returning true if the board should be closed
+    pub fn key_up(&mut self, keycode: KeyCode, mem: &Memory) -> bool {
+        match keycode {
+            KeyCode::Escape => return true,
+            KeyCode::Enter => {}
+            _ => return false,
+        }
+
+        let slot = self.party.get(self.slot).copied();
+        match self.rows[self.item_box.selection()] {
+            Row::Filter => {
+                self.filter = self.filter.next();
+                self.page = 0;
+            }
+            Row::Page | Row::Slot | Row::Wanted => self.step(1, mem),
+            Row::Listing(listing) if Some(listing.player) != self.local_id => {
+                if let Some((species, level)) = slot {
+                    self.request = Some(TradeRequest::Offer { to: listing.player, species, level });
+                    return true;
+                }
+            }
+            Row::Listing(_) => {}
+            Row::Post => {
+                if let Some((species, level)) = slot {
+                    self.request = Some(TradeRequest::Post { species, level, wanted: self.wanted });
+                }
+            }
+            Row::Remove => self.request = Some(TradeRequest::Remove),
+            Row::Back => return true,
+        }
+        self.refresh(mem);
+        false
+    }
+}
+
+/// The name of a species, or ANY for 0
+pub fn species_name(mem: &Memory, species: u8) -> String {
+    match species {
+        0 => "ANY".to_string(),
+        _ => text::decode(&extract::species_name(mem, species)),
+    }
+}
+
+/// The next species that can be asked for, skipping unused species ids. 0 (any species) comes
+/// before the first species.
+fn next_wanted(mem: &Memory, wanted: u8, step: isize) -> u8 {
+    let count = MAX_SPECIES_ID as isize + 1;
+    let mut species = wanted as isize;
+    loop {
+        species = (species + step).rem_euclid(count);
+        if species == 0 || !species_name(mem, species as u8).starts_with("MISSINGNO") {
+            return species as u8;
+        }
+    }
+}
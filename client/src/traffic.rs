@@ -0,0 +1,79 @@
+//! Menu showing how much is being sent to and received from the server, to help diagnose a poor
+//! connection
+use macroquad::prelude::KeyCode;
+use network_common::stats::TrafficStats;
+
+use crate::{
+    border::BorderRenderer,
+    common::{Rect, Renderer},
+    font::Font,
+    menu::ItemBox,
+};
+
+/// The number of event types listed, so that the list fits within the menu
+const MAX_EVENT_TYPES: usize = 6;
+
+pub struct TrafficView<'a> {
+    item_box: ItemBox<'a>,
+}
+
+impl<'a> TrafficView<'a> {
+    pub fn new(font: &'a Font, border: &'a BorderRenderer, rect: Rect) -> TrafficView<'a> {
+        TrafficView { item_box: ItemBox::new(vec!["BACK".to_string()], font, border, rect) }
+    }
+
+    /// Rebuild the lines from the latest statistics
+    pub fn refresh(&mut self, stats: &TrafficStats) {
+        let mut items = vec![
+            format!(
+                "SENT {} {}/S",
+                format_bytes(stats.bytes_sent as f32),
+                format_bytes(stats.bytes_sent_per_sec)
+            ),
+            format!(
+                "RECV {} {}/S",
+                format_bytes(stats.bytes_received as f32),
+                format_bytes(stats.bytes_received_per_sec)
+            ),
+            format!(
+                "EVENTS {:.0}/S OUT {:.0}/S IN",
+                stats.events_sent_per_sec, stats.events_received_per_sec
+            ),
+        ];
+        for (name, counts) in stats.busiest_events().into_iter().take(MAX_EVENT_TYPES) {
+            items.push(format!("{} {}/{}", name, counts.sent, counts.received));
+        }
+        items.push("BACK".to_string());
+        self.item_box.set_items(items);
+    }
+
+    pub fn draw(&self, renderer: &mut Renderer) {
+        self.item_box.draw(renderer);
+    }
+
+    pub fn key_down(&mut self, keycode: KeyCode) {
+        match keycode {
+            KeyCode::Up => self.item_box.move_up(),
+            KeyCode::Down => self.item_box.move_down(),
+            _ => {}
+        }
+    }
+
+    /// Handles a key release, returning true if the menu should be closed
+    pub fn key_up(&mut self, keycode: KeyCode) -> bool {
+        matches!(keycode, KeyCode::Escape | KeyCode::Enter)
+    }
+}
+
+/// A number of bytes, in the largest unit that keeps it above 1
+fn format_bytes(bytes: f32) -> String {
+    if bytes >= 1024.0 * 1024.0 {
+        format!("{:.1}MB", bytes / (1024.0 * 1024.0))
+    }
+    else if bytes >= 1024.0 {
+        format!("{:.1}KB", bytes / 1024.0)
+    }
+    else {
+        format!("{:.0}B", bytes)
+    }
+}
@@ -0,0 +1,57 @@
+//! Afterimages showing where other players have recently been. Besides looking nice, these make
+//! it easier to see when the movement of other players is jumpy or out of sync.
+use std::collections::{HashMap, VecDeque};
+
+use interface::{
+    data::{MovementData, PlayerData},
+    PlayerId,
+};
+
+/// The number of previous positions to show for each player
+const TRAIL_LENGTH: usize = 3;
+
+pub struct Trails {
+    /// The tiles each player has recently stood on, ending with their current tile
+    positions: HashMap<PlayerId, VecDeque<MovementData>>,
+}
+
+impl Trails {
+    pub fn new() -> Trails {
+        Trails { positions: HashMap::new() }
+    }
+
+    /// Record the current position of every player
+    pub fn update(&mut self, players: &HashMap<PlayerId, PlayerData>) {
+        self.positions.retain(|id, _| players.contains_key(id));
+
+        for (&id, player) in players {
+            let trail = self.positions.entry(id).or_default();
+            // Players are drawn standing still at each of their previous positions
+            let current = MovementData { walk_counter: 0, ..player.movement_data };
+
+            match trail.back() {
+                Some(last) if last.map_id != current.map_id => trail.clear(),
+                Some(last) if (last.map_x, last.map_y) == (current.map_x, current.map_y) => {
+                    continue
+                }
+                _ => {}
+            }
+
+            trail.push_back(current);
+            if trail.len() > TRAIL_LENGTH + 1 {
+                trail.pop_front();
+            }
+        }
+    }
+
+    /// The previous positions of a player, from oldest to newest
+    pub fn get(&self, id: PlayerId) -> impl Iterator<Item = &MovementData> {
+        let trail = self.positions.get(&id);
+        let previous = trail.map_or(0, |trail| trail.len().saturating_sub(1));
+        trail.into_iter().flatten().take(previous)
+    }
+
+    pub fn clear(&mut self) {
+        self.positions.clear();
+    }
+}
@@ -0,0 +1,59 @@
+//! Anonymous usage statistics, sent to the server when leaving by players that choose to share them
+//!
+//! A report only says how long the session lasted, how many times each feature was used and where
+//! the client last crashed. Nothing about the player or their game is included. Crashes are saved
+//! to a file as they happen, and reported at the end of the next session.
+use std::{collections::HashMap, fs, panic, path::PathBuf, time::Instant};
+
+use network_common::{codec::MAX_CRASH_LENGTH, Feature, UsageReport};
+
+use crate::paths;
+
+/// Where the location of the last crash is kept in the data directory until it has been reported
+pub const CRASH_FILE: &str = "pikemon-crash.txt";
+
+pub struct Usage {
+    started_at: Instant,
+    counts: HashMap<Feature, u32>,
+}
+
+impl Usage {
+    pub fn new() -> Usage {
+        Usage { started_at: Instant::now(), counts: HashMap::new() }
+    }
+
+    pub fn record(&mut self, feature: Feature) {
+        *self.counts.entry(feature).or_insert(0) += 1;
+    }
+
+    pub fn report(&self, crash: Option<String>) -> UsageReport {
+        UsageReport {
+            session_secs: self.started_at.elapsed().as_secs().try_into().unwrap_or(u32::MAX),
+            features: self.counts.iter().map(|(&feature, &count)| (feature, count)).collect(),
+            crash,
+        }
+    }
+}
+
+fn crash_path() -> PathBuf {
+    paths::data_dir().join(CRASH_FILE)
+}
+
+/// Save the location of any crash, as well as printing it as usual
+pub fn record_crashes() {
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        // Only the location is kept, since the message can include paths and server addresses
+        if let Some(location) = info.location() {
+            let _ = fs::write(crash_path(), format!("{}:{}", location.file(), location.line()));
+        }
+        default_hook(info);
+    }));
+}
+
+/// The location of the last crash, if it has not been reported yet
+pub fn take_last_crash() -> Option<String> {
+    let crash = fs::read_to_string(crash_path()).ok()?;
+    let _ = fs::remove_file(crash_path());
+    (crash.len() <= MAX_CRASH_LENGTH).then_some(crash)
+}
@@ -0,0 +1,104 @@
+//! Post-processing of the emulator's output before it is displayed
+use macroquad::{color::Color, shapes::draw_rectangle};
+
+use crate::{
+    common::Rect,
+    config::{DisplayFilter, VideoSettings},
+};
+
+/// The change in average brightness (out of 255) between two frames that is treated as a flash
+const FLASH_THRESHOLD: u32 = 48;
+
+/// The fraction of a flashing frame that is blended into the displayed frame
+const FLASH_BLEND_FACTOR: u32 = 4;
+
+/// The color drawn over the gaps between pixels for the scanline and LCD grid filters
+const GRID_COLOR: Color = Color { r: 0.0, g: 0.0, b: 0.0, a: 0.25 };
+
+/// Applies the configured video settings to emulator frames
+pub struct FrameFilter {
+    /// The last frame that was displayed
+    previous: Vec<u8>,
+}
+
+impl FrameFilter {
+    pub fn new() -> FrameFilter {
+        FrameFilter { previous: Vec::new() }
+    }
+
+    /// Process an RGBA frame in place
+    pub fn apply(&mut self, settings: &VideoSettings, frame: &mut [u8]) {
+        if self.previous.len() != frame.len() {
+            self.previous = frame.to_vec();
+        }
+
+        if settings.filter == DisplayFilter::Ghosting {
+            blend_frames(&self.previous, frame);
+        }
+
+        if settings.reduce_flashing {
+            limit_flashing(&self.previous, frame);
+        }
+
+        self.previous.copy_from_slice(frame);
+    }
+}
+
+/// Draws the parts of the display filter that are applied at the scaled resolution, on top of the
+/// emulator's screen.
+pub fn draw_overlay(settings: &VideoSettings, screen: Rect, scale: i32) {
+    let (draw_rows, draw_columns) = match settings.filter {
+        DisplayFilter::Scanlines => (true, false),
+        DisplayFilter::LcdGrid => (true, true),
+        DisplayFilter::None | DisplayFilter::Ghosting => return,
+    };
+
+    let (x, y) = (screen.x as f32, screen.y as f32);
+    let (width, height) = (screen.width as f32, screen.height as f32);
+    if draw_rows {
+        for row in (scale - 1..screen.height).step_by(scale as usize) {
+            draw_rectangle(x, y + row as f32, width, 1.0, GRID_COLOR);
+        }
+    }
+    if draw_columns {
+        for column in (scale - 1..screen.width).step_by(scale as usize) {
+            draw_rectangle(x + column as f32, y, 1.0, height, GRID_COLOR);
+        }
+    }
+}
+
+/// Mixes the new frame equally with the previously displayed frame
+fn blend_frames(previous: &[u8], frame: &mut [u8]) {
+    for (new, &old) in frame.iter_mut().zip(previous) {
+        *new = ((*new as u16 + old as u16) / 2) as u8;
+    }
+}
+
+/// Dampens rapid changes in brightness (e.g. battle flashes or palette inversions) by only moving
+/// part of the way towards the new frame. Frames that do not change much are left untouched, so
+/// normal movement is not blurred.
+fn limit_flashing(previous: &[u8], frame: &mut [u8]) {
+    let (previous_luma, current_luma) = (average_luma(previous), average_luma(frame));
+    if previous_luma.abs_diff(current_luma) < FLASH_THRESHOLD {
+        return;
+    }
+
+    for (new, &old) in frame.iter_mut().zip(previous) {
+        let (new_value, old_value) = (*new as i32, old as i32);
+        *new = (old_value + (new_value - old_value) / FLASH_BLEND_FACTOR as i32) as u8;
+    }
+}
+
+/// Computes the average brightness of an RGBA frame
+fn average_luma(frame: &[u8]) -> u32 {
+    let pixels = frame.len() / 4;
+    if pixels == 0 {
+        return 0;
+    }
+
+    let total: u64 = frame
+        .chunks_exact(4)
+        .map(|px| (2 * px[0] as u64 + 5 * px[1] as u64 + px[2] as u64) / 8)
+        .sum();
+    (total / pixels as u64) as u32
+}
@@ -0,0 +1,106 @@
+//! Smooth walking for other players between their movement updates
+//!
+//! Movement arrives at most `movement_rate` times a second, so a player's walk counter would
+//! otherwise stay frozen between updates and their walk would look choppy. Players who are part way
+//! through a step are moved along at the game's walking speed instead, and stop at the end of the
+//! step until an update says where they went next. Updates that are behind the predicted position
+//! on the same step are ignored, so players never move backwards.
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use interface::{
+    data::{MovementData, PlayerData},
+    values::Direction,
+    PlayerId,
+};
+
+/// How long the game takes to lower the walk counter by one, which moves the player two pixels.
+/// Walking one tile takes 16 frames.
+const STEP_TIME: Duration = Duration::from_micros(2_000_000 / 60);
+
+pub struct Walking {
+    /// When each player's walk counter was last updated or moved along
+    advanced_at: HashMap<PlayerId, Instant>,
+}
+
+impl Walking {
+    pub fn new() -> Walking {
+        Walking { advanced_at: HashMap::new() }
+    }
+
+    /// Apply a movement update received from the server
+    pub fn receive(&mut self, id: PlayerId, current: &mut MovementData, update: MovementData) {
+        if is_behind(&update, current) {
+            return;
+        }
+        *current = update;
+        self.advanced_at.insert(id, Instant::now());
+    }
+
+    pub fn remove(&mut self, id: PlayerId) {
+        self.advanced_at.remove(&id);
+    }
+
+    /// Move every player who is part way through a step along by the time since they last moved
+    pub fn advance(&mut self, players: &mut HashMap<PlayerId, PlayerData>) {
+        let now = Instant::now();
+        for (id, player) in players {
+            let movement_data = &mut player.movement_data;
+            if movement_data.walk_counter == 0 || movement_data.is_hidden() {
+                continue;
+            }
+
+            let advanced_at = self.advanced_at.entry(*id).or_insert(now);
+            let elapsed = now.saturating_duration_since(*advanced_at);
+            let steps = (elapsed.as_micros() / STEP_TIME.as_micros()).min(u8::MAX as u128) as u8;
+            if steps == 0 {
+                continue;
+            }
+            *advanced_at += STEP_TIME * steps as u32;
+
+            if steps < movement_data.walk_counter {
+                movement_data.walk_counter -= steps;
+            }
+            else {
+                // Finish the step, or stay just short of it if it leaves the map
+                match step_target(movement_data) {
+                    Some((x, y)) => {
+                        movement_data.map_x = x;
+                        movement_data.map_y = y;
+                        movement_data.walk_counter = 0;
+                    }
+                    None => movement_data.walk_counter = 1,
+                }
+            }
+        }
+    }
+}
+
+/// The tile that a player is stepping onto, unless it is off the edge of the map
+fn step_target(movement_data: &MovementData) -> Option<(u8, u8)> {
+    let (x, y) = (movement_data.map_x, movement_data.map_y);
+    match movement_data.direction {
+        Direction::Down => Some((x, y.checked_add(1)?)),
+        Direction::Up => Some((x, y.checked_sub(1)?)),
+        Direction::Left => Some((x.checked_sub(1)?, y)),
+        Direction::Right => Some((x.checked_add(1)?, y)),
+    }
+}
+
+/// Whether an update is for a step that the player has already been moved further along
+fn is_behind(update: &MovementData, predicted: &MovementData) -> bool {
+    if update.walk_counter == 0
+        || update.map_id != predicted.map_id
+        || update.direction != predicted.direction
+    {
+        return false;
+    }
+
+    let same_step = (update.map_x, update.map_y) == (predicted.map_x, predicted.map_y)
+        && update.walk_counter > predicted.walk_counter;
+    let finished_step = predicted.walk_counter == 0
+        && step_target(update) == Some((predicted.map_x, predicted.map_y));
+    same_step || finished_step
+}
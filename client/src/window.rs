@@ -0,0 +1,176 @@
+//! The window's title and taskbar icon, which show what is happening in the game while the player
+//! is looking at another window
+//!
+//! The windowing library only sets the title when the window is created, so both are changed
+//! through each platform's own interface: the window manager's functions on Windows, AppKit on
+//! macOS, and `xdotool` on other platforms, which only works under X11. Failures are only logged,
+//! and only once, since the title is changed often and neither is essential.
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Whether changing the window has failed before, so that it is not logged again
+static FAILED: AtomicBool = AtomicBool::new(false);
+
+fn log_failure(error: impl std::fmt::Display) {
+    if !FAILED.swap(true, Ordering::Relaxed) {
+        println!("Failed to update the window: {}", error);
+    }
+}
+
+/// Keeps the window's title up to date, only changing it when it is different
+#[derive(Default)]
+pub struct WindowTitle {
+    current: String,
+}
+
+impl WindowTitle {
+    pub fn set(&mut self, title: String) {
+        if title != self.current {
+            platform::set_title(&title);
+            self.current = title;
+        }
+    }
+}
+
+/// Flash the taskbar or dock icon until the window is brought to the front. Most desktops ignore
+/// this while the window already has focus.
+pub fn request_attention() {
+    platform::request_attention();
+}
+
+#[cfg(target_os = "windows")]
+mod platform {
+    use windows_sys::Win32::{
+        Foundation::{BOOL, HWND, LPARAM},
+        UI::WindowsAndMessaging::{
+            EnumWindows, FlashWindowEx, GetWindowThreadProcessId, IsWindowVisible, SetWindowTextW,
+            FLASHWINFO, FLASHW_TIMERNOFG, FLASHW_TRAY,
+        },
+    };
+
+    /// The visible window that belongs to this process
+    fn own_window() -> Option<HWND> {
+        unsafe extern "system" fn check(window: HWND, found: LPARAM) -> BOOL {
+            let mut process = 0;
+            GetWindowThreadProcessId(window, &mut process);
+            if process == std::process::id() && IsWindowVisible(window) != 0 {
+                *(found as *mut HWND) = window;
+                return 0;
+            }
+            1
+        }
+
+        let mut found: HWND = 0;
+        // Safety: `found` outlives the call, which is the only time that `check` writes to it
+        unsafe { EnumWindows(Some(check), &mut found as *mut HWND as LPARAM) };
+        (found != 0).then_some(found)
+    }
+
+    pub fn set_title(title: &str) {
+        let window = match own_window() {
+            Some(window) => window,
+            None => return super::log_failure("the window could not be found"),
+        };
+        let title: Vec<u16> = title.encode_utf16().chain([0]).collect();
+        // Safety: `title` is null terminated, and outlives the call
+        if unsafe { SetWindowTextW(window, title.as_ptr()) } == 0 {
+            super::log_failure(std::io::Error::last_os_error());
+        }
+    }
+
+    pub fn request_attention() {
+        let window = match own_window() {
+            Some(window) => window,
+            None => return super::log_failure("the window could not be found"),
+        };
+        let info = FLASHWINFO {
+            cbSize: std::mem::size_of::<FLASHWINFO>() as u32,
+            hwnd: window,
+            dwFlags: FLASHW_TRAY | FLASHW_TIMERNOFG,
+            uCount: 0,
+            dwTimeout: 0,
+        };
+        // Safety: `info` is fully initialized and outlives the call
+        unsafe { FlashWindowEx(&info) };
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod platform {
+    use std::ffi::{c_char, c_void, CString};
+
+    type Id = *mut c_void;
+
+    /// `NSInformationalRequest`, which bounces the dock icon once
+    const INFORMATIONAL_REQUEST: usize = 10;
+
+    #[link(name = "objc")]
+    extern "C" {
+        fn objc_getClass(name: *const c_char) -> Id;
+        fn sel_registerName(name: *const c_char) -> Id;
+        fn objc_msgSend();
+    }
+
+    /// Send a message with a single argument, or none if `arg` is null. `objc_msgSend` must be
+    /// called through a pointer of the method's own type.
+    unsafe fn send(receiver: Id, selector: &str, arg: Id) -> Id {
+        let selector = CString::new(selector).unwrap();
+        let selector = sel_registerName(selector.as_ptr());
+        let send: unsafe extern "C" fn(Id, Id, Id) -> Id =
+            std::mem::transmute(objc_msgSend as unsafe extern "C" fn());
+        send(receiver, selector, arg)
+    }
+
+    unsafe fn application() -> Id {
+        let class = objc_getClass(b"NSApplication\0".as_ptr() as *const c_char);
+        send(class, "sharedApplication", std::ptr::null_mut())
+    }
+
+    pub fn set_title(title: &str) {
+        let title = match CString::new(title) {
+            Ok(title) => title,
+            Err(e) => return super::log_failure(e),
+        };
+        // Safety: the selectors match the methods' types, and this is called from the main thread
+        unsafe {
+            let window = send(application(), "mainWindow", std::ptr::null_mut());
+            if window.is_null() {
+                return super::log_failure("the window could not be found");
+            }
+            let class = objc_getClass(b"NSString\0".as_ptr() as *const c_char);
+            let title = send(class, "stringWithUTF8String:", title.as_ptr() as Id);
+            send(window, "setTitle:", title);
+        }
+    }
+
+    pub fn request_attention() {
+        // Safety: the selector matches the method's type, and this is called from the main thread
+        unsafe {
+            send(application(), "requestUserAttention:", INFORMATIONAL_REQUEST as Id);
+        }
+    }
+}
+
+#[cfg(not(any(target_os = "windows", target_os = "macos")))]
+mod platform {
+    use std::{process::Command, thread};
+
+    /// Run `xdotool` on every window of this process, in the background
+    fn xdotool(args: &[&str]) {
+        let mut command = Command::new("xdotool");
+        command.args(["search", "--pid", &std::process::id().to_string(), "set_window"]);
+        command.args(args).arg("%@");
+        thread::spawn(move || match command.status() {
+            Ok(status) if !status.success() => super::log_failure(format!("xdotool {}", status)),
+            Ok(_) => {}
+            Err(e) => super::log_failure(e),
+        });
+    }
+
+    pub fn set_title(title: &str) {
+        xdotool(&["--name", title]);
+    }
+
+    pub fn request_attention() {
+        xdotool(&["--urgency", "1"]);
+    }
+}
@@ -0,0 +1,122 @@
+//! Cosmetic effects that every player on the server sees at the same time, derived from the seed
+//! and clock shared by the server
+use std::{
+    f32::consts::TAU,
+    time::{Duration, Instant},
+};
+
+use interface::maps;
+use macroquad::{color::Color, shapes::draw_rectangle};
+use network_common::{
+    shared::{self, SharedEffect, SharedSeed},
+    DayCycle,
+};
+
+use crate::{
+    border::BorderRenderer,
+    common::{Rect, Renderer},
+    font::{self, Font},
+};
+
+/// How long after its start time an effect can still be seen, so that players who join a little
+/// late do not miss it
+const EFFECT_WINDOW: u64 = 60;
+
+/// How long the shooting star is shown on screen
+const SHOOTING_STAR_DURATION: Duration = Duration::from_secs(10);
+
+/// The tint at sunset and sunrise, which fades into `NIGHT_TINT` as it gets darker
+const DUSK_TINT: Color = Color { r: 1.0, g: 0.5, b: 0.2, a: 0.0 };
+
+/// The tint in the middle of the night
+const NIGHT_TINT: Color = Color { r: 0.05, g: 0.05, b: 0.3, a: 0.0 };
+
+/// How strongly the screen is tinted in the middle of the night
+const MAX_TINT_ALPHA: f32 = 0.4;
+
+pub struct WorldEffects {
+    /// The seed for the current day, once it has been received from the server
+    seed: Option<SharedSeed>,
+    /// The last day that the shooting star was seen on
+    star_seen: Option<u64>,
+    /// When to stop showing the shooting star
+    star_until: Option<Instant>,
+    /// The server's day and night cycle, along with how far ahead of the local clock the server's
+    /// clock is in seconds
+    day_cycle: Option<(DayCycle, i64)>,
+}
+
+impl WorldEffects {
+    pub fn new() -> WorldEffects {
+        WorldEffects { seed: None, star_seen: None, star_until: None, day_cycle: None }
+    }
+
+    pub fn set_seed(&mut self, seed: SharedSeed) {
+        self.seed = Some(seed);
+    }
+
+    pub fn set_day_cycle(&mut self, cycle: DayCycle) {
+        let offset = cycle.server_time as i64 - shared::now_secs() as i64;
+        self.day_cycle = Some((cycle, offset));
+    }
+
+    /// How dark it is, from 0 at midday to 1 at midnight, if the server has a day and night cycle
+    fn darkness(&self) -> Option<f32> {
+        let (cycle, offset) = self.day_cycle?;
+        let server_time = shared::now_secs() as i64 + offset;
+        let progress = server_time.rem_euclid(cycle.length_secs as i64) as f32;
+        Some((1.0 - (TAU * progress / cycle.length_secs as f32).cos()) / 2.0)
+    }
+
+    /// Tint the screen for the time of day. Only towns and routes are tinted, since the time of day
+    /// can't be seen indoors.
+    pub fn draw_tint(&self, screen: Rect, map_id: u8) {
+        let darkness = match self.darkness() {
+            Some(darkness) if maps::is_outdoor(map_id) => darkness,
+            _ => return,
+        };
+
+        let mix = |dusk: f32, night: f32| dusk + (night - dusk) * darkness;
+        let tint = Color {
+            r: mix(DUSK_TINT.r, NIGHT_TINT.r),
+            g: mix(DUSK_TINT.g, NIGHT_TINT.g),
+            b: mix(DUSK_TINT.b, NIGHT_TINT.b),
+            a: MAX_TINT_ALPHA * darkness,
+        };
+        let (x, y) = (screen.x as f32, screen.y as f32);
+        draw_rectangle(x, y, screen.width as f32, screen.height as f32, tint);
+    }
+
+    /// Start any effects that are due, returning a notice to show in the chat
+    pub fn update(&mut self) -> Option<&'static str> {
+        let seed = self.seed?;
+        let now = shared::now_secs();
+        if now / shared::SECONDS_PER_DAY != seed.day || self.star_seen == Some(seed.day) {
+            return None;
+        }
+
+        let start = seed.time_of_day(SharedEffect::ShootingStar);
+        let time_of_day = now % shared::SECONDS_PER_DAY;
+        if (start..start + EFFECT_WINDOW).contains(&time_of_day) {
+            self.star_seen = Some(seed.day);
+            self.star_until = Some(Instant::now() + SHOOTING_STAR_DURATION);
+            return Some("A shooting star streaks across the sky! Everyone online can see it");
+        }
+        None
+    }
+
+    /// Draw any effects that are currently showing over the top of the screen
+    pub fn draw(
+        &self,
+        renderer: &mut Renderer,
+        font: &Font,
+        border: &BorderRenderer,
+        screen: Rect,
+    ) {
+        if matches!(self.star_until, Some(until) if Instant::now() < until) {
+            let label = "* SHOOTING STAR *";
+            let x = screen.x + (screen.width - label.len() as i32 * font.char_width()) / 2;
+            font::draw_label(renderer, font, border, label, x, screen.y + screen.height / 4);
+        }
+    }
+}
@@ -0,0 +1,89 @@
+//! Checked access to the ROM and the address space, for the hacks that read or patch data at
+//! offsets from the definitions
+//!
+//! Offsets that are wrong for the loaded ROM (an unsupported revision, or bad downloaded
+//! definitions) can point past the end of a bank, or at a bank the ROM does not have. These
+//! functions return an error describing the access instead of panicking, so the client can carry on
+//! without the feature that needed it.
+use std::{error::Error, fmt, ops::Range};
+
+use gb_emu::mmu::Memory;
+
+/// The size of a switchable ROM bank. Only the offset of an address within its bank is used.
+pub const ROM_BANK_SIZE: usize = 0x4000;
+
+/// The size of the address space that `Memory::sb` writes to
+const ADDRESS_SPACE_SIZE: usize = 0x10000;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AccessError {
+    /// A range of the ROM that runs past the end of its bank, or is in a bank the ROM does not have
+    Rom { bank: usize, addr: u16, len: usize },
+    /// A range of memory that runs past the end of the address space
+    Memory { addr: u16, len: usize },
+}
+
+impl Error for AccessError {}
+
+impl fmt::Display for AccessError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AccessError::Rom { bank, addr, len } => write!(
+                f,
+                "{} bytes at {:#06X} in ROM bank {:#04X} are outside of the ROM",
+                len, addr, bank
+            ),
+            AccessError::Memory { addr, len } => {
+                write!(f, "{} bytes at {:#06X} run past the end of memory", len, addr)
+            }
+        }
+    }
+}
+
+/// The range of a bank that `len` bytes at `addr` cover, or an error if they are not in the ROM.
+/// This takes the ROM's banks rather than the memory, so that offsets can be checked against a ROM
+/// before it is loaded.
+pub fn rom_range(
+    rom: &[impl AsRef<[u8]>],
+    bank: usize,
+    addr: u16,
+    len: usize,
+) -> Result<Range<usize>, AccessError> {
+    let start = (addr as usize) & (ROM_BANK_SIZE - 1);
+    match rom.get(bank).map(|data| data.as_ref()) {
+        Some(data) if start + len <= data.len() => Ok(start..start + len),
+        _ => Err(AccessError::Rom { bank, addr, len }),
+    }
+}
+
+/// Read `len` bytes from a ROM bank
+pub fn rom_bytes(mem: &Memory, bank: usize, addr: u16, len: usize) -> Result<&[u8], AccessError> {
+    let range = rom_range(&mem.cart.rom, bank, addr, len)?;
+    Ok(&mem.cart.rom[bank][range])
+}
+
+/// The bytes of a ROM bank from `addr` to the end of the bank
+pub fn rom_tail(mem: &Memory, bank: usize, addr: u16) -> Result<&[u8], AccessError> {
+    let start = (addr as usize) & (ROM_BANK_SIZE - 1);
+    let len = mem.cart.rom.get(bank).map_or(0, |data| data.len().saturating_sub(start));
+    rom_bytes(mem, bank, addr, len)
+}
+
+/// Overwrite bytes in a ROM bank, which the game reads as if they had always been there
+pub fn write_rom(mem: &mut Memory, bank: usize, addr: u16, data: &[u8]) -> Result<(), AccessError> {
+    let range = rom_range(&mem.cart.rom, bank, addr, data.len())?;
+    mem.cart.rom[bank][range].copy_from_slice(data);
+    Ok(())
+}
+
+/// Write bytes to consecutive addresses. Nothing is written if they would run past the end of the
+/// address space.
+pub fn write_bytes(mem: &mut Memory, addr: u16, data: &[u8]) -> Result<(), AccessError> {
+    if addr as usize + data.len() > ADDRESS_SPACE_SIZE {
+        return Err(AccessError::Memory { addr, len: data.len() });
+    }
+    for (i, &val) in data.iter().enumerate() {
+        mem.sb(addr + i as u16, val);
+    }
+    Ok(())
+}
@@ -19,6 +19,20 @@ impl MovementData {
         MovementData { map_id: 0, map_x: 0, map_y: 0, direction: Direction::Down, walk_counter: 0 }
     }
 
+    /// Movement data that only gives the area a player is in, and not where they are in it
+    pub fn hidden(map_id: u8) -> MovementData {
+        MovementData {
+            map_id,
+            map_x: HIDDEN_POSITION,
+            map_y: HIDDEN_POSITION,
+            ..MovementData::new()
+        }
+    }
+
+    pub fn is_hidden(&self) -> bool {
+        (self.map_x, self.map_y) == (HIDDEN_POSITION, HIDDEN_POSITION)
+    }
+
     /// Returns the tile that the player is currently moving towards
     pub fn move_target(&self) -> (u8, u8) {
         if self.walk_counter != 0 {
@@ -35,6 +49,54 @@ impl MovementData {
     }
 }
 
+/// The size of a player's sprite sheet: six 16x16 frames with one byte per pixel
+pub const SPRITE_SHEET_SIZE: usize = 16 * 16 * 6;
+
+/// The maximum number of characters in a player's name
+pub const MAX_NAME_LENGTH: usize = 11;
+
+/// The position given to players that only share which area they are in. No map is this large, so
+/// these players are never drawn or collided with.
+pub const HIDDEN_POSITION: u8 = 0xFF;
+
+/// The value of the walk counter when a player starts moving to a new tile
+pub const MAX_WALK_COUNTER: u8 = 8;
+
+/// The largest species id in the game
+pub const MAX_SPECIES_ID: u8 = 0xBE;
+
+/// The highest level that a Pokemon can reach
+pub const MAX_LEVEL: u8 = 100;
+
+/// The largest id of an item that can be bought (Max Elixer). Ids above this are unused, or are
+/// TMs and HMs.
+pub const MAX_ITEM_ID: u8 = 0x53;
+
+/// The number of different items that fit in the player's bag
+pub const BAG_CAPACITY: u8 = 20;
+
+/// The largest quantity of a single item that can be held
+pub const MAX_ITEM_QUANTITY: u8 = 99;
+
+/// The most money that the player can hold
+pub const MAX_MONEY: u32 = 999_999;
+
+/// The state of a Pokemon in battle, as shown to players watching the battle
+#[derive(Copy, Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct BattlerData {
+    pub species: u8,
+    pub level: u8,
+    pub hp: u16,
+    pub max_hp: u16,
+}
+
+/// The Pokemon currently fighting in a battle
+#[derive(Copy, Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct BattleSnapshot {
+    pub player: BattlerData,
+    pub enemy: BattlerData,
+}
+
 /// The sprite data for a 16x16 sprite
 #[derive(Clone, Copy)]
 pub struct SpriteData {
@@ -63,14 +125,12 @@ impl PlayerData {
 
     /// Check if this player is occupying a particular tile
     pub fn check_collision(&self, x: u8, y: u8) -> bool {
+        if self.movement_data.is_hidden() {
+            return false;
+        }
         (x, y) == (self.movement_data.map_x, self.movement_data.map_y)
             || (x, y) == self.movement_data.move_target()
     }
-
-    /// Check if one player is visible to another player
-    pub fn is_visible_to(&self, other: &PlayerData) -> bool {
-        self.movement_data.map_id == other.movement_data.map_id
-    }
 }
 
 #[derive(Clone, serde::Serialize, serde::Deserialize)]
@@ -82,6 +142,9 @@ pub struct Party {
 pub const BATTLE_DATA_SIZE: usize = 0x194;
 pub type BattleData = Vec<u8>;
 
+/// The largest move id in the game (Struggle)
+pub const MAX_MOVE_ID: u8 = 0xA5;
+
 #[derive(Clone, serde::Serialize, serde::Deserialize)]
 #[allow(missing_copy_implementations)]
 pub struct PokemonData {
@@ -0,0 +1,110 @@
+//! Definitions files, which replace the built-in offsets at runtime
+use std::{collections::HashMap, error::Error, fmt};
+
+use crate::{
+    checked::{self, AccessError},
+    extract,
+    offsets::{self, Offset},
+    rom,
+};
+
+/// The data read from the ROM, as the names of the bank and address offsets that locate it and the
+/// number of bytes that are read there. Lists of names are only checked for their first entry.
+const ROM_DATA: &[(&str, &str, usize)] = &[
+    ("RED_SPRITE_BANK", "RED_SPRITE_ADDR", extract::SPRITE_DATA_SIZE),
+    ("BLUE_SPRITE_BANK", "BLUE_SPRITE_ADDR", extract::SPRITE_DATA_SIZE),
+    ("OAK_SPRITE_BANK", "OAK_SPRITE_ADDR", extract::SPRITE_DATA_SIZE),
+    // 128 characters of 8 rows, at 1 bit per pixel
+    ("FONT_BANK", "FONT_ADDR", 128 * 8),
+    // 7 tiles of 8 rows, at 2 bits per pixel
+    ("BORDER_BANK", "BORDER_ADDR", 7 * 8 * 2),
+    ("MONSTER_NAMES_BANK", "MONSTER_NAMES_ADDR", 10),
+    ("ITEM_NAMES_BANK", "ITEM_NAMES_ADDR", 1),
+    // The largest party that is written there, with its header and terminator
+    ("PROF_OAK_DATA_BANK", "PROF_OAK_DATA_ADDR", 1 + 6 * 2 + 1),
+    ("BATTLE_BANK", "SELECT_ENEMY_MOVE", 1),
+];
+
+/// A set of offsets, along with the games that they are valid for
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct Definitions {
+    /// The titles (from the ROM header) of the games that these definitions are valid for
+    pub games: Vec<String>,
+    /// The value of each offset, by name. Offsets that are not listed use their built-in value.
+    pub offsets: HashMap<String, u32>,
+}
+
+#[derive(Debug)]
+pub enum DefinitionsError {
+    UnsupportedGame(String),
+    UnknownOffset(String),
+    InvalidValue(String),
+    /// The data located by the named address offset is not in the ROM
+    OutsideRom(String, AccessError),
+}
+
+impl Error for DefinitionsError {}
+
+impl fmt::Display for DefinitionsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DefinitionsError::UnsupportedGame(title) => {
+                write!(f, "definitions do not support the loaded game ({})", title)
+            }
+            DefinitionsError::UnknownOffset(name) => write!(f, "unknown offset: {}", name),
+            DefinitionsError::InvalidValue(name) => write!(f, "invalid value for offset: {}", name),
+            DefinitionsError::OutsideRom(name, e) => write!(f, "{} is wrong: {}", name, e),
+        }
+    }
+}
+
+impl Definitions {
+    /// Check that the definitions are valid for a ROM, and that the data they locate in the ROM is
+    /// there
+    pub fn validate(&self, rom: &[u8]) -> Result<(), DefinitionsError> {
+        let title = rom::title(rom).unwrap_or_default();
+        if !self.games.iter().any(|game| game == title) {
+            return Err(DefinitionsError::UnsupportedGame(title.to_string()));
+        }
+
+        for (name, &value) in &self.offsets {
+            let valid = match find_offset(name) {
+                Some(Offset::Address(_)) => value <= u16::MAX as u32,
+                Some(Offset::Bank(_)) => (value as usize) < rom.len() / rom::BANK_SIZE,
+                None => return Err(DefinitionsError::UnknownOffset(name.clone())),
+            };
+            if !valid {
+                return Err(DefinitionsError::InvalidValue(name.clone()));
+            }
+        }
+
+        // Offsets that are not listed keep their built-in value, which is checked along with them
+        let value =
+            |name: &str| self.offsets.get(name).copied().or_else(|| offsets::built_in(name));
+        let banks: Vec<&[u8]> = rom.chunks(rom::BANK_SIZE).collect();
+        for &(bank_name, addr_name, len) in ROM_DATA {
+            let (bank, addr) = (value(bank_name).unwrap_or(0), value(addr_name).unwrap_or(0));
+            checked::rom_range(&banks, bank as usize, addr as u16, len)
+                .map_err(|e| DefinitionsError::OutsideRom(addr_name.to_string(), e))?;
+        }
+
+        Ok(())
+    }
+
+    /// Replace the offsets in use with these definitions. The definitions should be validated
+    /// against the loaded ROM first.
+    pub fn apply(&self) {
+        offsets::reset();
+        for (name, &value) in &self.offsets {
+            match find_offset(name) {
+                Some(Offset::Address(address)) => address.set(value as u16),
+                Some(Offset::Bank(bank)) => bank.set(value as usize),
+                None => {}
+            }
+        }
+    }
+}
+
+fn find_offset(name: &str) -> Option<&'static Offset> {
+    offsets::ALL.iter().find(|(offset_name, _)| *offset_name == name).map(|(_, offset)| offset)
+}
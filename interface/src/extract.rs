@@ -4,25 +4,42 @@ use gb_emu::graphics;
 use gb_emu::mmu::Memory;
 
 use crate::{
-    data::{BattleData, MovementData, Party, PokemonData, BATTLE_DATA_SIZE},
-    offsets, text,
-    values::Direction,
+    checked,
+    data::{
+        BattleData, BattleSnapshot, BattlerData, MovementData, Party, PokemonData, BAG_CAPACITY,
+        BATTLE_DATA_SIZE, MAX_WALK_COUNTER,
+    },
+    maps, offsets,
+    rom::Generation,
+    text,
+    values::{ActiveBattle, Direction, PlayerSprite},
 };
 
+/// Whether the game has offsets for more than movement and chat, which Gen 2 does not have yet.
+/// Everything else that is read for other players is left out for Gen 2 games.
+fn has_game_data() -> bool {
+    offsets::generation() == Generation::One
+}
+
 pub fn movement_data(mem: &Memory) -> MovementData {
+    let walk_counter = match offsets::generation() {
+        Generation::One => mem.lb(offsets::WALK_COUNTER.get()),
+        // Gen 2 counts the frames left in the step, which is twice the walk counter
+        Generation::Two => mem.lb(offsets::WALK_COUNTER.get()).div_ceil(2).min(MAX_WALK_COUNTER),
+    };
     MovementData {
-        map_id: mem.lb(offsets::MAP_ID),
-        map_x: mem.lb(offsets::MAP_X),
-        map_y: mem.lb(offsets::MAP_Y),
-        direction: Direction::from_u8(mem.lb(offsets::PLAYER_DIR)).unwrap_or(Direction::Down),
-        walk_counter: mem.lb(offsets::WALK_COUNTER),
+        map_id: mem.lb(offsets::MAP_ID.get()),
+        map_x: mem.lb(offsets::MAP_X.get()),
+        map_y: mem.lb(offsets::MAP_Y.get()),
+        direction: Direction::from_u8(mem.lb(offsets::PLAYER_DIR.get())).unwrap_or(Direction::Down),
+        walk_counter,
     }
 }
 
 pub fn player_name(mem: &Memory) -> Vec<u8> {
     let mut name = vec![];
 
-    let mut offset = offsets::PLAYER_NAME_START;
+    let mut offset = offsets::PLAYER_NAME_START.get();
     for _ in 0..11 {
         match mem.lb(offset) {
             text::special::TERMINATOR => break,
@@ -33,11 +50,72 @@ pub fn player_name(mem: &Memory) -> Vec<u8> {
     name
 }
 
+/// The town or route that the player is in or was last in, without their exact position
+pub fn region(mem: &Memory) -> u8 {
+    let map_id = mem.lb(offsets::MAP_ID.get());
+    if !has_game_data() || maps::is_outdoor(map_id) {
+        map_id
+    }
+    else {
+        mem.lb(offsets::LAST_MAP.get())
+    }
+}
+
+/// The number of times the player has entered the Hall of Fame
+pub fn hall_of_fame_count(mem: &Memory) -> u8 {
+    if !has_game_data() {
+        return 0;
+    }
+    mem.lb(offsets::NUM_HOF_TEAMS.get())
+}
+
 pub fn battle_data(mem: &Memory) -> BattleData {
-    let base_offset = offsets::PLAYER_BATTLE_DATA_START;
+    let base_offset = offsets::PLAYER_BATTLE_DATA_START.get();
     (0..BATTLE_DATA_SIZE as u16).map(|i| mem.lb(base_offset + i)).collect()
 }
 
+/// The species and level of the Pokemon the player is battling, if they are in a wild battle
+pub fn wild_encounter(mem: &Memory) -> Option<(u8, u8)> {
+    if !has_game_data() || mem.lb(offsets::ACTIVE_BATTLE.get()) != ActiveBattle::Wild as u8 {
+        return None;
+    }
+    // For wild battles the current opponent is the species of the wild Pokemon
+    Some((mem.lb(offsets::CURRRENT_OPPONENT.get()), mem.lb(offsets::CURRENT_ENEMY_LEVEL.get())))
+}
+
+/// The Pokemon currently fighting, if the player is in a battle
+pub fn battle_snapshot(mem: &Memory) -> Option<BattleSnapshot> {
+    if !in_battle(mem) {
+        return None;
+    }
+    Some(BattleSnapshot {
+        player: battler_data(mem, offsets::BATTLE_MON.get()),
+        enemy: battler_data(mem, offsets::ENEMY_MON.get()),
+    })
+}
+
+fn battler_data(mem: &Memory, addr: u16) -> BattlerData {
+    // The game stores the stats of a Pokemon as big-endian values
+    let read_u16 = |addr| u16::from_be_bytes([mem.lb(addr), mem.lb(addr + 1)]);
+    BattlerData {
+        species: mem.lb(addr),
+        hp: read_u16(addr + 1),
+        level: mem.lb(addr + 14),
+        max_hp: read_u16(addr + 15),
+    }
+}
+
+/// The name of a species, read from the ROM
+pub fn species_name(mem: &Memory, species: u8) -> Vec<u8> {
+    const NAME_LENGTH: usize = 10;
+
+    let bank = offsets::MONSTER_NAMES_BANK.get();
+    let names = checked::rom_tail(mem, bank, offsets::MONSTER_NAMES_ADDR.get()).unwrap_or(&[]);
+    let offset = (species.saturating_sub(1) as usize) * NAME_LENGTH;
+    let name = names.get(offset..offset + NAME_LENGTH).unwrap_or(&[]);
+    name.iter().copied().take_while(|&val| val != text::special::TERMINATOR).collect()
+}
+
 fn pokemon_data(mem: &Memory, addr: u16) -> PokemonData {
     PokemonData {
         species: mem.lb(addr + 0),
@@ -68,44 +146,133 @@ fn pokemon_data(mem: &Memory, addr: u16) -> PokemonData {
     }
 }
 
+/// The species of each Pokemon in the player's party
+pub fn party_species(mem: &Memory) -> Vec<u8> {
+    const MAX_PARTY_SIZE: u8 = 6;
+    species_list(mem, offsets::PARTY_COUNT.get(), offsets::PARTY_SPECIES.get(), MAX_PARTY_SIZE)
+}
+
+/// The species and level of each Pokemon in the player's party
+pub fn party_levels(mem: &Memory) -> Vec<(u8, u8)> {
+    const LEVEL_OFFSET: u16 = 33;
+    let addrs = [
+        offsets::PARTY_POKE_1.get(),
+        offsets::PARTY_POKE_2.get(),
+        offsets::PARTY_POKE_3.get(),
+        offsets::PARTY_POKE_4.get(),
+        offsets::PARTY_POKE_5.get(),
+        offsets::PARTY_POKE_6.get(),
+    ];
+    let species = party_species(mem);
+    species
+        .into_iter()
+        .zip(addrs)
+        .map(|(species, addr)| (species, mem.lb(addr + LEVEL_OFFSET)))
+        .collect()
+}
+
+/// The species of each Pokemon in the current PC box
+pub fn box_species(mem: &Memory) -> Vec<u8> {
+    const MAX_BOX_SIZE: u8 = 20;
+    species_list(mem, offsets::BOX_COUNT.get(), offsets::BOX_SPECIES.get(), MAX_BOX_SIZE)
+}
+
+/// The total quantity of every item in the player's bag
+pub fn bag_item_count(mem: &Memory) -> u32 {
+    let num_items = mem.lb(offsets::NUM_BAG_ITEMS.get()).min(BAG_CAPACITY);
+    let quantities = (0..num_items as u16).map(|i| mem.lb(offsets::BAG_ITEMS.get() + 2 * i + 1));
+    quantities.map(|quantity| quantity as u32).sum()
+}
+
+/// The amount of money the player has
+pub fn money(mem: &Memory) -> u32 {
+    let addr = offsets::PLAYER_MONEY.get();
+    (0..3).map(|i| mem.lb(addr + i)).fold(0, |money, bcd| {
+        money * 100 + (bcd >> 4).min(9) as u32 * 10 + (bcd & 0xF).min(9) as u32
+    })
+}
+
+/// Whether the player is in a battle
+pub fn in_battle(mem: &Memory) -> bool {
+    has_game_data() && mem.lb(offsets::ACTIVE_BATTLE.get()) != ActiveBattle::None as u8
+}
+
+/// The name of an item, read from the ROM
+pub fn item_name(mem: &Memory, item: u8) -> Vec<u8> {
+    let bank = offsets::ITEM_NAMES_BANK.get();
+    let rom = checked::rom_tail(mem, bank, offsets::ITEM_NAMES_ADDR.get()).unwrap_or(&[]);
+    let mut names = rom.split(|&val| val == text::special::TERMINATOR);
+    names.nth(item.saturating_sub(1) as usize).unwrap_or(&[]).to_vec()
+}
+
+fn species_list(mem: &Memory, count_addr: u16, list_addr: u16, max_count: u8) -> Vec<u8> {
+    let count = mem.lb(count_addr).min(max_count);
+    (0..count as u16).map(|i| mem.lb(list_addr + i)).collect()
+}
+
 // Currently this has been changed to use a more specific method, however we may want to use this
 // for other things in the future. (e.g. server trainers)
 pub fn player_party(mem: &Memory) -> Party {
     Party {
-        num_pokemon: mem.lb(offsets::PARTY_COUNT),
+        num_pokemon: mem.lb(offsets::PARTY_COUNT.get()),
         pokemon: (
-            pokemon_data(mem, offsets::PARTY_POKE_1),
-            pokemon_data(mem, offsets::PARTY_POKE_2),
-            pokemon_data(mem, offsets::PARTY_POKE_3),
-            pokemon_data(mem, offsets::PARTY_POKE_4),
-            pokemon_data(mem, offsets::PARTY_POKE_5),
-            pokemon_data(mem, offsets::PARTY_POKE_6),
+            pokemon_data(mem, offsets::PARTY_POKE_1.get()),
+            pokemon_data(mem, offsets::PARTY_POKE_2.get()),
+            pokemon_data(mem, offsets::PARTY_POKE_3.get()),
+            pokemon_data(mem, offsets::PARTY_POKE_4.get()),
+            pokemon_data(mem, offsets::PARTY_POKE_5.get()),
+            pokemon_data(mem, offsets::PARTY_POKE_6.get()),
         ),
     }
 }
 
 pub fn default_sprite(mem: &Memory) -> Vec<u8> {
-    extract_sprite(mem, offsets::BLUE_SPRITE_BANK, offsets::BLUE_SPRITE_ADDR)
+    player_sprite(mem, PlayerSprite::default())
+}
+
+pub fn player_sprite(mem: &Memory, sprite: PlayerSprite) -> Vec<u8> {
+    match sprite {
+        PlayerSprite::Red => {
+            extract_sprite(mem, offsets::RED_SPRITE_BANK.get(), offsets::RED_SPRITE_ADDR.get())
+        }
+        PlayerSprite::Blue => {
+            extract_sprite(mem, offsets::BLUE_SPRITE_BANK.get(), offsets::BLUE_SPRITE_ADDR.get())
+        }
+        PlayerSprite::Oak => {
+            extract_sprite(mem, offsets::OAK_SPRITE_BANK.get(), offsets::OAK_SPRITE_ADDR.get())
+        }
+    }
 }
 
 const TILE_SIZE: usize = 8;
 
+const SPRITE_ELEMENTS: usize = 6;
+const SPRITE_TILES: usize = 4 * SPRITE_ELEMENTS;
+
+/// The size of a player's sprite sheet in the ROM, where each row of a tile is stored in two bytes
+pub(crate) const SPRITE_DATA_SIZE: usize = SPRITE_TILES * TILE_SIZE * 2;
+
 fn extract_sprite(mem: &Memory, bank: usize, addr: u16) -> Vec<u8> {
     const SPRITE_SIZE: usize = 16;
-    const NUM_ELEMENTS: usize = 6;
-    const NUM_TILES: usize = 4 * NUM_ELEMENTS;
-    const BUFFER_SIZE: usize = SPRITE_SIZE * SPRITE_SIZE * NUM_ELEMENTS;
+    const BUFFER_SIZE: usize = SPRITE_SIZE * SPRITE_SIZE * SPRITE_ELEMENTS;
 
     let mut buffer: Vec<_> = iter::repeat(0).take(BUFFER_SIZE).collect();
-    let mut sprite_offset = (addr & 0x3FFF) as usize;
+    let data = match checked::rom_bytes(mem, bank, addr, SPRITE_DATA_SIZE) {
+        Ok(data) => data,
+        Err(e) => {
+            println!("Failed to extract a sprite: {}", e);
+            return buffer;
+        }
+    };
+    let mut sprite_offset = 0;
 
     let (mut tile_x, mut tile_y) = (0, 0);
-    while tile_x + 2 * tile_y < NUM_TILES {
+    while tile_x + 2 * tile_y < SPRITE_TILES {
         for y in 0..TILE_SIZE {
             // Colors stored in the 2bpp format are split over two bytes. The color's lower bit is
             // stored in the first byte and the high bit is stored in the second byte.
-            let color_low = mem.cart.rom[bank][sprite_offset];
-            let color_high = mem.cart.rom[bank][sprite_offset + 1];
+            let color_low = data[sprite_offset];
+            let color_high = data[sprite_offset + 1];
             sprite_offset += 2;
 
             for x in 0..TILE_SIZE {
@@ -151,13 +318,25 @@ pub fn extract_texture(
     let num_y_tiles = height / TILE_SIZE;
 
     let mut output_buffer = vec![0; width * height * BYTES_PER_PIXEL_OUT];
-    let mut sprite_offset = (addr & 0x3FFF) as usize;
+    let len = num_x_tiles * num_y_tiles * TILE_SIZE * format as usize;
+    let data = match checked::rom_bytes(mem, bank, addr, len) {
+        Ok(data) => data,
+        Err(e) => {
+            println!("Failed to extract a texture: {}", e);
+            return output_buffer;
+        }
+    };
+    let mut sprite_offset = 0;
 
     let (mut tile_x, mut tile_y) = (0, 0);
     while tile_y < num_y_tiles {
         for y in 0..8 {
-            let color_low = mem.cart.rom[bank][sprite_offset];
-            let color_high = mem.cart.rom[bank][sprite_offset + 1];
+            // 1bpp textures only have the low bit of each color
+            let color_low = data[sprite_offset];
+            let color_high = match format {
+                TextureFormat::Bpp1 => 0,
+                TextureFormat::Bpp2 => data[sprite_offset + 1],
+            };
 
             sprite_offset += format as usize;
 
@@ -0,0 +1,220 @@
+//! A single entry point for frontends that embed the game
+//!
+//! The rest of this crate exposes the details of how the game is read and patched: offsets into
+//! memory, hooks that run after every instruction, and functions for extracting data. `Facade`
+//! wraps these into the handful of operations a frontend needs to run a multiplayer game, so a new
+//! frontend (or a test harness) only needs an emulator and this type. The lower level modules are
+//! still available for anything more specific, such as `extract` and `text`.
+use gb_emu::{cpu::Cpu, graphics, mmu::Memory};
+
+use crate::{
+    data::{BattleData, MovementData, PlayerData, SpriteData},
+    extract, hacks, offsets,
+    relay::{self, BattleRelay, ItemRule, Loser},
+    rom::Generation,
+    values::{Direction, PlayerSprite},
+    watch::{Changes, MemoryWatch},
+    InterfaceData, InterfaceState, NetworkRequest, PlayerId,
+};
+
+pub struct Facade {
+    /// The state shared between the game hooks and the frontend, including the other players
+    pub data: InterfaceData,
+    watch: MemoryWatch,
+}
+
+impl Facade {
+    pub fn new() -> Facade {
+        Facade { data: InterfaceData::new(), watch: MemoryWatch::new() }
+    }
+
+    /// Run every hook on the game. This must be called after each instruction the emulator runs.
+    pub fn on_tick(&mut self, cpu: &mut Cpu, mem: &mut Memory) {
+        // The hooks patch code that is only in Gen 1 games
+        if offsets::generation() != Generation::One {
+            return;
+        }
+        hacks::sprite_check(cpu, mem, &mut self.data);
+        hacks::battle_relay(cpu, mem, &mut self.data);
+        hacks::display_text(cpu, mem, &mut self.data);
+        hacks::sprite_update_tracker(cpu, mem, &mut self.data);
+    }
+
+    /// Whether the game is waiting on the frontend, e.g. for the opponent's move in a relayed
+    /// battle. The emulator should not be run while this is true.
+    pub fn is_waiting(&self) -> bool {
+        self.data.state == InterfaceState::Waiting
+    }
+
+    /// Take the request that the game has made of the network, if there is one
+    pub fn take_network_request(&mut self) -> NetworkRequest {
+        std::mem::replace(&mut self.data.network_request, NetworkRequest::None)
+    }
+
+    /// Stop waiting on the frontend, e.g. after a battle request was refused
+    pub fn resume(&mut self) {
+        self.data.state = InterfaceState::Normal;
+    }
+
+    /// Bring the local player's data up to date after a frame, only extracting the parts whose
+    /// memory has changed. Returns everything that changed, including parts of the game that are
+    /// not in the player's data, such as whether they are in a battle.
+    pub fn update_local_player(&mut self, mem: &Memory, player_data: &mut PlayerData) -> Changes {
+        let changes = self.watch.check(mem);
+        if changes.name {
+            player_data.name = extract::player_name(mem);
+        }
+        if changes.position {
+            player_data.movement_data = extract::movement_data(mem);
+        }
+        changes
+    }
+
+    /// Extract a sprite sheet for the local player
+    pub fn player_sprite(&self, mem: &Memory, sprite: PlayerSprite) -> Vec<u8> {
+        extract::player_sprite(mem, sprite)
+    }
+
+    /// The local player's party, to be sent to an opponent
+    pub fn battle_data(&self, mem: &Memory) -> BattleData {
+        extract::battle_data(mem)
+    }
+
+    /// Start a battle against another player's party, optionally with the opponent choosing the
+    /// enemy's moves. Returns false if the opponent is not a known player, or their party could not
+    /// be loaded into the game.
+    pub fn start_battle(
+        &mut self,
+        mem: &mut Memory,
+        opponent: PlayerId,
+        battle_data: BattleData,
+        relay: Option<ItemRule>,
+    ) -> bool {
+        let enemy = match self.data.players.get(&opponent) {
+            Some(enemy) => enemy,
+            None => return false,
+        };
+        if let Err(e) = crate::set_battle(mem, enemy, battle_data) {
+            println!("Failed to start the battle: {}", e);
+            return false;
+        }
+        self.data.battle_relay = relay.map(|item_rule| BattleRelay::new(opponent, item_rule, mem));
+        true
+    }
+
+    /// End the relayed battle early because one of the players forfeited it. The game stops waiting
+    /// for the opponent's moves and finishes the battle with the losing side fainted. Returns the
+    /// opponent, if there was a relayed battle.
+    pub fn forfeit_battle(&mut self, mem: &mut Memory, loser: Loser) -> Option<PlayerId> {
+        let relay = self.data.battle_relay.take()?;
+        if let Err(e) = relay::concede(mem, loser) {
+            println!("Failed to end the battle: {}", e);
+        }
+        self.resume();
+        Some(relay.opponent)
+    }
+
+    /// Fix the sequence of random numbers used by the game, so that both players in a battle see
+    /// the same results
+    pub fn set_rng_state(&self, mem: &mut Memory, state: [u8; 2]) {
+        crate::set_rng_state(mem, state);
+    }
+
+    /// Show a message in the game's text box
+    pub fn show_message(&mut self, text: &str) {
+        self.data.create_message_box(text);
+    }
+
+    /// Draw every other player that is visible to the local player to the screen. This should be
+    /// called during vblank, so that it does not affect partially drawn lines.
+    pub fn draw_players(&self, mem: &mut Memory, local: &PlayerData) {
+        if !self.data.sprites_enabled() {
+            return;
+        }
+        let map_id = local.movement_data.map_id;
+        for (&id, player) in &self.data.players {
+            if self.data.visibility.is_visible(id, player, map_id) {
+                let sprite_data = player_sprite_data(&local.movement_data, &player.movement_data);
+                crate::render_sprite(mem, &player.sprite, &sprite_data);
+            }
+        }
+    }
+}
+
+impl Default for Facade {
+    fn default() -> Facade {
+        Facade::new()
+    }
+}
+
+/// Get the sprite data for drawing a player at a position relative to the local player
+pub fn player_sprite_data(local: &MovementData, movement_data: &MovementData) -> SpriteData {
+    sprite_data_at(local, movement_data, player_position(movement_data))
+}
+
+/// Get the sprite data for drawing a player at a position on the map (in pixels) other than the
+/// one that their movement data gives, such as while smoothing out their movement
+pub fn sprite_data_at(
+    local: &MovementData,
+    movement_data: &MovementData,
+    position: (i32, i32),
+) -> SpriteData {
+    let (x, y) = player_draw_position(local, position);
+    let (index, flags) = sprite_index_and_flags(movement_data);
+    SpriteData { x: x as isize, y: y as isize, index: index as usize, flags }
+}
+
+/// Get the screen coordinates of where to draw a player at a position on the map, adjusted
+/// relative to the local player's screen
+fn player_draw_position(local: &MovementData, (other_x, other_y): (i32, i32)) -> (i32, i32) {
+    let base_x = (graphics::WIDTH as i32) / 2 - 16;
+    let base_y = (graphics::HEIGHT as i32) / 2 - 12;
+
+    let (self_x, self_y) = player_position(local);
+
+    (other_x - self_x + base_x, other_y - self_y + base_y)
+}
+
+/// Get the position of a player on their map in pixels, including any progress between tiles
+pub fn player_position(movement_data: &MovementData) -> (i32, i32) {
+    let x = movement_data.map_x as i32 * 16;
+    let y = movement_data.map_y as i32 * 16;
+
+    // Determine the offset of the player between tiles:
+    // When a player begins walking, the walk counter is set to 8. For each step the walk counter
+    // decreases by one, and the player is moved by two pixels, until the walk counter is 0. When
+    // we reach this point, the players map coordinate updated.
+    let ticks = movement_data.walk_counter;
+    let offset = if ticks == 0 { 0 } else { (8 - ticks) * 2 } as i32;
+
+    match movement_data.direction {
+        Direction::Down => (x, y + offset),
+        Direction::Up => (x, y - offset),
+        Direction::Left => (x - offset, y),
+        Direction::Right => (x + offset, y),
+    }
+}
+
+fn sprite_index_and_flags(movement_data: &MovementData) -> (isize, u8) {
+    // Determine the base sprite index and flags that need to be set based on the direction the
+    // player is currently facing.
+    let (mut index, mut flags) = match movement_data.direction {
+        Direction::Down => (0, 0x00),
+        Direction::Up => (1, 0x00),
+        Direction::Left => (2, 0x00),
+        Direction::Right => (2, 0x20),
+    };
+
+    // Set the flag that indicates background data may be drawn on top. I'm not sure if this is
+    // strictly necessary, however it seems to be set by most sprites.
+    flags |= 0x80;
+
+    // Change the frame which is displayed based on
+    index += match (movement_data.walk_counter / 4) & 1 {
+        0 => 0,
+        1 => 3,
+        _ => unreachable!(),
+    };
+
+    (index, flags)
+}
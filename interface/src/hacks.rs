@@ -2,33 +2,39 @@ use gb_emu::cpu::Cpu;
 use gb_emu::mmu::Memory;
 
 use super::{InterfaceData, InterfaceState, DataState, NetworkRequest, offsets, text};
+use crate::relay::{skip_enemy_move, withdraw_enemy, RelayAction};
+
+/// Shown when talking to another player, while asking them to battle
+pub const WAITING_FOR_OPPONENT: &str = "Waiting for\nopponent...";
 
 pub fn sprite_check(cpu: &mut Cpu, mem: &mut Memory, interface_data: &mut InterfaceData) {
-    if cpu.pc == offsets::OVERWORLD_LOOP_START {
+    if cpu.pc == offsets::OVERWORLD_LOOP_START.get() {
         interface_data.sprite_id_state = DataState::Normal;
     }
 
-    if (cpu.pc == offsets::SPRITE_CHECK_EXIT_1 && mem.lb(offsets::NUM_SPRITES) == 0) ||
-        cpu.pc == offsets::SPRITE_CHECK_EXIT_2
+    if (cpu.pc == offsets::SPRITE_CHECK_EXIT_1.get() && mem.lb(offsets::NUM_SPRITES.get()) == 0) ||
+        cpu.pc == offsets::SPRITE_CHECK_EXIT_2.get()
     {
-        let map_id = mem.lb(offsets::MAP_ID);
+        let map_id = mem.lb(offsets::MAP_ID.get());
 
         // Determine the tile that the player is trying to move into.
-        let mut x = mem.lb(offsets::MAP_X);
-        let mut y = mem.lb(offsets::MAP_Y);
-        match mem.lb(offsets::PLAYER_DIR) {
+        let mut x = mem.lb(offsets::MAP_X.get());
+        let mut y = mem.lb(offsets::MAP_Y.get());
+        match mem.lb(offsets::PLAYER_DIR.get()) {
             0x00 => y += 1, // Down
             0x04 => y -= 1, // Up
             0x0C => x += 1, // Right
             _    => x -= 1, // Left
         }
 
-        // Check if there are any other players that occupy this tile
+        // Check if there are any other visible players that occupy this tile
         for (id, player) in interface_data.players.iter() {
-            if player.movement_data.map_id == map_id && player.check_collision(x, y) {
+            if interface_data.visibility.is_visible(*id, player, map_id) &&
+                player.check_collision(x, y)
+            {
                 // If there was a player set a sentinel value so the game thinks that there is
                 // something in the way.
-                mem.sb(offsets::SPRITE_INDEX, 0xFF);
+                mem.sb(offsets::SPRITE_INDEX.get(), 0xFF);
                 interface_data.sprite_id_state = DataState::Hacked;
                 interface_data.last_interaction = *id;
                 break;
@@ -39,27 +45,27 @@ pub fn sprite_check(cpu: &mut Cpu, mem: &mut Memory, interface_data: &mut Interf
 
 pub fn display_text(cpu: &mut Cpu, mem: &mut Memory, interface_data: &mut InterfaceData) {
     if interface_data.sprite_id_state == DataState::Hacked &&
-        cpu.pc == offsets::DISPLAY_TEXT_ID_AFTER_INIT
+        cpu.pc == offsets::DISPLAY_TEXT_ID_AFTER_INIT.get()
     {
         // Skip unnecessary parts of the DISPLAY_TEXT_ID routine releated to finding the correct
         // message address when we are interacting with a hacked object.
-        cpu.jump(offsets::DISPLAY_TEXT_SETUP_DONE);
+        cpu.jump(offsets::DISPLAY_TEXT_SETUP_DONE.get());
         // Set the delay time (this is normally set in the middle of the code we just skipped)
-        mem.sb(offsets::FRAME_COUNTER, 30);
+        mem.sb(offsets::FRAME_COUNTER.get(), 30);
 
         interface_data.text_state = DataState::Hacked;
-        interface_data.create_message_box("PLAYER has nothing\nto say.");
+        interface_data.create_message_box(WAITING_FOR_OPPONENT);
 
         interface_data.network_request = NetworkRequest::Battle(interface_data.last_interaction);
-        // We probably want to defer this until as late as possible, to avoid latency causing too
-        // much of an issue
-        interface_data.state = InterfaceState::Waiting;
+        // The game keeps running while the opponent answers, and is only held once the message
+        // has been shown if they have not answered by then
+        interface_data.state = InterfaceState::Pending;
     }
 
     // If the text state is hacked when running the text processor, read from our message buffer
     // instead of from the emulator's memory
     if interface_data.text_state == DataState::Hacked &&
-        (cpu.pc == offsets::GET_NEXT_CHAR_1 || cpu.pc == offsets::GET_NEXT_CHAR_2)
+        (cpu.pc == offsets::GET_NEXT_CHAR_1.get() || cpu.pc == offsets::GET_NEXT_CHAR_2.get())
     {
         cpu.a = interface_data.current_message.pop_front().unwrap_or(text::special::TERMINATOR);
         cpu.pc += 1;
@@ -67,16 +73,100 @@ pub fn display_text(cpu: &mut Cpu, mem: &mut Memory, interface_data: &mut Interf
 
     // Ensure that when we leave the text processor, we reset the text state so that the next call
     // to the text processor will correctly read from the game.
-    if cpu.pc == offsets::TEXT_PROCESSOR_END {
+    if cpu.pc == offsets::TEXT_PROCESSOR_END.get() {
         interface_data.text_state = DataState::Normal;
+
+        // Hold the game here by waiting for a frame and returning to the same place, which keeps
+        // the screen and music running until the battle is ready or given up on
+        if interface_data.state == InterfaceState::Pending {
+            let [low, high] = offsets::TEXT_PROCESSOR_END.get().to_le_bytes();
+            cpu.sp -= 2;
+            mem.sb(cpu.sp, low);
+            mem.sb(cpu.sp + 1, high);
+            cpu.jump(offsets::DELAY_FRAME.get());
+        }
+    }
+}
+
+pub fn battle_relay(cpu: &mut Cpu, mem: &mut Memory, interface_data: &mut InterfaceData) {
+    let relay = match interface_data.battle_relay {
+        Some(ref mut relay) => relay,
+        None => return,
+    };
+
+    // The relay is finished once the battle ends
+    if mem.lb(offsets::ACTIVE_BATTLE.get()) == 0 {
+        interface_data.battle_relay = None;
+        return;
+    }
+
+    if cpu.pc != offsets::SELECT_ENEMY_MOVE.get() ||
+        mem.lb(offsets::LOADED_ROM_BANK.get()) as usize != offsets::BATTLE_BANK.get()
+    {
+        return;
+    }
+
+    // The player has already chosen what to do by the time the enemy's move is selected
+    if !relay.move_sent {
+        let action = relay.player_action(mem);
+        relay.outgoing.push_back(action);
+        relay.move_sent = true;
+    }
+
+    // Pop the return address, so that we either return from the routine without running it, or
+    // restart the call that got us here.
+    let return_addr = mem.lw(cpu.sp);
+    cpu.sp += 2;
+
+    // While the opponent is not responding, hold the battle at the start of the turn
+    let enemy_action = if relay.paused { None } else { relay.incoming.pop_front() };
+    match enemy_action {
+        Some(RelayAction::Move(enemy_move)) => {
+            mem.sb(offsets::ENEMY_SELECTED_MOVE.get(), enemy_move);
+            relay.move_sent = false;
+            cpu.jump(return_addr);
+        }
+        Some(RelayAction::Switch(index)) => {
+            skip_enemy_move(mem);
+            relay.move_sent = false;
+            let send_out = offsets::ENEMY_SEND_OUT.get();
+            let switched = send_out != 0 && match withdraw_enemy(mem, index) {
+                Ok(switched) => switched,
+                Err(e) => {
+                    println!("Failed to switch the enemy's Pokemon: {}", e);
+                    false
+                }
+            };
+            if switched {
+                // Send out the new Pokemon, which returns to where the enemy's move was selected
+                let [low, high] = return_addr.to_le_bytes();
+                cpu.sp -= 2;
+                mem.sb(cpu.sp, low);
+                mem.sb(cpu.sp + 1, high);
+                cpu.jump(send_out);
+            }
+            else {
+                cpu.jump(return_addr);
+            }
+        }
+        Some(RelayAction::Item) => {
+            skip_enemy_move(mem);
+            relay.move_sent = false;
+            cpu.jump(return_addr);
+        }
+        None => {
+            // Wait for the opponent's move, then run the call (which is 3 bytes long) again
+            cpu.jump(return_addr - 3);
+            interface_data.state = InterfaceState::Waiting;
+        }
     }
 }
 
 pub fn sprite_update_tracker(cpu: &Cpu, mem: &Memory, interface_data: &mut InterfaceData) {
-    if cpu.pc == offsets::UPDATE_SPRITES {
-        interface_data.sprites_enabled = mem.lb(offsets::SPRITES_ENABLED) == 0x01;
+    if cpu.pc == offsets::UPDATE_SPRITES.get() {
+        interface_data.sprites_enabled = mem.lb(offsets::SPRITES_ENABLED.get()) == 0x01;
     }
-    else if cpu.pc == offsets::CLEAR_SPRITES {
+    else if cpu.pc == offsets::CLEAR_SPRITES.get() {
         interface_data.sprites_enabled = false;
     }
 }
@@ -1,14 +1,29 @@
 //! Crate for interfacing with the emulator
+//!
+//! Frontends should start from [`Facade`], which runs the hooks on the game and wraps the
+//! operations needed to play with other players.
 use std::collections::{HashMap, VecDeque};
 
+use checked::AccessError;
 use gb_emu::{graphics, mmu::Memory};
 
+pub mod checked;
 pub mod data;
+pub mod definitions;
 pub mod extract;
+pub mod facade;
 pub mod hacks;
+pub mod maps;
 pub mod offsets;
+pub mod relay;
+pub mod rom;
 pub mod text;
+pub mod textbox;
 pub mod values;
+pub mod visibility;
+pub mod watch;
+
+pub use facade::Facade;
 
 #[derive(PartialEq)]
 enum DataState {
@@ -20,6 +35,9 @@ enum DataState {
 pub enum InterfaceState {
     Normal,
     Waiting,
+    /// The game keeps running, but is held once the current message has been shown until the
+    /// frontend resumes it
+    Pending,
 }
 
 pub type PlayerId = u32;
@@ -35,6 +53,10 @@ pub struct InterfaceData {
     pub network_request: NetworkRequest,
     pub players: HashMap<u32, data::PlayerData>,
     pub last_interaction: u32,
+    /// The battle that is being relayed with another player, if there is one
+    pub battle_relay: Option<relay::BattleRelay>,
+    /// Which of the other players are drawn and collided with
+    pub visibility: visibility::VisibilityPolicy,
     sprite_id_state: DataState,
     text_state: DataState,
     current_message: VecDeque<u8>,
@@ -48,6 +70,8 @@ impl InterfaceData {
             network_request: NetworkRequest::None,
             players: HashMap::new(),
             last_interaction: 0,
+            battle_relay: None,
+            visibility: visibility::VisibilityPolicy::default(),
             sprite_id_state: DataState::Normal,
             text_state: DataState::Normal,
             current_message: VecDeque::new(),
@@ -65,56 +89,133 @@ impl InterfaceData {
         self.current_message.push_back(text::special::END_MSG);
         self.current_message.push_back(text::special::TERMINATOR);
     }
+
+    /// The message waiting to be shown in the text box, in the game's encoding
+    pub fn pending_message(&self) -> Vec<u8> {
+        self.current_message.iter().copied().collect()
+    }
 }
 
 pub fn get_tile_id_addr(x: u8, y: u8) -> u16 {
     let y_offset = (((y + 4) & 0xF0) >> 3) as u16;
     let x_offset = ((x >> 3) + 0x14) as u16;
 
-    offsets::TILE_MAP + 20 * y_offset + x_offset
+    offsets::TILE_MAP.get() + 20 * y_offset + x_offset
 }
 
-/// Loads a target party into the OAK trainer data slot.
-pub fn load_trainer_party(party: data::Party, mem: &mut Memory) {
+/// Loads a target party into the OAK trainer data slot. The ROM is left unchanged if the slot is
+/// not where the offsets say.
+pub fn load_trainer_party(party: data::Party, mem: &mut Memory) -> Result<(), AccessError> {
     let pokemon = party.pokemon;
     let pokemon_array = [pokemon.0, pokemon.1, pokemon.2, pokemon.3, pokemon.4, pokemon.5];
 
-    let mut addr = (offsets::PROF_OAK_DATA_ADDR & 0x3FFF) as usize;
-    let bank = offsets::PROF_OAK_DATA_BANK;
-
-    mem.cart.rom[bank][addr] = 0xFF;
-    addr += 1;
+    let mut trainer_data = vec![0xFF];
     for mon in pokemon_array.iter().take(party.num_pokemon as usize) {
-        mem.cart.rom[bank][addr] = mon.level;
-        mem.cart.rom[bank][addr + 1] = mon.species;
-        addr += 2;
+        trainer_data.extend([mon.level, mon.species]);
+    }
+    trainer_data.push(0);
+
+    let bank = offsets::PROF_OAK_DATA_BANK.get();
+    checked::write_rom(mem, bank, offsets::PROF_OAK_DATA_ADDR.get(), &trainer_data)
+}
+
+/// Sets the state of the game's random number generator. The generator also mixes in the value of
+/// the divider register, which depends on the timing of the emulator, so this fixes the sequence of
+/// random numbers for a given sequence of inputs.
+pub fn set_rng_state(mem: &mut Memory, state: [u8; 2]) {
+    mem.sb(offsets::RANDOM_ADD.get(), state[0]);
+    mem.sb(offsets::RANDOM_SUB.get(), state[1]);
+}
+
+/// Start a battle against the enemy's party. The battle is not started if the enemy's data does not
+/// fit where the offsets say.
+pub fn set_battle(
+    mem: &mut Memory,
+    enemy: &data::PlayerData,
+    battle_data: data::BattleData,
+) -> Result<(), AccessError> {
+    checked::write_bytes(mem, offsets::ENEMY_BATTLE_DATA_START.get(), &battle_data)?;
+    let mut name = enemy.name.clone();
+    name.push(text::special::TERMINATOR);
+    checked::write_bytes(mem, offsets::ENEMY_NAME_START.get(), &name)?;
+
+    mem.sb(offsets::BATTLE_TYPE.get(), values::BattleType::Normal as u8);
+    mem.sb(offsets::ACTIVE_BATTLE.get(), values::ActiveBattle::Trainer as u8);
+    mem.sb(offsets::IS_LINK_BATTLE.get(), values::TRUE);
+    let opponent = values::TrainerClass::ProfOak as u8 + values::TRAINER_TAG;
+    mem.sb(offsets::CURRRENT_OPPONENT.get(), opponent);
+    Ok(())
+}
+
+/// Set the player's money, which is stored as binary-coded decimal. Gen 2 games are left unchanged,
+/// since they do not have offsets for money yet.
+pub fn set_money(mem: &mut Memory, money: u32) {
+    if offsets::generation() != rom::Generation::One {
+        return;
+    }
+    let money = money.min(data::MAX_MONEY);
+    let addr = offsets::PLAYER_MONEY.get();
+    for i in 0..3 {
+        let digits = money / 100u32.pow(2 - i as u32) % 100;
+        mem.sb(addr + i, (((digits / 10) << 4) | (digits % 10)) as u8);
     }
-    mem.cart.rom[bank][addr] = 0;
 }
 
-pub fn set_battle(mem: &mut Memory, enemy: &data::PlayerData, battle_data: data::BattleData) {
-    mem.sb(offsets::BATTLE_TYPE, values::BattleType::Normal as u8);
-    mem.sb(offsets::ACTIVE_BATTLE, values::ActiveBattle::Trainer as u8);
-    mem.sb(offsets::IS_LINK_BATTLE, values::TRUE);
-    mem.sb(offsets::CURRRENT_OPPONENT, values::TrainerClass::ProfOak as u8 + values::TRAINER_TAG);
+/// Add items to the player's bag, stacking them with any of the same item that are already there.
+/// Returns false without changing the bag if there is no room for them, or if the game does not
+/// have offsets for the bag yet.
+pub fn add_bag_item(mem: &mut Memory, item: u8, quantity: u8) -> bool {
+    if offsets::generation() != rom::Generation::One {
+        return false;
+    }
+    let num_items = mem.lb(offsets::NUM_BAG_ITEMS.get()).min(data::BAG_CAPACITY);
+    let items = offsets::BAG_ITEMS.get();
 
-    let mut offset = offsets::ENEMY_BATTLE_DATA_START;
-    for val in battle_data {
-        mem.sb(offset, val);
-        offset += 1;
+    for i in 0..num_items as u16 {
+        if mem.lb(items + 2 * i) == item {
+            let held = mem.lb(items + 2 * i + 1);
+            if held as u16 + quantity as u16 > data::MAX_ITEM_QUANTITY as u16 {
+                return false;
+            }
+            mem.sb(items + 2 * i + 1, held + quantity);
+            return true;
+        }
     }
 
-    offset = offsets::ENEMY_NAME_START;
-    for &val in &enemy.name {
-        mem.sb(offset, val);
-        offset += 1;
+    if num_items >= data::BAG_CAPACITY || quantity > data::MAX_ITEM_QUANTITY {
+        return false;
     }
-    mem.sb(offset, text::special::TERMINATOR);
+    let end = items + 2 * num_items as u16;
+    mem.sb(end, item);
+    mem.sb(end + 1, quantity);
+    // The list of items ends with a terminator
+    mem.sb(end + 2, 0xFF);
+    mem.sb(offsets::NUM_BAG_ITEMS.get(), num_items + 1);
+    true
 }
 
 /// Render a 16x16 sprite
 /// Returns true if the sprite was drawn to the screen
 pub fn render_sprite(mem: &mut Memory, spritesheet: &[u8], sprite_data: &data::SpriteData) -> bool {
+    draw_sprite(mem, spritesheet, sprite_data, false)
+}
+
+/// Render a faded copy of a 16x16 sprite, by only drawing every other pixel
+/// Returns true if the sprite was drawn to the screen
+pub fn render_afterimage(
+    mem: &mut Memory,
+    spritesheet: &[u8],
+    sprite_data: &data::SpriteData,
+) -> bool {
+    draw_sprite(mem, spritesheet, sprite_data, true)
+}
+
+fn draw_sprite(
+    mem: &mut Memory,
+    spritesheet: &[u8],
+    sprite_data: &data::SpriteData,
+    faded: bool,
+) -> bool {
     const SPRITE_HEIGHT: usize = 16;
     const SPRITE_WIDTH: usize = 16;
 
@@ -166,7 +267,14 @@ pub fn render_sprite(mem: &mut Memory, spritesheet: &[u8], sprite_data: &data::S
                 if flags & 0x20 == 0 { SPRITE_WIDTH - dx as usize - 1 } else { dx as usize };
             let color_id = sprite[tile_y * SPRITE_WIDTH + tile_x];
 
-            if color_id != 0 && (flags & 0x80 == 0 || px_priority == 0) && px_priority <= 3 {
+            // Faded sprites skip pixels in a checkerboard pattern
+            let skipped = faded && (sprite_data.x + dx + sprite_data.y + dy) % 2 != 0;
+
+            if !skipped
+                && color_id != 0
+                && (flags & 0x80 == 0 || px_priority == 0)
+                && px_priority <= 3
+            {
                 let color = graphics::palette_lookup(palette, color_id as usize);
                 graphics::write_pixel(
                     &mut gpu.framebuffer,
@@ -0,0 +1,273 @@
+//! Names of the maps in the game
+
+/// The name of each map, indexed by map id. Unused map ids have no name.
+#[rustfmt::skip]
+const MAP_NAMES: [Option<&str>; 0xF8] = [
+    /* 0x00 */ Some("Pallet Town"),
+    /* 0x01 */ Some("Viridian City"),
+    /* 0x02 */ Some("Pewter City"),
+    /* 0x03 */ Some("Cerulean City"),
+    /* 0x04 */ Some("Lavender Town"),
+    /* 0x05 */ Some("Vermilion City"),
+    /* 0x06 */ Some("Celadon City"),
+    /* 0x07 */ Some("Fuchsia City"),
+    /* 0x08 */ Some("Cinnabar Island"),
+    /* 0x09 */ Some("Indigo Plateau"),
+    /* 0x0A */ Some("Saffron City"),
+    /* 0x0B */ None,
+    /* 0x0C */ Some("Route 1"),
+    /* 0x0D */ Some("Route 2"),
+    /* 0x0E */ Some("Route 3"),
+    /* 0x0F */ Some("Route 4"),
+    /* 0x10 */ Some("Route 5"),
+    /* 0x11 */ Some("Route 6"),
+    /* 0x12 */ Some("Route 7"),
+    /* 0x13 */ Some("Route 8"),
+    /* 0x14 */ Some("Route 9"),
+    /* 0x15 */ Some("Route 10"),
+    /* 0x16 */ Some("Route 11"),
+    /* 0x17 */ Some("Route 12"),
+    /* 0x18 */ Some("Route 13"),
+    /* 0x19 */ Some("Route 14"),
+    /* 0x1A */ Some("Route 15"),
+    /* 0x1B */ Some("Route 16"),
+    /* 0x1C */ Some("Route 17"),
+    /* 0x1D */ Some("Route 18"),
+    /* 0x1E */ Some("Route 19"),
+    /* 0x1F */ Some("Route 20"),
+    /* 0x20 */ Some("Route 21"),
+    /* 0x21 */ Some("Route 22"),
+    /* 0x22 */ Some("Route 23"),
+    /* 0x23 */ Some("Route 24"),
+    /* 0x24 */ Some("Route 25"),
+    /* 0x25 */ Some("Red's House 1F"),
+    /* 0x26 */ Some("Red's House 2F"),
+    /* 0x27 */ Some("Blue's House"),
+    /* 0x28 */ Some("Oak's Lab"),
+    /* 0x29 */ Some("Viridian Pokecenter"),
+    /* 0x2A */ Some("Viridian Mart"),
+    /* 0x2B */ Some("Viridian School"),
+    /* 0x2C */ Some("Viridian House"),
+    /* 0x2D */ Some("Viridian Gym"),
+    /* 0x2E */ Some("Diglett's Cave"),
+    /* 0x2F */ Some("Viridian Forest Gate"),
+    /* 0x30 */ Some("Route 2 House"),
+    /* 0x31 */ Some("Route 2 Gate"),
+    /* 0x32 */ Some("Viridian Forest Gate"),
+    /* 0x33 */ Some("Viridian Forest"),
+    /* 0x34 */ Some("Museum 1F"),
+    /* 0x35 */ Some("Museum 2F"),
+    /* 0x36 */ Some("Pewter Gym"),
+    /* 0x37 */ Some("Pewter House"),
+    /* 0x38 */ Some("Pewter Mart"),
+    /* 0x39 */ Some("Pewter House"),
+    /* 0x3A */ Some("Pewter Pokecenter"),
+    /* 0x3B */ Some("Mt. Moon 1F"),
+    /* 0x3C */ Some("Mt. Moon B1F"),
+    /* 0x3D */ Some("Mt. Moon B2F"),
+    /* 0x3E */ Some("Cerulean House"),
+    /* 0x3F */ Some("Cerulean House"),
+    /* 0x40 */ Some("Cerulean Pokecenter"),
+    /* 0x41 */ Some("Cerulean Gym"),
+    /* 0x42 */ Some("Bike Shop"),
+    /* 0x43 */ Some("Cerulean Mart"),
+    /* 0x44 */ Some("Mt. Moon Pokecenter"),
+    /* 0x45 */ Some("Cerulean House"),
+    /* 0x46 */ Some("Route 5 Gate"),
+    /* 0x47 */ Some("Underground Path"),
+    /* 0x48 */ Some("Daycare"),
+    /* 0x49 */ Some("Route 6 Gate"),
+    /* 0x4A */ Some("Underground Path"),
+    /* 0x4B */ Some("Underground Path"),
+    /* 0x4C */ Some("Route 7 Gate"),
+    /* 0x4D */ Some("Underground Path"),
+    /* 0x4E */ Some("Underground Path"),
+    /* 0x4F */ Some("Route 8 Gate"),
+    /* 0x50 */ Some("Underground Path"),
+    /* 0x51 */ Some("Rock Tunnel Pokecenter"),
+    /* 0x52 */ Some("Rock Tunnel 1F"),
+    /* 0x53 */ Some("Power Plant"),
+    /* 0x54 */ Some("Route 11 Gate 1F"),
+    /* 0x55 */ Some("Diglett's Cave"),
+    /* 0x56 */ Some("Route 11 Gate 2F"),
+    /* 0x57 */ Some("Route 12 Gate 1F"),
+    /* 0x58 */ Some("Bill's House"),
+    /* 0x59 */ Some("Vermilion Pokecenter"),
+    /* 0x5A */ Some("Pokemon Fan Club"),
+    /* 0x5B */ Some("Vermilion Mart"),
+    /* 0x5C */ Some("Vermilion Gym"),
+    /* 0x5D */ Some("Vermilion House"),
+    /* 0x5E */ Some("Vermilion Dock"),
+    /* 0x5F */ Some("S.S. Anne 1F"),
+    /* 0x60 */ Some("S.S. Anne 2F"),
+    /* 0x61 */ Some("S.S. Anne 3F"),
+    /* 0x62 */ Some("S.S. Anne B1F"),
+    /* 0x63 */ Some("S.S. Anne Bow"),
+    /* 0x64 */ Some("S.S. Anne Kitchen"),
+    /* 0x65 */ Some("S.S. Anne Captain's Room"),
+    /* 0x66 */ Some("S.S. Anne 1F Rooms"),
+    /* 0x67 */ Some("S.S. Anne 2F Rooms"),
+    /* 0x68 */ Some("S.S. Anne B1F Rooms"),
+    /* 0x69 */ None,
+    /* 0x6A */ None,
+    /* 0x6B */ None,
+    /* 0x6C */ Some("Victory Road 1F"),
+    /* 0x6D */ None,
+    /* 0x6E */ None,
+    /* 0x6F */ None,
+    /* 0x70 */ None,
+    /* 0x71 */ Some("Lance's Room"),
+    /* 0x72 */ None,
+    /* 0x73 */ None,
+    /* 0x74 */ None,
+    /* 0x75 */ None,
+    /* 0x76 */ Some("Hall of Fame"),
+    /* 0x77 */ Some("Underground Path"),
+    /* 0x78 */ Some("Champion's Room"),
+    /* 0x79 */ Some("Underground Path"),
+    /* 0x7A */ Some("Celadon Mart 1F"),
+    /* 0x7B */ Some("Celadon Mart 2F"),
+    /* 0x7C */ Some("Celadon Mart 3F"),
+    /* 0x7D */ Some("Celadon Mart 4F"),
+    /* 0x7E */ Some("Celadon Mart Roof"),
+    /* 0x7F */ Some("Celadon Mart Elevator"),
+    /* 0x80 */ Some("Celadon Mansion 1F"),
+    /* 0x81 */ Some("Celadon Mansion 2F"),
+    /* 0x82 */ Some("Celadon Mansion 3F"),
+    /* 0x83 */ Some("Celadon Mansion Roof"),
+    /* 0x84 */ Some("Celadon Mansion House"),
+    /* 0x85 */ Some("Celadon Pokecenter"),
+    /* 0x86 */ Some("Celadon Gym"),
+    /* 0x87 */ Some("Game Corner"),
+    /* 0x88 */ Some("Celadon Mart 5F"),
+    /* 0x89 */ Some("Prize Room"),
+    /* 0x8A */ Some("Celadon Diner"),
+    /* 0x8B */ Some("Celadon House"),
+    /* 0x8C */ Some("Celadon Hotel"),
+    /* 0x8D */ Some("Lavender Pokecenter"),
+    /* 0x8E */ Some("Pokemon Tower 1F"),
+    /* 0x8F */ Some("Pokemon Tower 2F"),
+    /* 0x90 */ Some("Pokemon Tower 3F"),
+    /* 0x91 */ Some("Pokemon Tower 4F"),
+    /* 0x92 */ Some("Pokemon Tower 5F"),
+    /* 0x93 */ Some("Pokemon Tower 6F"),
+    /* 0x94 */ Some("Pokemon Tower 7F"),
+    /* 0x95 */ Some("Mr. Fuji's House"),
+    /* 0x96 */ Some("Lavender Mart"),
+    /* 0x97 */ Some("Lavender House"),
+    /* 0x98 */ Some("Fuchsia Mart"),
+    /* 0x99 */ Some("Fuchsia House"),
+    /* 0x9A */ Some("Fuchsia Pokecenter"),
+    /* 0x9B */ Some("Warden's House"),
+    /* 0x9C */ Some("Safari Zone Gate"),
+    /* 0x9D */ Some("Fuchsia Gym"),
+    /* 0x9E */ Some("Fuchsia Meeting Room"),
+    /* 0x9F */ Some("Seafoam Islands B1F"),
+    /* 0xA0 */ Some("Seafoam Islands B2F"),
+    /* 0xA1 */ Some("Seafoam Islands B3F"),
+    /* 0xA2 */ Some("Seafoam Islands B4F"),
+    /* 0xA3 */ Some("Vermilion House"),
+    /* 0xA4 */ Some("Fuchsia House"),
+    /* 0xA5 */ Some("Pokemon Mansion 1F"),
+    /* 0xA6 */ Some("Cinnabar Gym"),
+    /* 0xA7 */ Some("Cinnabar Lab"),
+    /* 0xA8 */ Some("Cinnabar Lab"),
+    /* 0xA9 */ Some("Cinnabar Lab"),
+    /* 0xAA */ Some("Cinnabar Lab"),
+    /* 0xAB */ Some("Cinnabar Pokecenter"),
+    /* 0xAC */ Some("Cinnabar Mart"),
+    /* 0xAD */ Some("Cinnabar Mart"),
+    /* 0xAE */ Some("Indigo Plateau Lobby"),
+    /* 0xAF */ Some("Copycat's House 1F"),
+    /* 0xB0 */ Some("Copycat's House 2F"),
+    /* 0xB1 */ Some("Fighting Dojo"),
+    /* 0xB2 */ Some("Saffron Gym"),
+    /* 0xB3 */ Some("Saffron House"),
+    /* 0xB4 */ Some("Saffron Mart"),
+    /* 0xB5 */ Some("Silph Co. 1F"),
+    /* 0xB6 */ Some("Saffron Pokecenter"),
+    /* 0xB7 */ Some("Mr. Psychic's House"),
+    /* 0xB8 */ Some("Route 15 Gate 1F"),
+    /* 0xB9 */ Some("Route 15 Gate 2F"),
+    /* 0xBA */ Some("Route 16 Gate 1F"),
+    /* 0xBB */ Some("Route 16 Gate 2F"),
+    /* 0xBC */ Some("Route 16 House"),
+    /* 0xBD */ Some("Route 12 House"),
+    /* 0xBE */ Some("Route 18 Gate 1F"),
+    /* 0xBF */ Some("Route 18 Gate 2F"),
+    /* 0xC0 */ Some("Seafoam Islands 1F"),
+    /* 0xC1 */ Some("Route 22 Gate"),
+    /* 0xC2 */ Some("Victory Road 2F"),
+    /* 0xC3 */ Some("Route 12 Gate 2F"),
+    /* 0xC4 */ Some("Vermilion House"),
+    /* 0xC5 */ Some("Diglett's Cave"),
+    /* 0xC6 */ Some("Victory Road 3F"),
+    /* 0xC7 */ Some("Rocket Hideout B1F"),
+    /* 0xC8 */ Some("Rocket Hideout B2F"),
+    /* 0xC9 */ Some("Rocket Hideout B3F"),
+    /* 0xCA */ Some("Rocket Hideout B4F"),
+    /* 0xCB */ Some("Rocket Hideout Elevator"),
+    /* 0xCC */ None,
+    /* 0xCD */ None,
+    /* 0xCE */ None,
+    /* 0xCF */ Some("Silph Co. 2F"),
+    /* 0xD0 */ Some("Silph Co. 3F"),
+    /* 0xD1 */ Some("Silph Co. 4F"),
+    /* 0xD2 */ Some("Silph Co. 5F"),
+    /* 0xD3 */ Some("Silph Co. 6F"),
+    /* 0xD4 */ Some("Silph Co. 7F"),
+    /* 0xD5 */ Some("Silph Co. 8F"),
+    /* 0xD6 */ Some("Pokemon Mansion 2F"),
+    /* 0xD7 */ Some("Pokemon Mansion 3F"),
+    /* 0xD8 */ Some("Pokemon Mansion B1F"),
+    /* 0xD9 */ Some("Safari Zone East"),
+    /* 0xDA */ Some("Safari Zone North"),
+    /* 0xDB */ Some("Safari Zone West"),
+    /* 0xDC */ Some("Safari Zone Center"),
+    /* 0xDD */ Some("Safari Zone Rest House"),
+    /* 0xDE */ Some("Safari Zone Secret House"),
+    /* 0xDF */ Some("Safari Zone Rest House"),
+    /* 0xE0 */ Some("Safari Zone Rest House"),
+    /* 0xE1 */ Some("Safari Zone Rest House"),
+    /* 0xE2 */ Some("Cerulean Cave 2F"),
+    /* 0xE3 */ Some("Cerulean Cave B1F"),
+    /* 0xE4 */ Some("Cerulean Cave 1F"),
+    /* 0xE5 */ Some("Name Rater's House"),
+    /* 0xE6 */ Some("Cerulean House"),
+    /* 0xE7 */ None,
+    /* 0xE8 */ Some("Rock Tunnel B1F"),
+    /* 0xE9 */ Some("Silph Co. 9F"),
+    /* 0xEA */ Some("Silph Co. 10F"),
+    /* 0xEB */ Some("Silph Co. 11F"),
+    /* 0xEC */ Some("Silph Co. Elevator"),
+    /* 0xED */ None,
+    /* 0xEE */ None,
+    /* 0xEF */ Some("Trade Center"),
+    /* 0xF0 */ Some("Colosseum"),
+    /* 0xF1 */ None,
+    /* 0xF2 */ None,
+    /* 0xF3 */ None,
+    /* 0xF4 */ None,
+    /* 0xF5 */ Some("Lorelei's Room"),
+    /* 0xF6 */ Some("Bruno's Room"),
+    /* 0xF7 */ Some("Agatha's Room"),
+];
+
+/// The last of the towns and routes, which come before every indoor map
+const LAST_OUTDOOR_MAP: u8 = 0x24;
+
+/// The map that the player's team is recorded on after defeating the Elite Four
+pub const HALL_OF_FAME: u8 = 0x76;
+
+/// The maps in the Safari Zone where Pokemon can be caught
+pub const SAFARI_ZONE: [u8; 4] = [0xD9, 0xDA, 0xDB, 0xDC];
+
+/// Get a human-readable name for a map
+pub fn map_name(map_id: u8) -> Option<&'static str> {
+    MAP_NAMES.get(map_id as usize).copied().flatten()
+}
+
+/// Whether a map is a town or route, rather than a building, cave or other indoor area
+pub fn is_outdoor(map_id: u8) -> bool {
+    map_id <= LAST_OUTDOOR_MAP
+}
@@ -1,90 +1,250 @@
-// Player positional data
-pub const MAP_ID: u16 = 0xD35E;
-pub const MAP_Y: u16 = 0xD361;
-pub const MAP_X: u16 = 0xD362;
-pub const PLAYER_DY: u16 = 0xC103;
-pub const PLAYER_DX: u16 = 0xC105;
-
-// The direction which the player is facing (0: down, 4: up, 8: left, 12: right)
-pub const PLAYER_DIR: u16 = 0xC109;
-
-// When a player moves, this value counts down from 8 to 0
-pub const WALK_COUNTER: u16 = 0xCFC5;
-
-// General player data
-pub const PLAYER_NAME_START: u16 = 0xD158;
-
-// The address of the player spritesheet encoded as 2bpp in the rom
-pub const RED_SPRITE_ADDR: u16 = 0x4180;
-pub const RED_SPRITE_BANK: usize = 5;
-pub const BLUE_SPRITE_ADDR: u16 = 0x4300;
-pub const BLUE_SPRITE_BANK: usize = 5;
-pub const OAK_SPRITE_ADDR: u16 = 0x4480;
-pub const OAK_SPRITE_BANK: usize = 5;
-
-// The address of the main font encoded as a 1bpp sprite in the rom
-pub const FONT_ADDR: u16 = 0x5A80;
-pub const FONT_BANK: usize = 4;
-
-// The address of the textbox border encoded as 2bpp sprite in the rom
-pub const BORDER_ADDR: u16 = 0x6288 + 2 * 8 * (4 * 6 + 1);
-pub const BORDER_BANK: usize = 4;
-
-// The location of the tile map
-pub const TILE_MAP: u16 = 0xC3A0;
-
-// Useful addresses for hacks
-pub const LOADED_ROM_BANK: u16 = 0xFFB8;
-pub const FRAME_COUNTER: u16 = 0xFFD5;
-pub const BANK_SWITCH: u16 = 0x35D6;
-
-// Addresses for sprite check hack
-pub const NUM_SPRITES: u16 = 0xD4E1;
-pub const OVERWORLD_LOOP_START: u16 = 0x03FF;
-pub const SPRITE_CHECK_START: u16 = 0x0B23;
-pub const SPRITE_CHECK_EXIT_1: u16 = 0x0BA0;
-pub const SPRITE_CHECK_EXIT_2: u16 = 0x0BC4;
-pub const SPRITE_INDEX: u16 = 0xFF8C;
-
-// Addresses for sprite update hack
-pub const CLEAR_SPRITES: u16 = 0x0082;
-pub const UPDATE_SPRITES: u16 = 0x2429;
-pub const SPRITES_ENABLED: u16 = 0xCFCB;
-
-// Addresses for display text hack
-pub const DISPLAY_TEXT_ID: u16 = 0x2920;
-pub const DISPLAY_TEXT_ID_AFTER_INIT: u16 = 0x292B;
-pub const DISPLAY_TEXT_SETUP_DONE: u16 = 0x29CD;
-pub const GET_NEXT_CHAR_1: u16 = 0x1B55;
-pub const GET_NEXT_CHAR_2: u16 = 0x1956;
-pub const TEXT_PROCESSOR_END: u16 = 0x1B5E;
-
-// Addresses for battle hack
-pub const TRAINER_CLASS: u16 = 0xD031;
-pub const TRAINER_NAME: u16 = 0xD04A;
-pub const TRAINER_NUM: u16 = 0xD05D;
-pub const ACTIVE_BATTLE: u16 = 0xD057;
-pub const CURRRENT_OPPONENT: u16 = 0xD059;
-pub const CURRENT_ENEMY_LEVEL: u16 = 0xD127;
-pub const CURRENT_ENEMY_NICK: u16 = 0x0000;
-pub const BATTLE_TYPE: u16 = 0xD05A;
-pub const IS_LINK_BATTLE: u16 = 0xD12B;
-
-// The Prof. Oak battle is unused by the game, so it is a convenient place to replace with our
-// battle data.
-pub const PROF_OAK_DATA_ADDR: u16 = 0x621D;
-pub const PROF_OAK_DATA_BANK: usize = 0xE;
-
-// Addresses for battle data
-pub const PLAYER_BATTLE_DATA_START: u16 = 0xD163;
-pub const ENEMY_BATTLE_DATA_START: u16 = 0xD89C;
-pub const ENEMY_NAME_START: u16 = 0xD887;
-
-// Addresses for specific party data
-pub const PARTY_COUNT: u16 = 0xD163;
-pub const PARTY_POKE_1: u16 = 0xD16B;
-pub const PARTY_POKE_2: u16 = 0xD197;
-pub const PARTY_POKE_3: u16 = 0xD1C3;
-pub const PARTY_POKE_4: u16 = 0xD1EF;
-pub const PARTY_POKE_5: u16 = 0xD21B;
-pub const PARTY_POKE_6: u16 = 0xD247;
+//! Addresses of data and code in the game
+//!
+//! Each offset has a built-in value that is correct for Pokemon Red and Blue. The offsets for the
+//! player's name and movement also have a value for Gold and Silver, which is used instead once the
+//! game is set to Gen 2 with `set_generation`, and the rest are 0 there. The values can be replaced
+//! at runtime by loading a definitions file (see `definitions`), which allows the offsets to be
+//! corrected without rebuilding the client.
+use std::sync::atomic::{AtomicBool, AtomicU16, AtomicUsize, Ordering};
+
+use crate::rom::Generation;
+
+/// An address in the game's address space
+pub struct Address(AtomicU16);
+
+impl Address {
+    const fn new(value: u16) -> Address {
+        Address(AtomicU16::new(value))
+    }
+
+    pub fn get(&self) -> u16 {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn set(&self, value: u16) {
+        self.0.store(value, Ordering::Relaxed)
+    }
+}
+
+/// The index of a ROM bank
+pub struct Bank(AtomicUsize);
+
+impl Bank {
+    const fn new(value: usize) -> Bank {
+        Bank(AtomicUsize::new(value))
+    }
+
+    pub fn get(&self) -> usize {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn set(&self, value: usize) {
+        self.0.store(value, Ordering::Relaxed)
+    }
+}
+
+pub(crate) enum Offset {
+    Address(&'static Address),
+    Bank(&'static Bank),
+}
+
+/// Whether the loaded game is from Gen 2, rather than Gen 1
+static GEN_2: AtomicBool = AtomicBool::new(false);
+
+pub fn generation() -> Generation {
+    if GEN_2.load(Ordering::Relaxed) {
+        Generation::Two
+    }
+    else {
+        Generation::One
+    }
+}
+
+/// Switch to the built-in offsets for a generation of games. This replaces any definitions that
+/// have been applied, so it should be called before applying them.
+pub fn set_generation(generation: Generation) {
+    GEN_2.store(generation == Generation::Two, Ordering::Relaxed);
+    reset();
+}
+
+/// The Gen 2 value of an offset, or 0 if the offset does not have one
+macro_rules! gen_2_value {
+    () => {
+        0
+    };
+    ($value:expr) => {
+        $value
+    };
+}
+
+macro_rules! define_offsets {
+    ($($name:ident: $kind:ident = $value:expr $(, gen_2: $gen_2:expr)?;)*) => {
+        $(pub static $name: $kind = $kind::new($value);)*
+
+        /// Every offset, along with its name
+        pub(crate) static ALL: &[(&str, Offset)] =
+            &[$((stringify!($name), Offset::$kind(&$name)),)*];
+
+        /// Restore every offset to its built-in value for the current generation
+        pub fn reset() {
+            let gen_2 = generation() == Generation::Two;
+            $($name.set(if gen_2 { gen_2_value!($($gen_2)?) } else { $value });)*
+        }
+
+        /// The built-in value of the offset called `name` for the current generation
+        pub(crate) fn built_in(name: &str) -> Option<u32> {
+            let gen_2 = generation() == Generation::Two;
+            $(
+                if name == stringify!($name) {
+                    return Some((if gen_2 { gen_2_value!($($gen_2)?) } else { $value }) as u32);
+                }
+            )*
+            None
+        }
+    };
+}
+
+define_offsets! {
+    // Player positional data. Gen 2 numbers maps within groups, and only the number is read.
+    MAP_ID: Address = 0xD35E, gen_2: 0xDA01;
+    MAP_Y: Address = 0xD361, gen_2: 0xDA02;
+    MAP_X: Address = 0xD362, gen_2: 0xDA03;
+
+    // The last outdoor map the player was on, which is where buildings exit to
+    LAST_MAP: Address = 0xD365;
+    PLAYER_DY: Address = 0xC103;
+    PLAYER_DX: Address = 0xC105;
+
+    // The direction which the player is facing (0: down, 4: up, 8: left, 12: right)
+    PLAYER_DIR: Address = 0xC109, gen_2: 0xD204;
+
+    // When a player moves, this value counts down from 8 to 0. Gen 2 counts the frames left in
+    // the step instead, from 16 to 0.
+    WALK_COUNTER: Address = 0xCFC5, gen_2: 0xD206;
+
+    // General player data
+    PLAYER_NAME_START: Address = 0xD158, gen_2: 0xD1A3;
+
+    // The number of teams that have been recorded in the Hall of Fame
+    NUM_HOF_TEAMS: Address = 0xD5A2;
+
+    // The address of the player spritesheet encoded as 2bpp in the rom
+    RED_SPRITE_ADDR: Address = 0x4180;
+    RED_SPRITE_BANK: Bank = 5;
+    BLUE_SPRITE_ADDR: Address = 0x4300;
+    BLUE_SPRITE_BANK: Bank = 5;
+    OAK_SPRITE_ADDR: Address = 0x4480;
+    OAK_SPRITE_BANK: Bank = 5;
+
+    // The address of the main font encoded as a 1bpp sprite in the rom
+    FONT_ADDR: Address = 0x5A80;
+    FONT_BANK: Bank = 4;
+
+    // The address of the textbox border encoded as 2bpp sprite in the rom
+    BORDER_ADDR: Address = 0x6288 + 2 * 8 * (4 * 6 + 1);
+    BORDER_BANK: Bank = 4;
+
+    // The location of the tile map
+    TILE_MAP: Address = 0xC3A0, gen_2: 0xC3A0;
+
+    // Useful addresses for hacks
+    LOADED_ROM_BANK: Address = 0xFFB8;
+    FRAME_COUNTER: Address = 0xFFD5;
+    BANK_SWITCH: Address = 0x35D6;
+
+    // The state of the random number generator
+    RANDOM_ADD: Address = 0xFFD3;
+    RANDOM_SUB: Address = 0xFFD4;
+
+    // Addresses for sprite check hack
+    NUM_SPRITES: Address = 0xD4E1;
+    OVERWORLD_LOOP_START: Address = 0x03FF;
+    SPRITE_CHECK_START: Address = 0x0B23;
+    SPRITE_CHECK_EXIT_1: Address = 0x0BA0;
+    SPRITE_CHECK_EXIT_2: Address = 0x0BC4;
+    SPRITE_INDEX: Address = 0xFF8C;
+
+    // Addresses for sprite update hack
+    CLEAR_SPRITES: Address = 0x0082;
+    UPDATE_SPRITES: Address = 0x2429;
+    SPRITES_ENABLED: Address = 0xCFCB;
+
+    // Addresses for display text hack
+    DISPLAY_TEXT_ID: Address = 0x2920;
+    DISPLAY_TEXT_ID_AFTER_INIT: Address = 0x292B;
+    DISPLAY_TEXT_SETUP_DONE: Address = 0x29CD;
+    GET_NEXT_CHAR_1: Address = 0x1B55;
+    GET_NEXT_CHAR_2: Address = 0x1956;
+    TEXT_PROCESSOR_END: Address = 0x1B5E;
+    DELAY_FRAME: Address = 0x20AF;
+
+    // Addresses for battle hack
+    TRAINER_CLASS: Address = 0xD031;
+    TRAINER_NAME: Address = 0xD04A;
+    TRAINER_NUM: Address = 0xD05D;
+    ACTIVE_BATTLE: Address = 0xD057;
+    CURRRENT_OPPONENT: Address = 0xD059;
+    CURRENT_ENEMY_LEVEL: Address = 0xD127;
+    CURRENT_ENEMY_NICK: Address = 0x0000;
+    BATTLE_TYPE: Address = 0xD05A;
+    IS_LINK_BATTLE: Address = 0xD12B;
+
+    // Addresses for battle relay hack
+    BATTLE_BANK: Bank = 0xF;
+    SELECT_ENEMY_MOVE: Address = 0x6429;
+    PLAYER_SELECTED_MOVE: Address = 0xCCDC;
+    ENEMY_SELECTED_MOVE: Address = 0xCCDD;
+    // Whether the player took their turn without a move (by using an item or switching), and the
+    // party position of the Pokemon they have out
+    TURN_TAKEN: Address = 0xCD6A;
+    PLAYER_MON_NUMBER: Address = 0xCC2F;
+    // The party position of the Pokemon chosen from a menu, or chosen by the enemy trainer
+    WHICH_POKEMON: Address = 0xCF92;
+    // Where the routine that sends out the enemy's next Pokemon continues once it has been chosen.
+    // There is no built-in value for this yet, so a relayed switch only costs the enemy its turn
+    // unless a definitions file gives it.
+    ENEMY_SEND_OUT: Address = 0x0000;
+
+    // The Pokemon currently in battle, stored as battle structs
+    BATTLE_MON: Address = 0xD014;
+    ENEMY_MON: Address = 0xCFE5;
+
+    // The names of each species, stored as 10 bytes per name in order of species id
+    MONSTER_NAMES_ADDR: Address = 0x421E;
+    MONSTER_NAMES_BANK: Bank = 7;
+
+    // The names of each item, stored in order of item id with each ending in a terminator
+    ITEM_NAMES_ADDR: Address = 0x472B;
+    ITEM_NAMES_BANK: Bank = 1;
+
+    // The Prof. Oak battle is unused by the game, so it is a convenient place to replace with our
+    // battle data.
+    PROF_OAK_DATA_ADDR: Address = 0x621D;
+    PROF_OAK_DATA_BANK: Bank = 0xE;
+
+    // Addresses for battle data
+    PLAYER_BATTLE_DATA_START: Address = 0xD163;
+    ENEMY_BATTLE_DATA_START: Address = 0xD89C;
+    ENEMY_NAME_START: Address = 0xD887;
+
+    // Addresses for specific party data
+    PARTY_COUNT: Address = 0xD163;
+    PARTY_SPECIES: Address = 0xD164;
+    PARTY_POKE_1: Address = 0xD16B;
+    PARTY_POKE_2: Address = 0xD197;
+    PARTY_POKE_3: Address = 0xD1C3;
+    PARTY_POKE_4: Address = 0xD1EF;
+    PARTY_POKE_5: Address = 0xD21B;
+    PARTY_POKE_6: Address = 0xD247;
+
+    // Addresses for the bag, stored as (item, quantity) pairs
+    NUM_BAG_ITEMS: Address = 0xD31D;
+    BAG_ITEMS: Address = 0xD31E;
+
+    // The player's money, stored as 6 binary-coded decimal digits
+    PLAYER_MONEY: Address = 0xD347;
+
+    // Addresses for the current PC box
+    BOX_COUNT: Address = 0xDA80;
+    BOX_SPECIES: Address = 0xDA81;
+}
@@ -0,0 +1,186 @@
+//! Relaying the actions chosen in a battle between two players
+//!
+//! Both players battle a copy of the other player's party. Instead of letting the game choose the
+//! enemy's action, what the other player did with their turn is sent over the network and used as
+//! the enemy's action: the same move, or sending out the same Pokemon. An item used by the other
+//! player only costs the enemy its turn, since what the item did is not relayed. The emulators are
+//! only kept loosely in sync: each side waits for the other player's action at the start of every
+//! turn, but the rest of the battle runs independently, including which Pokemon is sent out after
+//! one faints.
+//!
+//! Either player can forfeit the battle. Both sides then stop relaying moves and leave the losing
+//! side's Pokemon with no HP, so each game ends the battle the way it usually would: the player
+//! that forfeited blacks out, and the other player defeats them.
+use std::{collections::VecDeque, time::Instant};
+
+use gb_emu::mmu::Memory;
+
+use crate::{
+    checked::{self, AccessError},
+    data::MAX_MOVE_ID,
+    extract, offsets, PlayerId,
+};
+
+/// The number of Pokemon in a full party
+const PARTY_SIZE: u16 = 6;
+
+/// Where the Pokemon in the party data start, after the count and the list of species
+const PARTY_MONS_OFFSET: u16 = 8;
+
+/// The size of each Pokemon in the party data
+const PARTY_MON_SIZE: u16 = 44;
+
+/// Where a Pokemon's current HP is stored in both party and battle structs, as a big-endian value
+const HP_OFFSET: u16 = 1;
+
+/// Where a battle struct keeps the position of its Pokemon in the party
+const PARTY_POS_OFFSET: u16 = 3;
+
+/// The number of bytes from `HP_OFFSET` that the game copies back to the party when a trainer
+/// withdraws a Pokemon: the HP, level and status
+const WITHDRAWN_SIZE: usize = 4;
+
+/// The move selected for a side that can not move this turn, which skips its attack
+const CANNOT_MOVE: u8 = 0xFF;
+
+/// What a player did with their turn in a relayed battle
+#[derive(Copy, Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum RelayAction {
+    /// Used the move with this id
+    Move(u8),
+    /// Sent out the Pokemon at this position in their party
+    Switch(u8),
+    /// Used an item from their bag
+    Item,
+}
+
+impl RelayAction {
+    /// Whether the action could have been taken in the game
+    pub fn is_valid(&self) -> bool {
+        match *self {
+            RelayAction::Move(move_id) => move_id <= MAX_MOVE_ID,
+            RelayAction::Switch(index) => (index as u16) < PARTY_SIZE,
+            RelayAction::Item => true,
+        }
+    }
+}
+
+/// What happens when a player uses an item from their bag during a relayed battle
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum ItemRule {
+    #[default]
+    Allow,
+    /// Both players are told that an item was used
+    Warn,
+    /// The player that used the item forfeits the battle
+    Forfeit,
+}
+
+/// The side of a relayed battle that loses when a player forfeits
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Loser {
+    /// The local player forfeited
+    Player,
+    /// The opponent forfeited
+    Enemy,
+}
+
+pub struct BattleRelay {
+    pub opponent: PlayerId,
+    pub item_rule: ItemRule,
+    /// Actions chosen by the local player that are yet to be sent to the opponent
+    pub outgoing: VecDeque<RelayAction>,
+    /// Actions chosen by the opponent that are yet to be used
+    pub incoming: VecDeque<RelayAction>,
+    /// Whether the local player's action for the current turn has been sent
+    pub(crate) move_sent: bool,
+    /// The total number of items in the local player's bag when it was last checked
+    bag_items: Option<u32>,
+    /// The total number of items in the local player's bag at the start of the current turn
+    turn_bag_items: u32,
+    /// When a message was last received from the opponent
+    pub last_heard: Instant,
+    /// Whether the battle is paused because the opponent has stopped responding
+    pub paused: bool,
+}
+
+impl BattleRelay {
+    pub fn new(opponent: PlayerId, item_rule: ItemRule, mem: &Memory) -> BattleRelay {
+        BattleRelay {
+            opponent,
+            item_rule,
+            outgoing: VecDeque::new(),
+            incoming: VecDeque::new(),
+            move_sent: false,
+            bag_items: None,
+            turn_bag_items: extract::bag_item_count(mem),
+            last_heard: Instant::now(),
+            paused: false,
+        }
+    }
+
+    /// Check whether the local player has used an item from their bag since the last check
+    pub fn check_item_use(&mut self, mem: &Memory) -> bool {
+        // Items can not be added to the bag during a battle, so any decrease means one was used
+        let bag_items = extract::bag_item_count(mem);
+        matches!(self.bag_items.replace(bag_items), Some(last) if bag_items < last)
+    }
+
+    /// What the local player did with the current turn, once the enemy's action is about to be
+    /// chosen. A player that took their turn without a move either used an item, which leaves the
+    /// bag with fewer items, or sent out another Pokemon.
+    pub(crate) fn player_action(&mut self, mem: &Memory) -> RelayAction {
+        let bag_items = extract::bag_item_count(mem);
+        let used_item = bag_items < self.turn_bag_items;
+        self.turn_bag_items = bag_items;
+        if mem.lb(offsets::TURN_TAKEN.get()) == 0 {
+            RelayAction::Move(mem.lb(offsets::PLAYER_SELECTED_MOVE.get()))
+        }
+        else if used_item {
+            RelayAction::Item
+        }
+        else {
+            RelayAction::Switch(mem.lb(offsets::PLAYER_MON_NUMBER.get()))
+        }
+    }
+}
+
+/// Leave the enemy without a move this turn, since it used its turn on something else
+pub(crate) fn skip_enemy_move(mem: &mut Memory) {
+    mem.sb(offsets::ENEMY_SELECTED_MOVE.get(), CANNOT_MOVE);
+}
+
+/// Put the enemy's Pokemon back in its party the way the game does when a trainer withdraws one,
+/// and choose the one at `index` to be sent out next. Returns false without changing anything if
+/// that Pokemon can not be sent out, because it is already out, has fainted or does not exist.
+pub(crate) fn withdraw_enemy(mem: &mut Memory, index: u8) -> Result<bool, AccessError> {
+    let party = offsets::ENEMY_BATTLE_DATA_START.get();
+    let enemy_mon = offsets::ENEMY_MON.get();
+    let hp_addr = |index: u8| party + PARTY_MONS_OFFSET + index as u16 * PARTY_MON_SIZE + HP_OFFSET;
+    let current = mem.lb(enemy_mon + PARTY_POS_OFFSET);
+    let fainted = mem.lb(hp_addr(index)) == 0 && mem.lb(hp_addr(index) + 1) == 0;
+    if index >= mem.lb(party) || current as u16 >= PARTY_SIZE || index == current || fainted {
+        return Ok(false);
+    }
+
+    let withdrawn: Vec<u8> =
+        (0..WITHDRAWN_SIZE as u16).map(|i| mem.lb(enemy_mon + HP_OFFSET + i)).collect();
+    checked::write_bytes(mem, hp_addr(current), &withdrawn)?;
+    mem.sb(offsets::WHICH_POKEMON.get(), index);
+    Ok(true)
+}
+
+/// Leave every Pokemon on the losing side of a battle with no HP. The game checks for fainted
+/// Pokemon at the start of each turn, so it finishes the battle there and returns to the overworld.
+pub fn concede(mem: &mut Memory, loser: Loser) -> Result<(), AccessError> {
+    let (battle_mon, party) = match loser {
+        Loser::Player => (offsets::BATTLE_MON.get(), offsets::PLAYER_BATTLE_DATA_START.get()),
+        Loser::Enemy => (offsets::ENEMY_MON.get(), offsets::ENEMY_BATTLE_DATA_START.get()),
+    };
+    checked::write_bytes(mem, battle_mon + HP_OFFSET, &[0, 0])?;
+    for i in 0..PARTY_SIZE {
+        let hp = party + PARTY_MONS_OFFSET + i * PARTY_MON_SIZE + HP_OFFSET;
+        checked::write_bytes(mem, hp, &[0, 0])?;
+    }
+    Ok(())
+}
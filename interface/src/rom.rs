@@ -0,0 +1,95 @@
+//! Reads the cartridge header of a ROM, to identify the game and how it is saved
+use std::str;
+
+pub const BANK_SIZE: usize = 0x4000;
+
+/// The location of the game's title in the cartridge header
+const TITLE_START: usize = 0x134;
+const TITLE_END: usize = 0x144;
+
+/// The last byte of the title is a flag on Game Boy Color games, which have shorter titles
+const CGB_FLAG: usize = 0x143;
+
+/// The generations of games that `offsets` has built-in addresses for
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Generation {
+    /// Red and Blue
+    One,
+    /// Gold and Silver, which so far only have addresses for the player's name and movement
+    Two,
+}
+
+/// Games that the addresses in `offsets` are valid for. Gold and Silver's titles end with the
+/// manufacturer code.
+const SUPPORTED_TITLES: &[(&str, Generation)] = &[
+    ("POKEMON RED", Generation::One),
+    ("POKEMON BLUE", Generation::One),
+    ("POKEMON_GLDAAUE", Generation::Two),
+    ("POKEMON_SLVAAXE", Generation::Two),
+];
+
+/// Reads the title from the header of a ROM
+pub fn title(rom: &[u8]) -> Option<&str> {
+    let end = match rom.get(CGB_FLAG) {
+        Some(flag) if flag & 0x80 != 0 => CGB_FLAG,
+        _ => TITLE_END,
+    };
+    let title = rom.get(TITLE_START..end)?;
+    let len = title.iter().position(|&b| b == 0).unwrap_or(title.len());
+    str::from_utf8(&title[..len]).ok()
+}
+
+/// The location of the mask ROM version in the cartridge header, which is increased by each
+/// revision of a game
+const VERSION: usize = 0x14C;
+
+/// Reads the revision of the game from the header of a ROM
+pub fn revision(rom: &[u8]) -> u8 {
+    rom.get(VERSION).copied().unwrap_or(0)
+}
+
+/// The generation of a supported game, or None for any other ROM
+pub fn generation(rom: &[u8]) -> Option<Generation> {
+    title_generation(title(rom)?)
+}
+
+/// The generation of the supported game with a title, or None for any other title
+pub fn title_generation(title: &str) -> Option<Generation> {
+    SUPPORTED_TITLES.iter().find(|(supported, _)| *supported == title).map(|&(_, gen)| gen)
+}
+
+/// Checks whether a ROM is a game that this interface supports
+pub fn is_supported(rom: &[u8]) -> bool {
+    generation(rom).is_some()
+}
+
+/// The location of the cartridge type and the size of its RAM in the cartridge header
+const CART_TYPE: usize = 0x147;
+const RAM_SIZE: usize = 0x149;
+
+/// What a cartridge keeps in its battery-backed save, which decides the layout of its save file
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SaveFormat {
+    /// The number of bytes of cartridge RAM
+    pub ram_size: usize,
+    /// Whether the cartridge has a real time clock, like the MBC3 carts of Gold and Silver.
+    /// Emulators store the clock's state after the RAM in the save file.
+    pub rtc: bool,
+}
+
+/// Reads the save format from the header of a ROM
+pub fn save_format(rom: &[u8]) -> SaveFormat {
+    let cart_type = rom.get(CART_TYPE).copied().unwrap_or(0);
+    let rtc = matches!(cart_type, 0x0F | 0x10);
+    let ram_size = match (cart_type, rom.get(RAM_SIZE)) {
+        // MBC2 carts have 512 half-bytes of RAM built in, and report none in the header
+        (0x05 | 0x06, _) => 512,
+        (_, Some(1)) => 2 * 1024,
+        (_, Some(2)) => 8 * 1024,
+        (_, Some(3)) => 32 * 1024,
+        (_, Some(4)) => 128 * 1024,
+        (_, Some(5)) => 64 * 1024,
+        _ => 0,
+    };
+    SaveFormat { ram_size, rtc }
+}
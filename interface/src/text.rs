@@ -59,6 +59,44 @@ pub fn encode_char(char_: char) -> u8 {
     }
 }
 
+/// Converts a character from the game's encoding, the reverse of `encode_char`
+pub fn decode_char(val: u8) -> char {
+    match val {
+        0x80..=0x99 => (b'A' + (val - 0x80)) as char,
+
+        0x9A => '(',
+        0x9B => ')',
+        0x9C => ':',
+        0x9D => ';',
+        0x9E => '[',
+        0x9F => ']',
+
+        0xA0..=0xB9 => (b'a' + (val - 0xA0)) as char,
+
+        0xE0 => '\'',
+        0xE3 => '-',
+        0xE6 => '?',
+        0xE7 => '!',
+        0xE8 => '.',
+        0xED => '>',
+        0xF3 => '/',
+        0xF4 => ',',
+
+        0xF6..=0xFF => (b'0' + (val - 0xF6)) as char,
+
+        special::SPACE => ' ',
+        _ => '?',
+    }
+}
+
+/// Decodes text from the game, stopping at the terminator
+pub fn decode(text: &[u8]) -> String {
+    text.iter()
+        .take_while(|&&val| val != special::TERMINATOR)
+        .map(|&val| decode_char(val))
+        .collect()
+}
+
 pub struct Encoder<'a> {
     base: &'a str,
 }
@@ -0,0 +1,204 @@
+//! A model of how the game's text processor lays out a message in the text box, so that messages
+//! can be checked without running the game
+//!
+//! The text box shows two lines of `LINE_WIDTH` characters. The control characters used by
+//! messages move between the lines and wait for a button press, and everything else is drawn as a
+//! character. The model splits a message into the pages that the player sees each time the game
+//! waits for them, and fails on anything that the game would draw outside of the box.
+use std::{error::Error, fmt};
+
+use crate::text::{self, special};
+
+/// The number of characters that fit on a line of the text box
+pub const LINE_WIDTH: usize = 18;
+
+/// The number of lines shown in the text box at once
+pub const VISIBLE_LINES: usize = 2;
+
+/// Draws the player's name
+const PLAYER_NAME: u8 = 0x52;
+
+/// Draws the rival's name
+const RIVAL_NAME: u8 = 0x53;
+
+/// The names drawn in place of the name control characters
+#[derive(Clone, Debug)]
+pub struct Placeholders {
+    pub player: String,
+    pub rival: String,
+}
+
+impl Default for Placeholders {
+    fn default() -> Placeholders {
+        Placeholders { player: "RED".to_string(), rival: "BLUE".to_string() }
+    }
+}
+
+/// The lines shown in the text box before the game waits for a button press
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Page {
+    pub lines: Vec<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LayoutError {
+    /// A line runs past the right edge of the box
+    LineTooLong(String),
+    /// A line starts below the bottom of the box
+    TooManyLines,
+    /// A control character that the model does not handle
+    UnsupportedControl(u8),
+    /// The message ends without a terminator, so the game would keep reading past it
+    Unterminated,
+}
+
+impl Error for LayoutError {}
+
+impl fmt::Display for LayoutError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LayoutError::LineTooLong(line) => write!(
+                f,
+                "\"{}\" is {} characters, but a line fits {}",
+                line,
+                line.chars().count(),
+                LINE_WIDTH
+            ),
+            LayoutError::TooManyLines => write!(f, "more than {} lines on a page", VISIBLE_LINES),
+            LayoutError::UnsupportedControl(val) => write!(f, "unsupported control {:#04X}", val),
+            LayoutError::Unterminated => write!(f, "the message is not terminated"),
+        }
+    }
+}
+
+/// Lay out a message in the game's encoding, such as one made by `create_message_box`
+pub fn layout(message: &[u8], placeholders: &Placeholders) -> Result<Vec<Page>, LayoutError> {
+    let mut pages = vec![];
+    let mut lines = vec![String::new()];
+
+    // The message may start with the command that begins a text section
+    let message = message.strip_prefix(&[special::TEXT_START]).unwrap_or(message);
+    for &val in message {
+        match val {
+            special::LINE_DOWN => {
+                if lines.len() == VISIBLE_LINES {
+                    return Err(LayoutError::TooManyLines);
+                }
+                lines.push(String::new());
+            }
+            special::BOTTOM_LINE => match lines.len() {
+                1 => lines.push(String::new()),
+                _ => return Err(LayoutError::TooManyLines),
+            },
+            special::PARAGRAPH => {
+                pages.push(Page { lines });
+                lines = vec![String::new()];
+            }
+            special::SCROLL_LINE => {
+                let bottom = lines.last().cloned().unwrap_or_default();
+                pages.push(Page { lines });
+                lines = vec![bottom, String::new()];
+            }
+            special::END_MSG | special::END_PROMPT | special::TERMINATOR => {
+                pages.push(Page { lines });
+                return Ok(pages);
+            }
+            PLAYER_NAME => write(&mut lines, &placeholders.player)?,
+            RIVAL_NAME => write(&mut lines, &placeholders.rival)?,
+            0x49..=0x5F => return Err(LayoutError::UnsupportedControl(val)),
+            _ => write(&mut lines, &text::decode_char(val).to_string())?,
+        }
+    }
+    Err(LayoutError::Unterminated)
+}
+
+/// Add text to the current line, which must still fit in the box
+fn write(lines: &mut [String], text: &str) -> Result<(), LayoutError> {
+    let line = lines.last_mut().unwrap();
+    line.push_str(text);
+    if line.chars().count() > LINE_WIDTH {
+        return Err(LayoutError::LineTooLong(line.clone()));
+    }
+    Ok(())
+}
+
+/// Draw pages as they would appear in the text box, one box after another
+pub fn render(pages: &[Page]) -> String {
+    let border = format!("+{}+\n", "-".repeat(LINE_WIDTH));
+    let mut out = String::new();
+    for page in pages {
+        out.push_str(&border);
+        for i in 0..VISIBLE_LINES {
+            let line = page.lines.get(i).map_or("", |line| line.as_str());
+            out.push_str(&format!("|{:<width$}|\n", line, width = LINE_WIDTH));
+        }
+        out.push_str(&border);
+    }
+    out
+}
+
+/// Snapshot tests for messages shown in the text box. Each message is made with
+/// `create_message_box`, laid out by the model, and compared with the layout saved in
+/// `snapshots/text`. This catches changes to the encoding or layout of messages that would only
+/// otherwise be seen in the game. Run with `BLESS=1` to save the current layouts as the snapshots.
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::PathBuf};
+
+    use super::*;
+    use crate::{hacks, InterfaceData};
+
+    /// The snapshot of a message's layout, or of the error if it does not fit in the box
+    fn snapshot(message: &str) -> String {
+        let mut data = InterfaceData::new();
+        data.create_message_box(message);
+        match layout(&data.pending_message(), &Placeholders::default()) {
+            Ok(pages) => render(&pages),
+            Err(e) => format!("ERROR: {}\n", e),
+        }
+    }
+
+    fn check(name: &str, message: &str) {
+        let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("snapshots").join("text");
+        let path = dir.join(format!("{}.txt", name));
+        let actual = snapshot(message);
+        if std::env::var_os("BLESS").is_some() {
+            fs::create_dir_all(&dir).and_then(|_| fs::write(&path, &actual)).unwrap();
+            return;
+        }
+
+        let expected = fs::read_to_string(&path)
+            .unwrap_or_else(|e| panic!("no snapshot at {}: {}", path.display(), e));
+        assert_eq!(expected, actual, "the layout of {} has changed", name);
+    }
+
+    #[test]
+    fn waiting_for_opponent() {
+        check("waiting_for_opponent", hacks::WAITING_FOR_OPPONENT);
+    }
+
+    #[test]
+    fn charset() {
+        check("charset", "(A-Z) [a-z]: 0/9;\n'?!.,>");
+    }
+
+    #[test]
+    fn unsupported_chars() {
+        check("unsupported_chars", "Caf\u{e9} & 100%");
+    }
+
+    #[test]
+    fn full_width() {
+        check("full_width", "ABCDEFGHIJKLMNOPQR\nabcdefghijklmnopqr");
+    }
+
+    #[test]
+    fn line_too_long() {
+        check("line_too_long", "This line is too long for the box");
+    }
+
+    #[test]
+    fn too_many_lines() {
+        check("too_many_lines", "One\nTwo\nThree");
+    }
+}
@@ -28,6 +28,32 @@ impl Direction {
     }
 }
 
+/// Overworld sprites that can be used to represent a player
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum PlayerSprite {
+    Red,
+    #[default]
+    Blue,
+    Oak,
+}
+
+impl PlayerSprite {
+    pub const ALL: [PlayerSprite; 3] = [PlayerSprite::Red, PlayerSprite::Blue, PlayerSprite::Oak];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            PlayerSprite::Red => "RED",
+            PlayerSprite::Blue => "BLUE",
+            PlayerSprite::Oak => "OAK",
+        }
+    }
+
+    pub fn next(self) -> PlayerSprite {
+        let index = PlayerSprite::ALL.iter().position(|&s| s == self).unwrap_or(0);
+        PlayerSprite::ALL[(index + 1) % PlayerSprite::ALL.len()]
+    }
+}
+
 pub enum BattleType {
     Normal = 0,
     OldMan = 1,
@@ -40,6 +66,16 @@ pub enum ActiveBattle {
     Trainer = 2,
 }
 
+/// Species that can only be encountered once, and so are worth telling other players about
+pub const RARE_SPECIES: [u8; 6] = [
+    0x15, // Mew
+    0x49, // Moltres
+    0x4A, // Articuno
+    0x4B, // Zapdos
+    0x83, // Mewtwo
+    0x84, // Snorlax
+];
+
 pub enum TrainerClass {
     Unknown = 0x00,
     ProfOak = 0x1A,
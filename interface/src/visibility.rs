@@ -0,0 +1,72 @@
+//! Rules for which other players are drawn and can be bumped into
+//!
+//! Players are only ever seen by players on the same map, so players inside a building are hidden
+//! from everyone who is not in it. On top of that, the local player and the server can each choose
+//! a rule that hides more players, and a player is only shown if both rules allow it. Hidden
+//! players are skipped by the collision check as well as when drawing, so that nobody is blocked by
+//! a player they can not see.
+use std::collections::HashSet;
+
+use crate::{data::PlayerData, maps, PlayerId};
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum Visibility {
+    /// Every player on the same map
+    #[default]
+    Everyone,
+    /// Only players in towns and routes. Players in buildings, caves and other indoor areas are
+    /// hidden, even from the players in there with them.
+    Outdoors,
+    /// Only players that the local player has marked as friends
+    Friends,
+}
+
+impl Visibility {
+    pub const ALL: [Visibility; 3] =
+        [Visibility::Everyone, Visibility::Outdoors, Visibility::Friends];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Visibility::Everyone => "EVERYONE",
+            Visibility::Outdoors => "OUTDOORS",
+            Visibility::Friends => "FRIENDS",
+        }
+    }
+
+    pub fn next(self) -> Visibility {
+        let index = Visibility::ALL.iter().position(|&v| v == self).unwrap_or(0);
+        Visibility::ALL[(index + 1) % Visibility::ALL.len()]
+    }
+
+    /// Whether this rule allows a player on `map_id` to be shown
+    fn allows(self, map_id: u8, is_friend: bool) -> bool {
+        match self {
+            Visibility::Everyone => true,
+            Visibility::Outdoors => maps::is_outdoor(map_id),
+            Visibility::Friends => is_friend,
+        }
+    }
+}
+
+/// The rules deciding which other players the local player sees
+#[derive(Clone, Debug, Default)]
+pub struct VisibilityPolicy {
+    /// The rule chosen by the local player
+    pub local: Visibility,
+    /// The rule required by the server
+    pub server: Visibility,
+    /// The players that the local player has marked as friends
+    pub friends: HashSet<PlayerId>,
+}
+
+impl VisibilityPolicy {
+    /// Check if a player is visible to a local player on `map_id`
+    pub fn is_visible(&self, id: PlayerId, player: &PlayerData, map_id: u8) -> bool {
+        let movement = &player.movement_data;
+        if movement.map_id != map_id || movement.is_hidden() {
+            return false;
+        }
+        let is_friend = self.friends.contains(&id);
+        self.local.allows(map_id, is_friend) && self.server.allows(map_id, is_friend)
+    }
+}
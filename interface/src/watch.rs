@@ -0,0 +1,113 @@
+//! Noticing when the parts of the game's memory that are shared with other players change
+//!
+//! Extracting the local player's data means decoding their name and position and building a new
+//! `PlayerData` for it, which is wasted work while the player stands still or sits in a menu.
+//! `MemoryWatch` keeps a copy of the few bytes that this data is read from, and compares them
+//! after each frame, so that data is only extracted again once something it depends on changes.
+//!
+//! The party and the current box are watched as well, for noticing catches. The game's event flags
+//! are not watched, since nothing is read from them.
+use gb_emu::mmu::Memory;
+
+use crate::offsets::{self, Address};
+
+/// The longest name that is read from the game, including the terminator
+const NAME_LENGTH: u16 = 11;
+
+/// The most Pokemon that the party holds
+const PARTY_SIZE: u16 = 6;
+
+/// The parts of the watched memory that changed since it was last checked
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Changes {
+    /// The player's map, coordinates, direction or walk counter
+    pub position: bool,
+    pub name: bool,
+    /// Whether the player is in a battle, and what they are battling
+    pub battle: bool,
+    /// The number of teams in the Hall of Fame
+    pub hall_of_fame: bool,
+    /// The species in the player's party, or the number of Pokemon in the current box
+    pub party: bool,
+}
+
+impl Changes {
+    /// Everything changed, e.g. before memory has been checked for the first time
+    pub const ALL: Changes =
+        Changes { position: true, name: true, battle: true, hall_of_fame: true, party: true };
+
+    /// The changes in either `self` or `other`, for collecting changes over several frames
+    pub fn union(self, other: Changes) -> Changes {
+        Changes {
+            position: self.position || other.position,
+            name: self.name || other.name,
+            battle: self.battle || other.battle,
+            hall_of_fame: self.hall_of_fame || other.hall_of_fame,
+            party: self.party || other.party,
+        }
+    }
+}
+
+/// Watched bytes, as the address they start at, how many there are, and how to mark what they are
+/// part of as changed
+type Watched = (&'static Address, u16, fn(&mut Changes));
+
+/// The watched bytes. The addresses are read again each time, since they change with the
+/// generation and definitions.
+fn watched() -> [Watched; 13] {
+    [
+        (&offsets::MAP_ID, 1, |changes| changes.position = true),
+        (&offsets::MAP_X, 1, |changes| changes.position = true),
+        (&offsets::MAP_Y, 1, |changes| changes.position = true),
+        (&offsets::PLAYER_DIR, 1, |changes| changes.position = true),
+        (&offsets::WALK_COUNTER, 1, |changes| changes.position = true),
+        (&offsets::PLAYER_NAME_START, NAME_LENGTH, |changes| changes.name = true),
+        (&offsets::ACTIVE_BATTLE, 1, |changes| changes.battle = true),
+        (&offsets::CURRRENT_OPPONENT, 1, |changes| changes.battle = true),
+        (&offsets::CURRENT_ENEMY_LEVEL, 1, |changes| changes.battle = true),
+        (&offsets::NUM_HOF_TEAMS, 1, |changes| changes.hall_of_fame = true),
+        (&offsets::PARTY_COUNT, 1, |changes| changes.party = true),
+        (&offsets::PARTY_SPECIES, PARTY_SIZE, |changes| changes.party = true),
+        (&offsets::BOX_COUNT, 1, |changes| changes.party = true),
+    ]
+}
+
+pub struct MemoryWatch {
+    /// The watched bytes as they were when memory was last checked, or empty before the first check
+    last: Vec<u8>,
+}
+
+impl MemoryWatch {
+    pub fn new() -> MemoryWatch {
+        MemoryWatch { last: vec![] }
+    }
+
+    /// Compare the watched bytes with the last time this was called. Everything has changed the
+    /// first time.
+    pub fn check(&mut self, mem: &Memory) -> Changes {
+        let mut changes = if self.last.is_empty() { Changes::ALL } else { Changes::default() };
+        let mut index = 0;
+        for (address, len, mark) in watched() {
+            let start = address.get();
+            for offset in 0..len {
+                let byte = mem.lb(start.wrapping_add(offset));
+                match self.last.get_mut(index) {
+                    Some(last) if *last == byte => {}
+                    Some(last) => {
+                        *last = byte;
+                        mark(&mut changes);
+                    }
+                    None => self.last.push(byte),
+                }
+                index += 1;
+            }
+        }
+        changes
+    }
+}
+
+impl Default for MemoryWatch {
+    fn default() -> MemoryWatch {
+        MemoryWatch::new()
+    }
+}
@@ -0,0 +1,15 @@
+//! Fuzz target for the packet decoder. Run with `cargo fuzz run decode_packet` from the
+//! `network_common` directory.
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use network_common::{codec, error::NetworkError};
+
+fuzz_target!(|data: &[u8]| {
+    let _ = codec::decode_packet(data);
+    let _ = codec::decode_datagram(data);
+
+    // Also exercise the framing, which may see several packets in a single read. Each successful
+    // read consumes at least one byte, so this always terminates.
+    let mut reader = data;
+    while let Ok(Some(_)) | Err(NetworkError::DecodeError) = codec::read_packet(&mut reader) {}
+});
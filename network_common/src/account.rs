@@ -0,0 +1,160 @@
+//! Player accounts, so that a player keeps their name across sessions and other players can not
+//! take it
+//!
+//! After the server's verdict on the game, clients send a single 0 byte to join as a guest, or the
+//! kind of login followed by the account name and the secret, each with a length byte in front. The
+//! server answers with a byte saying whether the login was accepted. Accepted logins are followed
+//! by a token, again with a length byte in front, that can be used in place of the password from
+//! then on. The token is empty if the client already logged in with one, and for guests.
+//!
+//! Passwords are sent as they are typed, so servers that accounts matter on should use TLS.
+use std::io::prelude::*;
+
+use interface::data::MAX_NAME_LENGTH;
+
+use crate::error::{NetworkError, NetworkResult};
+
+/// The longest password or token that can be sent
+pub const MAX_SECRET_LENGTH: usize = 64;
+
+/// The shortest password that an account can be registered with
+pub const MIN_PASSWORD_LENGTH: usize = 6;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum LoginKind {
+    /// Log in to an existing account with its password
+    Password = 1,
+    /// Log in to an existing account with a token from an earlier login
+    Token = 2,
+    /// Create a new account with the given password, and log in to it
+    Register = 3,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Login {
+    pub kind: LoginKind,
+    pub name: String,
+    /// The password, or the token for `LoginKind::Token`
+    pub secret: String,
+}
+
+/// Why the server refused a login
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum LoginError {
+    /// There is no such account, or the password or token is wrong
+    WrongCredentials = 1,
+    /// Registering a name that already has an account
+    NameTaken = 2,
+    /// Registering a name that can not be shown in the game
+    InvalidName = 3,
+    /// Registering with a password shorter than `MIN_PASSWORD_LENGTH`
+    PasswordTooShort = 4,
+}
+
+impl std::fmt::Display for LoginError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            LoginError::WrongCredentials => f.write_str("the account name or password is wrong"),
+            LoginError::NameTaken => f.write_str("that name already has an account"),
+            LoginError::InvalidName => {
+                write!(f, "account names must be 1 to {} letters or digits", MAX_NAME_LENGTH)
+            }
+            LoginError::PasswordTooShort => {
+                write!(f, "passwords must be at least {} characters long", MIN_PASSWORD_LENGTH)
+            }
+        }
+    }
+}
+
+/// Whether a name can be registered. Names are compared without case, and are shown in upper case,
+/// so they only use characters that the game's font has in both cases.
+pub fn valid_name(name: &str) -> bool {
+    (1..=MAX_NAME_LENGTH).contains(&name.len()) && name.chars().all(|c| c.is_ascii_alphanumeric())
+}
+
+fn write_string<W: Write>(writer: &mut W, value: &str, max_len: usize) -> NetworkResult<()> {
+    if value.len() > max_len {
+        return Err(NetworkError::EncodeError);
+    }
+    writer.write_all(&[value.len() as u8])?;
+    writer.write_all(value.as_bytes())?;
+    Ok(())
+}
+
+fn read_string<R: Read>(reader: &mut R, max_len: usize) -> NetworkResult<String> {
+    let mut len = [0];
+    reader.read_exact(&mut len)?;
+    if len[0] as usize > max_len {
+        return Err(NetworkError::DecodeError);
+    }
+    let mut data = vec![0; len[0] as usize];
+    reader.read_exact(&mut data)?;
+    String::from_utf8(data).map_err(|_| NetworkError::DecodeError)
+}
+
+/// Log in to an account, or join as a guest if `login` is None
+pub fn write_login<W: Write>(writer: &mut W, login: Option<&Login>) -> NetworkResult<()> {
+    let mut data = vec![];
+    match login {
+        Some(login) => {
+            data.push(login.kind as u8);
+            write_string(&mut data, &login.name, MAX_NAME_LENGTH)?;
+            write_string(&mut data, &login.secret, MAX_SECRET_LENGTH)?;
+        }
+        None => data.push(0),
+    }
+    writer.write_all(&data)?;
+    Ok(())
+}
+
+/// Read a client's login, or None for a guest
+pub fn read_login<R: Read>(reader: &mut R) -> NetworkResult<Option<Login>> {
+    let mut kind = [0];
+    reader.read_exact(&mut kind)?;
+    let kind = match kind[0] {
+        0 => return Ok(None),
+        1 => LoginKind::Password,
+        2 => LoginKind::Token,
+        3 => LoginKind::Register,
+        _ => return Err(NetworkError::DecodeError),
+    };
+    let name = read_string(reader, MAX_NAME_LENGTH)?;
+    let secret = read_string(reader, MAX_SECRET_LENGTH)?;
+    Ok(Some(Login { kind, name, secret }))
+}
+
+/// Tell a client whether its login was accepted, with a new token for the account if there is one
+pub fn write_answer<W: Write>(
+    writer: &mut W,
+    answer: Result<Option<&str>, LoginError>,
+) -> NetworkResult<()> {
+    let mut data = vec![];
+    match answer {
+        Ok(token) => {
+            data.push(0);
+            write_string(&mut data, token.unwrap_or_default(), MAX_SECRET_LENGTH)?;
+        }
+        Err(e) => data.push(e as u8),
+    }
+    writer.write_all(&data)?;
+    Ok(())
+}
+
+/// Read the server's answer to a login, failing if it was refused. Returns the new token for the
+/// account, if the server sent one.
+pub fn read_answer<R: Read>(reader: &mut R) -> NetworkResult<Option<String>> {
+    let mut byte = [0];
+    reader.read_exact(&mut byte)?;
+    let error = match byte[0] {
+        0 => {
+            let token = read_string(reader, MAX_SECRET_LENGTH)?;
+            return Ok((!token.is_empty()).then_some(token));
+        }
+        1 => LoginError::WrongCredentials,
+        2 => LoginError::NameTaken,
+        3 => LoginError::InvalidName,
+        4 => LoginError::PasswordTooShort,
+        _ => return Err(NetworkError::DecodeError),
+    };
+    Err(NetworkError::LoginRefused(error))
+}
@@ -0,0 +1,118 @@
+//! A master server, which lists the Pikemon servers that announce themselves to it so that players
+//! can browse them (see `network_common::master`).
+//!
+//! Usage: `master_server [port]`, where the port defaults to `master::DEFAULT_MASTER_PORT`. Both
+//! TCP and UDP need to be open on the port.
+use std::{
+    collections::HashMap,
+    io::prelude::*,
+    net::{Ipv4Addr, SocketAddr, TcpListener, UdpSocket},
+    process,
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use network_common::{codec, discovery, master};
+
+const WRITE_TIMEOUT: Duration = Duration::from_secs(5);
+
+struct Listing {
+    announced_at: Instant,
+    /// The server's answer to the last probe, once it has answered one
+    answer: Option<Vec<u8>>,
+}
+
+type Listings = Arc<Mutex<HashMap<SocketAddr, Listing>>>;
+
+fn main() {
+    let port = match std::env::args().nth(1) {
+        Some(port) => match port.parse() {
+            Ok(port) => port,
+            Err(_) => {
+                eprintln!("Invalid port: {}", port);
+                process::exit(1);
+            }
+        },
+        None => master::DEFAULT_MASTER_PORT,
+    };
+
+    let (listener, udp_socket) = match (
+        TcpListener::bind((Ipv4Addr::UNSPECIFIED, port)),
+        UdpSocket::bind((Ipv4Addr::UNSPECIFIED, port)),
+    ) {
+        (Ok(listener), Ok(udp_socket)) => (listener, udp_socket),
+        (Err(e), _) | (_, Err(e)) => {
+            eprintln!("Failed to listen on port {}: {}", port, e);
+            process::exit(1);
+        }
+    };
+    println!("Listing servers on port {}", port);
+
+    let listings: Listings = Arc::default();
+    let datagram_listings = listings.clone();
+    thread::spawn(move || receive_datagrams(udp_socket, datagram_listings));
+
+    for stream in listener.incoming() {
+        let mut stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                println!("Failed to accept a connection: {}", e);
+                continue;
+            }
+        };
+        let list = current_list(&listings);
+        let result =
+            stream.set_write_timeout(Some(WRITE_TIMEOUT)).and_then(|_| stream.write_all(&list));
+        if let Err(e) = result {
+            println!("Failed to send the list: {}", e);
+        }
+    }
+}
+
+/// Probe the servers that announce themselves, and keep the answers that come back
+fn receive_datagrams(socket: UdpSocket, listings: Listings) {
+    let mut buffer = [0; codec::MAX_DATAGRAM_SIZE];
+    loop {
+        let (len, addr) = match socket.recv_from(&mut buffer) {
+            Ok(received) => received,
+            Err(e) => {
+                println!("Failed to receive a datagram: {}", e);
+                continue;
+            }
+        };
+        let data = &buffer[..len];
+
+        let mut listings = listings.lock().unwrap();
+        listings.retain(|_, listing| listing.announced_at.elapsed() < master::EXPIRY);
+        if let Some(port) = master::decode_announcement(data) {
+            let server = SocketAddr::new(addr.ip(), port);
+            if !listings.contains_key(&server) && listings.len() >= master::MAX_LISTED {
+                continue;
+            }
+            let listing = listings
+                .entry(server)
+                .or_insert(Listing { announced_at: Instant::now(), answer: None });
+            listing.announced_at = Instant::now();
+            if let Err(e) = socket.send_to(&discovery::encode_probe(), server) {
+                println!("Failed to probe {}: {}", server, e);
+            }
+        }
+        else if discovery::decode_answer(data).is_some() {
+            if let Some(listing) = listings.get_mut(&addr) {
+                listing.answer = Some(data.to_vec());
+            }
+        }
+    }
+}
+
+/// The list of servers that have answered, for sending to a client
+fn current_list(listings: &Listings) -> Vec<u8> {
+    let listings = listings.lock().unwrap();
+    let servers: Vec<_> = listings
+        .iter()
+        .filter(|(_, listing)| listing.announced_at.elapsed() < master::EXPIRY)
+        .filter_map(|(&addr, listing)| Some((addr, listing.answer.clone()?)))
+        .collect();
+    master::encode_list(&servers)
+}
@@ -0,0 +1,13 @@
+//! Prints a JSON description of the network protocol, generated from the `NetworkEvent` type.
+//!
+//! This allows third-party servers and tools to keep in sync with the wire format without having
+//! to read the source code.
+fn main() {
+    match network_common::schema::protocol_schema() {
+        Ok(registry) => println!("{}", serde_json::to_string_pretty(&registry).unwrap()),
+        Err(e) => {
+            eprintln!("Failed to generate protocol schema: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,575 @@
+//! The client side of the network connection
+//!
+//! Events are sent over TCP, except for movement updates, which are sent over UDP if the server
+//! supports it. Movement is sent often and a lost update is soon replaced by the next one, so it is
+//! better for it to arrive quickly than reliably. Movement is always sent over the connection when it
+//! uses TLS or WebSocket, which datagrams would bypass. During a relayed battle, the same UDP socket
+//! is used to send battle messages straight to the opponent where possible (see `peer.rs`).
+//!
+//! A `Ping` is sent to the server as soon as the player joins and then every `HEARTBEAT_INTERVAL`,
+//! and the connection is given up on if nothing is received from the server for
+//! `CONNECTION_TIMEOUT`. The network thread then stops, which the game sees as its receiving
+//! channel being disconnected. Each ping carries the time it was sent, which the server echoes back
+//! in its `Pong`, so the round trip time is measured without keeping track of pings in flight.
+//!
+//! Once the handshakes are done, a single thread serves the connection without blocking. It waits
+//! on the connection, the UDP socket and the game's channel at once (see `poll.rs`), so it can stop
+//! as soon as the game drops its channel or the connection closes. Events that the game queues
+//! while the thread is busy are written to the connection together, which saves a system call per
+//! event and lets TLS and WebSocket wrap them in a single record or message. The channels to and
+//! from the game are bounded (see `outbox.rs`), and the thread stops taking the game's events while
+//! more than `MAX_QUEUED_BYTES` are waiting for the server. Likewise, it stops reading from the
+//! server while the game has no room for the events already read.
+//!
+//! When the player leaves, the game sends `PlayerQuit` (see `disconnect`), and the thread writes
+//! everything still queued before closing the connection, so other players see them leave straight
+//! away instead of when their connection times out.
+use std::{
+    io::{self, BufReader},
+    net::{Shutdown, SocketAddr, TcpStream, UdpSocket},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use crossbeam_channel::{Receiver, RecvTimeoutError, Sender, TryRecvError};
+
+use crate::{
+    account::{self, Login},
+    codec::{self, Compression},
+    connection::Connection,
+    error::{NetworkError, NetworkResult},
+    game::{self, GameInfo},
+    outbox::Outbox,
+    peer::{Outgoing, Peers},
+    poll::{self, Poller},
+    simulator::{NetworkConditions, Simulator},
+    stats::Traffic,
+    transport::Transport,
+    version, NetworkEvent, PlayerId, CONNECTION_TIMEOUT, HEARTBEAT_INTERVAL,
+};
+
+/// How long to keep writing queued events to the server once the player has left
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// The most bytes to queue for the server before the game's events are left in their channel. The
+/// game then holds back its own events, keeping only its newest movement.
+const MAX_QUEUED_BYTES: usize = 64 * 1024;
+
+/// How often to try again to pass on events that the game has not had room for
+const RETRY_INTERVAL: Duration = Duration::from_millis(10);
+
+pub struct NetworkManager {
+    pub socket: Box<dyn Transport>,
+    pub local_update_receiver: poll::Receiver<NetworkEvent>,
+    pub global_update_sender: Sender<NetworkEvent>,
+    /// Whether to accept and send compressed packets, if the server also does
+    pub compression: Compression,
+    /// The game being played, which the server checks before letting the player join
+    pub game: GameInfo,
+    /// The account to log in to, or None to join as a guest
+    pub login: Option<Login>,
+    /// Updated with the round trip time to the server whenever a `Pong` arrives
+    pub latency: Latency,
+    /// Updated with every event and byte sent to or received from the server
+    pub traffic: Traffic,
+    /// The conditions to simulate on the connection once joined, for testing (see `simulator.rs`)
+    pub simulate: NetworkConditions,
+}
+
+/// The round trip time to the server, shared between the network threads and the game
+#[derive(Clone, Default)]
+pub struct Latency {
+    /// Smoothed round trip time in microseconds, or 0 before the first `Pong` arrives
+    micros: Arc<AtomicU64>,
+}
+
+impl Latency {
+    /// The current round trip time, or None if it has not been measured yet
+    pub fn get(&self) -> Option<Duration> {
+        match self.micros.load(Ordering::Relaxed) {
+            0 => None,
+            micros => Some(Duration::from_micros(micros)),
+        }
+    }
+
+    /// Add a new measurement. Each one moves the current value an eighth of the way towards it, as
+    /// TCP does, so a single slow packet doesn't make the connection look bad.
+    fn record(&self, sample: Duration) {
+        let sample = (sample.as_micros() as u64).max(1);
+        let smoothed = match self.micros.load(Ordering::Relaxed) {
+            0 => sample,
+            current => (current * 7 + sample) / 8,
+        };
+        self.micros.store(smoothed, Ordering::Relaxed);
+    }
+}
+
+/// The timestamp sent in a `Ping`, in milliseconds since the connection was made. This wraps
+/// around after 49 days, which `round_trip` allows for.
+fn timestamp(connected_at: Instant) -> u32 {
+    connected_at.elapsed().as_millis() as u32
+}
+
+fn round_trip(connected_at: Instant, sent_at: u32) -> Duration {
+    Duration::from_millis(timestamp(connected_at).wrapping_sub(sent_at) as u64)
+}
+
+/// What the server told the client while it joined
+pub struct Joined {
+    /// The id that the server assigned to this player
+    pub player_id: PlayerId,
+    /// A new token for the account that was logged in to, to be used in place of its password next
+    /// time
+    pub token: Option<String>,
+}
+
+/// Performs the version, compression, game, login and join handshakes with the server, and then
+/// spawns a thread for sending and receiving network events.
+pub fn handle_network(network_manager: NetworkManager) -> NetworkResult<Joined> {
+    let mut reader = BufReader::new(network_manager.socket);
+
+    // The handshakes block, so give up on a server that never answers them
+    reader.get_ref().tcp().set_read_timeout(Some(CONNECTION_TIMEOUT))?;
+
+    // Check that the server speaks the same protocol before reading any packets from it
+    version::write_preamble(reader.get_mut())?;
+    codec::write_compression(reader.get_mut(), network_manager.compression)?;
+    game::write_game(reader.get_mut(), &network_manager.game)?;
+    version::check(version::read_preamble(&mut reader)?)?;
+    let compression = codec::agreed_compression(
+        network_manager.compression,
+        codec::read_compression(&mut reader)?,
+    );
+    game::read_verdict(&mut reader)?;
+    account::write_login(reader.get_mut(), network_manager.login.as_ref())?;
+    let token = account::read_answer(&mut reader)?;
+
+    let player_id = match codec::read_packet(&mut reader)? {
+        Some(NetworkEvent::PlayerJoin(id)) => id,
+        _ => return Err(NetworkError::DecodeError),
+    };
+
+    let global_update_sender = network_manager.global_update_sender;
+
+    // Servers that support UDP send a token for it straight after the player joins
+    let movement_channel = match codec::read_packet(&mut reader)? {
+        Some(NetworkEvent::MovementChannel(_)) if !reader.get_ref().allows_datagrams() => None,
+        Some(NetworkEvent::MovementChannel(token)) => {
+            match MovementChannel::open(reader.get_ref().tcp(), token) {
+                Ok(movement_channel) => Some(movement_channel),
+                Err(e) => {
+                    println!("Failed to open a UDP socket, sending movement over TCP: {}", e);
+                    None
+                }
+            }
+        }
+        Some(packet) => {
+            let _ = global_update_sender.send(packet);
+            None
+        }
+        None => None,
+    };
+
+    let simulate = network_manager.simulate;
+    let simulator = (!simulate.is_perfect()).then(|| {
+        println!("Simulating network conditions: {:?}", simulate);
+        Simulator::new(simulate)
+    });
+
+    let mut connection = Connection::from_reader(reader, compression)?;
+    connection.set_traffic(network_manager.traffic.clone());
+    let mut session = Session {
+        connection,
+        movement_channel,
+        local_update_receiver: network_manager.local_update_receiver,
+        global_updates: Outbox::new(global_update_sender),
+        latency: network_manager.latency,
+        traffic: network_manager.traffic,
+        peers: Peers::new(player_id),
+        player_id,
+        connected_at: Instant::now(),
+        simulator,
+    };
+
+    // Measure the round trip time straight away, instead of waiting for the first heartbeat
+    session.ping()?;
+
+    thread::spawn(move || session.run());
+
+    Ok(Joined { player_id, token })
+}
+
+/// The UDP socket for movement updates. It is not connected to the server, so that it can also
+/// reach the opponent in a relayed battle through the path that the server has seen it use.
+struct MovementChannel {
+    socket: UdpSocket,
+    token: u64,
+    server: SocketAddr,
+}
+
+impl MovementChannel {
+    /// Open a UDP socket, using the same kind of address as the TCP connection to the server
+    fn open(socket: &TcpStream, token: u64) -> io::Result<MovementChannel> {
+        let udp_socket = UdpSocket::bind((socket.local_addr()?.ip(), 0))?;
+        udp_socket.set_nonblocking(true)?;
+        Ok(MovementChannel { socket: udp_socket, token, server: socket.peer_addr()? })
+    }
+}
+
+/// Everything that the network thread needs once the handshakes are done
+struct Session {
+    connection: Connection,
+    /// The UDP socket for movement updates, if the server supports them
+    movement_channel: Option<MovementChannel>,
+    /// Direct connections to opponents in relayed battles, which need the movement channel
+    peers: Peers,
+    local_update_receiver: poll::Receiver<NetworkEvent>,
+    /// Events for the game, which are held while its channel is full
+    global_updates: Outbox<Sender<NetworkEvent>>,
+    latency: Latency,
+    traffic: Traffic,
+    player_id: PlayerId,
+    connected_at: Instant,
+    /// Holds back events in both directions, if a poor connection is being simulated
+    simulator: Option<Simulator>,
+}
+
+impl Session {
+    fn run(mut self) {
+        let mut poller = Poller::new();
+        let mut next_ping = Instant::now() + HEARTBEAT_INTERVAL;
+        // When the connection was last read after a pause, so that time spent not reading it does
+        // not count towards the timeout
+        let mut reading_since = Instant::now();
+        loop {
+            let now = Instant::now();
+            // Stop reading from the server while the game has no room for what has already been
+            // read, leaving the rest to wait in the connection instead of in memory
+            let reading = !self.global_updates.is_holding();
+            if !reading {
+                reading_since = now;
+            }
+            // The server answers every ping, so a connection that stays silent for longer than
+            // this has been dropped
+            let received_at = match &self.simulator {
+                Some(simulator) => simulator.received_at(),
+                None => self.connection.received_at(),
+            };
+            let timeout_at = received_at.max(reading_since) + CONNECTION_TIMEOUT;
+            if now >= timeout_at {
+                println!("Connection to the server timed out");
+                break;
+            }
+            if now >= next_ping {
+                next_ping = now + HEARTBEAT_INTERVAL;
+                if self.ping().is_err() {
+                    break;
+                }
+            }
+            if let Err(e) = self.connection.flush() {
+                println!("Disconnected from server: {}", e);
+                break;
+            }
+            let _ = self.global_updates.flush();
+
+            // Stop taking events from the game while the server is not keeping up with them
+            let backed_up = self.connection.queued_len() >= MAX_QUEUED_BYTES;
+            let mut wake_at = next_ping.min(timeout_at);
+            if self.global_updates.is_holding() {
+                wake_at = wake_at.min(now + RETRY_INTERVAL);
+            }
+            let outgoing = self.peers.poll();
+            self.send_outgoing(outgoing);
+            if let Some(next_poll) = self.peers.next_poll() {
+                wake_at = wake_at.min(next_poll);
+            }
+            if let Some(next_due) = self.simulate() {
+                wake_at = wake_at.min(next_due);
+            }
+
+            poller.clear();
+            let tcp = match (reading, self.connection.wants_write()) {
+                (true, write) => Some(poller.add(self.connection.tcp(), write)),
+                (false, true) => Some(poller.add_writable(self.connection.tcp())),
+                (false, false) => None,
+            };
+            let udp =
+                self.movement_channel.as_ref().map(|channel| poller.add(&channel.socket, false));
+            let local = (!backed_up).then(|| poller.add(self.local_update_receiver.waker(), false));
+            if poller.wait(wake_at.saturating_duration_since(now)).is_err() {
+                break;
+            }
+
+            if reading && tcp.is_some_and(|tcp| poller.is_ready(tcp)) && !self.receive_packets() {
+                break;
+            }
+            if udp.is_some_and(|udp| poller.is_ready(udp)) {
+                self.receive_movement();
+            }
+            if local.is_some_and(|local| poller.is_ready(local)) && !self.send_local_updates() {
+                break;
+            }
+        }
+
+        self.close();
+    }
+
+    /// Send whatever is left before closing the connection, if the server will take it within
+    /// `CLOSE_TIMEOUT`
+    fn close(&mut self) {
+        let deadline = Instant::now() + CLOSE_TIMEOUT;
+        let mut poller = Poller::new();
+        while self.connection.flush().is_ok() && self.connection.wants_write() {
+            let now = Instant::now();
+            if now >= deadline {
+                break;
+            }
+            poller.clear();
+            poller.add(self.connection.tcp(), true);
+            if poller.wait(deadline - now).is_err() {
+                break;
+            }
+        }
+        let _ = self.connection.tcp().shutdown(Shutdown::Both);
+    }
+
+    fn ping(&mut self) -> NetworkResult<()> {
+        let ping = NetworkEvent::Ping(self.player_id, timestamp(self.connected_at));
+        self.traffic.record_sent(&ping);
+        match &mut self.simulator {
+            Some(simulator) => {
+                simulator.send(ping);
+                Ok(())
+            }
+            None => self.connection.send(&ping),
+        }
+    }
+
+    /// Pass on the events that the simulator has held back for long enough. Returns when the next
+    /// one is due, if any are waiting.
+    fn simulate(&mut self) -> Option<Instant> {
+        let simulator = self.simulator.as_mut()?;
+        let sent = simulator.take_sent();
+        let received = simulator.take_received();
+        for event in sent {
+            self.dispatch(event);
+        }
+        for packet in received {
+            self.handle_packet(packet);
+        }
+        self.simulator.as_ref()?.next_due()
+    }
+
+    /// Pass on the packets that the server has sent. Returns false once the connection has closed.
+    fn receive_packets(&mut self) -> bool {
+        let packets = match self.connection.receive() {
+            Ok(packets) => packets,
+            Err(e) => {
+                println!("Disconnected from server: {}", e);
+                return false;
+            }
+        };
+
+        for packet in packets {
+            match packet {
+                Ok(packet) => {
+                    self.traffic.record_received(&packet);
+                    self.receive_packet(packet);
+                }
+                // Invalid packets are skipped, since the rest of the stream is still usable
+                Err(_) => println!("Received an invalid packet from the server"),
+            }
+        }
+
+        if self.connection.is_closed() {
+            println!("Disconnected from server");
+            return false;
+        }
+        true
+    }
+
+    /// Pass on a packet that has arrived, once the simulator has held it back if there is one
+    fn receive_packet(&mut self, packet: NetworkEvent) {
+        match &mut self.simulator {
+            Some(simulator) => simulator.receive(packet),
+            None => self.handle_packet(packet),
+        }
+    }
+
+    fn handle_packet(&mut self, packet: NetworkEvent) {
+        match packet {
+            NetworkEvent::Pong(sent_at) => {
+                self.latency.record(round_trip(self.connected_at, sent_at));
+            }
+
+            // Direct connections are handled here, and only need the movement channel
+            NetworkEvent::PeerAddress(_, peer, addr, token) => {
+                if self.movement_channel.is_some() {
+                    self.peers.open(peer, addr, token);
+                }
+            }
+
+            NetworkEvent::PeerPacket(_, from, sequence, message) => {
+                if let Some(event) = self.peers.receive_relayed(from, sequence, message) {
+                    let _ = self.global_updates.send(event);
+                }
+            }
+
+            NetworkEvent::PlayerQuit(id) => {
+                self.peers.close(id);
+                let _ = self.global_updates.send(NetworkEvent::PlayerQuit(id));
+            }
+
+            packet => {
+                // TODO: better error handling
+                let _ = self.global_updates.send(packet);
+            }
+        }
+    }
+
+    /// Pass on the movement updates that the server has sent over UDP, and the battle messages that
+    /// opponents have sent directly
+    fn receive_movement(&mut self) {
+        let mut buffer = [0; codec::MAX_DATAGRAM_SIZE];
+        loop {
+            let channel = match &self.movement_channel {
+                Some(movement_channel) => movement_channel,
+                None => return,
+            };
+            let (len, addr) = match channel.socket.recv_from(&mut buffer) {
+                Ok(received) => received,
+                // Reported when an earlier datagram could not be delivered
+                Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => continue,
+                Err(_) => break,
+            };
+            self.traffic.record_bytes_received(len);
+
+            if addr != channel.server {
+                let (replies, event) = self.peers.receive(addr, &buffer[..len]);
+                self.send_outgoing(replies);
+                if let Some(event) = event {
+                    self.traffic.record_received(&event);
+                    self.receive_packet(event);
+                }
+                continue;
+            }
+            match codec::decode_datagram(&buffer[..len]) {
+                Ok((received_token, packet)) if received_token == channel.token => {
+                    self.traffic.record_received(&packet);
+                    self.receive_packet(packet);
+                }
+                _ => println!("Received an invalid datagram from the server"),
+            }
+        }
+    }
+
+    /// Send datagrams to opponents, and anything that could not be sent directly to the server
+    fn send_outgoing(&mut self, outgoing: Vec<Outgoing>) {
+        for outgoing in outgoing {
+            let sent = match (&outgoing, &self.movement_channel) {
+                (Outgoing::Direct(addr, datagram), Some(channel)) => {
+                    channel.socket.send_to(datagram, addr)
+                }
+                _ => Ok(0),
+            };
+            match (outgoing, sent) {
+                (Outgoing::Direct(..), Ok(len)) => self.traffic.record_bytes_sent(len),
+                (Outgoing::Direct(addr, _), Err(e)) => {
+                    println!("Failed to send a datagram to {}: {}", addr, e);
+                }
+                (Outgoing::Server(event), _) => {
+                    if let Err(e) = self.connection.send(&event) {
+                        println!("Failed to send a packet to the server: {}", e);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Queue everything that the game has sent to be written to the connection together. Returns
+    /// false once the player has left, or the game has dropped its end of the channel.
+    fn send_local_updates(&mut self) -> bool {
+        loop {
+            let event = match self.local_update_receiver.try_recv() {
+                Ok(event) => event,
+                Err(TryRecvError::Empty) => return true,
+                Err(TryRecvError::Disconnected) => return false,
+            };
+            self.traffic.record_sent(&event);
+
+            let events = match &mut self.simulator {
+                Some(simulator) if !matches!(event, NetworkEvent::PlayerQuit(_)) => {
+                    simulator.send(event);
+                    continue;
+                }
+                // Leaving is not held back, so that the connection can close once it is sent
+                Some(simulator) => {
+                    let mut events = simulator.take_all_sent();
+                    events.push(event);
+                    events
+                }
+                None => vec![event],
+            };
+            for event in events {
+                if !self.dispatch(event) {
+                    return false;
+                }
+            }
+        }
+    }
+
+    /// Send an event to the server, or straight to the opponent. Returns false once it is the
+    /// player leaving.
+    fn dispatch(&mut self, event: NetworkEvent) -> bool {
+        if let (NetworkEvent::MovementUpdate(..), Some(channel)) = (&event, &self.movement_channel)
+        {
+            // Fall back to TCP if the datagram could not be sent
+            let sent = codec::encode_datagram(channel.token, &event)
+                .and_then(|datagram| Ok(channel.socket.send_to(&datagram, channel.server)?));
+            if let Ok(len) = sent {
+                self.traffic.record_bytes_sent(len);
+                return true;
+            }
+        }
+
+        // Battle messages go straight to the opponent once a direct connection is open
+        let event = match self.peers.send(event) {
+            Outgoing::Server(event) => event,
+            direct => {
+                self.send_outgoing(vec![direct]);
+                return true;
+            }
+        };
+
+        if let Err(e) = self.connection.send(&event) {
+            println!("Failed to send a packet to the server: {}", e);
+        }
+        !matches!(event, NetworkEvent::PlayerQuit(_))
+    }
+}
+
+/// Tell the server that the player is leaving, and wait for the network thread to send what is
+/// still queued and close the connection. Events received in the meantime are dropped.
+pub fn disconnect(
+    player_id: PlayerId,
+    local_updates: &mut Outbox<poll::Sender<NetworkEvent>>,
+    global_update_receiver: &Receiver<NetworkEvent>,
+) {
+    if local_updates.send(NetworkEvent::PlayerQuit(player_id)).is_err() {
+        return;
+    }
+    let deadline = Instant::now() + CLOSE_TIMEOUT * 2;
+    while Instant::now() < deadline {
+        // Events that were held back are sent as the network thread makes room for them
+        let _ = local_updates.flush();
+        match global_update_receiver.recv_timeout(RETRY_INTERVAL) {
+            Ok(_) | Err(RecvTimeoutError::Timeout) => {}
+            // The network thread drops its end of the channel once the connection has closed
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+    }
+}
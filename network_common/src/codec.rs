@@ -0,0 +1,583 @@
+//! Encoding and decoding of packets sent over the network.
+//!
+//! Each packet is a `NetworkEvent` in the binary encoding from `wire`, prefixed by its length as a
+//! little endian `u32` and a byte giving the `Compression` of the rest of the packet. Movement
+//! updates may also be sent as UDP datagrams, which start with the sender's or recipient's token as
+//! a little endian `u64` instead, and are never compressed. The same goes for battle messages sent
+//! straight between two players (see `peer`), which start with the token for the pair.
+//!
+//! Straight after the version preamble, each end sends the compression that it accepts. Packets are
+//! only compressed if the other end accepts it, and reading always accepts either.
+//!
+//! Packets come from untrusted peers, so everything here must handle arbitrary input without
+//! panicking or allocating unbounded amounts of memory. Packets that decode successfully are also
+//! checked for values that the rest of the game is not prepared to handle (e.g. battle data that
+//! would overflow the enemy party in the emulator's memory).
+use std::io::prelude::*;
+
+use flate2::{read::DeflateDecoder, write::DeflateEncoder};
+
+use interface::{
+    data::{
+        BattlerData, MovementData, PlayerData, BATTLE_DATA_SIZE, MAX_ITEM_ID, MAX_ITEM_QUANTITY,
+        MAX_LEVEL, MAX_MONEY, MAX_NAME_LENGTH, MAX_SPECIES_ID, MAX_WALK_COUNTER, SPRITE_SHEET_SIZE,
+    },
+    text,
+};
+
+use crate::{
+    error::{NetworkError, NetworkResult},
+    wire, NetworkEvent, ShopItem, TradeListing, MAX_LEADERBOARD_LENGTH, MAX_SHOP_ITEMS,
+    MAX_SNAPSHOT_PLAYERS, MAX_TRADE_LISTINGS, MAX_UPDATE_RATE, MIN_DAY_LENGTH, QUICK_CHAT_PHRASES,
+};
+
+/// The maximum size of an encoded packet, not including its length
+pub const MAX_PACKET_SIZE: usize = 64 * 1024;
+
+/// The size of the length that each packet starts with
+const LENGTH_SIZE: usize = 4;
+
+/// Packets smaller than this are sent uncompressed, since there is little to gain from them
+const COMPRESSION_THRESHOLD: usize = 128;
+
+/// How the body of a packet is encoded
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Compression {
+    None = 0,
+    Deflate = 1,
+}
+
+impl Compression {
+    fn from_byte(byte: u8) -> Option<Compression> {
+        match byte {
+            0 => Some(Compression::None),
+            1 => Some(Compression::Deflate),
+            _ => None,
+        }
+    }
+}
+
+/// The maximum size of a datagram, which keeps it well below the size that would be fragmented
+pub const MAX_DATAGRAM_SIZE: usize = 512;
+
+/// The size of the token that each datagram starts with
+const TOKEN_SIZE: usize = 8;
+
+/// The maximum number of characters in a chat message
+pub const MAX_CHAT_LENGTH: usize = 256;
+
+/// The maximum number of characters in a server's message of the day
+pub const MAX_MOTD_LENGTH: usize = 512;
+
+/// The most features that a usage report can count
+const MAX_USAGE_FEATURES: usize = 32;
+
+/// The longest crash location that a usage report can give
+pub const MAX_CRASH_LENGTH: usize = 128;
+
+/// The number of colors that can be used in a sprite
+const SPRITE_COLORS: u8 = 4;
+
+/// Read and decode a single packet. Returns `Ok(None)` if the connection was closed.
+///
+/// A `DecodeError` means that the packet was invalid but has been fully consumed, so the caller may
+/// continue reading. Any other error leaves the stream in an unknown state.
+pub fn read_packet<R: BufRead>(reader: &mut R) -> NetworkResult<Option<NetworkEvent>> {
+    if reader.fill_buf()?.is_empty() {
+        return Ok(None);
+    }
+
+    let mut length = [0; LENGTH_SIZE];
+    reader.read_exact(&mut length)?;
+    let length = u32::from_le_bytes(length) as usize;
+    if length > MAX_PACKET_SIZE {
+        return Err(NetworkError::PacketTooLarge);
+    }
+
+    let mut buffer = vec![0; length];
+    reader.read_exact(&mut buffer)?;
+    decode_packet(&buffer).map(Some)
+}
+
+/// Split the first packet off the front of the data received so far, returning the packet without
+/// its length (for `decode_packet`) and the data after it. Returns `Ok(None)` if the whole packet
+/// has not arrived yet.
+pub fn split_packet(data: &[u8]) -> NetworkResult<Option<(&[u8], &[u8])>> {
+    let length = match data.get(..LENGTH_SIZE) {
+        Some(length) => u32::from_le_bytes(length.try_into().unwrap()) as usize,
+        None => return Ok(None),
+    };
+    if length > MAX_PACKET_SIZE {
+        return Err(NetworkError::PacketTooLarge);
+    }
+    Ok(data[LENGTH_SIZE..].get(..length).map(|packet| (packet, &data[LENGTH_SIZE + length..])))
+}
+
+/// Tell the other end which compression this end accepts
+pub fn write_compression<W: Write>(writer: &mut W, accepted: Compression) -> NetworkResult<()> {
+    writer.write_all(&[accepted as u8])?;
+    Ok(())
+}
+
+/// Read the compression that the other end accepts
+pub fn read_compression<R: Read>(reader: &mut R) -> NetworkResult<Compression> {
+    let mut byte = [0];
+    reader.read_exact(&mut byte)?;
+    Compression::from_byte(byte[0]).ok_or(NetworkError::DecodeError)
+}
+
+/// The compression to send packets with, given the compression accepted by each end
+pub fn agreed_compression(own: Compression, other: Compression) -> Compression {
+    match (own, other) {
+        (Compression::Deflate, Compression::Deflate) => Compression::Deflate,
+        _ => Compression::None,
+    }
+}
+
+/// Encode a packet, including its length. Large packets are compressed if the other end accepts it
+/// and it makes them smaller.
+pub fn encode_packet(event: &NetworkEvent, compression: Compression) -> NetworkResult<Vec<u8>> {
+    let data = wire::to_vec(event)?;
+    if data.len() > MAX_PACKET_SIZE {
+        return Err(NetworkError::EncodeError);
+    }
+
+    let compressed = match compression {
+        Compression::Deflate if data.len() >= COMPRESSION_THRESHOLD => {
+            Some(compress(&data)?).filter(|compressed| compressed.len() < data.len())
+        }
+        _ => None,
+    };
+
+    let mut packet = vec![0; LENGTH_SIZE];
+    match compressed {
+        Some(compressed) => {
+            packet.push(Compression::Deflate as u8);
+            packet.extend(compressed);
+        }
+        None => {
+            packet.push(Compression::None as u8);
+            packet.extend(data);
+        }
+    }
+
+    let length = packet.len() - LENGTH_SIZE;
+    if length > MAX_PACKET_SIZE {
+        return Err(NetworkError::EncodeError);
+    }
+    packet[..LENGTH_SIZE].copy_from_slice(&(length as u32).to_le_bytes());
+    Ok(packet)
+}
+
+/// Encode a movement update as a datagram, starting with the token of the player that it is sent
+/// to or from
+pub fn encode_datagram(token: u64, event: &NetworkEvent) -> NetworkResult<Vec<u8>> {
+    let mut datagram = token.to_le_bytes().to_vec();
+    datagram.extend(wire::to_vec(event)?);
+    if datagram.len() > MAX_DATAGRAM_SIZE {
+        return Err(NetworkError::EncodeError);
+    }
+    Ok(datagram)
+}
+
+/// Decode and validate a datagram, returning its token along with the movement update it contains
+pub fn decode_datagram(data: &[u8]) -> NetworkResult<(u64, NetworkEvent)> {
+    match decode_any_datagram(data)? {
+        (token, event @ NetworkEvent::MovementUpdate(..)) => Ok((token, event)),
+        _ => Err(NetworkError::DecodeError),
+    }
+}
+
+/// Decode and validate a datagram sent straight from another player, returning its token along
+/// with the event it contains
+pub fn decode_peer_datagram(data: &[u8]) -> NetworkResult<(u64, NetworkEvent)> {
+    match decode_any_datagram(data)? {
+        (token, event @ NetworkEvent::PeerHello(..))
+        | (token, event @ NetworkEvent::PeerPacket(..))
+        | (token, event @ NetworkEvent::PeerAck(..)) => Ok((token, event)),
+        _ => Err(NetworkError::DecodeError),
+    }
+}
+
+fn decode_any_datagram(data: &[u8]) -> NetworkResult<(u64, NetworkEvent)> {
+    if data.len() > MAX_DATAGRAM_SIZE {
+        return Err(NetworkError::PacketTooLarge);
+    }
+    if data.len() < TOKEN_SIZE {
+        return Err(NetworkError::DecodeError);
+    }
+
+    let (token, data) = data.split_at(TOKEN_SIZE);
+    let token = u64::from_le_bytes(token.try_into().unwrap());
+    Ok((token, decode_event(data)?))
+}
+
+/// Decode and validate a single packet, without its length
+pub fn decode_packet(data: &[u8]) -> NetworkResult<NetworkEvent> {
+    if data.len() > MAX_PACKET_SIZE {
+        return Err(NetworkError::PacketTooLarge);
+    }
+
+    let (&compression, data) = data.split_first().ok_or(NetworkError::DecodeError)?;
+    match Compression::from_byte(compression) {
+        Some(Compression::None) => decode_event(data),
+        Some(Compression::Deflate) => decode_event(&decompress(data)?),
+        None => Err(NetworkError::DecodeError),
+    }
+}
+
+/// Decode and validate an uncompressed event
+fn decode_event(data: &[u8]) -> NetworkResult<NetworkEvent> {
+    let event = wire::from_slice(data)?;
+    validate(&event)?;
+    Ok(event)
+}
+
+fn compress(data: &[u8]) -> NetworkResult<Vec<u8>> {
+    let mut encoder = DeflateEncoder::new(vec![], flate2::Compression::default());
+    encoder.write_all(data).map_err(|_| NetworkError::EncodeError)?;
+    encoder.finish().map_err(|_| NetworkError::EncodeError)
+}
+
+/// Decompress the body of a packet. A small packet can decompress to a huge amount of data, so the
+/// output is limited to the same size as an uncompressed packet.
+fn decompress(data: &[u8]) -> NetworkResult<Vec<u8>> {
+    let mut decoded = vec![];
+    DeflateDecoder::new(data)
+        .take(MAX_PACKET_SIZE as u64 + 1)
+        .read_to_end(&mut decoded)
+        .map_err(|_| NetworkError::DecodeError)?;
+    if decoded.len() > MAX_PACKET_SIZE {
+        return Err(NetworkError::PacketTooLarge);
+    }
+    Ok(decoded)
+}
+
+/// Check that a decoded event only contains values that are safe to pass to the game
+pub fn validate(event: &NetworkEvent) -> NetworkResult<()> {
+    let valid = match event {
+        NetworkEvent::FullUpdate(_, _, player_data) => valid_player_data(player_data),
+        NetworkEvent::PlayerSnapshot(players) => {
+            players.len() <= MAX_SNAPSHOT_PLAYERS
+                && players.iter().all(|(_, _, player_data)| valid_player_data(player_data))
+        }
+        NetworkEvent::MovementUpdate(_, _, movement_data) => valid_movement_data(movement_data),
+        NetworkEvent::Chat(_, _, msg)
+        | NetworkEvent::BattleChat(_, _, _, msg)
+        | NetworkEvent::AwayMessage(_, _, msg) => msg.chars().count() <= MAX_CHAT_LENGTH,
+        NetworkEvent::Motd(motd) => motd.chars().count() <= MAX_MOTD_LENGTH,
+        NetworkEvent::BattleDataResponse(_, _, battle_data)
+        | NetworkEvent::RelayBattleData(_, _, battle_data) => battle_data.len() == BATTLE_DATA_SIZE,
+        NetworkEvent::BattleAction(_, _, action) => action.is_valid(),
+        NetworkEvent::PeerPacket(to, from, _, message) => {
+            validate(&message.to_event(*to, *from)).is_ok()
+        }
+        NetworkEvent::BattleQuickChat(_, _, phrase) => {
+            (*phrase as usize) < QUICK_CHAT_PHRASES.len()
+        }
+        NetworkEvent::WildEncounter(_, species, _) => valid_species(*species),
+        NetworkEvent::SafariCatch(_, species) => valid_species(*species),
+        NetworkEvent::TradeListingPost(player, species, level, wanted) => {
+            valid_listing(&TradeListing {
+                player: *player,
+                species: *species,
+                level: *level,
+                wanted: *wanted,
+            })
+        }
+        NetworkEvent::TradeBoard(listings) => {
+            listings.len() <= MAX_TRADE_LISTINGS && listings.iter().all(valid_listing)
+        }
+        NetworkEvent::TradeOffer(_, _, species, level) => {
+            valid_species(*species) && valid_level(*level)
+        }
+        NetworkEvent::ShopCatalog(items) => {
+            items.len() <= MAX_SHOP_ITEMS && items.iter().all(valid_shop_item)
+        }
+        NetworkEvent::ShopPurchase(_, item, quantity) => {
+            valid_item(*item) && (1..=MAX_ITEM_QUANTITY).contains(quantity)
+        }
+        NetworkEvent::DayCycle(cycle) => cycle.length_secs >= MIN_DAY_LENGTH,
+        NetworkEvent::SafariUpdate(status) => status.leaderboard.len() <= MAX_LEADERBOARD_LENGTH,
+        NetworkEvent::RateLimits(limits) => {
+            let valid_rate = |rate| (1..=MAX_UPDATE_RATE).contains(&rate);
+            valid_rate(limits.min_movement_rate) && valid_rate(limits.min_receive_rate)
+        }
+        NetworkEvent::UsageReport(report) => {
+            report.features.len() <= MAX_USAGE_FEATURES
+                && report.crash.as_ref().is_none_or(|crash| crash.len() <= MAX_CRASH_LENGTH)
+        }
+        NetworkEvent::BattleSnapshot(_, _, snapshot) => {
+            valid_battler(&snapshot.player) && valid_battler(&snapshot.enemy)
+        }
+        NetworkEvent::UpdateRequest
+        | NetworkEvent::ResyncRequest(_)
+        | NetworkEvent::PlayerJoin(_)
+        | NetworkEvent::MovementChannel(_)
+        | NetworkEvent::ChatAck(_)
+        | NetworkEvent::PlayerQuit(_)
+        | NetworkEvent::Observe(_)
+        | NetworkEvent::LiteMode(..)
+        | NetworkEvent::BattleDataRequest(..)
+        | NetworkEvent::BattleRequestCancel(..)
+        | NetworkEvent::BattleRequestRefused(..)
+        | NetworkEvent::BattleSeedCommit(..)
+        | NetworkEvent::BattleSeed(..)
+        | NetworkEvent::BattleSeedReveal(..)
+        | NetworkEvent::HallOfFame(..)
+        | NetworkEvent::SpectateRequest(..)
+        | NetworkEvent::SpectateStop(..)
+        | NetworkEvent::SpectateEnd(..)
+        | NetworkEvent::SafariStart(_)
+        | NetworkEvent::SharedSeed(_)
+        | NetworkEvent::TradeListingRemove(_)
+        | NetworkEvent::TradeBoardRequest(_)
+        | NetworkEvent::BattleRules(..)
+        | NetworkEvent::BattleItemUsed(..)
+        | NetworkEvent::BattleKeepAlive(..)
+        | NetworkEvent::BattleForfeit(..)
+        | NetworkEvent::PeerAddress(..)
+        | NetworkEvent::PeerHello(..)
+        | NetworkEvent::PeerAck(..)
+        | NetworkEvent::Ping(..)
+        | NetworkEvent::Pong(_)
+        | NetworkEvent::PlayerPaused(..)
+        | NetworkEvent::Visibility(_)
+        | NetworkEvent::ServerFailure => true,
+    };
+
+    if valid {
+        Ok(())
+    }
+    else {
+        Err(NetworkError::DecodeError)
+    }
+}
+
+fn valid_player_data(player_data: &PlayerData) -> bool {
+    // Names are drawn directly using the game's font, so they must only contain printable
+    // characters.
+    let valid_name = player_data.name.len() <= MAX_NAME_LENGTH
+        && player_data.name.iter().all(|&c| c >= 0x80 || c == text::special::SPACE);
+
+    let valid_sprite = player_data.sprite.len() == SPRITE_SHEET_SIZE
+        && player_data.sprite.iter().all(|&color_id| color_id < SPRITE_COLORS);
+
+    valid_name && valid_sprite && valid_movement_data(&player_data.movement_data)
+}
+
+fn valid_movement_data(movement_data: &MovementData) -> bool {
+    movement_data.walk_counter <= MAX_WALK_COUNTER
+}
+
+/// Species ids are used to look up names in the ROM, so they must be within the game's tables
+fn valid_species(species: u8) -> bool {
+    species != 0 && species <= MAX_SPECIES_ID
+}
+
+fn valid_level(level: u8) -> bool {
+    (1..=MAX_LEVEL).contains(&level)
+}
+
+fn valid_item(item: u8) -> bool {
+    (1..=MAX_ITEM_ID).contains(&item)
+}
+
+/// Items must be ones that can be held, and must cost something to buy
+pub fn valid_shop_item(item: &ShopItem) -> bool {
+    valid_item(item.item) && (1..=MAX_MONEY).contains(&item.price)
+}
+
+fn valid_listing(listing: &TradeListing) -> bool {
+    valid_species(listing.species)
+        && valid_level(listing.level)
+        && (listing.wanted == 0 || valid_species(listing.wanted))
+}
+
+fn valid_battler(battler: &BattlerData) -> bool {
+    valid_species(battler.species) && battler.hp <= battler.max_hp
+}
+
+#[cfg(test)]
+mod tests {
+    use interface::values::Direction;
+
+    use super::*;
+    use crate::{Feature, UsageReport};
+
+    fn player() -> PlayerData {
+        PlayerData {
+            name: vec![0x80; MAX_NAME_LENGTH],
+            sprite: vec![SPRITE_COLORS - 1; SPRITE_SHEET_SIZE],
+            movement_data: MovementData {
+                map_id: 1,
+                map_x: 2,
+                map_y: 3,
+                direction: Direction::Up,
+                walk_counter: MAX_WALK_COUNTER,
+            },
+        }
+    }
+
+    fn is_valid(event: NetworkEvent) -> bool {
+        validate(&event).is_ok()
+    }
+
+    /// Wrap an uncompressed event in the compression byte that `decode_packet` expects
+    fn uncompressed(event: &NetworkEvent) -> Vec<u8> {
+        let mut data = vec![Compression::None as u8];
+        data.extend(wire::to_vec(event).unwrap());
+        data
+    }
+
+    #[test]
+    fn packets_round_trip() {
+        let event = NetworkEvent::PlayerSnapshot(vec![(1, 2, player()); MAX_SNAPSHOT_PLAYERS]);
+        for compression in [Compression::None, Compression::Deflate] {
+            let packet = encode_packet(&event, compression).unwrap();
+            assert_eq!(packet[LENGTH_SIZE], compression as u8);
+
+            let (data, rest) = split_packet(&packet).unwrap().unwrap();
+            assert!(rest.is_empty());
+            let encoded = wire::to_vec(&event).unwrap();
+            assert_eq!(wire::to_vec(&decode_packet(data).unwrap()).unwrap(), encoded);
+            let read = read_packet(&mut &packet[..]).unwrap().unwrap();
+            assert_eq!(wire::to_vec(&read).unwrap(), encoded);
+        }
+    }
+
+    #[test]
+    fn partial_packets_are_not_split() {
+        let packet = encode_packet(&NetworkEvent::UpdateRequest, Compression::None).unwrap();
+        assert_eq!(split_packet(&packet[..LENGTH_SIZE - 1]).unwrap(), None);
+        assert_eq!(split_packet(&packet[..packet.len() - 1]).unwrap(), None);
+    }
+
+    #[test]
+    fn oversized_lengths_are_rejected() {
+        let length = (MAX_PACKET_SIZE as u32 + 1).to_le_bytes();
+        assert!(matches!(split_packet(&length), Err(NetworkError::PacketTooLarge)));
+        assert!(matches!(read_packet(&mut &length[..]), Err(NetworkError::PacketTooLarge)));
+    }
+
+    #[test]
+    fn compressed_packets_are_limited() {
+        let mut packet = vec![Compression::Deflate as u8];
+        packet.extend(compress(&vec![0; MAX_PACKET_SIZE + 1]).unwrap());
+        assert!(packet.len() < MAX_PACKET_SIZE);
+        assert!(matches!(decode_packet(&packet), Err(NetworkError::PacketTooLarge)));
+    }
+
+    #[test]
+    fn invalid_packets_are_rejected() {
+        let motd = NetworkEvent::Motd("x".repeat(MAX_MOTD_LENGTH + 1));
+        assert!(matches!(decode_packet(&uncompressed(&motd)), Err(NetworkError::DecodeError)));
+        assert!(matches!(decode_packet(&[2]), Err(NetworkError::DecodeError)));
+        assert!(matches!(decode_packet(&[]), Err(NetworkError::DecodeError)));
+    }
+
+    #[test]
+    fn messages_are_limited() {
+        // Lengths are counted in characters rather than bytes
+        let longest = "é".repeat(MAX_CHAT_LENGTH);
+        let too_long = "é".repeat(MAX_CHAT_LENGTH + 1);
+        assert!(is_valid(NetworkEvent::Chat(1, 2, longest.clone())));
+        assert!(!is_valid(NetworkEvent::Chat(1, 2, too_long.clone())));
+        assert!(is_valid(NetworkEvent::BattleChat(1, 2, 3, longest.clone())));
+        assert!(!is_valid(NetworkEvent::BattleChat(1, 2, 3, too_long.clone())));
+        assert!(is_valid(NetworkEvent::AwayMessage(1, 2, longest)));
+        assert!(!is_valid(NetworkEvent::AwayMessage(1, 2, too_long)));
+
+        assert!(is_valid(NetworkEvent::Motd("é".repeat(MAX_MOTD_LENGTH))));
+        assert!(!is_valid(NetworkEvent::Motd("é".repeat(MAX_MOTD_LENGTH + 1))));
+    }
+
+    #[test]
+    fn players_are_checked() {
+        assert!(is_valid(NetworkEvent::FullUpdate(1, 2, player())));
+
+        let mut name = player();
+        name.name.push(0x80);
+        let mut sprite = player();
+        sprite.sprite[0] = SPRITE_COLORS;
+        let mut movement = player();
+        movement.movement_data.walk_counter += 1;
+        for invalid in [name, sprite, movement] {
+            assert!(!is_valid(NetworkEvent::FullUpdate(1, 2, invalid.clone())));
+            let players = vec![(1, 2, player()), (3, 4, invalid)];
+            assert!(!is_valid(NetworkEvent::PlayerSnapshot(players)));
+        }
+    }
+
+    #[test]
+    fn snapshots_are_limited() {
+        let players = vec![(1, 2, player()); MAX_SNAPSHOT_PLAYERS];
+        assert!(is_valid(NetworkEvent::PlayerSnapshot(players.clone())));
+
+        let mut too_many = players;
+        too_many.push((1, 2, player()));
+        assert!(!is_valid(NetworkEvent::PlayerSnapshot(too_many)));
+    }
+
+    #[test]
+    fn shop_purchases_are_checked() {
+        assert!(is_valid(NetworkEvent::ShopPurchase(1, MAX_ITEM_ID, 1)));
+        assert!(is_valid(NetworkEvent::ShopPurchase(1, 1, MAX_ITEM_QUANTITY)));
+        assert!(!is_valid(NetworkEvent::ShopPurchase(1, 1, 0)));
+        assert!(!is_valid(NetworkEvent::ShopPurchase(1, 1, MAX_ITEM_QUANTITY + 1)));
+        assert!(!is_valid(NetworkEvent::ShopPurchase(1, 0, 1)));
+        assert!(!is_valid(NetworkEvent::ShopPurchase(1, MAX_ITEM_ID + 1, 1)));
+    }
+
+    #[test]
+    fn shop_catalogs_are_checked() {
+        let item = ShopItem { item: 1, price: MAX_MONEY };
+        assert!(is_valid(NetworkEvent::ShopCatalog(vec![item; MAX_SHOP_ITEMS])));
+        assert!(!is_valid(NetworkEvent::ShopCatalog(vec![item; MAX_SHOP_ITEMS + 1])));
+        assert!(!is_valid(NetworkEvent::ShopCatalog(vec![ShopItem { item: 1, price: 0 }])));
+        assert!(!is_valid(NetworkEvent::ShopCatalog(vec![ShopItem {
+            item: 1,
+            price: MAX_MONEY + 1
+        }])));
+        assert!(!is_valid(NetworkEvent::ShopCatalog(vec![ShopItem { item: 0, price: 1 }])));
+    }
+
+    #[test]
+    fn trades_are_checked() {
+        assert!(is_valid(NetworkEvent::TradeListingPost(1, 1, 1, 0)));
+        assert!(is_valid(NetworkEvent::TradeListingPost(1, MAX_SPECIES_ID, MAX_LEVEL, 1)));
+        assert!(!is_valid(NetworkEvent::TradeListingPost(1, 0, 1, 0)));
+        assert!(!is_valid(NetworkEvent::TradeListingPost(1, MAX_SPECIES_ID + 1, 1, 0)));
+        assert!(!is_valid(NetworkEvent::TradeListingPost(1, 1, 0, 0)));
+        assert!(!is_valid(NetworkEvent::TradeListingPost(1, 1, MAX_LEVEL + 1, 0)));
+        assert!(!is_valid(NetworkEvent::TradeListingPost(1, 1, 1, MAX_SPECIES_ID + 1)));
+
+        let listing = TradeListing { player: 1, species: 1, level: 1, wanted: 0 };
+        assert!(is_valid(NetworkEvent::TradeBoard(vec![listing; MAX_TRADE_LISTINGS])));
+        assert!(!is_valid(NetworkEvent::TradeBoard(vec![listing; MAX_TRADE_LISTINGS + 1])));
+        let invalid = TradeListing { species: 0, ..listing };
+        assert!(!is_valid(NetworkEvent::TradeBoard(vec![listing, invalid])));
+
+        assert!(is_valid(NetworkEvent::TradeOffer(1, 2, 1, 1)));
+        assert!(!is_valid(NetworkEvent::TradeOffer(1, 2, 0, 1)));
+        assert!(!is_valid(NetworkEvent::TradeOffer(1, 2, 1, MAX_LEVEL + 1)));
+    }
+
+    #[test]
+    fn usage_reports_are_limited() {
+        let report = UsageReport {
+            session_secs: 1,
+            features: vec![(Feature::Chat, 1); MAX_USAGE_FEATURES],
+            crash: Some("x".repeat(MAX_CRASH_LENGTH)),
+        };
+        assert!(is_valid(NetworkEvent::UsageReport(report.clone())));
+        assert!(is_valid(NetworkEvent::UsageReport(UsageReport { crash: None, ..report.clone() })));
+
+        let mut features = report.clone();
+        features.features.push((Feature::Chat, 1));
+        assert!(!is_valid(NetworkEvent::UsageReport(features)));
+
+        let crash = UsageReport { crash: Some("x".repeat(MAX_CRASH_LENGTH + 1)), ..report };
+        assert!(!is_valid(NetworkEvent::UsageReport(crash)));
+    }
+}
@@ -0,0 +1,258 @@
+//! Connections that packets are sent and received over without blocking
+//!
+//! Once its handshakes are done, a connection is switched to non-blocking, so that one thread can
+//! serve it along with any others by polling their sockets (see `poll.rs`). Data that is received
+//! is kept until it makes up whole packets, and packets that are sent are queued until the socket
+//! can take them.
+//!
+//! Packets are queued in two lanes. Urgent packets, like chat and battle events, are written ahead
+//! of the normal ones that are still queued, so that they are not held up behind a backlog of
+//! movement. Packets within a lane are written in the order they were sent.
+use std::{
+    collections::VecDeque,
+    io::{self, BufReader},
+    mem,
+    net::TcpStream,
+    time::Instant,
+};
+
+use crate::{
+    codec::{self, Compression},
+    error::NetworkResult,
+    stats::Traffic,
+    transport::Transport,
+    NetworkEvent,
+};
+
+/// How much is read from the socket at a time
+const READ_SIZE: usize = 16 * 1024;
+
+/// The most that is read from a connection each time it is polled, so that a connection that never
+/// stops sending can not hold up the others
+const MAX_READ_PER_POLL: usize = 8 * READ_SIZE;
+
+/// How many bytes of normal packets are gathered into one write. Urgent packets only wait for the
+/// write in progress, so this limits how long they can be held up.
+const WRITE_SIZE: usize = 16 * 1024;
+
+/// Which lane a packet is queued in
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Priority {
+    Normal,
+    Urgent,
+}
+
+impl Priority {
+    /// Chat and battle events are urgent. Events about the players on the server stay in the normal
+    /// lane with movement, so that they keep their order (e.g. a player leaving is never seen
+    /// before their last update).
+    pub fn of(event: &NetworkEvent) -> Priority {
+        match event {
+            NetworkEvent::Chat(..)
+            | NetworkEvent::ChatAck(_)
+            | NetworkEvent::BattleChat(..)
+            | NetworkEvent::BattleQuickChat(..)
+            | NetworkEvent::AwayMessage(..)
+            | NetworkEvent::BattleDataRequest(..)
+            | NetworkEvent::BattleDataResponse(..)
+            | NetworkEvent::BattleRequestCancel(..)
+            | NetworkEvent::BattleRequestRefused(..)
+            | NetworkEvent::BattleSeedCommit(..)
+            | NetworkEvent::BattleSeed(..)
+            | NetworkEvent::BattleSeedReveal(..)
+            | NetworkEvent::RelayBattleData(..)
+            | NetworkEvent::BattleAction(..)
+            | NetworkEvent::BattleRules(..)
+            | NetworkEvent::BattleItemUsed(..)
+            | NetworkEvent::BattleKeepAlive(..)
+            | NetworkEvent::BattleForfeit(..)
+            | NetworkEvent::PeerPacket(..)
+            | NetworkEvent::PeerAck(..) => Priority::Urgent,
+            _ => Priority::Normal,
+        }
+    }
+}
+
+pub struct Connection {
+    transport: Box<dyn Transport>,
+    /// The compression agreed with the other end during the handshake
+    compression: Compression,
+    /// Data received that does not make up a whole packet yet
+    received: Vec<u8>,
+    /// The packets being written to the socket, which always ends at the end of a packet
+    outgoing: Vec<u8>,
+    /// Urgent packets waiting to be written
+    urgent: Vec<u8>,
+    /// Normal packets waiting to be written, oldest first
+    normal: VecDeque<Vec<u8>>,
+    /// The total size of the normal packets
+    normal_len: usize,
+    /// When data was last received, to notice connections that have gone silent
+    received_at: Instant,
+    closed: bool,
+    /// Where the bytes written and read are counted, if anywhere
+    traffic: Option<Traffic>,
+}
+
+impl Connection {
+    pub fn new(transport: Box<dyn Transport>, compression: Compression) -> io::Result<Connection> {
+        transport.tcp().set_nonblocking(true)?;
+        // Packets are already gathered into as few writes as possible, so waiting to gather more
+        // would only delay them
+        transport.tcp().set_nodelay(true)?;
+        Ok(Connection {
+            transport,
+            compression,
+            received: vec![],
+            outgoing: vec![],
+            urgent: vec![],
+            normal: VecDeque::new(),
+            normal_len: 0,
+            received_at: Instant::now(),
+            closed: false,
+            traffic: None,
+        })
+    }
+
+    /// Continue a connection whose handshake was read through a `BufReader`, keeping anything that
+    /// it had already read past the handshake
+    pub fn from_reader(
+        reader: BufReader<Box<dyn Transport>>,
+        compression: Compression,
+    ) -> io::Result<Connection> {
+        let received = reader.buffer().to_vec();
+        let mut connection = Connection::new(reader.into_inner(), compression)?;
+        connection.received = received;
+        Ok(connection)
+    }
+
+    /// Count the bytes written to and read from the connection from now on
+    pub fn set_traffic(&mut self, traffic: Traffic) {
+        self.traffic = Some(traffic);
+    }
+
+    pub fn tcp(&self) -> &TcpStream {
+        self.transport.tcp()
+    }
+
+    pub fn compression(&self) -> Compression {
+        self.compression
+    }
+
+    pub fn received_at(&self) -> Instant {
+        self.received_at
+    }
+
+    /// Whether the other end has closed the connection
+    pub fn is_closed(&self) -> bool {
+        self.closed
+    }
+
+    /// Whether anything is waiting for the socket to be writable
+    pub fn wants_write(&self) -> bool {
+        self.queued_len() > 0 || self.transport.wants_write()
+    }
+
+    /// The amount of data waiting to be sent
+    pub fn queued_len(&self) -> usize {
+        self.outgoing.len() + self.urgent.len() + self.normal_len
+    }
+
+    /// Queue a packet to be sent by the next `flush`
+    pub fn send(&mut self, event: &NetworkEvent) -> NetworkResult<()> {
+        let packet = codec::encode_packet(event, self.compression)?;
+        self.send_encoded(packet, Priority::of(event));
+        Ok(())
+    }
+
+    /// Queue a packet that has already been encoded with this connection's compression
+    pub fn send_encoded(&mut self, packet: Vec<u8>, priority: Priority) {
+        match priority {
+            Priority::Urgent => self.urgent.extend(packet),
+            Priority::Normal => {
+                self.normal_len += packet.len();
+                self.normal.push_back(packet);
+            }
+        }
+    }
+
+    /// Start writing the next packets once the previous ones have been written, urgent ones first
+    fn next_write(&mut self) {
+        if !self.urgent.is_empty() {
+            self.outgoing = mem::take(&mut self.urgent);
+            return;
+        }
+        while self.outgoing.len() < WRITE_SIZE {
+            match self.normal.pop_front() {
+                Some(packet) => {
+                    self.normal_len -= packet.len();
+                    self.outgoing.extend(packet);
+                }
+                None => break,
+            }
+        }
+    }
+
+    /// Read everything that has arrived and return the packets that it completes. Packets that
+    /// could not be decoded are returned as errors, since the rest of the stream is still usable.
+    /// An error from this means the connection has failed.
+    pub fn receive(&mut self) -> NetworkResult<Vec<NetworkResult<NetworkEvent>>> {
+        let mut buffer = [0; READ_SIZE];
+        let mut read = 0;
+        while !self.closed && read < MAX_READ_PER_POLL {
+            match self.transport.read(&mut buffer) {
+                Ok(0) => self.closed = true,
+                Ok(len) => {
+                    self.received.extend(&buffer[..len]);
+                    self.received_at = Instant::now();
+                    read += len;
+                    if let Some(traffic) = &self.traffic {
+                        traffic.record_bytes_received(len);
+                    }
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        let mut packets = vec![];
+        let mut rest = &self.received[..];
+        while let Some((packet, after)) = codec::split_packet(rest)? {
+            packets.push(codec::decode_packet(packet));
+            rest = after;
+        }
+        let used = self.received.len() - rest.len();
+        self.received.drain(..used);
+        Ok(packets)
+    }
+
+    /// Write as much of what has been queued as the socket will take without blocking
+    pub fn flush(&mut self) -> io::Result<()> {
+        loop {
+            if self.outgoing.is_empty() {
+                self.next_write();
+            }
+            if self.outgoing.is_empty() {
+                break;
+            }
+            match self.transport.write(&self.outgoing) {
+                // The transport has buffered all it can until the socket is writable
+                Ok(0) => break,
+                Ok(len) => {
+                    self.outgoing.drain(..len);
+                    if let Some(traffic) = &self.traffic {
+                        traffic.record_bytes_sent(len);
+                    }
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        match self.transport.flush() {
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(()),
+            result => result,
+        }
+    }
+}
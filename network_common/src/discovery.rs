@@ -0,0 +1,105 @@
+//! Finding servers on the local network
+//!
+//! Clients broadcast a probe over UDP to `DEFAULT_PORT`, which servers receive on the same socket as
+//! movement datagrams and answer with their name, protocol version and number of players. Probes are
+//! padded to the size of the largest answer, so that servers can not be used to amplify traffic.
+use std::{
+    io,
+    net::{Ipv4Addr, SocketAddr, UdpSocket},
+    time::{Duration, Instant},
+};
+
+use crate::{version::PROTOCOL_VERSION, DEFAULT_PORT};
+
+const PROBE_MAGIC: &[u8] = b"PKMN?";
+const ANSWER_MAGIC: &[u8] = b"PKMN!";
+
+/// The longest server name that is sent in answers
+pub const MAX_NAME_LENGTH: usize = 32;
+
+/// The size of every probe, which is the size of the largest answer
+pub(crate) const PROBE_SIZE: usize = ANSWER_MAGIC.len() + 2 + 4 + 1 + MAX_NAME_LENGTH;
+
+/// How often probes are sent while searching
+const PROBE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A server that answered a probe
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ServerInfo {
+    pub name: String,
+    pub players: u32,
+    pub version: u16,
+}
+
+impl ServerInfo {
+    pub fn is_compatible(&self) -> bool {
+        self.version == PROTOCOL_VERSION
+    }
+}
+
+pub fn encode_probe() -> Vec<u8> {
+    let mut probe = PROBE_MAGIC.to_vec();
+    probe.resize(PROBE_SIZE, 0);
+    probe
+}
+
+pub fn is_probe(data: &[u8]) -> bool {
+    data.len() >= PROBE_SIZE && data.starts_with(PROBE_MAGIC)
+}
+
+/// The answer that a server sends to a probe. Names longer than `MAX_NAME_LENGTH` are cut short.
+pub fn encode_answer(name: &str, players: u32) -> Vec<u8> {
+    let name = &name.as_bytes()[..name.len().min(MAX_NAME_LENGTH)];
+    let mut answer = ANSWER_MAGIC.to_vec();
+    answer.extend(PROTOCOL_VERSION.to_le_bytes());
+    answer.extend(players.to_le_bytes());
+    answer.push(name.len() as u8);
+    answer.extend(name);
+    answer
+}
+
+pub fn decode_answer(data: &[u8]) -> Option<ServerInfo> {
+    let data = data.strip_prefix(ANSWER_MAGIC)?;
+    let version = u16::from_le_bytes(data.get(..2)?.try_into().ok()?);
+    let players = u32::from_le_bytes(data.get(2..6)?.try_into().ok()?);
+    let name_len = *data.get(6)? as usize;
+    let name = data.get(7..7 + name_len.min(MAX_NAME_LENGTH))?;
+    Some(ServerInfo { name: String::from_utf8_lossy(name).into_owned(), players, version })
+}
+
+/// A search for servers on the local network, and on this machine
+pub struct Search {
+    socket: UdpSocket,
+    next_probe: Instant,
+}
+
+impl Search {
+    pub fn new() -> io::Result<Search> {
+        let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+        socket.set_broadcast(true)?;
+        socket.set_nonblocking(true)?;
+        Ok(Search { socket, next_probe: Instant::now() })
+    }
+
+    /// Send a probe if one is due, and return the servers that have answered since the last call.
+    /// Servers keep answering later probes, so their number of players stays up to date.
+    pub fn poll(&mut self) -> Vec<(SocketAddr, ServerInfo)> {
+        if Instant::now() >= self.next_probe {
+            self.next_probe = Instant::now() + PROBE_INTERVAL;
+            let probe = encode_probe();
+            // Broadcasts also reach this machine, unless it has no network to broadcast on
+            if self.socket.send_to(&probe, (Ipv4Addr::BROADCAST, DEFAULT_PORT)).is_err() {
+                let _ = self.socket.send_to(&probe, (Ipv4Addr::LOCALHOST, DEFAULT_PORT));
+            }
+        }
+
+        let mut found = vec![];
+        let mut buffer = [0; PROBE_SIZE];
+        while let Ok((len, addr)) = self.socket.recv_from(&mut buffer) {
+            if let Some(info) = decode_answer(&buffer[..len]) {
+                found.push((addr, info));
+            }
+        }
+        found
+    }
+}
@@ -1,5 +1,7 @@
 use std::{error::Error, fmt, io};
 
+use crate::{account::LoginError, version};
+
 pub type NetworkResult<T> = Result<T, NetworkError>;
 
 #[derive(Debug)]
@@ -10,6 +12,17 @@ pub enum NetworkError {
     RecvError,
     DecodeError,
     EncodeError,
+    PacketTooLarge,
+    /// The other end of the connection is not a Pikemon client or server
+    UnknownPeer,
+    /// The other end uses a version of the protocol that this build can not talk to
+    VersionMismatch(u16),
+    /// The server does not allow the game that the client is playing
+    GameRejected,
+    /// The server already has as many players as it allows
+    ServerFull,
+    /// The server refused the account name and password or token that were sent
+    LoginRefused(LoginError),
 }
 
 impl Error for NetworkError {}
@@ -22,6 +35,18 @@ impl fmt::Display for NetworkError {
             NetworkError::RecvError => f.write_str("receiving on a closed channel"),
             NetworkError::DecodeError => f.write_str("received invalid network data"),
             NetworkError::EncodeError => f.write_str("failed to encode network data"),
+            NetworkError::PacketTooLarge => f.write_str("received a packet that was too large"),
+            NetworkError::UnknownPeer => f.write_str("connected to something other than Pikemon"),
+            NetworkError::VersionMismatch(version) => write!(
+                f,
+                "the other end uses protocol version {}, but this build uses version {}, so both \
+                 need to be updated to the same release",
+                version,
+                version::PROTOCOL_VERSION
+            ),
+            NetworkError::GameRejected => f.write_str("the server does not allow this game"),
+            NetworkError::ServerFull => f.write_str("the server is full"),
+            NetworkError::LoginRefused(e) => write!(f, "the server refused to log in: {}", e),
         }
     }
 }
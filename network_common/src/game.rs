@@ -0,0 +1,133 @@
+//! The game that a client is playing, so that servers can turn away games that their players
+//! could not battle or trade with
+//!
+//! After the compression byte, clients send the title and revision from their ROM's cartridge
+//! header, and the server answers with a single byte saying whether the game is allowed. Clients
+//! without a game (like the terminal client) send an empty title, which is always allowed since
+//! they can not battle.
+use std::io::prelude::*;
+
+use interface::rom;
+use sha2::{Digest, Sha256};
+
+use crate::error::{NetworkError, NetworkResult};
+
+/// The longest title in a cartridge header
+pub const MAX_TITLE_LENGTH: usize = 16;
+
+/// A game identified by its cartridge header
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct GameInfo {
+    /// The title from the cartridge header, e.g. `POKEMON RED`, or empty if there is no game
+    pub title: String,
+    /// The mask ROM version, which is increased by each revision of a game
+    pub revision: u8,
+}
+
+impl GameInfo {
+    /// Identify the game from a ROM's cartridge header. Characters that servers would reject are
+    /// left out of the title.
+    pub fn from_rom(data: &[u8]) -> GameInfo {
+        let title = rom::title(data).unwrap_or_default();
+        GameInfo {
+            title: title.chars().filter(|c| (' '..='~').contains(c)).collect(),
+            revision: rom::revision(data),
+        }
+    }
+
+    pub fn is_none(&self) -> bool {
+        self.title.is_empty()
+    }
+}
+
+/// The SHA-256 hash of a ROM, in hex, which tells apart ROMs that share a title and revision
+pub fn rom_hash(data: &[u8]) -> String {
+    Sha256::digest(data).iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+pub fn write_game<W: Write>(writer: &mut W, game: &GameInfo) -> NetworkResult<()> {
+    let title = game.title.as_bytes();
+    if title.len() > MAX_TITLE_LENGTH {
+        return Err(NetworkError::EncodeError);
+    }
+    let mut data = vec![title.len() as u8];
+    data.extend(title);
+    data.push(game.revision);
+    writer.write_all(&data)?;
+    Ok(())
+}
+
+/// Read the game that a client is playing. Titles that are not printable ASCII are rejected, since
+/// they are shown in the server's log.
+pub fn read_game<R: Read>(reader: &mut R) -> NetworkResult<GameInfo> {
+    let mut len = [0];
+    reader.read_exact(&mut len)?;
+    let len = len[0] as usize;
+    if len > MAX_TITLE_LENGTH {
+        return Err(NetworkError::DecodeError);
+    }
+
+    let mut data = vec![0; len + 1];
+    reader.read_exact(&mut data)?;
+    let revision = data.pop().unwrap();
+    if !data.iter().all(|c| (0x20..0x7F).contains(c)) {
+        return Err(NetworkError::DecodeError);
+    }
+    let title = String::from_utf8(data).map_err(|_| NetworkError::DecodeError)?;
+    Ok(GameInfo { title, revision })
+}
+
+/// The server's answer to a client that has sent its game
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Verdict {
+    GameRejected = 0,
+    Allowed = 1,
+    /// The game is allowed, but there is no room for another player
+    ServerFull = 2,
+}
+
+/// Tell a client whether it can join the server
+pub fn write_verdict<W: Write>(writer: &mut W, verdict: Verdict) -> NetworkResult<()> {
+    writer.write_all(&[verdict as u8])?;
+    Ok(())
+}
+
+/// Read the server's answer to the game that was sent, failing if the client can not join
+pub fn read_verdict<R: Read>(reader: &mut R) -> NetworkResult<()> {
+    let mut byte = [0];
+    reader.read_exact(&mut byte)?;
+    match byte[0] {
+        1 => Ok(()),
+        0 => Err(NetworkError::GameRejected),
+        2 => Err(NetworkError::ServerFull),
+        _ => Err(NetworkError::DecodeError),
+    }
+}
+
+/// The games that a server allows, or every game if none are given
+#[derive(Clone, Debug, Default)]
+pub struct AllowedGames {
+    titles: Vec<String>,
+}
+
+impl AllowedGames {
+    /// Parse a comma separated list of titles, e.g. `POKEMON RED,POKEMON BLUE`
+    pub fn parse(list: &str) -> AllowedGames {
+        let titles = list.split(',').map(str::trim).filter(|title| !title.is_empty());
+        AllowedGames { titles: titles.map(str::to_string).collect() }
+    }
+
+    pub fn allows(&self, game: &GameInfo) -> bool {
+        game.is_none() || self.titles.is_empty() || self.titles.contains(&game.title)
+    }
+}
+
+/// Whether two players can battle each other. Battle data is only understood by games of the same
+/// generation, so games that are not supported must have the same title, and players without a
+/// game can not battle at all.
+pub fn compatible(a: &GameInfo, b: &GameInfo) -> bool {
+    match (rom::title_generation(&a.title), rom::title_generation(&b.title)) {
+        (Some(a), Some(b)) => a == b,
+        _ => !a.is_none() && a.title == b.title,
+    }
+}
@@ -1,18 +1,434 @@
-use interface::data::{PlayerData, MovementData, BattleData};
+use std::{net::SocketAddr, time::Duration};
 
+use interface::{
+    data::{PlayerData, MovementData, BattleData, BattleSnapshot},
+    relay::{ItemRule, RelayAction},
+    visibility::Visibility,
+};
+use peer::DirectMessage;
+use seed::{Seed, SeedCommitment};
+use sequence::Sequence;
+use shared::SharedSeed;
+
+pub mod account;
+pub mod client;
+pub mod codec;
+pub mod connection;
+pub mod discovery;
 pub mod error;
+pub mod game;
+pub mod master;
+pub mod outbox;
+pub mod peer;
+pub mod poll;
+pub mod schema;
+pub mod seed;
+pub mod sequence;
+pub mod shared;
+pub mod simulator;
+pub mod socks;
+pub mod stats;
+pub mod tls;
+pub mod transport;
+pub mod version;
+pub mod websocket;
+pub mod wire;
 
 pub type PlayerId = u32;
 
-#[derive(Clone, serde::Serialize, serde::Deserialize)]
+/// Identifies a chat message among the others from the same player. Clients count them up, so that
+/// a message sent again because its acknowledgement was lost is only passed on once.
+pub type ChatId = u32;
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
 pub enum NetworkEvent {
-    FullUpdate(PlayerId, PlayerData),
-    MovementUpdate(PlayerId, MovementData),
+    /// (player, sequence, data) Everything that other players see of the player. Full and movement
+    /// updates from a player are numbered in the order they were sent.
+    FullUpdate(PlayerId, Sequence, PlayerData),
+    /// (player, sequence, movement) Sent instead of a full update when only the player's position
+    /// has changed
+    MovementUpdate(PlayerId, Sequence, MovementData),
     UpdateRequest,
+    /// (player) Sent by a client that suspects its view of other players is out of date, e.g. after
+    /// a stall. The server answers with one or more `PlayerSnapshot` events.
+    ResyncRequest(PlayerId),
+    /// The newest state that the server has received from other players, as (player, sequence,
+    /// data). Snapshots of more than `MAX_SNAPSHOT_PLAYERS` players are split over several events.
+    PlayerSnapshot(Vec<(PlayerId, Sequence, PlayerData)>),
     PlayerJoin(PlayerId),
+    /// (token) Sent by the server after `PlayerJoin`, so that movement updates can be sent over
+    /// UDP. Datagrams between the server and the player start with this token.
+    MovementChannel(u64),
     PlayerQuit(PlayerId),
-    Chat(PlayerId, String),
+    /// (player) Sent straight after joining by a client that only wants to watch the server, e.g.
+    /// to moderate it. The server does not count it as a player, and ignores everything it sends
+    /// apart from pings and resync requests, while still passing on every player and chat message.
+    Observe(PlayerId),
+    /// (player, on) Sent by a client on a slow connection to ask for lite mode, where the server
+    /// only sends it other players' newest positions `LITE_UPDATE_RATE` times a second, and leaves
+    /// out chat and rare encounters. Battles can not be watched by or from lite clients. The server
+    /// answers with the same event, and also sends it unasked when the client falls too far behind.
+    LiteMode(PlayerId, bool),
+    /// (player, id, message)
+    Chat(PlayerId, ChatId, String),
+    /// (id) Sent by the server to confirm that it has passed on a chat message
+    ChatAck(ChatId),
+    /// (to, from) Asks for the target's party to battle. The request may be sent again if there is
+    /// no response, and the same response should be sent to each copy.
     BattleDataRequest(PlayerId, PlayerId),
-    BattleDataResponse(PlayerId, BattleData),
+    /// (to, from, battle data) The sender's party, in response to a battle request
+    BattleDataResponse(PlayerId, PlayerId, BattleData),
+    /// (to, from) The sender has given up waiting for a response to its battle request
+    BattleRequestCancel(PlayerId, PlayerId),
+    /// (to, target) Sent by the server when a battle request was dropped, because the sender has
+    /// already sent a request to the target recently
+    BattleRequestRefused(PlayerId, PlayerId),
+    /// (to, from, commitment) Sent before a battle request to commit to a secret seed
+    BattleSeedCommit(PlayerId, PlayerId, SeedCommitment),
+    /// (to, from, seed) The opponent's seed, sent before their battle data
+    BattleSeed(PlayerId, PlayerId, Seed),
+    /// (to, from, seed) Reveals the secret seed once the opponent's seed has been received
+    BattleSeedReveal(PlayerId, PlayerId, Seed),
+    /// (to, from, battle data) Sent before a battle request to start a relayed battle, where both
+    /// players battle each other's party
+    RelayBattleData(PlayerId, PlayerId, BattleData),
+    /// (to, from, action) What the sender did with their turn during a relayed battle
+    BattleAction(PlayerId, PlayerId, RelayAction),
+    /// (to, from, item rule) The rules for a relayed battle, sent before the battle data
+    BattleRules(PlayerId, PlayerId, ItemRule),
+    /// (to, from) The sender used an item from their bag during a relayed battle
+    BattleItemUsed(PlayerId, PlayerId),
+    /// (to, from) Sent regularly during a relayed battle, so that the opponent can tell whether
+    /// the sender is still connected
+    BattleKeepAlive(PlayerId, PlayerId),
+    /// (to, from) The sender has conceded the relayed battle, which the opponent wins
+    BattleForfeit(PlayerId, PlayerId),
+    /// (to, peer, address, token) Sent by the server when a relayed battle starts, with the address
+    /// that the opponent's datagrams come from and a token for datagrams between the two players
+    PeerAddress(PlayerId, PlayerId, SocketAddr, u64),
+    /// (to, from) Sent straight to the opponent, to open a path through NATs
+    PeerHello(PlayerId, PlayerId),
+    /// (to, from, sequence, message) A battle message sent straight to the opponent, or through the
+    /// server if it could not be
+    PeerPacket(PlayerId, PlayerId, u32, DirectMessage),
+    /// (to, from, sequence) Acknowledges a battle message that arrived straight from the sender
+    PeerAck(PlayerId, PlayerId, u32),
+    /// (player, teams) Sent when a player enters the Hall of Fame, with the number of teams they
+    /// have recorded there
+    HallOfFame(PlayerId, u8),
+    /// (player, species, level) Sent when a player encounters a rare wild Pokemon
+    WildEncounter(PlayerId, u8, u8),
+    /// (to, from) Asks a player to share the state of their battle
+    SpectateRequest(PlayerId, PlayerId),
+    /// (to, from) Stop sharing the state of a battle with the sender
+    SpectateStop(PlayerId, PlayerId),
+    /// (to, from, snapshot) The current state of the sender's battle
+    BattleSnapshot(PlayerId, PlayerId, BattleSnapshot),
+    /// (to, from) The sender's battle has ended, or can not be watched
+    SpectateEnd(PlayerId, PlayerId),
+    /// (battle, from, message) A chat message for everyone in a battle, which is identified by the
+    /// player whose battle it is. Only passed on to that player and the players watching them.
+    BattleChat(PlayerId, PlayerId, ChatId, String),
+    /// (player) Asks the server to start a Safari Zone event
+    SafariStart(PlayerId),
+    /// (player, species) Sent when a player catches a Pokemon during a Safari Zone event
+    SafariCatch(PlayerId, u8),
+    /// Sent by the server when a Safari Zone event starts, changes or ends
+    SafariUpdate(SafariStatus),
+    /// Sent by the server to new clients, with the slowest update rates that it allows
+    RateLimits(RateLimits),
+    /// Sent by the server to new clients and at the start of each day, for effects that every
+    /// player sees at the same time
+    SharedSeed(SharedSeed),
+    /// (player, species, level, wanted) Lists a Pokemon from the player's party on the trade board,
+    /// replacing their previous listing. A wanted species of 0 means any species.
+    TradeListingPost(PlayerId, u8, u8, u8),
+    /// (player) Removes the player's listing from the trade board
+    TradeListingRemove(PlayerId),
+    /// (player) Asks the server for the listings on the trade board
+    TradeBoardRequest(PlayerId),
+    /// Sent by the server with every listing on the trade board, in response to a request or a
+    /// change to the player's own listing
+    TradeBoard(Vec<TradeListing>),
+    /// (to, from, species, level) Offers a Pokemon in exchange for the recipient's listing
+    TradeOffer(PlayerId, PlayerId, u8, u8),
+    /// (player, timestamp) Sent regularly by clients, so that a connection that has been dropped
+    /// without being closed is noticed by both ends. The timestamp is only meaningful to the client.
+    Ping(PlayerId, u32),
+    /// (timestamp) The server's response to a `Ping`, echoing its timestamp so that the client can
+    /// measure the round trip time
+    Pong(u32),
+    /// Sent by the server to new clients, with the items that its shop sells
+    ShopCatalog(Vec<ShopItem>),
+    /// (player, item, quantity) Sent when a player buys items from the server's shop
+    ShopPurchase(PlayerId, u8, u8),
+    /// Sent by the server to new clients if it has a day and night cycle
+    DayCycle(DayCycle),
+    /// (player, paused) Sent when a player's game is paused because they have left it, and again
+    /// when they come back
+    PlayerPaused(PlayerId, bool),
+    /// (to, from, phrase) One of the `QUICK_CHAT_PHRASES`, sent to the opponent during a relayed
+    /// battle
+    BattleQuickChat(PlayerId, PlayerId, u8),
+    /// (to, from, message) Sent instead of answering a battle request or trade offer while the
+    /// sender is away and their game is on autopilot
+    AwayMessage(PlayerId, PlayerId, String),
+    /// Sent by the server to new clients if it hides more players than each client chooses to
+    Visibility(Visibility),
+    /// Sent by the server to new clients if it has a message of the day
+    Motd(String),
+    /// Anonymous statistics about a session, sent when leaving by players that have chosen to share
+    /// them. Reports do not say which player they are from.
+    UsageReport(UsageReport),
     ServerFailure,
 }
+
+impl NetworkEvent {
+    /// The name of the event's variant, for telling events apart in logs and statistics
+    pub fn name(&self) -> &'static str {
+        match self {
+            NetworkEvent::FullUpdate(..) => "FullUpdate",
+            NetworkEvent::MovementUpdate(..) => "MovementUpdate",
+            NetworkEvent::UpdateRequest => "UpdateRequest",
+            NetworkEvent::ResyncRequest(_) => "ResyncRequest",
+            NetworkEvent::PlayerSnapshot(_) => "PlayerSnapshot",
+            NetworkEvent::PlayerJoin(..) => "PlayerJoin",
+            NetworkEvent::MovementChannel(..) => "MovementChannel",
+            NetworkEvent::PlayerQuit(..) => "PlayerQuit",
+            NetworkEvent::Observe(_) => "Observe",
+            NetworkEvent::LiteMode(..) => "LiteMode",
+            NetworkEvent::Chat(..) => "Chat",
+            NetworkEvent::ChatAck(..) => "ChatAck",
+            NetworkEvent::BattleDataRequest(..) => "BattleDataRequest",
+            NetworkEvent::BattleDataResponse(..) => "BattleDataResponse",
+            NetworkEvent::BattleRequestCancel(..) => "BattleRequestCancel",
+            NetworkEvent::BattleRequestRefused(..) => "BattleRequestRefused",
+            NetworkEvent::BattleSeedCommit(..) => "BattleSeedCommit",
+            NetworkEvent::BattleSeed(..) => "BattleSeed",
+            NetworkEvent::BattleSeedReveal(..) => "BattleSeedReveal",
+            NetworkEvent::RelayBattleData(..) => "RelayBattleData",
+            NetworkEvent::BattleAction(..) => "BattleAction",
+            NetworkEvent::BattleRules(..) => "BattleRules",
+            NetworkEvent::BattleItemUsed(..) => "BattleItemUsed",
+            NetworkEvent::BattleKeepAlive(..) => "BattleKeepAlive",
+            NetworkEvent::BattleForfeit(..) => "BattleForfeit",
+            NetworkEvent::PeerAddress(..) => "PeerAddress",
+            NetworkEvent::PeerHello(..) => "PeerHello",
+            NetworkEvent::PeerPacket(..) => "PeerPacket",
+            NetworkEvent::PeerAck(..) => "PeerAck",
+            NetworkEvent::HallOfFame(..) => "HallOfFame",
+            NetworkEvent::WildEncounter(..) => "WildEncounter",
+            NetworkEvent::SpectateRequest(..) => "SpectateRequest",
+            NetworkEvent::SpectateStop(..) => "SpectateStop",
+            NetworkEvent::BattleSnapshot(..) => "BattleSnapshot",
+            NetworkEvent::SpectateEnd(..) => "SpectateEnd",
+            NetworkEvent::BattleChat(..) => "BattleChat",
+            NetworkEvent::SafariStart(..) => "SafariStart",
+            NetworkEvent::SafariCatch(..) => "SafariCatch",
+            NetworkEvent::SafariUpdate(..) => "SafariUpdate",
+            NetworkEvent::RateLimits(..) => "RateLimits",
+            NetworkEvent::SharedSeed(..) => "SharedSeed",
+            NetworkEvent::TradeListingPost(..) => "TradeListingPost",
+            NetworkEvent::TradeListingRemove(..) => "TradeListingRemove",
+            NetworkEvent::TradeBoardRequest(..) => "TradeBoardRequest",
+            NetworkEvent::TradeBoard(..) => "TradeBoard",
+            NetworkEvent::TradeOffer(..) => "TradeOffer",
+            NetworkEvent::Ping(..) => "Ping",
+            NetworkEvent::Pong(..) => "Pong",
+            NetworkEvent::ShopCatalog(..) => "ShopCatalog",
+            NetworkEvent::ShopPurchase(..) => "ShopPurchase",
+            NetworkEvent::DayCycle(..) => "DayCycle",
+            NetworkEvent::PlayerPaused(..) => "PlayerPaused",
+            NetworkEvent::BattleQuickChat(..) => "BattleQuickChat",
+            NetworkEvent::AwayMessage(..) => "AwayMessage",
+            NetworkEvent::Visibility(..) => "Visibility",
+            NetworkEvent::Motd(_) => "Motd",
+            NetworkEvent::UsageReport(..) => "UsageReport",
+            NetworkEvent::ServerFailure => "ServerFailure",
+        }
+    }
+
+    /// The player that a client's event says it is from. Servers drop events whose sender is not
+    /// the player on the connection they arrived on, so that no one can act as another player.
+    /// None for events that do not name their sender.
+    pub fn sender(&self) -> Option<PlayerId> {
+        match *self {
+            NetworkEvent::FullUpdate(player, ..)
+            | NetworkEvent::MovementUpdate(player, ..)
+            | NetworkEvent::ResyncRequest(player)
+            | NetworkEvent::PlayerQuit(player)
+            | NetworkEvent::Observe(player)
+            | NetworkEvent::LiteMode(player, _)
+            | NetworkEvent::Chat(player, ..)
+            | NetworkEvent::HallOfFame(player, _)
+            | NetworkEvent::WildEncounter(player, ..)
+            | NetworkEvent::SafariStart(player)
+            | NetworkEvent::SafariCatch(player, _)
+            | NetworkEvent::TradeListingPost(player, ..)
+            | NetworkEvent::TradeListingRemove(player)
+            | NetworkEvent::TradeBoardRequest(player)
+            | NetworkEvent::Ping(player, _)
+            | NetworkEvent::ShopPurchase(player, ..)
+            | NetworkEvent::PlayerPaused(player, _) => Some(player),
+
+            NetworkEvent::BattleDataRequest(_, from)
+            | NetworkEvent::BattleDataResponse(_, from, _)
+            | NetworkEvent::BattleRequestCancel(_, from)
+            | NetworkEvent::BattleSeedCommit(_, from, _)
+            | NetworkEvent::BattleSeed(_, from, _)
+            | NetworkEvent::BattleSeedReveal(_, from, _)
+            | NetworkEvent::RelayBattleData(_, from, _)
+            | NetworkEvent::BattleAction(_, from, _)
+            | NetworkEvent::BattleRules(_, from, _)
+            | NetworkEvent::BattleItemUsed(_, from)
+            | NetworkEvent::BattleKeepAlive(_, from)
+            | NetworkEvent::BattleForfeit(_, from)
+            | NetworkEvent::PeerHello(_, from)
+            | NetworkEvent::PeerPacket(_, from, ..)
+            | NetworkEvent::PeerAck(_, from, _)
+            | NetworkEvent::SpectateRequest(_, from)
+            | NetworkEvent::SpectateStop(_, from)
+            | NetworkEvent::BattleSnapshot(_, from, _)
+            | NetworkEvent::SpectateEnd(_, from)
+            | NetworkEvent::BattleChat(_, from, ..)
+            | NetworkEvent::TradeOffer(_, from, ..)
+            | NetworkEvent::BattleQuickChat(_, from, _)
+            | NetworkEvent::AwayMessage(_, from, _) => Some(from),
+
+            _ => None,
+        }
+    }
+
+    /// Whether the event is one that only servers send, which servers drop if a client sends it
+    pub fn is_from_server(&self) -> bool {
+        matches!(
+            self,
+            NetworkEvent::UpdateRequest
+                | NetworkEvent::PlayerSnapshot(_)
+                | NetworkEvent::PlayerJoin(_)
+                | NetworkEvent::MovementChannel(_)
+                | NetworkEvent::ChatAck(_)
+                | NetworkEvent::BattleRequestRefused(..)
+                | NetworkEvent::PeerAddress(..)
+                | NetworkEvent::SafariUpdate(_)
+                | NetworkEvent::RateLimits(_)
+                | NetworkEvent::SharedSeed(_)
+                | NetworkEvent::TradeBoard(_)
+                | NetworkEvent::Pong(_)
+                | NetworkEvent::ShopCatalog(_)
+                | NetworkEvent::DayCycle(_)
+                | NetworkEvent::Visibility(_)
+                | NetworkEvent::Motd(_)
+                | NetworkEvent::ServerFailure
+        )
+    }
+}
+
+/// The port that servers listen on, and that clients connect to when an address does not give one
+pub const DEFAULT_PORT: u16 = 8080;
+
+/// How often clients send a `Ping` to the server
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How long either end of a connection waits without receiving anything before giving up on it
+pub const CONNECTION_TIMEOUT: Duration = Duration::from_secs(20);
+
+/// The fastest rate, in updates per second, that clients send movement or check for updates
+pub const MAX_UPDATE_RATE: u32 = 60;
+
+/// How many times per second clients in lite mode send their movement, and are sent the movement
+/// of other players
+pub const LITE_UPDATE_RATE: u32 = 2;
+
+/// The slowest rates, in updates per second, that a server allows clients to use. Clients that
+/// update less often than this would appear to stutter or lag behind for other players.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct RateLimits {
+    pub min_movement_rate: u32,
+    pub min_receive_rate: u32,
+}
+
+/// Phrases that players can send to their opponent during a battle with a single key, without
+/// opening the chat. They are sent by their position in this list.
+pub const QUICK_CHAT_PHRASES: [&str; 4] = ["Good luck!", "Nice move!", "Well played!", "Oops!"];
+
+/// The most players included in a single `PlayerSnapshot`, which keeps it below the maximum packet
+/// size
+pub const MAX_SNAPSHOT_PLAYERS: usize = 32;
+
+/// The maximum number of players included in a Safari Zone leaderboard
+pub const MAX_LEADERBOARD_LENGTH: usize = 10;
+
+/// The maximum number of listings on the trade board
+pub const MAX_TRADE_LISTINGS: usize = 100;
+
+/// A Pokemon that a player has offered for trade
+#[derive(Copy, Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct TradeListing {
+    pub player: PlayerId,
+    pub species: u8,
+    pub level: u8,
+    /// The species wanted in return, or 0 for any species
+    pub wanted: u8,
+}
+
+/// The maximum number of items that a server's shop can sell, which is as many as fit on one page
+/// of the shop in the client
+pub const MAX_SHOP_ITEMS: usize = 9;
+
+/// An item sold by the server's shop, with its price in the game's money
+#[derive(Copy, Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct ShopItem {
+    pub item: u8,
+    pub price: u32,
+}
+
+/// The shortest day and night cycle that a server can use, in seconds. Shorter cycles would change
+/// the brightness of the screen distractingly fast.
+pub const MIN_DAY_LENGTH: u32 = 60;
+
+/// A day and night cycle that every player sees at the same time, timed by the server's clock
+#[derive(Copy, Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct DayCycle {
+    /// The server's time when this was sent, in seconds since the Unix epoch
+    pub server_time: u64,
+    /// The length of a full day and night, in seconds
+    pub length_secs: u32,
+}
+
+/// The state of a Safari Zone event
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct SafariStatus {
+    /// The number of seconds until the event ends, or 0 once it has ended
+    pub remaining_secs: u32,
+    /// The number of Pokemon caught by the players that have caught the most, in descending order
+    pub leaderboard: Vec<(PlayerId, u32)>,
+}
+
+/// The features that are counted in usage reports
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub enum Feature {
+    Chat,
+    QuickChat,
+    BattleRequest,
+    Forfeit,
+    WatchBattle,
+    PlayerList,
+    NetworkStats,
+    SafariEvent,
+    TradeBoard,
+    ServerShop,
+    SwapGame,
+    UpdateDefinitions,
+}
+
+/// How a session was used, without anything about the player or their game
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct UsageReport {
+    /// How long the client was connected, in seconds
+    pub session_secs: u32,
+    /// The number of times each feature was used
+    pub features: Vec<(Feature, u32)>,
+    /// Where the client crashed in the previous session, as a source location
+    pub crash: Option<String>,
+}
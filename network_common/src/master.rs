@@ -0,0 +1,128 @@
+//! Listing public servers on a master server, so that players can pick one without knowing its
+//! address
+//!
+//! Servers run with `--master ADDRESS` send an announcement over UDP to the master server every
+//! `ANNOUNCE_INTERVAL`, from the socket that they receive movement on. The master server answers by
+//! sending the same probe that clients use on the local network (see `discovery`), and lists each
+//! server that answers along with its answer. This way only servers that can really be reached are
+//! listed, and their number of players stays up to date. Announcements are padded to the size of a
+//! probe, so that the master server can not be used to amplify traffic, and servers that stop
+//! announcing are dropped after `EXPIRY`.
+//!
+//! Clients fetch the list over TCP on the same port. The master server writes every listed server
+//! as soon as a client connects, then closes the connection.
+use std::{
+    io::{self, prelude::*},
+    net::{SocketAddr, ToSocketAddrs},
+    time::Duration,
+};
+
+use crate::{
+    discovery::{self, ServerInfo},
+    transport,
+};
+
+/// The port that master servers listen on, used when the address does not give one
+pub const DEFAULT_MASTER_PORT: u16 = 8079;
+
+const ANNOUNCE_MAGIC: &[u8] = b"PKMN+";
+
+/// How often servers announce themselves
+pub const ANNOUNCE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How long a server stays listed after its last announcement
+pub const EXPIRY: Duration = Duration::from_secs(90);
+
+/// The most servers that a master server lists
+pub const MAX_LISTED: usize = 256;
+
+/// The longest server address in a list, which is longer than any written IP address and port
+const MAX_ADDRESS_LENGTH: usize = 64;
+
+/// The size of the longest list
+const MAX_LIST_SIZE: usize = 2 + MAX_LISTED * (2 + MAX_ADDRESS_LENGTH + discovery::PROBE_SIZE);
+
+const FETCH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The announcement that a server listening on `port` sends to the master server
+pub fn encode_announcement(port: u16) -> Vec<u8> {
+    let mut announcement = ANNOUNCE_MAGIC.to_vec();
+    announcement.extend(port.to_le_bytes());
+    announcement.resize(discovery::PROBE_SIZE, 0);
+    announcement
+}
+
+/// The port that an announcement gives
+pub fn decode_announcement(data: &[u8]) -> Option<u16> {
+    if data.len() < discovery::PROBE_SIZE {
+        return None;
+    }
+    let data = data.strip_prefix(ANNOUNCE_MAGIC)?;
+    Some(u16::from_le_bytes(data.get(..2)?.try_into().ok()?))
+}
+
+/// The list sent to clients, made from the address of each server and its answer to a probe
+pub fn encode_list(servers: &[(SocketAddr, Vec<u8>)]) -> Vec<u8> {
+    let servers = &servers[..servers.len().min(MAX_LISTED)];
+    let mut list = (servers.len() as u16).to_le_bytes().to_vec();
+    for (addr, answer) in servers {
+        write_field(&mut list, addr.to_string().as_bytes());
+        write_field(&mut list, answer);
+    }
+    list
+}
+
+/// Read a list from a master server. Servers with answers that can not be read are left out.
+pub fn decode_list(data: &[u8]) -> Option<Vec<(SocketAddr, ServerInfo)>> {
+    let count = u16::from_le_bytes(data.get(..2)?.try_into().ok()?) as usize;
+    let mut rest = &data[2..];
+    let mut servers = vec![];
+    for _ in 0..count.min(MAX_LISTED) {
+        let addr = read_field(&mut rest)?;
+        let answer = read_field(&mut rest)?;
+        let addr = std::str::from_utf8(addr).ok()?.parse().ok()?;
+        if let Some(info) = discovery::decode_answer(answer) {
+            servers.push((addr, info));
+        }
+    }
+    Some(servers)
+}
+
+fn write_field(data: &mut Vec<u8>, field: &[u8]) {
+    let field = &field[..field.len().min(u8::MAX as usize)];
+    data.push(field.len() as u8);
+    data.extend(field);
+}
+
+fn read_field<'a>(data: &mut &'a [u8]) -> Option<&'a [u8]> {
+    let (&len, rest) = data.split_first()?;
+    let field = rest.get(..len as usize)?;
+    *data = &rest[len as usize..];
+    Some(field)
+}
+
+/// Look up the address to announce a server to, e.g. `master.example.com`. Servers only listen on
+/// IPv4, so only IPv4 addresses are used.
+pub fn resolve(master: &str) -> io::Result<SocketAddr> {
+    let (host, port) = transport::split_address(master, DEFAULT_MASTER_PORT)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let message = format!("no IPv4 addresses found for {}", host);
+    (host, port)
+        .to_socket_addrs()?
+        .find(SocketAddr::is_ipv4)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, message))
+}
+
+/// Fetch the servers listed by a master server
+pub fn fetch(master: &str) -> io::Result<Vec<(SocketAddr, ServerInfo)>> {
+    let (host, port) = transport::split_address(master, DEFAULT_MASTER_PORT)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let socket = transport::connect_host(host, port)?;
+    socket.set_read_timeout(Some(FETCH_TIMEOUT))?;
+
+    let mut data = vec![];
+    socket.take(MAX_LIST_SIZE as u64).read_to_end(&mut data)?;
+    decode_list(&data).ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidData, "the master server sent an invalid list")
+    })
+}
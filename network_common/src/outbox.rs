@@ -0,0 +1,134 @@
+//! Sending events over a bounded channel without blocking
+//!
+//! The channels between the game and the network thread are bounded, so that a stalled socket or a
+//! game that has stopped reading can not make them grow without limit. Neither side can wait for
+//! the other, so events that do not fit are held in an `Outbox` until there is room. Only the
+//! newest movement update from each player is held, since it replaces the ones before it (and
+//! older movement is dropped by its sequence number anyway). A full update likewise replaces any
+//! held full update from the same player, keeping the place of the older one. Every other event,
+//! like chat and battle events, is held in order, up to `MAX_HELD` events. Past that the receiver
+//! has stopped keeping up altogether, and new events are dropped rather than held without limit.
+use std::collections::{HashMap, VecDeque};
+
+use crossbeam_channel::{SendError, Sender, TrySendError};
+
+use crate::{poll, NetworkEvent, PlayerId};
+
+/// The number of events that the channels between the game and the network thread hold
+pub const CHANNEL_CAPACITY: usize = 256;
+
+/// The most events, other than movement, that an `Outbox` holds while its channel is full
+pub const MAX_HELD: usize = CHANNEL_CAPACITY * 4;
+
+/// A channel that can be sent to without blocking
+pub trait TrySend {
+    fn try_send(&self, event: NetworkEvent) -> Result<(), TrySendError<NetworkEvent>>;
+}
+
+impl TrySend for Sender<NetworkEvent> {
+    fn try_send(&self, event: NetworkEvent) -> Result<(), TrySendError<NetworkEvent>> {
+        Sender::try_send(self, event)
+    }
+}
+
+impl TrySend for poll::Sender<NetworkEvent> {
+    fn try_send(&self, event: NetworkEvent) -> Result<(), TrySendError<NetworkEvent>> {
+        poll::Sender::try_send(self, event)
+    }
+}
+
+/// Sends events to a bounded channel, holding the ones that do not fit until there is room
+pub struct Outbox<S> {
+    sender: S,
+    /// Events that must be sent, oldest first
+    held: VecDeque<NetworkEvent>,
+    /// The newest movement update from each player, sent after the other held events
+    held_movement: HashMap<PlayerId, NetworkEvent>,
+}
+
+impl<S: TrySend> Outbox<S> {
+    pub fn new(sender: S) -> Outbox<S> {
+        Outbox { sender, held: VecDeque::new(), held_movement: HashMap::new() }
+    }
+
+    /// Send an event, or hold it if the channel is full. Fails once the receiver has been dropped.
+    pub fn send(&mut self, event: NetworkEvent) -> Result<(), SendError<NetworkEvent>> {
+        // Events are held in the order they were sent, so nothing can overtake them
+        self.flush()?;
+        if self.is_holding() {
+            self.hold(event);
+            return Ok(());
+        }
+        match self.sender.try_send(event) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(event)) => {
+                self.hold(event);
+                Ok(())
+            }
+            Err(TrySendError::Disconnected(event)) => Err(SendError(event)),
+        }
+    }
+
+    /// Send as many of the held events as there is room for
+    pub fn flush(&mut self) -> Result<(), SendError<NetworkEvent>> {
+        while let Some(event) = self.held.pop_front() {
+            if let Some(event) = self.try_send(event)? {
+                self.held.push_front(event);
+                return Ok(());
+            }
+        }
+        let players: Vec<_> = self.held_movement.keys().copied().collect();
+        for player in players {
+            let event = self.held_movement.remove(&player).unwrap();
+            if let Some(event) = self.try_send(event)? {
+                self.held_movement.insert(player, event);
+                return Ok(());
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether any events are waiting for room in the channel
+    pub fn is_holding(&self) -> bool {
+        !self.held.is_empty() || !self.held_movement.is_empty()
+    }
+
+    /// Send an event, giving it back if the channel is full
+    fn try_send(
+        &self,
+        event: NetworkEvent,
+    ) -> Result<Option<NetworkEvent>, SendError<NetworkEvent>> {
+        match self.sender.try_send(event) {
+            Ok(()) => Ok(None),
+            Err(TrySendError::Full(event)) => Ok(Some(event)),
+            Err(TrySendError::Disconnected(event)) => Err(SendError(event)),
+        }
+    }
+
+    fn hold(&mut self, event: NetworkEvent) {
+        match event {
+            NetworkEvent::MovementUpdate(player, ..) => {
+                self.held_movement.insert(player, event);
+            }
+            NetworkEvent::FullUpdate(player, ..) => {
+                let held = self.held.iter_mut().find(|held| match held {
+                    NetworkEvent::FullUpdate(id, ..) => *id == player,
+                    _ => false,
+                });
+                match held {
+                    Some(held) => *held = event,
+                    None => self.push(event),
+                }
+            }
+            event => self.push(event),
+        }
+    }
+
+    fn push(&mut self, event: NetworkEvent) {
+        if self.held.len() >= MAX_HELD {
+            println!("Dropped {} as too many events are waiting to be passed on", event.name());
+            return;
+        }
+        self.held.push_back(event);
+    }
+}
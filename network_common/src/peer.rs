@@ -0,0 +1,291 @@
+//! Direct connections between the two players in a relayed battle
+//!
+//! Battle messages normally go through the server. When a relayed battle starts, the server tells
+//! each player the address that it sees the other player's datagrams come from, along with a token
+//! for the pair. Both players then send hellos to each other from their movement socket, which
+//! opens a path through any NAT that allows it (UDP hole punching). Once anything arrives from the
+//! opponent, battle messages are sent to them directly.
+//!
+//! Every direct message has a sequence number and is acknowledged. A message that is not
+//! acknowledged in time is sent through the server instead, and the rest of the battle goes through
+//! the server too. The sequence numbers let the receiver drop a message that arrives both ways. If
+//! nothing arrives from the opponent within `PUNCH_TIMEOUT`, the players keep using the server.
+use std::{
+    collections::{HashMap, VecDeque},
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+use interface::relay::RelayAction;
+
+use crate::{codec, NetworkEvent, PlayerId};
+
+/// How often to send a hello while opening a direct connection
+const PUNCH_INTERVAL: Duration = Duration::from_millis(200);
+
+/// How long to try to open a direct connection before using the server
+const PUNCH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long to wait for a direct message to be acknowledged before sending it through the server
+const ACK_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// How many received sequence numbers to remember, for dropping messages that arrive twice
+const MAX_SEEN: usize = 64;
+
+/// A battle message that can be sent straight to the opponent, without the players that it is
+/// between
+#[derive(Copy, Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum DirectMessage {
+    /// (action) See `NetworkEvent::BattleAction`
+    Action(RelayAction),
+    /// (phrase) See `NetworkEvent::BattleQuickChat`
+    QuickChat(u8),
+    ItemUsed,
+    KeepAlive,
+    Forfeit,
+}
+
+impl DirectMessage {
+    /// Take the message out of an event, along with who it is for, if it can be sent directly
+    pub fn from_event(event: &NetworkEvent) -> Option<(PlayerId, DirectMessage)> {
+        match *event {
+            NetworkEvent::BattleAction(to, _, action) => Some((to, DirectMessage::Action(action))),
+            NetworkEvent::BattleQuickChat(to, _, phrase) => {
+                Some((to, DirectMessage::QuickChat(phrase)))
+            }
+            NetworkEvent::BattleItemUsed(to, _) => Some((to, DirectMessage::ItemUsed)),
+            NetworkEvent::BattleKeepAlive(to, _) => Some((to, DirectMessage::KeepAlive)),
+            NetworkEvent::BattleForfeit(to, _) => Some((to, DirectMessage::Forfeit)),
+            _ => None,
+        }
+    }
+
+    /// The event that the message was taken out of
+    pub fn to_event(self, to: PlayerId, from: PlayerId) -> NetworkEvent {
+        match self {
+            DirectMessage::Action(action) => NetworkEvent::BattleAction(to, from, action),
+            DirectMessage::QuickChat(phrase) => NetworkEvent::BattleQuickChat(to, from, phrase),
+            DirectMessage::ItemUsed => NetworkEvent::BattleItemUsed(to, from),
+            DirectMessage::KeepAlive => NetworkEvent::BattleKeepAlive(to, from),
+            DirectMessage::Forfeit => NetworkEvent::BattleForfeit(to, from),
+        }
+    }
+}
+
+/// Where to send an event
+pub enum Outgoing {
+    /// Send a datagram straight to the opponent
+    Direct(SocketAddr, Vec<u8>),
+    /// Send the event to the server over the connection
+    Server(NetworkEvent),
+}
+
+enum State {
+    /// Sending hellos until something arrives from the opponent
+    Punching {
+        started_at: Instant,
+        next_hello: Instant,
+    },
+    Direct,
+    /// Using the server, because a direct connection could not be made or stopped working
+    Server,
+}
+
+struct Link {
+    addr: SocketAddr,
+    token: u64,
+    state: State,
+    next_sequence: u32,
+    /// Direct messages that have not been acknowledged yet, with when they were sent
+    unacked: Vec<(Instant, NetworkEvent)>,
+    /// The sequence numbers of the latest messages received
+    seen: VecDeque<u32>,
+}
+
+impl Link {
+    /// Check whether a message is new, and remember it if it is
+    fn is_new(&mut self, sequence: u32) -> bool {
+        if self.seen.contains(&sequence) {
+            return false;
+        }
+        if self.seen.len() == MAX_SEEN {
+            self.seen.pop_front();
+        }
+        self.seen.push_back(sequence);
+        true
+    }
+}
+
+/// The direct connections to other players, kept by the network thread
+pub struct Peers {
+    local: PlayerId,
+    links: HashMap<PlayerId, Link>,
+}
+
+impl Peers {
+    pub fn new(local: PlayerId) -> Peers {
+        Peers { local, links: HashMap::new() }
+    }
+
+    /// Start opening a direct connection to a player, replacing any previous one
+    pub fn open(&mut self, peer: PlayerId, addr: SocketAddr, token: u64) {
+        let now = Instant::now();
+        let link = Link {
+            addr,
+            token,
+            state: State::Punching { started_at: now, next_hello: now },
+            next_sequence: 0,
+            unacked: vec![],
+            seen: VecDeque::new(),
+        };
+        self.links.insert(peer, link);
+    }
+
+    pub fn close(&mut self, peer: PlayerId) {
+        self.links.remove(&peer);
+    }
+
+    /// Decide how to send an event from the game. Battle messages for a player with a working
+    /// direct connection are sent straight to them, and everything else goes to the server.
+    pub fn send(&mut self, event: NetworkEvent) -> Outgoing {
+        let (to, message) = match DirectMessage::from_event(&event) {
+            Some(direct) => direct,
+            None => return Outgoing::Server(event),
+        };
+        let link = match self.links.get_mut(&to) {
+            Some(link) if matches!(link.state, State::Direct) => link,
+            _ => return Outgoing::Server(event),
+        };
+
+        let sequence = link.next_sequence;
+        link.next_sequence = link.next_sequence.wrapping_add(1);
+        let packet = NetworkEvent::PeerPacket(to, self.local, sequence, message);
+        match codec::encode_datagram(link.token, &packet) {
+            Ok(datagram) => {
+                link.unacked.push((Instant::now(), packet));
+                Outgoing::Direct(link.addr, datagram)
+            }
+            Err(_) => Outgoing::Server(packet),
+        }
+    }
+
+    /// Handle a datagram from another player. Returns what to send back, and the battle message it
+    /// carried if it is one that has not been received before.
+    pub fn receive(
+        &mut self,
+        addr: SocketAddr,
+        data: &[u8],
+    ) -> (Vec<Outgoing>, Option<NetworkEvent>) {
+        let (token, event) = match codec::decode_peer_datagram(data) {
+            Ok(datagram) => datagram,
+            Err(_) => {
+                println!("Received an invalid datagram from: {}", addr);
+                return (vec![], None);
+            }
+        };
+        let (to, from) = match &event {
+            NetworkEvent::PeerHello(to, from)
+            | NetworkEvent::PeerAck(to, from, _)
+            | NetworkEvent::PeerPacket(to, from, ..) => (*to, *from),
+            _ => return (vec![], None),
+        };
+        let link = match self.links.get_mut(&from) {
+            Some(link) if link.token == token && to == self.local => link,
+            _ => {
+                println!("Received a datagram for an unknown battle from: {}", addr);
+                return (vec![], None);
+            }
+        };
+
+        // The opponent's NAT may have changed the port since the server saw it
+        link.addr = addr;
+        let mut replies = vec![];
+        if matches!(link.state, State::Punching { .. }) {
+            println!("Opened a direct connection to player: {}", from);
+            link.state = State::Direct;
+            // The opponent may not have heard from us yet
+            replies.push(encode(link, NetworkEvent::PeerHello(from, self.local)));
+        }
+
+        let received = match event {
+            NetworkEvent::PeerAck(_, _, sequence) => {
+                link.unacked.retain(|(_, packet)| sequence_of(packet) != Some(sequence));
+                None
+            }
+            NetworkEvent::PeerPacket(_, _, sequence, message) => {
+                replies.push(encode(link, NetworkEvent::PeerAck(from, self.local, sequence)));
+                link.is_new(sequence).then(|| message.to_event(to, from))
+            }
+            _ => None,
+        };
+        (replies.into_iter().flatten().collect(), received)
+    }
+
+    /// Unwrap a direct message that the opponent sent through the server instead, unless it has
+    /// already arrived directly
+    pub fn receive_relayed(
+        &mut self,
+        from: PlayerId,
+        sequence: u32,
+        message: DirectMessage,
+    ) -> Option<NetworkEvent> {
+        if let Some(link) = self.links.get_mut(&from) {
+            if !link.is_new(sequence) {
+                return None;
+            }
+        }
+        Some(message.to_event(self.local, from))
+    }
+
+    /// Send hellos while opening connections, and send messages that were not acknowledged in time
+    /// through the server
+    pub fn poll(&mut self) -> Vec<Outgoing> {
+        let now = Instant::now();
+        let mut outgoing = vec![];
+        for (&peer, link) in &mut self.links {
+            if let State::Punching { started_at, next_hello } = &mut link.state {
+                if now - *started_at > PUNCH_TIMEOUT {
+                    println!("Could not open a direct connection to player: {}", peer);
+                    link.state = State::Server;
+                }
+                else if now >= *next_hello {
+                    *next_hello = now + PUNCH_INTERVAL;
+                    let hello = NetworkEvent::PeerHello(peer, self.local);
+                    outgoing.extend(encode(link, hello));
+                }
+            }
+
+            if link.unacked.iter().any(|(sent_at, _)| now - *sent_at > ACK_TIMEOUT) {
+                println!("Direct connection to player: {} stopped working", peer);
+                link.state = State::Server;
+            }
+            if matches!(link.state, State::Server) {
+                outgoing.extend(link.unacked.drain(..).map(|(_, packet)| Outgoing::Server(packet)));
+            }
+        }
+        outgoing
+    }
+
+    /// When `poll` next has something to do
+    pub fn next_poll(&self) -> Option<Instant> {
+        self.links
+            .values()
+            .filter_map(|link| match link.state {
+                State::Punching { next_hello, .. } => Some(next_hello),
+                _ => link.unacked.iter().map(|(sent_at, _)| *sent_at + ACK_TIMEOUT).min(),
+            })
+            .min()
+    }
+}
+
+fn sequence_of(packet: &NetworkEvent) -> Option<u32> {
+    match packet {
+        NetworkEvent::PeerPacket(_, _, sequence, _) => Some(*sequence),
+        _ => None,
+    }
+}
+
+fn encode(link: &Link, event: NetworkEvent) -> Option<Outgoing> {
+    let datagram = codec::encode_datagram(link.token, &event).ok()?;
+    Some(Outgoing::Direct(link.addr, datagram))
+}
@@ -0,0 +1,230 @@
+//! Waiting on many sockets at once, so that a single thread can serve every connection
+//!
+//! A `Poller` waits until one of the sockets added to it can be read or written without blocking,
+//! or until a timeout runs out. Threads waiting on a poller are sent values through a `channel`,
+//! whose senders wake the poller through a `Waker` socket of their own.
+//!
+//! This uses `poll` on Unix and `WSAPoll` on Windows, which work the same way.
+use std::{
+    io,
+    net::{Ipv4Addr, UdpSocket},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use crossbeam_channel::{SendError, TryRecvError, TrySendError};
+
+/// Something that can be added to a `Poller`, such as a socket or a `Waker`
+pub use sys::Source;
+
+#[cfg(unix)]
+mod sys {
+    pub use std::os::fd::AsRawFd as Source;
+
+    pub use libc::{pollfd as PollFd, POLLIN, POLLOUT};
+
+    pub fn poll_fd(source: &impl Source, events: i16) -> PollFd {
+        PollFd { fd: source.as_raw_fd(), events, revents: 0 }
+    }
+
+    pub fn poll(fds: &mut [PollFd], millis: i32) -> i32 {
+        // Safety: `fds` points to `fds.len()` initialized entries, which are only used for the
+        // duration of the call
+        unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, millis) }
+    }
+}
+
+#[cfg(windows)]
+mod sys {
+    pub use std::os::windows::io::AsRawSocket as Source;
+
+    use windows_sys::Win32::Networking::WinSock::{WSAPoll, SOCKET};
+    pub use windows_sys::Win32::Networking::WinSock::{POLLIN, POLLOUT, WSAPOLLFD as PollFd};
+
+    pub fn poll_fd(source: &impl Source, events: i16) -> PollFd {
+        PollFd { fd: source.as_raw_socket() as SOCKET, events, revents: 0 }
+    }
+
+    pub fn poll(fds: &mut [PollFd], millis: i32) -> i32 {
+        // Safety: `fds` points to `fds.len()` initialized entries, which are only used for the
+        // duration of the call. The standard library has started Winsock for the sockets in it.
+        unsafe { WSAPoll(fds.as_mut_ptr(), fds.len() as u32, millis) }
+    }
+}
+
+#[derive(Default)]
+pub struct Poller {
+    fds: Vec<sys::PollFd>,
+}
+
+impl Poller {
+    pub fn new() -> Poller {
+        Poller { fds: vec![] }
+    }
+
+    /// Wait for a socket to be readable, or to be writable as well if `write` is set. Returns the
+    /// index to check with `is_ready` once the poller has waited.
+    pub fn add(&mut self, source: &impl Source, write: bool) -> usize {
+        let events = if write { sys::POLLIN | sys::POLLOUT } else { sys::POLLIN };
+        self.fds.push(sys::poll_fd(source, events));
+        self.fds.len() - 1
+    }
+
+    /// Wait for a socket to be writable only, leaving whatever there is to read for later
+    pub fn add_writable(&mut self, source: &impl Source) -> usize {
+        self.fds.push(sys::poll_fd(source, sys::POLLOUT));
+        self.fds.len() - 1
+    }
+
+    /// Wait until any of the sockets that have been added are ready, or the timeout runs out
+    pub fn wait(&mut self, timeout: Duration) -> io::Result<()> {
+        // `WSAPoll` fails when it is given no sockets, rather than waiting
+        if self.fds.is_empty() {
+            std::thread::sleep(timeout);
+            return Ok(());
+        }
+        // Rounded up, so that the poller does not spin while less than a millisecond is left
+        let millis = timeout.as_micros().div_ceil(1000);
+        let millis = millis.min(i32::MAX as u128) as i32;
+        loop {
+            if sys::poll(&mut self.fds, millis) >= 0 {
+                return Ok(());
+            }
+            let error = io::Error::last_os_error();
+            if error.kind() != io::ErrorKind::Interrupted {
+                return Err(error);
+            }
+        }
+    }
+
+    /// Whether a socket was ready when the poller last waited. This includes sockets that have
+    /// been closed or have failed, so that reading them reports why.
+    pub fn is_ready(&self, index: usize) -> bool {
+        self.fds[index].revents != 0
+    }
+
+    /// Remove every socket, so that a different set can be waited on
+    pub fn clear(&mut self) {
+        self.fds.clear();
+    }
+}
+
+/// Wakes up a `Poller` that it has been added to, from any thread. This sends a datagram to a
+/// socket on this machine, which makes the socket readable.
+pub struct Waker {
+    socket: UdpSocket,
+    /// Whether a datagram has been sent since the waker was last reset, so that waking it many
+    /// times in a row only sends one
+    pending: AtomicBool,
+}
+
+impl Waker {
+    pub fn new() -> io::Result<Waker> {
+        let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0))?;
+        socket.connect(socket.local_addr()?)?;
+        socket.set_nonblocking(true)?;
+        Ok(Waker { socket, pending: AtomicBool::new(false) })
+    }
+
+    pub fn wake(&self) {
+        if !self.pending.swap(true, Ordering::SeqCst) {
+            let _ = self.socket.send(&[0]);
+        }
+    }
+
+    /// Let the waker be woken again. This is done before checking for whatever it is woken for,
+    /// so that a wake that happens during the check is not missed.
+    fn reset(&self) {
+        if self.pending.swap(false, Ordering::SeqCst) {
+            let mut buffer = [0];
+            while self.socket.recv(&mut buffer).is_ok() {}
+        }
+    }
+}
+
+#[cfg(unix)]
+impl std::os::fd::AsRawFd for Waker {
+    fn as_raw_fd(&self) -> std::os::fd::RawFd {
+        self.socket.as_raw_fd()
+    }
+}
+
+#[cfg(windows)]
+impl std::os::windows::io::AsRawSocket for Waker {
+    fn as_raw_socket(&self) -> std::os::windows::io::RawSocket {
+        self.socket.as_raw_socket()
+    }
+}
+
+/// A channel to a thread that waits on a `Poller`, which wakes it whenever a value is sent and
+/// once every sender has been dropped
+pub fn channel<T>() -> io::Result<(Sender<T>, Receiver<T>)> {
+    with_waker(crossbeam_channel::unbounded())
+}
+
+/// A `channel` that holds at most `capacity` values, after which `send` blocks and `try_send` fails
+pub fn bounded<T>(capacity: usize) -> io::Result<(Sender<T>, Receiver<T>)> {
+    with_waker(crossbeam_channel::bounded(capacity))
+}
+
+fn with_waker<T>(
+    (sender, receiver): (crossbeam_channel::Sender<T>, crossbeam_channel::Receiver<T>),
+) -> io::Result<(Sender<T>, Receiver<T>)> {
+    let waker = Arc::new(Waker::new()?);
+    Ok((Sender { sender, waker: WakeOnDrop(waker.clone()) }, Receiver { receiver, waker }))
+}
+
+pub struct Sender<T> {
+    sender: crossbeam_channel::Sender<T>,
+    /// Declared after `sender`, so that the receiver is woken once the channel has been
+    /// disconnected by the last sender being dropped
+    waker: WakeOnDrop,
+}
+
+impl<T> Sender<T> {
+    pub fn send(&self, value: T) -> Result<(), SendError<T>> {
+        self.sender.send(value)?;
+        self.waker.0.wake();
+        Ok(())
+    }
+
+    pub fn try_send(&self, value: T) -> Result<(), TrySendError<T>> {
+        self.sender.try_send(value)?;
+        self.waker.0.wake();
+        Ok(())
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Sender<T> {
+        Sender { sender: self.sender.clone(), waker: WakeOnDrop(self.waker.0.clone()) }
+    }
+}
+
+struct WakeOnDrop(Arc<Waker>);
+
+impl Drop for WakeOnDrop {
+    fn drop(&mut self) {
+        self.0.wake();
+    }
+}
+
+pub struct Receiver<T> {
+    receiver: crossbeam_channel::Receiver<T>,
+    waker: Arc<Waker>,
+}
+
+impl<T> Receiver<T> {
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+        self.waker.reset();
+        self.receiver.try_recv()
+    }
+
+    /// The waker to add to a `Poller`, which is woken when a value is sent
+    pub fn waker(&self) -> &Waker {
+        &self.waker
+    }
+}
@@ -0,0 +1,20 @@
+//! Machine-readable description of the network protocol
+use interface::values::Direction;
+use serde_reflection::{Registry, Result, Tracer, TracerConfig};
+
+use crate::{peer::DirectMessage, Feature, NetworkEvent};
+
+/// Trace the format of all types that are sent over the network. The returned registry maps each
+/// type name to its format (the fields of structs and the variants of enums).
+pub fn protocol_schema() -> Result<Registry> {
+    let mut tracer = Tracer::new(TracerConfig::default());
+
+    // Enums nested inside of other types must be traced first so that the tracer knows how to
+    // construct a value for them.
+    tracer.trace_simple_type::<Direction>()?;
+    tracer.trace_simple_type::<DirectMessage>()?;
+    tracer.trace_simple_type::<Feature>()?;
+    tracer.trace_simple_type::<NetworkEvent>()?;
+
+    tracer.registry()
+}
@@ -0,0 +1,31 @@
+//! Commit-reveal exchange of the random seed used for battles
+//!
+//! The player starting a battle commits to a secret seed by sending its hash. The opponent replies
+//! with their own seed, and then the secret seed is revealed. The battle seed is derived from both,
+//! so neither player can choose it: the first player cannot change their seed after seeing the
+//! opponent's, and the opponent does not know the first player's seed when choosing theirs.
+use sha2::{Digest, Sha256};
+
+pub type Seed = [u8; 16];
+pub type SeedCommitment = [u8; 32];
+
+pub fn generate() -> Seed {
+    let mut seed = [0; 16];
+    getrandom::getrandom(&mut seed).expect("failed to generate a random seed");
+    seed
+}
+
+pub fn commitment(seed: &Seed) -> SeedCommitment {
+    Sha256::digest(seed).into()
+}
+
+/// Check that a revealed seed matches the commitment made earlier
+pub fn verify(commitment: &SeedCommitment, seed: &Seed) -> bool {
+    self::commitment(seed) == *commitment
+}
+
+/// Derive the battle seed from the seeds of both players, in the form of the game's RNG state
+pub fn combine(first: &Seed, second: &Seed) -> [u8; 2] {
+    let hash = Sha256::new().chain_update(first).chain_update(second).finalize();
+    [hash[0], hash[1]]
+}
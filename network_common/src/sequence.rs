@@ -0,0 +1,120 @@
+//! Sequence numbers for player updates, so that updates which arrive late are not applied over
+//! newer ones
+//!
+//! Movement updates are sent over UDP, where a datagram can be delayed behind ones sent after it.
+//! Each client numbers its `FullUpdate` and `MovementUpdate` events from a single counter, and
+//! receivers drop movement that is older than the newest update they have applied from the player,
+//! instead of snapping the player back to where they were.
+use std::collections::HashMap;
+
+use crate::PlayerId;
+
+/// The number of an update. Numbers wrap around, so they are compared by the distance between
+/// them rather than by which is larger.
+pub type Sequence = u32;
+
+/// Numbers the updates sent by the local player
+#[derive(Default)]
+pub struct SequenceCounter {
+    next: Sequence,
+}
+
+impl SequenceCounter {
+    /// The number of the next update, moving the counter on past it
+    pub fn advance(&mut self) -> Sequence {
+        let seq = self.next;
+        self.next = self.next.wrapping_add(1);
+        seq
+    }
+}
+
+/// The newest update that has been applied from each player
+#[derive(Default)]
+pub struct LatestUpdates {
+    latest: HashMap<PlayerId, Sequence>,
+}
+
+impl LatestUpdates {
+    /// Record an update from a player, returning false if it is not newer than one that has already
+    /// been applied. The first update from a player is always accepted.
+    pub fn accept(&mut self, player: PlayerId, seq: Sequence) -> bool {
+        match self.latest.get(&player) {
+            Some(&latest) if !is_newer(seq, latest) => false,
+            _ => {
+                self.latest.insert(player, seq);
+                true
+            }
+        }
+    }
+
+    pub fn remove(&mut self, player: PlayerId) {
+        self.latest.remove(&player);
+    }
+}
+
+/// Whether `a` was numbered after `b`, allowing for the counter wrapping around
+pub fn is_newer(a: Sequence, b: Sequence) -> bool {
+    (a.wrapping_sub(b) as i32) > 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn equal_is_not_newer() {
+        for seq in [0, 1, u16::MAX as Sequence, Sequence::MAX] {
+            assert!(!is_newer(seq, seq));
+        }
+    }
+
+    #[test]
+    fn later_is_newer() {
+        assert!(is_newer(1, 0));
+        assert!(!is_newer(0, 1));
+        assert!(is_newer(1000, 10));
+    }
+
+    #[test]
+    fn wraps_around() {
+        assert!(is_newer(0, Sequence::MAX));
+        assert!(is_newer(5, Sequence::MAX - 5));
+        assert!(!is_newer(Sequence::MAX, 0));
+        // Numbers are not cut down to 16 bits, so nothing wraps around at `u16::MAX`
+        let max = u16::MAX as Sequence;
+        assert!(is_newer(max + 1, max));
+        assert!(!is_newer(max, max + 1));
+    }
+
+    #[test]
+    fn half_range() {
+        let half = 1 << (Sequence::BITS - 1);
+        // Anything less than half of the range ahead is newer, anything further is older
+        assert!(is_newer(half - 1, 0));
+        assert!(!is_newer(half, 0));
+        assert!(!is_newer(half + 1, 0));
+        assert!(is_newer(0, half + 1));
+        assert!(is_newer(10 + half - 1, 10));
+        assert!(!is_newer(10 + half, 10));
+    }
+
+    #[test]
+    fn counter_wraps_around() {
+        let mut counter = SequenceCounter { next: Sequence::MAX };
+        let (a, b) = (counter.advance(), counter.advance());
+        assert_eq!((a, b), (Sequence::MAX, 0));
+        assert!(is_newer(b, a));
+    }
+
+    #[test]
+    fn late_updates_are_dropped() {
+        let mut latest = LatestUpdates::default();
+        let player = 1;
+        assert!(latest.accept(player, Sequence::MAX));
+        assert!(latest.accept(player, 1));
+        assert!(!latest.accept(player, 0));
+        assert!(!latest.accept(player, 1));
+        latest.remove(player);
+        assert!(latest.accept(player, 0));
+    }
+}
@@ -0,0 +1,62 @@
+//! Random values shared by every player on a server
+//!
+//! The server picks a seed for each day and sends it to every client. Small cosmetic effects are
+//! derived from the seed on each client, so that everyone online sees them at the same time
+//! without the server needing to know about each effect.
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use sha2::{Digest, Sha256};
+
+use crate::seed::Seed;
+
+pub const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// The seed for a single day (UTC)
+#[derive(Copy, Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct SharedSeed {
+    /// The number of days since the Unix epoch
+    pub day: u64,
+    pub seed: u64,
+}
+
+/// Effects that are derived from the shared seed. Each effect gets its own random value, so adding
+/// an effect does not change the others.
+#[derive(Copy, Clone, Debug)]
+pub enum SharedEffect {
+    /// A shooting star that everyone online sees once a day
+    ShootingStar,
+}
+
+impl SharedSeed {
+    /// Derive the seed for a day from the server's secret seed, so that future days can not be
+    /// predicted by clients
+    pub fn for_day(server_seed: &Seed, day: u64) -> SharedSeed {
+        let hash =
+            Sha256::new().chain_update(server_seed).chain_update(day.to_le_bytes()).finalize();
+        SharedSeed { day, seed: u64::from_le_bytes(hash[..8].try_into().unwrap()) }
+    }
+
+    /// A random value for an effect, which is the same for every client
+    pub fn roll(&self, effect: SharedEffect) -> u64 {
+        let hash = Sha256::new()
+            .chain_update(self.seed.to_le_bytes())
+            .chain_update([effect as u8])
+            .finalize();
+        u64::from_le_bytes(hash[..8].try_into().unwrap())
+    }
+
+    /// The time that an effect happens, in seconds after the start of the day
+    pub fn time_of_day(&self, effect: SharedEffect) -> u64 {
+        self.roll(effect) % SECONDS_PER_DAY
+    }
+}
+
+/// The current time, in seconds since the Unix epoch
+pub fn now_secs() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_secs())
+}
+
+/// The number of days since the Unix epoch
+pub fn current_day() -> u64 {
+    now_secs() / SECONDS_PER_DAY
+}
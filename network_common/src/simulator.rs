@@ -0,0 +1,163 @@
+//! A simulated poor connection, so that interpolation, battle handshakes and timeouts can be tried
+//! out locally
+//!
+//! The simulator sits inside the network thread, between the session and its sockets (see
+//! `client.rs`). Every event sent to or received from the server is held back for the latency plus
+//! a random amount of jitter, including the pings that measure the round trip time. Movement
+//! updates, which are sent over UDP where possible, can also be lost or arrive after the updates
+//! sent after them. Every other event is only delayed, and stays in order as it would over TCP.
+//! Events are only seen to arrive once they are let through, so a connection that is slower than
+//! `CONNECTION_TIMEOUT` times out as it would for real.
+use std::time::{Duration, Instant};
+
+use crate::{seed, NetworkEvent};
+
+/// The conditions to simulate. The default is a perfect connection, which needs no simulator.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct NetworkConditions {
+    /// Added to every event in each direction, in milliseconds
+    pub latency_ms: u32,
+    /// The most extra time added to each event at random, in milliseconds
+    pub jitter_ms: u32,
+    /// The chance of dropping each movement update, in percent
+    pub loss_percent: u8,
+    /// The chance of holding each movement update back long enough for later ones to overtake it,
+    /// in percent
+    pub reorder_percent: u8,
+}
+
+impl NetworkConditions {
+    pub fn is_perfect(&self) -> bool {
+        *self == NetworkConditions::default()
+    }
+}
+
+/// Events waiting to be passed on, with the time that each is due
+struct DelayQueue {
+    conditions: NetworkConditions,
+    queue: Vec<(Instant, NetworkEvent)>,
+    /// When the last in-order event is due, so that later ones are not passed on before it
+    ordered_until: Instant,
+    rng: u64,
+}
+
+impl DelayQueue {
+    fn new(conditions: NetworkConditions) -> DelayQueue {
+        let seed = seed::generate();
+        let rng = u64::from_le_bytes(seed[..8].try_into().unwrap()) | 1;
+        DelayQueue { conditions, queue: vec![], ordered_until: Instant::now(), rng }
+    }
+
+    /// A random number below `max`, or 0 if `max` is 0
+    fn random(&mut self, max: u32) -> u32 {
+        // xorshift64, which is plenty for choosing delays
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        if max == 0 {
+            0
+        }
+        else {
+            (self.rng % max as u64) as u32
+        }
+    }
+
+    fn chance(&mut self, percent: u8) -> bool {
+        self.random(100) < percent as u32
+    }
+
+    fn push(&mut self, event: NetworkEvent) {
+        let conditions = self.conditions;
+        let delay = conditions.latency_ms + self.random(conditions.jitter_ms + 1);
+        let mut due = Instant::now() + Duration::from_millis(delay as u64);
+        if let NetworkEvent::MovementUpdate(..) = event {
+            if self.chance(conditions.loss_percent) {
+                return;
+            }
+            if self.chance(conditions.reorder_percent) {
+                let held = conditions.latency_ms + conditions.jitter_ms + 1;
+                due += Duration::from_millis(held as u64);
+            }
+        }
+        else {
+            due = due.max(self.ordered_until);
+            self.ordered_until = due;
+        }
+        self.queue.push((due, event));
+    }
+
+    /// Take the events that are due, in the order they are due
+    fn take_due(&mut self) -> Vec<NetworkEvent> {
+        let now = Instant::now();
+        self.queue.sort_by_key(|(due, _)| *due);
+        let count = self.queue.iter().take_while(|(due, _)| *due <= now).count();
+        self.queue.drain(..count).map(|(_, event)| event).collect()
+    }
+
+    /// When the next event is due, if any are waiting
+    fn next_due(&self) -> Option<Instant> {
+        self.queue.iter().map(|(due, _)| *due).min()
+    }
+}
+
+/// Both directions of a simulated connection to the server
+pub struct Simulator {
+    outgoing: DelayQueue,
+    incoming: DelayQueue,
+    /// When an event from the server was last let through
+    received_at: Instant,
+}
+
+impl Simulator {
+    pub fn new(conditions: NetworkConditions) -> Simulator {
+        Simulator {
+            outgoing: DelayQueue::new(conditions),
+            incoming: DelayQueue::new(conditions),
+            received_at: Instant::now(),
+        }
+    }
+
+    /// Hold back an event on its way to the server
+    pub fn send(&mut self, event: NetworkEvent) {
+        self.outgoing.push(event);
+    }
+
+    /// Hold back an event on its way from the server
+    pub fn receive(&mut self, event: NetworkEvent) {
+        self.incoming.push(event);
+    }
+
+    /// The events that are due to be sent to the server
+    pub fn take_sent(&mut self) -> Vec<NetworkEvent> {
+        self.outgoing.take_due()
+    }
+
+    /// Everything still on its way to the server, which is sent straight away as the player leaves
+    pub fn take_all_sent(&mut self) -> Vec<NetworkEvent> {
+        self.outgoing.queue.sort_by_key(|(due, _)| *due);
+        self.outgoing.queue.drain(..).map(|(_, event)| event).collect()
+    }
+
+    /// The events from the server that are due to arrive
+    pub fn take_received(&mut self) -> Vec<NetworkEvent> {
+        let received = self.incoming.take_due();
+        if !received.is_empty() {
+            self.received_at = Instant::now();
+        }
+        received
+    }
+
+    /// When an event from the server last arrived, which the connection timeout counts from
+    pub fn received_at(&self) -> Instant {
+        self.received_at
+    }
+
+    /// When the next event in either direction is due, if any are waiting
+    pub fn next_due(&self) -> Option<Instant> {
+        match (self.outgoing.next_due(), self.incoming.next_due()) {
+            (Some(outgoing), Some(incoming)) => Some(outgoing.min(incoming)),
+            (outgoing, incoming) => outgoing.or(incoming),
+        }
+    }
+}
@@ -0,0 +1,141 @@
+//! Connecting to servers through a SOCKS5 proxy, for players behind networks that block the game's
+//! port or who reach servers through a tunnel (e.g. `ssh -D` or Tor)
+//!
+//! Only the TCP connection goes through the proxy. Movement updates are sent over the connection
+//! instead of UDP, since datagrams sent straight to the server would bypass the proxy. The server's
+//! name is sent to the proxy to resolve, so that it is never looked up locally.
+use std::{
+    io::{self, prelude::*},
+    net::{IpAddr, TcpStream},
+};
+
+use crate::transport::{self, Transport};
+
+/// The port that SOCKS proxies listen on, used when the proxy address does not give one
+pub const DEFAULT_PROXY_PORT: u16 = 1080;
+
+const VERSION: u8 = 5;
+const METHOD_NO_AUTH: u8 = 0x00;
+const METHOD_NONE_ACCEPTABLE: u8 = 0xFF;
+const COMMAND_CONNECT: u8 = 0x01;
+const ADDRESS_IPV4: u8 = 0x01;
+const ADDRESS_DOMAIN: u8 = 0x03;
+const ADDRESS_IPV6: u8 = 0x04;
+const REPLY_SUCCEEDED: u8 = 0x00;
+
+/// A connection to a server that goes through a proxy
+pub struct ProxiedStream(TcpStream);
+
+impl Read for ProxiedStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl Write for ProxiedStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+impl Transport for ProxiedStream {
+    fn tcp(&self) -> &TcpStream {
+        &self.0
+    }
+}
+
+impl ProxiedStream {
+    pub fn into_inner(self) -> TcpStream {
+        self.0
+    }
+}
+
+/// Connect to `host` and `port` through the proxy at `proxy`, e.g. `127.0.0.1:9050`. Only proxies
+/// that do not ask for a username and password are supported.
+pub fn connect(proxy: &str, host: &str, port: u16) -> io::Result<ProxiedStream> {
+    let (proxy_host, proxy_port) = transport::split_address(proxy, DEFAULT_PROXY_PORT)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let mut socket = transport::connect_host(proxy_host, proxy_port)?;
+
+    socket.write_all(&[VERSION, 1, METHOD_NO_AUTH])?;
+    let mut choice = [0; 2];
+    socket.read_exact(&mut choice)?;
+    match choice {
+        [VERSION, METHOD_NO_AUTH] => {}
+        [VERSION, METHOD_NONE_ACCEPTABLE] => {
+            return Err(io::Error::other("the proxy requires authentication"));
+        }
+        _ => return Err(invalid_data("the proxy does not support SOCKS5")),
+    }
+
+    let mut request = vec![VERSION, COMMAND_CONNECT, 0];
+    match host.parse::<IpAddr>() {
+        Ok(IpAddr::V4(ip)) => {
+            request.push(ADDRESS_IPV4);
+            request.extend_from_slice(&ip.octets());
+        }
+        Ok(IpAddr::V6(ip)) => {
+            request.push(ADDRESS_IPV6);
+            request.extend_from_slice(&ip.octets());
+        }
+        Err(_) => {
+            let name = u8::try_from(host.len()).map_err(|_| {
+                io::Error::new(io::ErrorKind::InvalidInput, "the server's name is too long")
+            })?;
+            request.push(ADDRESS_DOMAIN);
+            request.push(name);
+            request.extend_from_slice(host.as_bytes());
+        }
+    }
+    request.extend_from_slice(&port.to_be_bytes());
+    socket.write_all(&request)?;
+
+    let mut reply = [0; 4];
+    socket.read_exact(&mut reply)?;
+    if reply[0] != VERSION {
+        return Err(invalid_data("the proxy sent an invalid reply"));
+    }
+    if reply[1] != REPLY_SUCCEEDED {
+        let message = format!("the proxy could not connect: {}", reply_error(reply[1]));
+        return Err(io::Error::other(message));
+    }
+
+    // The reply ends with the address that the proxy connected from, which is not needed
+    let address_len = match reply[3] {
+        ADDRESS_IPV4 => 4,
+        ADDRESS_IPV6 => 16,
+        ADDRESS_DOMAIN => {
+            let mut len = [0];
+            socket.read_exact(&mut len)?;
+            len[0] as usize
+        }
+        _ => return Err(invalid_data("the proxy sent an invalid address")),
+    };
+    let mut bound_address = vec![0; address_len + 2];
+    socket.read_exact(&mut bound_address)?;
+
+    Ok(ProxiedStream(socket))
+}
+
+/// A description of a reply code other than success
+fn reply_error(code: u8) -> &'static str {
+    match code {
+        0x01 => "general failure",
+        0x02 => "connection not allowed by the proxy's rules",
+        0x03 => "network unreachable",
+        0x04 => "host unreachable",
+        0x05 => "connection refused",
+        0x06 => "TTL expired",
+        0x07 => "command not supported",
+        0x08 => "address type not supported",
+        _ => "unknown error",
+    }
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
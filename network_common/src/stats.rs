@@ -0,0 +1,170 @@
+//! Traffic statistics for a connection, to help tell why the game stutters on a poor connection
+//!
+//! The network thread records every event and every byte that it sends or receives, and the game
+//! reads the totals along with the rates over the last `RATE_WINDOW`. Bytes include the framing
+//! and compression of each packet, and movement updates sent over UDP. The last `RECENT_EVENTS`
+//! events are also kept in order, for crash reports.
+use std::{
+    collections::{BTreeMap, VecDeque},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::NetworkEvent;
+
+/// How long rates are measured over
+const RATE_WINDOW: Duration = Duration::from_secs(1);
+
+/// The number of events kept in the order they were sent or received
+pub const RECENT_EVENTS: usize = 32;
+
+/// An event that was sent or received recently
+#[derive(Copy, Clone, Debug)]
+pub struct RecentEvent {
+    /// The name of the event's `NetworkEvent` variant
+    pub name: &'static str,
+    pub sent: bool,
+    pub at: Instant,
+}
+
+/// The number of events of one type that have been sent and received
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct EventCounts {
+    pub sent: u64,
+    pub received: u64,
+}
+
+/// Totals since the connection was made, along with the rates over the last window
+#[derive(Clone, Debug, Default)]
+pub struct TrafficStats {
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    pub events_sent: u64,
+    pub events_received: u64,
+    pub bytes_sent_per_sec: f32,
+    pub bytes_received_per_sec: f32,
+    pub events_sent_per_sec: f32,
+    pub events_received_per_sec: f32,
+    /// The counts for each type of event, by the name of its `NetworkEvent` variant
+    pub events: BTreeMap<&'static str, EventCounts>,
+    /// The last events sent or received, oldest first
+    pub recent: VecDeque<RecentEvent>,
+}
+
+impl TrafficStats {
+    /// The types of event that have been sent or received the most, most first
+    pub fn busiest_events(&self) -> Vec<(&'static str, EventCounts)> {
+        let mut events: Vec<_> =
+            self.events.iter().map(|(&name, &counts)| (name, counts)).collect();
+        events.sort_by_key(|(_, counts)| std::cmp::Reverse(counts.sent + counts.received));
+        events
+    }
+}
+
+struct Counters {
+    stats: TrafficStats,
+    window_start: Instant,
+    /// The totals at the start of the current window: bytes sent and received, events sent and
+    /// received
+    window_totals: [u64; 4],
+}
+
+impl Counters {
+    fn totals(&self) -> [u64; 4] {
+        let stats = &self.stats;
+        [stats.bytes_sent, stats.bytes_received, stats.events_sent, stats.events_received]
+    }
+
+    /// Start a new window once the current one has ended, updating the rates from it
+    fn roll(&mut self) {
+        let elapsed = self.window_start.elapsed();
+        if elapsed < RATE_WINDOW {
+            return;
+        }
+
+        let totals = self.totals();
+        let secs = elapsed.as_secs_f32();
+        let rate = |i: usize| (totals[i] - self.window_totals[i]) as f32 / secs;
+        let rates = [rate(0), rate(1), rate(2), rate(3)];
+        let stats = &mut self.stats;
+        stats.bytes_sent_per_sec = rates[0];
+        stats.bytes_received_per_sec = rates[1];
+        stats.events_sent_per_sec = rates[2];
+        stats.events_received_per_sec = rates[3];
+
+        self.window_start = Instant::now();
+        self.window_totals = totals;
+    }
+}
+
+/// The traffic on a connection, shared between the network thread and the game
+#[derive(Clone)]
+pub struct Traffic {
+    counters: Arc<Mutex<Counters>>,
+}
+
+impl Default for Traffic {
+    fn default() -> Traffic {
+        let counters = Counters {
+            stats: TrafficStats::default(),
+            window_start: Instant::now(),
+            window_totals: [0; 4],
+        };
+        Traffic { counters: Arc::new(Mutex::new(counters)) }
+    }
+}
+
+impl Traffic {
+    /// The current statistics
+    pub fn stats(&self) -> TrafficStats {
+        let mut counters = self.counters.lock().unwrap();
+        counters.roll();
+        counters.stats.clone()
+    }
+
+    pub fn record_bytes_sent(&self, len: usize) {
+        self.update(|stats| stats.bytes_sent += len as u64);
+    }
+
+    pub fn record_bytes_received(&self, len: usize) {
+        self.update(|stats| stats.bytes_received += len as u64);
+    }
+
+    pub fn record_sent(&self, event: &NetworkEvent) {
+        self.update(|stats| {
+            stats.events_sent += 1;
+            stats.events.entry(event.name()).or_default().sent += 1;
+            add_recent(stats, event, true);
+        });
+    }
+
+    pub fn record_received(&self, event: &NetworkEvent) {
+        self.update(|stats| {
+            stats.events_received += 1;
+            stats.events.entry(event.name()).or_default().received += 1;
+            add_recent(stats, event, false);
+        });
+    }
+
+    /// The last events sent or received, oldest first. This does not wait for the network thread,
+    /// so that it can be called while panicking, and is empty if the statistics are being updated.
+    pub fn recent_events(&self) -> Vec<RecentEvent> {
+        match self.counters.try_lock() {
+            Ok(counters) => counters.stats.recent.iter().copied().collect(),
+            Err(_) => vec![],
+        }
+    }
+
+    fn update(&self, f: impl FnOnce(&mut TrafficStats)) {
+        let mut counters = self.counters.lock().unwrap();
+        counters.roll();
+        f(&mut counters.stats);
+    }
+}
+
+fn add_recent(stats: &mut TrafficStats, event: &NetworkEvent, sent: bool) {
+    if stats.recent.len() == RECENT_EVENTS {
+        stats.recent.pop_front();
+    }
+    stats.recent.push_back(RecentEvent { name: event.name(), sent, at: Instant::now() });
+}
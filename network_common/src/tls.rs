@@ -0,0 +1,159 @@
+//! Optional TLS for connections between clients and servers, so that chat and player data are not
+//! sent as plain text
+//!
+//! Servers enable it by loading a certificate and private key, and clients then connect with it
+//! turned on. Both ends run the usual protocol, starting with the version preamble, inside the TLS
+//! session. Movement datagrams can not be sent through it, so clients using TLS send their
+//! movement over TCP instead.
+use std::{
+    io::{self, prelude::*},
+    net::TcpStream,
+    path::Path,
+    sync::Arc,
+};
+
+use rustls::{
+    crypto::ring,
+    pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer, ServerName},
+    ClientConnection, Connection, RootCertStore, ServerConnection,
+};
+pub use rustls::{ClientConfig, ServerConfig};
+
+use crate::transport::Transport;
+
+/// How much encrypted data is read from the socket at a time. This is kept below the limit on
+/// decrypted data that a session will buffer.
+const READ_SIZE: usize = 4096;
+
+pub struct TlsStream {
+    socket: TcpStream,
+    session: Connection,
+}
+
+impl TlsStream {
+    /// Complete the TLS handshake, while the socket is still blocking
+    fn new(mut socket: TcpStream, mut session: Connection) -> io::Result<TlsStream> {
+        while session.is_handshaking() {
+            session.complete_io(&mut socket)?;
+        }
+        Ok(TlsStream { socket, session })
+    }
+
+    /// Send everything that the session has queued up, such as encrypted data or key updates
+    fn send_queued(&mut self) -> io::Result<()> {
+        while self.session.wants_write() {
+            self.session.write_tls(&mut self.socket)?;
+        }
+        Ok(())
+    }
+
+    /// Send what can be sent without waiting, leaving the rest queued in the session
+    fn try_send_queued(&mut self) -> io::Result<()> {
+        match self.send_queued() {
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(()),
+            result => result,
+        }
+    }
+}
+
+impl Transport for TlsStream {
+    fn tcp(&self) -> &TcpStream {
+        &self.socket
+    }
+
+    fn wants_write(&self) -> bool {
+        self.session.wants_write()
+    }
+}
+
+impl Read for TlsStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut received = [0; READ_SIZE];
+        loop {
+            match self.session.reader().read(buf) {
+                // No data has been decrypted yet
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                result => return result,
+            }
+
+            let len = self.socket.read(&mut received)?;
+            let mut data = &received[..len];
+            // Reading nothing tells the session that the connection was closed
+            loop {
+                self.session.read_tls(&mut data)?;
+                self.session.process_new_packets().map_err(io::Error::other)?;
+                if data.is_empty() {
+                    break;
+                }
+            }
+            self.try_send_queued()?;
+        }
+    }
+}
+
+impl Write for TlsStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = self.session.writer().write(buf)?;
+        self.try_send_queued()?;
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.session.writer().flush()?;
+        self.send_queued()
+    }
+}
+
+/// The settings used by clients, which trust the usual web certificate authorities along with an
+/// optional certificate, for servers that use a self-signed one
+pub fn client_config(trusted: Option<&Path>) -> Result<Arc<ClientConfig>, String> {
+    let mut roots = RootCertStore::empty();
+    roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+    if let Some(path) = trusted {
+        for cert in load_certs(path)? {
+            roots.add(cert).map_err(|e| e.to_string())?;
+        }
+    }
+
+    let config = ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .map_err(|e| e.to_string())?
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    Ok(Arc::new(config))
+}
+
+/// The settings used by servers, from a certificate chain and private key in PEM files
+pub fn server_config(cert_path: &Path, key_path: &Path) -> Result<Arc<ServerConfig>, String> {
+    let certs = load_certs(cert_path)?;
+    let key = PrivateKeyDer::from_pem_file(key_path)
+        .map_err(|e| format!("failed to read {}: {}", key_path.display(), e))?;
+
+    let config = ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .map_err(|e| e.to_string())?
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .map_err(|e| e.to_string())?;
+    Ok(Arc::new(config))
+}
+
+fn load_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>, String> {
+    CertificateDer::pem_file_iter(path)
+        .and_then(|certs| certs.collect())
+        .map_err(|e| format!("failed to read {}: {}", path.display(), e))
+}
+
+/// Start a TLS session with a server, checking that its certificate is valid for `host`
+pub fn connect(socket: TcpStream, host: &str, config: Arc<ClientConfig>) -> io::Result<TlsStream> {
+    let name = ServerName::try_from(host.to_string())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let session = ClientConnection::new(config, name).map_err(io::Error::other)?;
+    TlsStream::new(socket, session.into())
+}
+
+/// Start a TLS session with a client that has just connected
+pub fn accept(socket: TcpStream, config: Arc<ServerConfig>) -> io::Result<TlsStream> {
+    let session = ServerConnection::new(config).map_err(io::Error::other)?;
+    TlsStream::new(socket, session.into())
+}
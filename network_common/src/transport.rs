@@ -0,0 +1,141 @@
+//! The connections that packets are sent over
+//!
+//! Clients and servers connect over TCP, optionally wrapped in TLS (see `tls.rs`) and framed as
+//! WebSocket messages (see `websocket.rs`). Clients can also reach the server through a SOCKS5
+//! proxy (see `socks.rs`). Everything past the connection itself only sees a `Transport`, so the
+//! rest of the protocol is the same for all of them.
+use std::{
+    io::{self, prelude::*},
+    net::{TcpStream, ToSocketAddrs},
+    sync::Arc,
+};
+
+use crate::{
+    socks,
+    tls::{self, ClientConfig},
+    websocket, DEFAULT_PORT,
+};
+
+/// How clients wrap their connection to a server
+#[derive(Clone, Default)]
+pub struct ClientOptions {
+    /// Encrypt the connection, checking the server's certificate with these settings
+    pub tls: Option<Arc<ClientConfig>>,
+    /// Frame the connection as WebSocket messages
+    pub websocket: bool,
+    /// Connect through this SOCKS5 proxy, e.g. `127.0.0.1:1080`
+    pub proxy: Option<String>,
+}
+
+/// A connection to the other end that packets can be sent and received over
+///
+/// Connections are switched to non-blocking once their handshakes are done (see `connection.rs`).
+/// From then on, reads and writes return `WouldBlock` instead of waiting, without losing any data
+/// that was partly received or sent. TLS and WebSocket accept every write in full, and keep what
+/// can not be sent straight away until the next `flush`.
+pub trait Transport: Read + Write + Send {
+    /// The TCP connection underneath, used for polling, addresses and shutting down
+    fn tcp(&self) -> &TcpStream;
+
+    /// Whether data that has already been written is waiting for the socket to be writable
+    fn wants_write(&self) -> bool {
+        false
+    }
+
+    /// Whether movement may be sent over UDP alongside this connection. Encrypted and proxied
+    /// connections keep everything on the connection itself.
+    fn allows_datagrams(&self) -> bool {
+        false
+    }
+}
+
+impl Transport for TcpStream {
+    fn tcp(&self) -> &TcpStream {
+        self
+    }
+
+    fn allows_datagrams(&self) -> bool {
+        true
+    }
+}
+
+/// Split a server address into its host and port, using `DEFAULT_PORT` if it does not give one.
+/// Hosts can be names or IP addresses, with IPv6 addresses in brackets if they are followed by a
+/// port, e.g. `example.com`, `192.168.0.2:8080`, `::1` or `[::1]:8080`.
+pub fn parse_address(addr: &str) -> Result<(&str, u16), String> {
+    split_address(addr, DEFAULT_PORT)
+}
+
+/// Split an address into its host and port like `parse_address`, with a different default port
+pub fn split_address(addr: &str, default_port: u16) -> Result<(&str, u16), String> {
+    let addr = addr.trim();
+    let (host, port) = match addr.strip_prefix('[') {
+        Some(bracketed) => match bracketed.split_once(']') {
+            Some((host, "")) => (host, None),
+            Some((host, rest)) => match rest.strip_prefix(':') {
+                Some(port) => (host, Some(port)),
+                None => return Err(format!("unexpected text after ']' in {}", addr)),
+            },
+            None => return Err(format!("missing ']' in {}", addr)),
+        },
+        // More than one colon means that this is an IPv6 address without a port
+        None if addr.matches(':').count() > 1 => (addr, None),
+        None => match addr.split_once(':') {
+            Some((host, port)) => (host, Some(port)),
+            None => (addr, None),
+        },
+    };
+
+    if host.is_empty() {
+        return Err("the address has no host".to_string());
+    }
+    match port {
+        Some(port) => Ok((host, port.parse().map_err(|_| format!("invalid port: {}", port))?)),
+        None => Ok((host, default_port)),
+    }
+}
+
+/// Connect to a host, trying every address that it resolves to until one accepts
+pub fn connect_host(host: &str, port: u16) -> io::Result<TcpStream> {
+    let message = format!("no addresses found for {}", host);
+    let mut result = Err(io::Error::new(io::ErrorKind::NotFound, message));
+    for socket_addr in (host, port).to_socket_addrs()? {
+        result = TcpStream::connect(socket_addr);
+        if result.is_ok() {
+            break;
+        }
+    }
+    result
+}
+
+/// Connect to a server, then start TLS and WebSocket framing if they are enabled. With a proxy,
+/// the proxy resolves the server's host instead.
+pub fn connect(addr: &str, options: &ClientOptions) -> io::Result<Box<dyn Transport>> {
+    let (host, port) =
+        parse_address(addr).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+    let mut transport: Box<dyn Transport> = match (&options.proxy, &options.tls) {
+        (Some(proxy), Some(config)) => {
+            let socket = socks::connect(proxy, host, port)?.into_inner();
+            Box::new(tls::connect(socket, host, config.clone())?)
+        }
+        (Some(proxy), None) => Box::new(socks::connect(proxy, host, port)?),
+        (None, tls) => {
+            let socket = connect_host(host, port)?;
+            match tls {
+                Some(config) => Box::new(tls::connect(socket, host, config.clone())?),
+                None => Box::new(socket),
+            }
+        }
+    };
+    if options.websocket {
+        let host_header = if host.contains(':') {
+            format!("[{}]:{}", host, port)
+        }
+        else {
+            format!("{}:{}", host, port)
+        };
+        transport = Box::new(websocket::connect(transport, &host_header, "/")?);
+    }
+    Ok(transport)
+}
@@ -0,0 +1,47 @@
+//! Protocol versions, so that clients and servers from incompatible builds fail to connect with a
+//! clear error instead of misreading each other's packets
+//!
+//! Both ends start a connection by sending `MAGIC` followed by their `PROTOCOL_VERSION` as a little
+//! endian `u16`, before any packets are sent. This preamble must stay the same in every version,
+//! since it is the only part of the protocol that every build can read.
+use std::io::prelude::*;
+
+use crate::error::{NetworkError, NetworkResult};
+
+/// The bytes that every connection starts with
+pub const MAGIC: [u8; 4] = *b"PKMN";
+
+/// The version of the network protocol. This must be increased whenever `NetworkEvent`, or the way
+/// that packets are encoded, changes.
+pub const PROTOCOL_VERSION: u16 = 20;
+
+/// The size of the preamble sent at the start of each connection
+const PREAMBLE_SIZE: usize = MAGIC.len() + 2;
+
+pub fn write_preamble<W: Write>(writer: &mut W) -> NetworkResult<()> {
+    let mut preamble = MAGIC.to_vec();
+    preamble.extend(PROTOCOL_VERSION.to_le_bytes());
+    writer.write_all(&preamble)?;
+    Ok(())
+}
+
+/// Read the other end's preamble, returning the version of the protocol that it uses
+pub fn read_preamble<R: Read>(reader: &mut R) -> NetworkResult<u16> {
+    let mut preamble = [0; PREAMBLE_SIZE];
+    reader.read_exact(&mut preamble)?;
+    if preamble[..MAGIC.len()] != MAGIC {
+        return Err(NetworkError::UnknownPeer);
+    }
+    Ok(u16::from_le_bytes([preamble[MAGIC.len()], preamble[MAGIC.len() + 1]]))
+}
+
+/// Check whether this build can talk to one using another version of the protocol. Events are
+/// identified by their position in `NetworkEvent`, so only the same version is compatible.
+pub fn check(version: u16) -> NetworkResult<()> {
+    if version == PROTOCOL_VERSION {
+        Ok(())
+    }
+    else {
+        Err(NetworkError::VersionMismatch(version))
+    }
+}
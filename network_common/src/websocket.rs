@@ -0,0 +1,301 @@
+//! WebSocket framing for connections, so that the protocol can be bridged to browsers and passed
+//! through HTTP proxies
+//!
+//! After the HTTP upgrade handshake, both ends send the usual protocol as binary messages, starting
+//! with the version preamble. Each end reads the messages it receives as one stream of bytes, so
+//! packets do not have to line up with messages, although this end sends each write as one message.
+use std::{
+    io::{self, prelude::*},
+    net::TcpStream,
+};
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use ring::digest;
+
+use crate::{codec::MAX_PACKET_SIZE, seed, transport::Transport};
+
+/// Added to the client's key by the server, to show that it understood the upgrade request
+const ACCEPT_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// The longest HTTP request or response header read during the handshake
+const MAX_HEADER_SIZE: usize = 8 * 1024;
+
+/// The largest message that will be received, which leaves room for a full packet and its header
+const MAX_MESSAGE_SIZE: u64 = MAX_PACKET_SIZE as u64 + 64;
+
+/// How much is read from the connection at a time
+const READ_SIZE: usize = 4096;
+
+const OPCODE_CONTINUATION: u8 = 0x0;
+const OPCODE_BINARY: u8 = 0x2;
+const OPCODE_CLOSE: u8 = 0x8;
+const OPCODE_PING: u8 = 0x9;
+const OPCODE_PONG: u8 = 0xa;
+
+pub struct WebSocket {
+    inner: Box<dyn Transport>,
+    /// Clients mask every frame they send, and servers never do
+    masked: bool,
+    /// Data received that does not make up a whole frame yet
+    received: Vec<u8>,
+    /// The data of the last message received, which has been read up to `position`
+    message: Vec<u8>,
+    position: usize,
+    /// Frames that have not been written to the connection yet
+    outgoing: Vec<u8>,
+    closed: bool,
+}
+
+impl WebSocket {
+    fn new(inner: Box<dyn Transport>, masked: bool) -> WebSocket {
+        WebSocket {
+            inner,
+            masked,
+            received: vec![],
+            message: vec![],
+            position: 0,
+            outgoing: vec![],
+            closed: false,
+        }
+    }
+
+    /// Queue a frame and send as much as can be sent without waiting
+    fn send_frame(&mut self, opcode: u8, payload: &[u8]) -> io::Result<()> {
+        self.outgoing.push(0x80 | opcode);
+        let mask_bit = if self.masked { 0x80 } else { 0 };
+        match payload.len() {
+            len @ 0..=125 => self.outgoing.push(mask_bit | len as u8),
+            len @ 126..=0xffff => {
+                self.outgoing.push(mask_bit | 126);
+                self.outgoing.extend((len as u16).to_be_bytes());
+            }
+            len => {
+                self.outgoing.push(mask_bit | 127);
+                self.outgoing.extend((len as u64).to_be_bytes());
+            }
+        }
+
+        if self.masked {
+            let mut mask = [0; 4];
+            mask.copy_from_slice(&seed::generate()[..4]);
+            self.outgoing.extend(mask);
+            self.outgoing.extend(payload.iter().enumerate().map(|(i, byte)| byte ^ mask[i % 4]));
+        }
+        else {
+            self.outgoing.extend(payload);
+        }
+
+        match self.send_queued() {
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(()),
+            result => result,
+        }
+    }
+
+    fn send_queued(&mut self) -> io::Result<()> {
+        while !self.outgoing.is_empty() {
+            match self.inner.write(&self.outgoing)? {
+                // The connection underneath has buffered all it can until the socket is writable
+                0 => return Err(io::ErrorKind::WouldBlock.into()),
+                len => {
+                    self.outgoing.drain(..len);
+                }
+            }
+        }
+        self.inner.flush()
+    }
+
+    /// Take the first whole frame from the data received, answering pings along the way. Returns
+    /// true once a frame with data has been taken, or the connection has been closed.
+    fn take_frame(&mut self) -> io::Result<bool> {
+        let (header, len) = match parse_frame(&self.received)? {
+            Some(frame) => frame,
+            None => return Ok(false),
+        };
+        let mut payload = self.received[header.len..header.len + len].to_vec();
+        payload.iter_mut().enumerate().for_each(|(i, byte)| *byte ^= header.mask[i % 4]);
+        self.received.drain(..header.len + len);
+
+        match header.opcode {
+            // Fragmented messages are read as one stream anyway, so each fragment is passed on as
+            // it arrives
+            OPCODE_BINARY | OPCODE_CONTINUATION => {
+                self.message = payload;
+                self.position = 0;
+                Ok(true)
+            }
+            OPCODE_CLOSE => {
+                let _ = self.send_frame(OPCODE_CLOSE, &payload[..payload.len().min(2)]);
+                self.closed = true;
+                Ok(true)
+            }
+            OPCODE_PING => self.send_frame(OPCODE_PONG, &payload).map(|_| false),
+            OPCODE_PONG => Ok(false),
+            _ => Err(invalid_data("unexpected WebSocket frame")),
+        }
+    }
+}
+
+/// The parts of a frame's header that are needed to read it
+struct FrameHeader {
+    opcode: u8,
+    mask: [u8; 4],
+    /// The size of the header itself
+    len: usize,
+}
+
+/// Parse the header of the frame at the start of `data`, returning it with the length of the
+/// frame's payload, or None if the whole frame has not been received yet
+fn parse_frame(data: &[u8]) -> io::Result<Option<(FrameHeader, usize)>> {
+    let (first, second) = match data {
+        [first, second, ..] => (*first, *second),
+        _ => return Ok(None),
+    };
+
+    let (len, mut header_len) = match second & 0x7f {
+        126 => match data.get(2..4) {
+            Some(len) => (u16::from_be_bytes(len.try_into().unwrap()) as u64, 4),
+            None => return Ok(None),
+        },
+        127 => match data.get(2..10) {
+            Some(len) => (u64::from_be_bytes(len.try_into().unwrap()), 10),
+            None => return Ok(None),
+        },
+        len => (len as u64, 2),
+    };
+    if len > MAX_MESSAGE_SIZE {
+        return Err(invalid_data("WebSocket message is too long"));
+    }
+
+    let mut mask = [0; 4];
+    if second & 0x80 != 0 {
+        match data.get(header_len..header_len + 4) {
+            Some(received) => mask.copy_from_slice(received),
+            None => return Ok(None),
+        }
+        header_len += 4;
+    }
+
+    if data.len() < header_len + len as usize {
+        return Ok(None);
+    }
+    Ok(Some((FrameHeader { opcode: first & 0x0f, mask, len: header_len }, len as usize)))
+}
+
+impl Transport for WebSocket {
+    fn tcp(&self) -> &TcpStream {
+        self.inner.tcp()
+    }
+
+    fn wants_write(&self) -> bool {
+        !self.outgoing.is_empty() || self.inner.wants_write()
+    }
+}
+
+impl Read for WebSocket {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut received = [0; READ_SIZE];
+        while self.position == self.message.len() {
+            if self.closed {
+                return Ok(0);
+            }
+            if self.take_frame()? {
+                continue;
+            }
+
+            match self.inner.read(&mut received)? {
+                0 => self.closed = true,
+                len => self.received.extend(&received[..len]),
+            }
+        }
+
+        let len = buf.len().min(self.message.len() - self.position);
+        buf[..len].copy_from_slice(&self.message[self.position..self.position + len]);
+        self.position += len;
+        Ok(len)
+    }
+}
+
+impl Write for WebSocket {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.send_frame(OPCODE_BINARY, buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.send_queued()
+    }
+}
+
+/// Upgrade a connection to a server, sending the same request as a browser would
+pub fn connect(mut transport: Box<dyn Transport>, host: &str, path: &str) -> io::Result<WebSocket> {
+    let key = BASE64.encode(seed::generate());
+
+    let request = format!(
+        "GET {} HTTP/1.1\r\nHost: {}\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+         Sec-WebSocket-Key: {}\r\nSec-WebSocket-Version: 13\r\n\r\n",
+        path, host, key
+    );
+    transport.write_all(request.as_bytes())?;
+
+    let response = read_header(&mut transport)?;
+    let status = response.split(' ').nth(1);
+    if status != Some("101") {
+        return Err(io::Error::other("the server refused to upgrade to a WebSocket"));
+    }
+    if header_value(&response, "sec-websocket-accept") != Some(&*accept_key(&key)) {
+        return Err(invalid_data("the server sent the wrong WebSocket accept key"));
+    }
+
+    Ok(WebSocket::new(transport, true))
+}
+
+/// Accept the upgrade request from a client that has just connected
+pub fn accept(mut transport: Box<dyn Transport>) -> io::Result<WebSocket> {
+    let request = read_header(&mut transport)?;
+    let key = header_value(&request, "sec-websocket-key")
+        .ok_or_else(|| invalid_data("not a WebSocket upgrade request"))?;
+
+    let response = format!(
+        "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+         Sec-WebSocket-Accept: {}\r\n\r\n",
+        accept_key(key)
+    );
+    transport.write_all(response.as_bytes())?;
+
+    Ok(WebSocket::new(transport, false))
+}
+
+/// Read an HTTP header up to the blank line that ends it. This is read one byte at a time, so that
+/// none of the messages after it are consumed.
+fn read_header(transport: &mut Box<dyn Transport>) -> io::Result<String> {
+    let mut header = vec![];
+    while !header.ends_with(b"\r\n\r\n") {
+        if header.len() == MAX_HEADER_SIZE {
+            return Err(invalid_data("HTTP header is too long"));
+        }
+        let mut byte = [0];
+        transport.read_exact(&mut byte)?;
+        header.push(byte[0]);
+    }
+    String::from_utf8(header).map_err(|_| invalid_data("HTTP header is not valid UTF-8"))
+}
+
+/// The value of a field in an HTTP header, with the name compared case insensitively
+fn header_value<'a>(header: &'a str, name: &str) -> Option<&'a str> {
+    header.lines().skip(1).find_map(|line| {
+        let (field, value) = line.split_once(':')?;
+        field.trim().eq_ignore_ascii_case(name).then(|| value.trim())
+    })
+}
+
+fn accept_key(key: &str) -> String {
+    let hash = digest::digest(
+        &digest::SHA1_FOR_LEGACY_USE_ONLY,
+        format!("{}{}", key, ACCEPT_GUID).as_bytes(),
+    );
+    BASE64.encode(hash)
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
@@ -0,0 +1,867 @@
+//! A compact binary encoding for values sent over the network
+//!
+//! Values are written in the order that their fields are declared, without any field names or
+//! type information, so both sides must agree on the types being sent. Integers wider than a byte
+//! are written as variable length integers (7 bits per byte, least significant first), which keeps
+//! ids, counters and lengths small. Strings, byte arrays and sequences are prefixed by their length,
+//! and enums by the index of their variant.
+//!
+//! Like the rest of the decoder, this must handle arbitrary input without panicking or allocating
+//! more memory than the size of the input.
+use serde::{
+    de::{self, DeserializeSeed, IntoDeserializer, Visitor},
+    ser::{self, Serialize},
+};
+
+use crate::{
+    codec,
+    error::{NetworkError, NetworkResult},
+};
+
+/// The most elements in a decoded sequence or map. Packets are at most `MAX_PACKET_SIZE` bytes, so
+/// only sequences of values that take no bytes at all, like `()`, could be longer.
+const MAX_LEN: u64 = codec::MAX_PACKET_SIZE as u64;
+
+/// Encode a value
+pub fn to_vec<T: Serialize>(value: &T) -> NetworkResult<Vec<u8>> {
+    let mut serializer = Serializer { output: Vec::new() };
+    value.serialize(&mut serializer)?;
+    Ok(serializer.output)
+}
+
+/// Decode a value, which must use all of the input
+pub fn from_slice<'de, T: de::Deserialize<'de>>(data: &'de [u8]) -> NetworkResult<T> {
+    let mut deserializer = Deserializer { input: data };
+    let value = T::deserialize(&mut deserializer)?;
+    if !deserializer.input.is_empty() {
+        return Err(NetworkError::DecodeError);
+    }
+    Ok(value)
+}
+
+impl ser::Error for NetworkError {
+    fn custom<T: std::fmt::Display>(_: T) -> NetworkError {
+        NetworkError::EncodeError
+    }
+}
+
+impl de::Error for NetworkError {
+    fn custom<T: std::fmt::Display>(_: T) -> NetworkError {
+        NetworkError::DecodeError
+    }
+}
+
+struct Serializer {
+    output: Vec<u8>,
+}
+
+impl Serializer {
+    fn write_varint(&mut self, mut value: u64) {
+        while value >= 0x80 {
+            self.output.push(value as u8 | 0x80);
+            value >>= 7;
+        }
+        self.output.push(value as u8);
+    }
+
+    fn write_signed(&mut self, value: i64) {
+        // Zigzag encoding, so that small negative numbers are also short
+        self.write_varint(((value << 1) ^ (value >> 63)) as u64);
+    }
+
+    fn write_len(&mut self, len: Option<usize>) -> NetworkResult<()> {
+        let len = len.ok_or(NetworkError::EncodeError)?;
+        self.write_varint(len as u64);
+        Ok(())
+    }
+}
+
+impl ser::Serializer for &mut Serializer {
+    type Ok = ();
+    type Error = NetworkError;
+
+    type SerializeSeq = Self;
+    type SerializeTuple = Self;
+    type SerializeTupleStruct = Self;
+    type SerializeTupleVariant = Self;
+    type SerializeMap = Self;
+    type SerializeStruct = Self;
+    type SerializeStructVariant = Self;
+
+    fn serialize_bool(self, v: bool) -> NetworkResult<()> {
+        self.output.push(v as u8);
+        Ok(())
+    }
+
+    fn serialize_i8(self, v: i8) -> NetworkResult<()> {
+        self.output.push(v as u8);
+        Ok(())
+    }
+
+    fn serialize_i16(self, v: i16) -> NetworkResult<()> {
+        self.write_signed(v.into());
+        Ok(())
+    }
+
+    fn serialize_i32(self, v: i32) -> NetworkResult<()> {
+        self.write_signed(v.into());
+        Ok(())
+    }
+
+    fn serialize_i64(self, v: i64) -> NetworkResult<()> {
+        self.write_signed(v);
+        Ok(())
+    }
+
+    fn serialize_u8(self, v: u8) -> NetworkResult<()> {
+        self.output.push(v);
+        Ok(())
+    }
+
+    fn serialize_u16(self, v: u16) -> NetworkResult<()> {
+        self.write_varint(v.into());
+        Ok(())
+    }
+
+    fn serialize_u32(self, v: u32) -> NetworkResult<()> {
+        self.write_varint(v.into());
+        Ok(())
+    }
+
+    fn serialize_u64(self, v: u64) -> NetworkResult<()> {
+        self.write_varint(v);
+        Ok(())
+    }
+
+    fn serialize_f32(self, v: f32) -> NetworkResult<()> {
+        self.output.extend_from_slice(&v.to_le_bytes());
+        Ok(())
+    }
+
+    fn serialize_f64(self, v: f64) -> NetworkResult<()> {
+        self.output.extend_from_slice(&v.to_le_bytes());
+        Ok(())
+    }
+
+    fn serialize_char(self, v: char) -> NetworkResult<()> {
+        self.serialize_str(v.encode_utf8(&mut [0; 4]))
+    }
+
+    fn serialize_str(self, v: &str) -> NetworkResult<()> {
+        self.serialize_bytes(v.as_bytes())
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> NetworkResult<()> {
+        self.write_varint(v.len() as u64);
+        self.output.extend_from_slice(v);
+        Ok(())
+    }
+
+    fn serialize_none(self) -> NetworkResult<()> {
+        self.output.push(0);
+        Ok(())
+    }
+
+    fn serialize_some<T: ?Sized + Serialize>(self, value: &T) -> NetworkResult<()> {
+        self.output.push(1);
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> NetworkResult<()> {
+        Ok(())
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> NetworkResult<()> {
+        Ok(())
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        variant_index: u32,
+        _variant: &'static str,
+    ) -> NetworkResult<()> {
+        self.write_varint(variant_index.into());
+        Ok(())
+    }
+
+    fn serialize_newtype_struct<T: ?Sized + Serialize>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> NetworkResult<()> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: ?Sized + Serialize>(
+        self,
+        _name: &'static str,
+        variant_index: u32,
+        _variant: &'static str,
+        value: &T,
+    ) -> NetworkResult<()> {
+        self.write_varint(variant_index.into());
+        value.serialize(self)
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> NetworkResult<Self> {
+        self.write_len(len)?;
+        Ok(self)
+    }
+
+    fn serialize_tuple(self, _len: usize) -> NetworkResult<Self> {
+        Ok(self)
+    }
+
+    fn serialize_tuple_struct(self, _name: &'static str, _len: usize) -> NetworkResult<Self> {
+        Ok(self)
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> NetworkResult<Self> {
+        self.write_varint(variant_index.into());
+        Ok(self)
+    }
+
+    fn serialize_map(self, len: Option<usize>) -> NetworkResult<Self> {
+        self.write_len(len)?;
+        Ok(self)
+    }
+
+    fn serialize_struct(self, _name: &'static str, _len: usize) -> NetworkResult<Self> {
+        Ok(self)
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> NetworkResult<Self> {
+        self.write_varint(variant_index.into());
+        Ok(self)
+    }
+
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+}
+
+impl ser::SerializeSeq for &mut Serializer {
+    type Ok = ();
+    type Error = NetworkError;
+
+    fn serialize_element<T: ?Sized + Serialize>(&mut self, value: &T) -> NetworkResult<()> {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> NetworkResult<()> {
+        Ok(())
+    }
+}
+
+impl ser::SerializeTuple for &mut Serializer {
+    type Ok = ();
+    type Error = NetworkError;
+
+    fn serialize_element<T: ?Sized + Serialize>(&mut self, value: &T) -> NetworkResult<()> {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> NetworkResult<()> {
+        Ok(())
+    }
+}
+
+impl ser::SerializeTupleStruct for &mut Serializer {
+    type Ok = ();
+    type Error = NetworkError;
+
+    fn serialize_field<T: ?Sized + Serialize>(&mut self, value: &T) -> NetworkResult<()> {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> NetworkResult<()> {
+        Ok(())
+    }
+}
+
+impl ser::SerializeTupleVariant for &mut Serializer {
+    type Ok = ();
+    type Error = NetworkError;
+
+    fn serialize_field<T: ?Sized + Serialize>(&mut self, value: &T) -> NetworkResult<()> {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> NetworkResult<()> {
+        Ok(())
+    }
+}
+
+impl ser::SerializeMap for &mut Serializer {
+    type Ok = ();
+    type Error = NetworkError;
+
+    fn serialize_key<T: ?Sized + Serialize>(&mut self, key: &T) -> NetworkResult<()> {
+        key.serialize(&mut **self)
+    }
+
+    fn serialize_value<T: ?Sized + Serialize>(&mut self, value: &T) -> NetworkResult<()> {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> NetworkResult<()> {
+        Ok(())
+    }
+}
+
+impl ser::SerializeStruct for &mut Serializer {
+    type Ok = ();
+    type Error = NetworkError;
+
+    fn serialize_field<T: ?Sized + Serialize>(
+        &mut self,
+        _key: &'static str,
+        value: &T,
+    ) -> NetworkResult<()> {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> NetworkResult<()> {
+        Ok(())
+    }
+}
+
+impl ser::SerializeStructVariant for &mut Serializer {
+    type Ok = ();
+    type Error = NetworkError;
+
+    fn serialize_field<T: ?Sized + Serialize>(
+        &mut self,
+        _key: &'static str,
+        value: &T,
+    ) -> NetworkResult<()> {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> NetworkResult<()> {
+        Ok(())
+    }
+}
+
+struct Deserializer<'de> {
+    input: &'de [u8],
+}
+
+impl<'de> Deserializer<'de> {
+    fn read_bytes(&mut self, len: usize) -> NetworkResult<&'de [u8]> {
+        if len > self.input.len() {
+            return Err(NetworkError::DecodeError);
+        }
+        let (bytes, rest) = self.input.split_at(len);
+        self.input = rest;
+        Ok(bytes)
+    }
+
+    fn read_u8(&mut self) -> NetworkResult<u8> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    fn read_varint(&mut self) -> NetworkResult<u64> {
+        let mut value = 0;
+        for shift in (0..64).step_by(7) {
+            let byte = self.read_u8()?;
+            let bits = u64::from(byte & 0x7f);
+            // Reject values that do not fit in 64 bits
+            if shift == 63 && bits > 1 {
+                return Err(NetworkError::DecodeError);
+            }
+            value |= bits << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(NetworkError::DecodeError)
+    }
+
+    fn read_signed(&mut self) -> NetworkResult<i64> {
+        let value = self.read_varint()?;
+        Ok((value >> 1) as i64 ^ -((value & 1) as i64))
+    }
+
+    /// Read the length of a sequence or map. Elements can take no bytes at all, so this is not
+    /// checked against the rest of the input, which instead limits how much is reserved for them
+    /// (see `Elements::size_hint`).
+    fn read_len(&mut self) -> NetworkResult<usize> {
+        let len = self.read_varint()?;
+        if len > MAX_LEN {
+            return Err(NetworkError::DecodeError);
+        }
+        Ok(len as usize)
+    }
+
+    fn read_slice(&mut self) -> NetworkResult<&'de [u8]> {
+        let len = self.read_len()?;
+        self.read_bytes(len)
+    }
+
+    fn read_str(&mut self) -> NetworkResult<&'de str> {
+        std::str::from_utf8(self.read_slice()?).map_err(|_| NetworkError::DecodeError)
+    }
+
+    fn read_array<const N: usize>(&mut self) -> NetworkResult<[u8; N]> {
+        Ok(self.read_bytes(N)?.try_into().unwrap())
+    }
+}
+
+/// Convert a decoded integer to a narrower type
+fn narrow<T: TryFrom<W>, W>(value: W) -> NetworkResult<T> {
+    T::try_from(value).map_err(|_| NetworkError::DecodeError)
+}
+
+impl<'de> de::Deserializer<'de> for &mut Deserializer<'de> {
+    type Error = NetworkError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> NetworkResult<V::Value> {
+        // The encoding does not describe its own types
+        Err(NetworkError::DecodeError)
+    }
+
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> NetworkResult<V::Value> {
+        match self.read_u8()? {
+            0 => visitor.visit_bool(false),
+            1 => visitor.visit_bool(true),
+            _ => Err(NetworkError::DecodeError),
+        }
+    }
+
+    fn deserialize_i8<V: Visitor<'de>>(self, visitor: V) -> NetworkResult<V::Value> {
+        visitor.visit_i8(self.read_u8()? as i8)
+    }
+
+    fn deserialize_i16<V: Visitor<'de>>(self, visitor: V) -> NetworkResult<V::Value> {
+        visitor.visit_i16(narrow(self.read_signed()?)?)
+    }
+
+    fn deserialize_i32<V: Visitor<'de>>(self, visitor: V) -> NetworkResult<V::Value> {
+        visitor.visit_i32(narrow(self.read_signed()?)?)
+    }
+
+    fn deserialize_i64<V: Visitor<'de>>(self, visitor: V) -> NetworkResult<V::Value> {
+        visitor.visit_i64(self.read_signed()?)
+    }
+
+    fn deserialize_u8<V: Visitor<'de>>(self, visitor: V) -> NetworkResult<V::Value> {
+        visitor.visit_u8(self.read_u8()?)
+    }
+
+    fn deserialize_u16<V: Visitor<'de>>(self, visitor: V) -> NetworkResult<V::Value> {
+        visitor.visit_u16(narrow(self.read_varint()?)?)
+    }
+
+    fn deserialize_u32<V: Visitor<'de>>(self, visitor: V) -> NetworkResult<V::Value> {
+        visitor.visit_u32(narrow(self.read_varint()?)?)
+    }
+
+    fn deserialize_u64<V: Visitor<'de>>(self, visitor: V) -> NetworkResult<V::Value> {
+        visitor.visit_u64(self.read_varint()?)
+    }
+
+    fn deserialize_f32<V: Visitor<'de>>(self, visitor: V) -> NetworkResult<V::Value> {
+        visitor.visit_f32(f32::from_le_bytes(self.read_array()?))
+    }
+
+    fn deserialize_f64<V: Visitor<'de>>(self, visitor: V) -> NetworkResult<V::Value> {
+        visitor.visit_f64(f64::from_le_bytes(self.read_array()?))
+    }
+
+    fn deserialize_char<V: Visitor<'de>>(self, visitor: V) -> NetworkResult<V::Value> {
+        let mut chars = self.read_str()?.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) => visitor.visit_char(c),
+            _ => Err(NetworkError::DecodeError),
+        }
+    }
+
+    fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> NetworkResult<V::Value> {
+        visitor.visit_borrowed_str(self.read_str()?)
+    }
+
+    fn deserialize_string<V: Visitor<'de>>(self, visitor: V) -> NetworkResult<V::Value> {
+        self.deserialize_str(visitor)
+    }
+
+    fn deserialize_bytes<V: Visitor<'de>>(self, visitor: V) -> NetworkResult<V::Value> {
+        visitor.visit_borrowed_bytes(self.read_slice()?)
+    }
+
+    fn deserialize_byte_buf<V: Visitor<'de>>(self, visitor: V) -> NetworkResult<V::Value> {
+        self.deserialize_bytes(visitor)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> NetworkResult<V::Value> {
+        match self.read_u8()? {
+            0 => visitor.visit_none(),
+            1 => visitor.visit_some(self),
+            _ => Err(NetworkError::DecodeError),
+        }
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> NetworkResult<V::Value> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> NetworkResult<V::Value> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> NetworkResult<V::Value> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> NetworkResult<V::Value> {
+        let len = self.read_len()?;
+        visitor.visit_seq(Elements { deserializer: self, remaining: len })
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(self, len: usize, visitor: V) -> NetworkResult<V::Value> {
+        visitor.visit_seq(Elements { deserializer: self, remaining: len })
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        len: usize,
+        visitor: V,
+    ) -> NetworkResult<V::Value> {
+        self.deserialize_tuple(len, visitor)
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> NetworkResult<V::Value> {
+        let len = self.read_len()?;
+        visitor.visit_map(Elements { deserializer: self, remaining: len })
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> NetworkResult<V::Value> {
+        self.deserialize_tuple(fields.len(), visitor)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> NetworkResult<V::Value> {
+        visitor.visit_enum(self)
+    }
+
+    fn deserialize_identifier<V: Visitor<'de>>(self, _visitor: V) -> NetworkResult<V::Value> {
+        Err(NetworkError::DecodeError)
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, _visitor: V) -> NetworkResult<V::Value> {
+        Err(NetworkError::DecodeError)
+    }
+
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+}
+
+/// The elements of a sequence, tuple, struct or map
+struct Elements<'a, 'de> {
+    deserializer: &'a mut Deserializer<'de>,
+    remaining: usize,
+}
+
+impl<'a, 'de> de::SeqAccess<'de> for Elements<'a, 'de> {
+    type Error = NetworkError;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> NetworkResult<Option<T::Value>> {
+        if self.remaining == 0 {
+            return Ok(None);
+        }
+        self.remaining -= 1;
+        seed.deserialize(&mut *self.deserializer).map(Some)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.remaining.min(self.deserializer.input.len()))
+    }
+}
+
+impl<'a, 'de> de::MapAccess<'de> for Elements<'a, 'de> {
+    type Error = NetworkError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> NetworkResult<Option<K::Value>> {
+        if self.remaining == 0 {
+            return Ok(None);
+        }
+        self.remaining -= 1;
+        seed.deserialize(&mut *self.deserializer).map(Some)
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> NetworkResult<V::Value> {
+        seed.deserialize(&mut *self.deserializer)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.remaining.min(self.deserializer.input.len()))
+    }
+}
+
+impl<'de> de::EnumAccess<'de> for &mut Deserializer<'de> {
+    type Error = NetworkError;
+    type Variant = Self;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(self, seed: V) -> NetworkResult<(V::Value, Self)> {
+        let index: u32 = narrow(self.read_varint()?)?;
+        let deserializer: de::value::U32Deserializer<NetworkError> = index.into_deserializer();
+        let variant = seed.deserialize(deserializer)?;
+        Ok((variant, self))
+    }
+}
+
+impl<'de> de::VariantAccess<'de> for &mut Deserializer<'de> {
+    type Error = NetworkError;
+
+    fn unit_variant(self) -> NetworkResult<()> {
+        Ok(())
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, seed: T) -> NetworkResult<T::Value> {
+        seed.deserialize(self)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, len: usize, visitor: V) -> NetworkResult<V::Value> {
+        de::Deserializer::deserialize_tuple(self, len, visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> NetworkResult<V::Value> {
+        de::Deserializer::deserialize_tuple(self, fields.len(), visitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, SocketAddr};
+
+    use interface::{
+        data::{BattleSnapshot, BattlerData, MovementData, PlayerData},
+        relay::{ItemRule, RelayAction},
+        values::Direction,
+        visibility::Visibility,
+    };
+
+    use super::*;
+    use crate::{
+        peer::DirectMessage, shared::SharedSeed, DayCycle, Feature, NetworkEvent, RateLimits,
+        SafariStatus, ShopItem, TradeListing, UsageReport,
+    };
+
+    fn movement() -> MovementData {
+        MovementData {
+            map_id: 1,
+            map_x: 200,
+            map_y: 3,
+            direction: Direction::Left,
+            walk_counter: 4,
+        }
+    }
+
+    fn player() -> PlayerData {
+        PlayerData { name: vec![0x80; 7], sprite: vec![0xAA; 300], movement_data: movement() }
+    }
+
+    fn battler() -> BattlerData {
+        BattlerData { species: 0x99, level: 100, hp: 300, max_hp: 999 }
+    }
+
+    /// One of each event, with values that take more than one byte where they can
+    fn every_event() -> Vec<NetworkEvent> {
+        let snapshot = BattleSnapshot { player: battler(), enemy: battler() };
+        let listing = TradeListing { player: 70000, species: 1, level: 5, wanted: 0 };
+        let addr = SocketAddr::from((Ipv4Addr::new(192, 168, 1, 20), 8080));
+        vec![
+            NetworkEvent::FullUpdate(1, u32::MAX, player()),
+            NetworkEvent::MovementUpdate(2, 300, movement()),
+            NetworkEvent::UpdateRequest,
+            NetworkEvent::ResyncRequest(3),
+            NetworkEvent::PlayerSnapshot(vec![(4, 5, player()), (6, 7, player())]),
+            NetworkEvent::PlayerJoin(8),
+            NetworkEvent::MovementChannel(u64::MAX),
+            NetworkEvent::PlayerQuit(9),
+            NetworkEvent::Observe(10),
+            NetworkEvent::LiteMode(11, true),
+            NetworkEvent::Chat(12, 13, "Hello, world!".to_string()),
+            NetworkEvent::ChatAck(14),
+            NetworkEvent::BattleDataRequest(15, 16),
+            NetworkEvent::BattleDataResponse(17, 18, vec![0x55; 0x194]),
+            NetworkEvent::BattleRequestCancel(19, 20),
+            NetworkEvent::BattleRequestRefused(21, 22),
+            NetworkEvent::BattleSeedCommit(23, 24, [0xC3; 32]),
+            NetworkEvent::BattleSeed(25, 26, [0x3C; 16]),
+            NetworkEvent::BattleSeedReveal(27, 28, [0xFF; 16]),
+            NetworkEvent::RelayBattleData(29, 30, vec![1, 2, 3]),
+            NetworkEvent::BattleAction(31, 32, RelayAction::Switch(2)),
+            NetworkEvent::BattleRules(33, 34, ItemRule::Forfeit),
+            NetworkEvent::BattleItemUsed(35, 36),
+            NetworkEvent::BattleKeepAlive(37, 38),
+            NetworkEvent::BattleForfeit(39, 40),
+            NetworkEvent::PeerAddress(41, 42, addr, 0x0123_4567_89AB_CDEF),
+            NetworkEvent::PeerHello(43, 44),
+            NetworkEvent::PeerPacket(45, 46, 47, DirectMessage::Action(RelayAction::Move(0xA5))),
+            NetworkEvent::PeerAck(48, 49, 50),
+            NetworkEvent::HallOfFame(51, 50),
+            NetworkEvent::WildEncounter(52, 0x15, 70),
+            NetworkEvent::SpectateRequest(53, 54),
+            NetworkEvent::SpectateStop(55, 56),
+            NetworkEvent::BattleSnapshot(57, 58, snapshot),
+            NetworkEvent::SpectateEnd(59, 60),
+            NetworkEvent::BattleChat(61, 62, 63, "GG".to_string()),
+            NetworkEvent::SafariStart(64),
+            NetworkEvent::SafariCatch(65, 0x10),
+            NetworkEvent::SafariUpdate(SafariStatus {
+                remaining_secs: 600,
+                leaderboard: vec![(66, 3), (67, 1)],
+            }),
+            NetworkEvent::RateLimits(RateLimits { min_movement_rate: 10, min_receive_rate: 20 }),
+            NetworkEvent::SharedSeed(SharedSeed { day: 20000, seed: u64::MAX - 1 }),
+            NetworkEvent::TradeListingPost(68, 1, 5, 4),
+            NetworkEvent::TradeListingRemove(69),
+            NetworkEvent::TradeBoardRequest(70),
+            NetworkEvent::TradeBoard(vec![listing, listing]),
+            NetworkEvent::TradeOffer(71, 72, 0x99, 30),
+            NetworkEvent::Ping(73, 123_456_789),
+            NetworkEvent::Pong(987_654_321),
+            NetworkEvent::ShopCatalog(vec![ShopItem { item: 4, price: 200 }]),
+            NetworkEvent::ShopPurchase(74, 4, 99),
+            NetworkEvent::DayCycle(DayCycle { server_time: 1_700_000_000, length_secs: 3600 }),
+            NetworkEvent::PlayerPaused(75, false),
+            NetworkEvent::BattleQuickChat(76, 77, 3),
+            NetworkEvent::AwayMessage(78, 79, "Back soon".to_string()),
+            NetworkEvent::Visibility(Visibility::Friends),
+            NetworkEvent::Motd("Welcome!".to_string()),
+            NetworkEvent::UsageReport(UsageReport {
+                session_secs: 5000,
+                features: vec![(Feature::Chat, 2), (Feature::UpdateDefinitions, 1)],
+                crash: Some("src/main.rs:1".to_string()),
+            }),
+            NetworkEvent::ServerFailure,
+        ]
+    }
+
+    #[test]
+    fn every_event_round_trips() {
+        for event in every_event() {
+            let encoded = to_vec(&event).unwrap();
+            let decoded: NetworkEvent = from_slice(&encoded).unwrap();
+            assert_eq!(decoded.name(), event.name());
+            // Events can not be compared, but anything lost or changed would encode differently
+            assert_eq!(to_vec(&decoded).unwrap(), encoded, "{:?}", event);
+        }
+    }
+
+    #[test]
+    fn every_variant_is_tested() {
+        let events = every_event();
+        let mut indices: Vec<u8> = events.iter().map(|event| to_vec(event).unwrap()[0]).collect();
+        indices.sort_unstable();
+        indices.dedup();
+        assert_eq!(indices.len(), events.len());
+        assert!(indices.iter().all(|&index| (index as usize) < events.len()));
+        // The variant after the last one tested does not exist
+        assert!(from_slice::<NetworkEvent>(&[events.len() as u8]).is_err());
+    }
+
+    #[test]
+    fn truncated_events_are_rejected() {
+        for event in every_event() {
+            let encoded = to_vec(&event).unwrap();
+            for len in 0..encoded.len() {
+                assert!(from_slice::<NetworkEvent>(&encoded[..len]).is_err(), "{:?}", event);
+            }
+        }
+    }
+
+    #[test]
+    fn trailing_bytes_are_rejected() {
+        let mut encoded = to_vec(&NetworkEvent::PlayerJoin(1)).unwrap();
+        encoded.push(0);
+        assert!(from_slice::<NetworkEvent>(&encoded).is_err());
+    }
+
+    #[test]
+    fn lengths_past_the_input_are_rejected() {
+        // A chat message that claims to be longer than the rest of the packet
+        let mut encoded = to_vec(&NetworkEvent::Chat(1, 2, "Hi".to_string())).unwrap();
+        let len_at = encoded.len() - 3;
+        encoded[len_at] = 3;
+        assert!(from_slice::<NetworkEvent>(&encoded).is_err());
+
+        // A snapshot with more players than it holds
+        let mut encoded = to_vec(&NetworkEvent::PlayerSnapshot(vec![(1, 2, player())])).unwrap();
+        encoded[1] = 2;
+        assert!(from_slice::<NetworkEvent>(&encoded).is_err());
+    }
+
+    #[test]
+    fn oversized_values_are_rejected() {
+        // Lengths longer than any packet
+        let mut encoded = vec![4];
+        encoded.extend(to_vec(&(MAX_LEN + 1)).unwrap());
+        assert!(from_slice::<NetworkEvent>(&encoded).is_err());
+        assert!(from_slice::<Vec<()>>(&to_vec(&(MAX_LEN + 1)).unwrap()).is_err());
+
+        // Integers too wide for their type
+        assert!(from_slice::<u16>(&to_vec(&0x10000u32).unwrap()).is_err());
+        assert!(from_slice::<u32>(&to_vec(&u64::MAX).unwrap()).is_err());
+        assert!(from_slice::<u64>(&[0xFF; 10]).is_err());
+        assert!(from_slice::<u64>(&[0x80; 11]).is_err());
+    }
+
+    #[test]
+    fn invalid_tags_are_rejected() {
+        assert!(from_slice::<bool>(&[2]).is_err());
+        assert!(from_slice::<Option<u8>>(&[2, 0]).is_err());
+        assert!(from_slice::<String>(&[2, 0xC3, 0x28]).is_err());
+    }
+
+    #[test]
+    fn zero_sized_elements_are_decoded() {
+        let units = vec![(); 1000];
+        let encoded = to_vec(&units).unwrap();
+        assert_eq!(encoded.len(), 2);
+        assert_eq!(from_slice::<Vec<()>>(&encoded).unwrap(), units);
+    }
+}
@@ -0,0 +1,1068 @@
+//! Compatibility tests for Pikemon servers.
+//!
+//! Connects clients to a running server using the same network stack as the game client, and checks
+//! that the server correctly relays join, movement, chat, battle and quit events between them. This
+//! makes it possible to catch protocol regressions, and to check that alternate server
+//! implementations are compatible with the client.
+//!
+//! Usage: `protocol_test [address]`, where the address defaults to `localhost:8080`.
+use std::{
+    io::{prelude::*, BufReader},
+    net::{Shutdown, TcpStream},
+    process, thread,
+    time::{Duration, Instant},
+};
+
+use crossbeam_channel::{Receiver, RecvTimeoutError};
+use interface::{
+    data::{BattleSnapshot, BattlerData, MovementData, PlayerData},
+    relay::{ItemRule, RelayAction},
+    text,
+    values::Direction,
+};
+use network_common::{
+    account::{self, Login, LoginError, LoginKind},
+    client::{self, Latency, NetworkManager},
+    codec::{self, Compression},
+    game::{self, GameInfo},
+    outbox::CHANNEL_CAPACITY,
+    peer::DirectMessage,
+    poll, seed,
+    shared::{self, SharedSeed},
+    simulator::NetworkConditions,
+    stats::Traffic,
+    version, Feature, NetworkEvent, PlayerId, UsageReport,
+};
+
+/// How long to wait for an expected event before failing a test
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long to wait when checking that an event is *not* received
+const QUIET_PERIOD: Duration = Duration::from_millis(250);
+
+type TestResult = Result<(), String>;
+
+/// A named test, run against the address of the server
+type Test = (&'static str, fn(&str) -> TestResult);
+
+struct TestClient {
+    id: PlayerId,
+    /// The token that the server gave for the account that was logged in to, if any
+    token: Option<String>,
+    socket: TcpStream,
+    latency: Latency,
+    sender: poll::Sender<NetworkEvent>,
+    receiver: Receiver<NetworkEvent>,
+}
+
+impl TestClient {
+    fn connect(addr: &str) -> Result<TestClient, String> {
+        TestClient::connect_with(addr, Compression::Deflate, test_game())
+    }
+
+    fn connect_with(
+        addr: &str,
+        compression: Compression,
+        game: GameInfo,
+    ) -> Result<TestClient, String> {
+        TestClient::join(addr, compression, game, None)
+    }
+
+    fn connect_as(addr: &str, login: Login) -> Result<TestClient, String> {
+        TestClient::join(addr, Compression::Deflate, test_game(), Some(login))
+    }
+
+    fn join(
+        addr: &str,
+        compression: Compression,
+        game: GameInfo,
+        login: Option<Login>,
+    ) -> Result<TestClient, String> {
+        let socket = TcpStream::connect(addr)
+            .map_err(|e| format!("failed to connect to {}: {}", addr, e))?;
+
+        let (local_update_sender, local_update_receiver) =
+            poll::bounded(CHANNEL_CAPACITY).map_err(|e| e.to_string())?;
+        let (global_update_sender, global_update_receiver) =
+            crossbeam_channel::bounded(CHANNEL_CAPACITY);
+
+        let latency = Latency::default();
+        let network_manager = NetworkManager {
+            socket: Box::new(socket.try_clone().map_err(|e| e.to_string())?),
+            local_update_receiver,
+            global_update_sender,
+            compression,
+            game,
+            login,
+            latency: latency.clone(),
+            traffic: Traffic::default(),
+            simulate: NetworkConditions::default(),
+        };
+        let joined = client::handle_network(network_manager)
+            .map_err(|e| format!("join handshake failed: {}", e))?;
+
+        Ok(TestClient {
+            id: joined.player_id,
+            token: joined.token,
+            socket,
+            latency,
+            sender: local_update_sender,
+            receiver: global_update_receiver,
+        })
+    }
+
+    fn send(&self, event: NetworkEvent) -> TestResult {
+        self.sender.send(event).map_err(|_| "network thread has stopped".to_string())
+    }
+
+    /// Wait for an event matching `predicate`, ignoring any other events that are received first
+    fn expect<F>(&self, description: &str, predicate: F) -> Result<NetworkEvent, String>
+    where
+        F: Fn(&NetworkEvent) -> bool,
+    {
+        let deadline = Instant::now() + TIMEOUT;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            match self.receiver.recv_timeout(remaining) {
+                Ok(event) if predicate(&event) => return Ok(event),
+                Ok(_) => {}
+                Err(RecvTimeoutError::Timeout) => {
+                    return Err(format!("player {} did not receive {}", self.id, description))
+                }
+                Err(RecvTimeoutError::Disconnected) => {
+                    return Err(format!("player {} was disconnected", self.id))
+                }
+            }
+        }
+    }
+
+    /// Check that no event matching `predicate` is received for a short period of time
+    fn expect_none<F>(&self, description: &str, predicate: F) -> TestResult
+    where
+        F: Fn(&NetworkEvent) -> bool,
+    {
+        let deadline = Instant::now() + QUIET_PERIOD;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            match self.receiver.recv_timeout(remaining) {
+                Ok(event) if predicate(&event) => {
+                    return Err(format!("player {} unexpectedly received {}", self.id, description))
+                }
+                Ok(_) => {}
+                Err(_) => return Ok(()),
+            }
+        }
+    }
+}
+
+impl Drop for TestClient {
+    fn drop(&mut self) {
+        let _ = self.socket.shutdown(Shutdown::Both);
+    }
+}
+
+/// The game that test clients claim to be playing, which servers should allow by default
+fn test_game() -> GameInfo {
+    GameInfo { title: "POKEMON RED".to_string(), revision: 0 }
+}
+
+fn test_player(name: &str) -> PlayerData {
+    PlayerData {
+        name: text::Encoder::new(name).collect(),
+        sprite: vec![0; 16 * 16 * 6],
+        movement_data: MovementData {
+            map_id: 0,
+            map_x: 5,
+            map_y: 6,
+            direction: Direction::Down,
+            walk_counter: 0,
+        },
+    }
+}
+
+fn test_version(addr: &str) -> TestResult {
+    let mut socket = TcpStream::connect(addr).map_err(|e| e.to_string())?;
+    socket.set_read_timeout(Some(TIMEOUT)).map_err(|e| e.to_string())?;
+    match version::read_preamble(&mut socket) {
+        Ok(version::PROTOCOL_VERSION) => {}
+        Ok(version) => return Err(format!("the server uses protocol version {}", version)),
+        Err(e) => return Err(format!("the server did not send its version: {}", e)),
+    }
+    if let Err(e) = codec::read_compression(&mut socket) {
+        return Err(format!("the server did not send its compression: {}", e));
+    }
+
+    // Clients using another version are disconnected before they join
+    let mut preamble = version::MAGIC.to_vec();
+    preamble.extend((version::PROTOCOL_VERSION + 1).to_le_bytes());
+    socket.write_all(&preamble).map_err(|e| e.to_string())?;
+    match codec::read_packet(&mut BufReader::new(socket)) {
+        Ok(None) | Err(_) => Ok(()),
+        Ok(Some(_)) => {
+            Err("a client with another protocol version was allowed to join".to_string())
+        }
+    }
+}
+
+fn test_join(addr: &str) -> TestResult {
+    let a = TestClient::connect(addr)?;
+    let b = TestClient::connect(addr)?;
+    if a.id == b.id {
+        return Err(format!("both players were assigned the same id: {}", a.id));
+    }
+
+    // New players are told the slowest update rates that the server allows
+    b.expect("rate limits", |e| matches!(e, NetworkEvent::RateLimits(_)))?;
+
+    // Every player is given the same seed for today's shared effects
+    let today = shared::current_day();
+    let seed = |client: &TestClient| -> Result<SharedSeed, String> {
+        let event = client.expect(
+            "the shared seed",
+            |e| matches!(e, NetworkEvent::SharedSeed(seed) if seed.day == today),
+        )?;
+        match event {
+            NetworkEvent::SharedSeed(seed) => Ok(seed),
+            _ => unreachable!(),
+        }
+    };
+    if seed(&a)? != seed(&b)? {
+        return Err("players were given different shared seeds".to_string());
+    }
+
+    // Existing players are asked to send their state to the new player
+    a.expect("an update request", |e| matches!(e, NetworkEvent::UpdateRequest))?;
+    Ok(())
+}
+
+fn test_movement(addr: &str) -> TestResult {
+    let a = TestClient::connect(addr)?;
+    let b = TestClient::connect(addr)?;
+
+    let mut player = test_player("RED");
+    a.send(NetworkEvent::FullUpdate(a.id, 0, player.clone()))?;
+    b.expect("a full update", |e| match e {
+        NetworkEvent::FullUpdate(id, 0, data) => *id == a.id && *data == player,
+        _ => false,
+    })?;
+
+    player.movement_data.direction = Direction::Right;
+    player.movement_data.walk_counter = 8;
+    let movement = player.movement_data;
+    a.send(NetworkEvent::MovementUpdate(a.id, 1, movement))?;
+    b.expect("a movement update", |e| match e {
+        NetworkEvent::MovementUpdate(id, 1, data) => *id == a.id && *data == movement,
+        _ => false,
+    })?;
+
+    // Once both players have sent movement, servers that support UDP send it to them that way
+    b.send(NetworkEvent::MovementUpdate(b.id, 0, movement))?;
+    a.expect("a movement update in reply", |e| match e {
+        NetworkEvent::MovementUpdate(id, 0, data) => *id == b.id && *data == movement,
+        _ => false,
+    })?;
+    a.send(NetworkEvent::MovementUpdate(a.id, 2, movement))?;
+    b.expect("a second movement update", |e| match e {
+        NetworkEvent::MovementUpdate(id, 2, data) => *id == a.id && *data == movement,
+        _ => false,
+    })?;
+    Ok(())
+}
+
+fn test_movement_checks(addr: &str) -> TestResult {
+    let a = TestClient::connect(addr)?;
+    let b = TestClient::connect(addr)?;
+
+    let player = test_player("RED");
+    a.send(NetworkEvent::FullUpdate(a.id, 0, player.clone()))?;
+    b.expect("a full update", |e| matches!(e, NetworkEvent::FullUpdate(id, ..) if *id == a.id))?;
+
+    // Jumping across the map is only shown as far as the player could have walked
+    let start = player.movement_data;
+    let teleport = MovementData { map_x: 200, ..start };
+    a.send(NetworkEvent::MovementUpdate(a.id, 1, teleport))?;
+    b.expect("a shortened movement update", |e| match e {
+        NetworkEvent::MovementUpdate(id, 1, data) => {
+            *id == a.id && data.map_x > start.map_x && data.map_x < teleport.map_x
+        }
+        _ => false,
+    })?;
+
+    // Maps that are not in the game are not shown at all
+    let unknown_map = MovementData { map_id: 0xFF, ..start };
+    a.send(NetworkEvent::MovementUpdate(a.id, 2, unknown_map))?;
+    b.expect_none("movement to a map that is not in the game", |e| match e {
+        NetworkEvent::MovementUpdate(id, 2, _) => *id == a.id,
+        _ => false,
+    })?;
+
+    // Coming out of privacy mode straight away can not go far from where the player was hidden
+    a.send(NetworkEvent::MovementUpdate(a.id, 3, MovementData::hidden(start.map_id)))?;
+    let unhidden = MovementData { map_x: 250, map_y: 250, ..start };
+    a.send(NetworkEvent::MovementUpdate(a.id, 4, unhidden))?;
+    b.expect("a shortened movement update out of privacy mode", |e| match e {
+        NetworkEvent::MovementUpdate(id, 4, data) => *id == a.id && data.map_x < unhidden.map_x,
+        _ => false,
+    })?;
+
+    // Only a few map changes can be made in quick succession
+    for seq in 5..11 {
+        thread::sleep(Duration::from_millis(60));
+        let map_change = MovementData { map_id: (seq % 2) as u8, ..start };
+        a.send(NetworkEvent::MovementUpdate(a.id, seq, map_change))?;
+    }
+    b.expect("the first map changes", |e| match e {
+        NetworkEvent::MovementUpdate(id, 8, _) => *id == a.id,
+        _ => false,
+    })?;
+    // The last update is back on the map that the player was last shown on, so it is not a change
+    b.expect_none("too many map changes", |e| match e {
+        NetworkEvent::MovementUpdate(id, 9, _) => *id == a.id,
+        _ => false,
+    })
+}
+
+fn test_resync(addr: &str) -> TestResult {
+    let a = TestClient::connect(addr)?;
+    let b = TestClient::connect(addr)?;
+
+    let mut player = test_player("BLUE");
+    a.send(NetworkEvent::FullUpdate(a.id, 0, player.clone()))?;
+    player.movement_data.walk_counter = 8;
+    a.send(NetworkEvent::MovementUpdate(a.id, 1, player.movement_data))?;
+    b.expect("a movement update", |e| matches!(e, NetworkEvent::MovementUpdate(_, 1, _)))?;
+
+    // The snapshot has the newest movement applied to the last full update
+    b.send(NetworkEvent::ResyncRequest(b.id))?;
+    b.expect("a player snapshot", |e| match e {
+        NetworkEvent::PlayerSnapshot(players) => {
+            players.iter().any(|(id, seq, data)| *id == a.id && *seq == 1 && *data == player)
+        }
+        _ => false,
+    })?;
+
+    // Snapshots can be large, so asking again straight away is ignored
+    b.send(NetworkEvent::ResyncRequest(b.id))?;
+    b.expect_none("a repeated snapshot", |e| matches!(e, NetworkEvent::PlayerSnapshot(_)))
+}
+
+fn test_observe(addr: &str) -> TestResult {
+    let a = TestClient::connect(addr)?;
+    let o = TestClient::connect_with(addr, Compression::Deflate, GameInfo::default())?;
+
+    a.send(NetworkEvent::FullUpdate(a.id, 0, test_player("BLUE")))?;
+    o.expect("a full update", |e| matches!(e, NetworkEvent::FullUpdate(id, ..) if *id == a.id))?;
+
+    // Observers can still catch up on other players
+    o.send(NetworkEvent::Observe(o.id))?;
+    o.send(NetworkEvent::ResyncRequest(o.id))?;
+    o.expect("a player snapshot", |e| match e {
+        NetworkEvent::PlayerSnapshot(players) => players.iter().any(|(id, ..)| *id == a.id),
+        _ => false,
+    })?;
+
+    // Nothing that an observer sends reaches other players
+    o.send(NetworkEvent::FullUpdate(o.id, 0, test_player("RED")))?;
+    o.send(NetworkEvent::Chat(o.id, 0, "Hello".to_string()))?;
+    a.expect_none("an update or chat from the observer", |e| match e {
+        NetworkEvent::FullUpdate(id, ..) | NetworkEvent::Chat(id, ..) => *id == o.id,
+        _ => false,
+    })?;
+
+    a.send(NetworkEvent::Chat(a.id, 0, "Hello".to_string()))?;
+    o.expect("a chat message", |e| matches!(e, NetworkEvent::Chat(id, ..) if *id == a.id))?;
+
+    // Observers are not players, so can not be asked to battle
+    a.send(NetworkEvent::BattleDataRequest(o.id, a.id))?;
+    a.expect("a refused battle request", |e| match e {
+        NetworkEvent::BattleRequestRefused(to, target) => *to == a.id && *target == o.id,
+        _ => false,
+    })?;
+    Ok(())
+}
+
+fn test_lite_mode(addr: &str) -> TestResult {
+    let a = TestClient::connect(addr)?;
+    let b = TestClient::connect(addr)?;
+
+    a.send(NetworkEvent::LiteMode(a.id, true))?;
+    a.expect("lite mode", |e| matches!(e, NetworkEvent::LiteMode(id, true) if *id == a.id))?;
+
+    // Lite clients are still sent full updates, but only the newest movement after a short wait
+    let mut player = test_player("RED");
+    b.send(NetworkEvent::FullUpdate(b.id, 0, player.clone()))?;
+    a.expect("a full update", |e| matches!(e, NetworkEvent::FullUpdate(id, ..) if *id == b.id))?;
+    for seq in 1..=3 {
+        player.movement_data.map_x += 1;
+        b.send(NetworkEvent::MovementUpdate(b.id, seq, player.movement_data))?;
+    }
+    let movement = player.movement_data;
+    a.expect("the newest movement update", |e| match e {
+        NetworkEvent::MovementUpdate(id, 3, data) => *id == b.id && *data == movement,
+        _ => false,
+    })?;
+
+    // Chat is left out, and battles can not be watched
+    b.send(NetworkEvent::Chat(b.id, 0, "Hello".to_string()))?;
+    a.expect_none("a chat message", |e| matches!(e, NetworkEvent::Chat(..)))?;
+    b.send(NetworkEvent::SpectateRequest(a.id, b.id))?;
+    b.expect("the end of spectating", |e| match e {
+        NetworkEvent::SpectateEnd(to, from) => *to == b.id && *from == a.id,
+        _ => false,
+    })?;
+    Ok(())
+}
+
+fn test_accounts(addr: &str) -> TestResult {
+    // A new name each run, since accounts outlast the test on servers that keep them
+    let name: String = seed::generate()[..5].iter().map(|b| format!("{:02X}", b)).collect();
+    let name = format!("T{}", name);
+    let login = |kind, secret: &str| Login { kind, name: name.clone(), secret: secret.to_string() };
+    let refused = |login: Login, error: LoginError| match TestClient::connect_as(addr, login) {
+        Ok(_) => Err(format!("a login was accepted that should fail with: {}", error)),
+        Err(e) if e.contains(&error.to_string()) => Ok(()),
+        Err(e) => Err(format!("a login failed with the wrong error: {}", e)),
+    };
+
+    let a = TestClient::connect_as(addr, login(LoginKind::Register, "password"))?;
+    let token = a.token.clone().ok_or("no token was given for a new account")?;
+    refused(login(LoginKind::Register, "password"), LoginError::NameTaken)?;
+    refused(login(LoginKind::Password, "wrong password"), LoginError::WrongCredentials)?;
+    refused(login(LoginKind::Token, "wrong token"), LoginError::WrongCredentials)?;
+    let b = TestClient::connect_as(addr, login(LoginKind::Token, &token))?;
+    if b.token.is_some() {
+        return Err("a new token was given for a login with a token".to_string());
+    }
+
+    // Players are shown under their account's name, and guests can not use it
+    let guest = TestClient::connect(addr)?;
+    let shown_name = |e: &NetworkEvent, id| match e {
+        NetworkEvent::FullUpdate(from, _, data) if *from == id => Some(text::decode(&data.name)),
+        _ => None,
+    };
+    a.send(NetworkEvent::FullUpdate(a.id, 0, test_player("RED")))?;
+    guest.expect("the account's name", |e| shown_name(e, a.id) == Some(name.clone()))?;
+    guest.send(NetworkEvent::FullUpdate(guest.id, 0, test_player(&name)))?;
+    a.expect("a guest renamed", |e| matches!(shown_name(e, guest.id), Some(n) if n != name))?;
+    Ok(())
+}
+
+fn test_spoofed_sender(addr: &str) -> TestResult {
+    let a = TestClient::connect(addr)?;
+    let b = TestClient::connect(addr)?;
+    let c = TestClient::connect(addr)?;
+
+    // Events sent as another player are dropped, and do not close the sender's connection
+    a.send(NetworkEvent::FullUpdate(b.id, 0, test_player("RED")))?;
+    a.send(NetworkEvent::Chat(b.id, 0, "Spoofed".to_string()))?;
+    c.expect_none("an event sent as another player", |e| match e {
+        NetworkEvent::FullUpdate(id, ..) | NetworkEvent::Chat(id, ..) => *id == b.id,
+        _ => false,
+    })?;
+    a.send(NetworkEvent::Chat(a.id, 0, "Hello".to_string()))?;
+    c.expect("a chat message", |e| matches!(e, NetworkEvent::Chat(id, ..) if *id == a.id))?;
+    Ok(())
+}
+
+fn test_chat(addr: &str) -> TestResult {
+    let a = TestClient::connect(addr)?;
+    let b = TestClient::connect(addr)?;
+
+    let msg = "Hello from the protocol test!";
+    a.send(NetworkEvent::Chat(a.id, 7, msg.to_string()))?;
+    b.expect("a chat message", |e| match e {
+        NetworkEvent::Chat(id, 7, data) => *id == a.id && data == msg,
+        _ => false,
+    })?;
+
+    // The sender is told that the message was delivered, but it is not echoed back to them
+    a.expect("a chat acknowledgement", |e| matches!(e, NetworkEvent::ChatAck(7)))?;
+    a.expect_none("its own chat message", |e| matches!(e, NetworkEvent::Chat(..)))?;
+
+    // A message sent again, e.g. because its acknowledgement was lost, is acknowledged again but
+    // only passed on once
+    a.send(NetworkEvent::Chat(a.id, 7, msg.to_string()))?;
+    a.expect("a repeated chat acknowledgement", |e| matches!(e, NetworkEvent::ChatAck(7)))?;
+    b.expect_none("a repeated chat message", |e| matches!(e, NetworkEvent::Chat(..)))
+}
+
+fn test_battle(addr: &str) -> TestResult {
+    let a = TestClient::connect(addr)?;
+    let b = TestClient::connect(addr)?;
+
+    let secret = seed::generate();
+    let commitment = seed::commitment(&secret);
+    a.send(NetworkEvent::BattleSeedCommit(b.id, a.id, commitment))?;
+    b.expect("a battle seed commitment", |e| match e {
+        NetworkEvent::BattleSeedCommit(to, from, c) => {
+            *to == b.id && *from == a.id && *c == commitment
+        }
+        _ => false,
+    })?;
+
+    a.send(NetworkEvent::BattleDataRequest(b.id, a.id))?;
+    b.expect("a battle request", |e| match e {
+        NetworkEvent::BattleDataRequest(to, from) => *to == b.id && *from == a.id,
+        _ => false,
+    })?;
+
+    let opponent_seed = seed::generate();
+    b.send(NetworkEvent::BattleSeed(a.id, b.id, opponent_seed))?;
+    a.expect("the opponent's battle seed", |e| match e {
+        NetworkEvent::BattleSeed(to, from, s) => {
+            *to == a.id && *from == b.id && *s == opponent_seed
+        }
+        _ => false,
+    })?;
+
+    let battle_data: Vec<u8> = (0..interface::data::BATTLE_DATA_SIZE).map(|i| i as u8).collect();
+    b.send(NetworkEvent::BattleDataResponse(a.id, b.id, battle_data.clone()))?;
+    a.expect("a battle response", |e| match e {
+        NetworkEvent::BattleDataResponse(to, from, data) => {
+            *to == a.id && *from == b.id && *data == battle_data
+        }
+        _ => false,
+    })?;
+
+    a.send(NetworkEvent::BattleSeedReveal(b.id, a.id, secret))?;
+    b.expect("the revealed battle seed", |e| match e {
+        NetworkEvent::BattleSeedReveal(to, from, s) => *to == b.id && *from == a.id && *s == secret,
+        _ => false,
+    })?;
+    Ok(())
+}
+
+fn test_repeated_request(addr: &str) -> TestResult {
+    let a = TestClient::connect(addr)?;
+    let b = TestClient::connect(addr)?;
+
+    // Copies of a request that has not been answered yet are passed on
+    for _ in 0..2 {
+        a.send(NetworkEvent::BattleDataRequest(b.id, a.id))?;
+        b.expect("a battle request", |e| matches!(e, NetworkEvent::BattleDataRequest(..)))?;
+    }
+
+    // Only the first answer is passed on
+    let battle_data = vec![0; interface::data::BATTLE_DATA_SIZE];
+    for _ in 0..2 {
+        b.send(NetworkEvent::BattleDataResponse(a.id, b.id, battle_data.clone()))?;
+    }
+    a.expect("a battle response", |e| matches!(e, NetworkEvent::BattleDataResponse(..)))?;
+    a.expect_none("a second battle response", |e| matches!(e, NetworkEvent::BattleDataResponse(..)))
+}
+
+fn test_request_cooldown(addr: &str) -> TestResult {
+    let a = TestClient::connect(addr)?;
+    let b = TestClient::connect(addr)?;
+
+    a.send(NetworkEvent::BattleDataRequest(b.id, a.id))?;
+    b.expect("a battle request", |e| matches!(e, NetworkEvent::BattleDataRequest(..)))?;
+    a.send(NetworkEvent::BattleRequestCancel(b.id, a.id))?;
+    b.expect("a cancelled battle request", |e| match e {
+        NetworkEvent::BattleRequestCancel(to, from) => *to == b.id && *from == a.id,
+        _ => false,
+    })?;
+
+    // A new request straight away is dropped, and the sender is told why
+    a.send(NetworkEvent::BattleDataRequest(b.id, a.id))?;
+    a.expect("a refused battle request", |e| match e {
+        NetworkEvent::BattleRequestRefused(to, target) => *to == a.id && *target == b.id,
+        _ => false,
+    })?;
+    b.expect_none("a repeated battle request", |e| matches!(e, NetworkEvent::BattleDataRequest(..)))
+}
+
+fn test_game_compatibility(addr: &str) -> TestResult {
+    // Players without a game can join any server, but can not be asked to battle
+    let a = TestClient::connect(addr)?;
+    let b = TestClient::connect_with(addr, Compression::Deflate, GameInfo::default())?;
+
+    a.send(NetworkEvent::BattleDataRequest(b.id, a.id))?;
+    a.expect("a refused battle request", |e| match e {
+        NetworkEvent::BattleRequestRefused(to, target) => *to == a.id && *target == b.id,
+        _ => false,
+    })?;
+    b.expect_none("a battle request", |e| matches!(e, NetworkEvent::BattleDataRequest(..)))
+}
+
+fn test_away_message(addr: &str) -> TestResult {
+    let a = TestClient::connect(addr)?;
+    let b = TestClient::connect(addr)?;
+
+    a.send(NetworkEvent::BattleDataRequest(b.id, a.id))?;
+    b.expect("a battle request", |e| matches!(e, NetworkEvent::BattleDataRequest(..)))?;
+    b.send(NetworkEvent::AwayMessage(a.id, b.id, "Away".to_string()))?;
+    a.expect("an away message", |e| match e {
+        NetworkEvent::AwayMessage(to, from, msg) => *to == a.id && *from == b.id && msg == "Away",
+        _ => false,
+    })?;
+
+    // The away message answered the request, so a late response is not passed on
+    let battle_data = vec![0; interface::data::BATTLE_DATA_SIZE];
+    b.send(NetworkEvent::BattleDataResponse(a.id, b.id, battle_data))?;
+    a.expect_none("a battle response", |e| matches!(e, NetworkEvent::BattleDataResponse(..)))
+}
+
+fn test_relay_battle(addr: &str) -> TestResult {
+    let a = TestClient::connect(addr)?;
+    let b = TestClient::connect(addr)?;
+
+    a.send(NetworkEvent::BattleRules(b.id, a.id, ItemRule::Forfeit))?;
+    b.expect("the battle rules", |e| match e {
+        NetworkEvent::BattleRules(to, from, rule) => {
+            *to == b.id && *from == a.id && *rule == ItemRule::Forfeit
+        }
+        _ => false,
+    })?;
+
+    let battle_data: Vec<u8> = (0..interface::data::BATTLE_DATA_SIZE).map(|i| i as u8).collect();
+    a.send(NetworkEvent::RelayBattleData(b.id, a.id, battle_data.clone()))?;
+    b.expect("relayed battle data", |e| match e {
+        NetworkEvent::RelayBattleData(to, from, data) => {
+            *to == b.id && *from == a.id && *data == battle_data
+        }
+        _ => false,
+    })?;
+
+    a.send(NetworkEvent::BattleAction(b.id, a.id, RelayAction::Move(0x21)))?;
+    b.expect("the opponent's move", |e| match e {
+        NetworkEvent::BattleAction(to, from, action) => {
+            *to == b.id && *from == a.id && *action == RelayAction::Move(0x21)
+        }
+        _ => false,
+    })?;
+
+    b.send(NetworkEvent::BattleAction(a.id, b.id, RelayAction::Switch(2)))?;
+    a.expect("the opponent's switch", |e| match e {
+        NetworkEvent::BattleAction(to, from, action) => {
+            *to == a.id && *from == b.id && *action == RelayAction::Switch(2)
+        }
+        _ => false,
+    })?;
+
+    b.send(NetworkEvent::BattleAction(a.id, b.id, RelayAction::Item))?;
+    a.expect("the opponent's item", |e| match e {
+        NetworkEvent::BattleAction(to, from, action) => {
+            *to == a.id && *from == b.id && *action == RelayAction::Item
+        }
+        _ => false,
+    })?;
+
+    // Switching to a Pokemon past the end of a party is not a valid action
+    b.send(NetworkEvent::BattleAction(a.id, b.id, RelayAction::Switch(6)))?;
+    a.expect_none("an impossible switch", |e| match e {
+        NetworkEvent::BattleAction(_, from, action) => {
+            *from == b.id && *action == RelayAction::Switch(6)
+        }
+        _ => false,
+    })?;
+
+    b.send(NetworkEvent::BattleQuickChat(a.id, b.id, 1))?;
+    a.expect(
+        "a quick chat message",
+        |e| matches!(e, NetworkEvent::BattleQuickChat(to, from, 1) if *to == a.id && *from == b.id),
+    )?;
+
+    b.send(NetworkEvent::BattleKeepAlive(a.id, b.id))?;
+    a.expect(
+        "a keep alive message",
+        |e| matches!(e, NetworkEvent::BattleKeepAlive(to, from) if *to == a.id && *from == b.id),
+    )?;
+
+    b.send(NetworkEvent::BattleItemUsed(a.id, b.id))?;
+    a.expect(
+        "a broken battle rule",
+        |e| matches!(e, NetworkEvent::BattleItemUsed(to, from) if *to == a.id && *from == b.id),
+    )?;
+
+    b.send(NetworkEvent::BattleForfeit(a.id, b.id))?;
+    a.expect(
+        "a forfeited battle",
+        |e| matches!(e, NetworkEvent::BattleForfeit(to, from) if *to == a.id && *from == b.id),
+    )?;
+
+    // Messages that could not be sent directly go through the server, and are unwrapped by the
+    // recipient's network thread
+    let action = RelayAction::Move(0x21);
+    b.send(NetworkEvent::PeerPacket(a.id, b.id, 7, DirectMessage::Action(action)))?;
+    a.expect("a battle message that could not be sent directly", |e| match e {
+        NetworkEvent::BattleAction(to, from, received) => {
+            *to == a.id && *from == b.id && *received == action
+        }
+        _ => false,
+    })?;
+    Ok(())
+}
+
+fn test_hall_of_fame(addr: &str) -> TestResult {
+    let a = TestClient::connect(addr)?;
+    let b = TestClient::connect(addr)?;
+
+    a.send(NetworkEvent::HallOfFame(a.id, 1))?;
+    b.expect(
+        "a Hall of Fame announcement",
+        |e| matches!(e, NetworkEvent::HallOfFame(id, 1) if *id == a.id),
+    )?;
+
+    // Players that join later should also be told about it
+    let c = TestClient::connect(addr)?;
+    c.expect(
+        "an earlier Hall of Fame entry",
+        |e| matches!(e, NetworkEvent::HallOfFame(id, 1) if *id == a.id),
+    )?;
+    Ok(())
+}
+
+fn test_paused(addr: &str) -> TestResult {
+    let a = TestClient::connect(addr)?;
+    let b = TestClient::connect(addr)?;
+
+    a.send(NetworkEvent::PlayerPaused(a.id, true))?;
+    b.expect(
+        "a paused game",
+        |e| matches!(e, NetworkEvent::PlayerPaused(id, true) if *id == a.id),
+    )?;
+
+    // Players that join later should also be told about it, until the game is resumed
+    let c = TestClient::connect(addr)?;
+    c.expect(
+        "an earlier paused game",
+        |e| matches!(e, NetworkEvent::PlayerPaused(id, true) if *id == a.id),
+    )?;
+    a.send(NetworkEvent::PlayerPaused(a.id, false))?;
+    c.expect(
+        "a resumed game",
+        |e| matches!(e, NetworkEvent::PlayerPaused(id, false) if *id == a.id),
+    )?;
+    Ok(())
+}
+
+fn test_spectate(addr: &str) -> TestResult {
+    let a = TestClient::connect(addr)?;
+    let b = TestClient::connect(addr)?;
+
+    a.send(NetworkEvent::WildEncounter(a.id, 0x83, 70))?;
+    b.expect(
+        "a wild encounter announcement",
+        |e| matches!(e, NetworkEvent::WildEncounter(id, 0x83, 70) if *id == a.id),
+    )?;
+
+    b.send(NetworkEvent::SpectateRequest(a.id, b.id))?;
+    a.expect(
+        "a spectate request",
+        |e| matches!(e, NetworkEvent::SpectateRequest(to, from) if *to == a.id && *from == b.id),
+    )?;
+
+    let battler = BattlerData { species: 0x83, level: 70, hp: 100, max_hp: 200 };
+    let snapshot = BattleSnapshot { player: battler, enemy: battler };
+    a.send(NetworkEvent::BattleSnapshot(b.id, a.id, snapshot))?;
+    b.expect("a battle snapshot", |e| match e {
+        NetworkEvent::BattleSnapshot(to, from, s) => *to == b.id && *from == a.id && *s == snapshot,
+        _ => false,
+    })?;
+
+    let msg = "Throw a ball!";
+    b.send(NetworkEvent::BattleChat(a.id, b.id, 3, msg.to_string()))?;
+    a.expect("a battle chat message", |e| match e {
+        NetworkEvent::BattleChat(battle, from, 3, data) => {
+            *battle == a.id && *from == b.id && data == msg
+        }
+        _ => false,
+    })?;
+    b.expect("a battle chat acknowledgement", |e| matches!(e, NetworkEvent::ChatAck(3)))?;
+
+    // Players that are not watching the battle can not chat in it
+    let c = TestClient::connect(addr)?;
+    c.send(NetworkEvent::BattleChat(a.id, c.id, 0, "Hello?".to_string()))?;
+    a.expect_none("battle chat from a non-spectator", |e| {
+        matches!(e, NetworkEvent::BattleChat(..))
+    })?;
+
+    a.send(NetworkEvent::SpectateEnd(b.id, a.id))?;
+    b.expect(
+        "the end of the battle",
+        |e| matches!(e, NetworkEvent::SpectateEnd(to, from) if *to == b.id && *from == a.id),
+    )?;
+
+    // The battle's chat is closed once it has ended
+    a.send(NetworkEvent::BattleChat(a.id, a.id, 0, "Got it!".to_string()))?;
+    b.expect_none("battle chat after the battle", |e| matches!(e, NetworkEvent::BattleChat(..)))
+}
+
+fn test_safari(addr: &str) -> TestResult {
+    let a = TestClient::connect(addr)?;
+    let b = TestClient::connect(addr)?;
+
+    // The server may already be running an event, in which case only the sender is told about it
+    a.send(NetworkEvent::SafariStart(a.id))?;
+    a.expect(
+        "a running Safari Zone event",
+        |e| matches!(e, NetworkEvent::SafariUpdate(status) if status.remaining_secs > 0),
+    )?;
+
+    b.send(NetworkEvent::SafariCatch(b.id, 0x1D))?;
+    a.expect("an updated leaderboard", |e| match e {
+        NetworkEvent::SafariUpdate(status) => status.leaderboard.first() == Some(&(b.id, 1)),
+        _ => false,
+    })?;
+    Ok(())
+}
+
+fn test_trade_board(addr: &str) -> TestResult {
+    let a = TestClient::connect(addr)?;
+    let b = TestClient::connect(addr)?;
+
+    a.send(NetworkEvent::TradeListingPost(a.id, 0x1D, 12, 0x54))?;
+    a.expect("the trade board with the new listing", |e| match e {
+        NetworkEvent::TradeBoard(listings) => listings.iter().any(|l| l.player == a.id),
+        _ => false,
+    })?;
+
+    b.send(NetworkEvent::TradeBoardRequest(b.id))?;
+    b.expect("the trade board", |e| match e {
+        NetworkEvent::TradeBoard(listings) => listings
+            .iter()
+            .any(|l| l.player == a.id && l.species == 0x1D && l.level == 12 && l.wanted == 0x54),
+        _ => false,
+    })?;
+
+    b.send(NetworkEvent::TradeOffer(a.id, b.id, 0x54, 9))?;
+    a.expect(
+        "a trade offer",
+        |e| matches!(e, NetworkEvent::TradeOffer(to, from, 0x54, 9) if *to == a.id && *from == b.id),
+    )?;
+
+    // Offers can only be made for players that have a listing
+    a.send(NetworkEvent::TradeOffer(b.id, a.id, 0x1D, 12))?;
+    b.expect_none("an offer without a listing", |e| matches!(e, NetworkEvent::TradeOffer(..)))?;
+
+    a.send(NetworkEvent::TradeListingRemove(a.id))?;
+    a.expect("the trade board without the listing", |e| match e {
+        NetworkEvent::TradeBoard(listings) => listings.iter().all(|l| l.player != a.id),
+        _ => false,
+    })?;
+    Ok(())
+}
+
+/// Join the server without the client's network threads, so that packets can be written and read
+/// directly
+fn join_directly(addr: &str) -> Result<(TcpStream, BufReader<TcpStream>, PlayerId), String> {
+    let mut socket = TcpStream::connect(addr).map_err(|e| e.to_string())?;
+    socket.set_read_timeout(Some(TIMEOUT)).map_err(|e| e.to_string())?;
+    let mut reader = BufReader::new(socket.try_clone().map_err(|e| e.to_string())?);
+    version::write_preamble(&mut socket).map_err(|e| e.to_string())?;
+    codec::write_compression(&mut socket, Compression::None).map_err(|e| e.to_string())?;
+    game::write_game(&mut socket, &test_game()).map_err(|e| e.to_string())?;
+    version::read_preamble(&mut reader).map_err(|e| e.to_string())?;
+    codec::read_compression(&mut reader).map_err(|e| e.to_string())?;
+    game::read_verdict(&mut reader).map_err(|e| e.to_string())?;
+    account::write_login(&mut socket, None).map_err(|e| e.to_string())?;
+    account::read_answer(&mut reader).map_err(|e| e.to_string())?;
+    match codec::read_packet(&mut reader) {
+        Ok(Some(NetworkEvent::PlayerJoin(id))) => Ok((socket, reader, id)),
+        _ => Err("the server did not send a player id".to_string()),
+    }
+}
+
+fn test_heartbeat(addr: &str) -> TestResult {
+    // Pongs are handled by the network thread, so this reads from the server directly
+    let (mut socket, mut reader, id) = join_directly(addr)?;
+
+    let ping = NetworkEvent::Ping(id, 7);
+    let ping = codec::encode_packet(&ping, Compression::None).map_err(|e| e.to_string())?;
+    socket.write_all(&ping).map_err(|e| e.to_string())?;
+    loop {
+        match codec::read_packet(&mut reader) {
+            Ok(Some(NetworkEvent::Pong(7))) => break,
+            Ok(Some(_)) => {}
+            _ => return Err(format!("player {} did not receive a pong", id)),
+        }
+    }
+    let _ = socket.shutdown(Shutdown::Both);
+    Ok(())
+}
+
+fn test_batching(addr: &str) -> TestResult {
+    // Clients write every packet they have queued at once, which the server must read one by one
+    let (mut socket, mut reader, id) = join_directly(addr)?;
+
+    let mut batch = vec![];
+    for timestamp in 1..=3 {
+        let ping = NetworkEvent::Ping(id, timestamp);
+        batch.extend(codec::encode_packet(&ping, Compression::None).map_err(|e| e.to_string())?);
+    }
+    socket.write_all(&batch).map_err(|e| e.to_string())?;
+
+    let mut expected = 1;
+    while expected <= 3 {
+        match codec::read_packet(&mut reader) {
+            Ok(Some(NetworkEvent::Pong(timestamp))) if timestamp == expected => expected += 1,
+            Ok(Some(NetworkEvent::Pong(timestamp))) => {
+                return Err(format!("expected pong {}, got {}", expected, timestamp))
+            }
+            Ok(Some(_)) => {}
+            _ => return Err(format!("player {} did not receive every pong", id)),
+        }
+    }
+    let _ = socket.shutdown(Shutdown::Both);
+    Ok(())
+}
+
+fn test_latency(addr: &str) -> TestResult {
+    // Clients ping the server as soon as they join, so the round trip time is known straight away
+    let a = TestClient::connect(addr)?;
+    let deadline = Instant::now() + TIMEOUT;
+    while a.latency.get().is_none() {
+        if Instant::now() > deadline {
+            return Err("the round trip time was not measured".to_string());
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    Ok(())
+}
+
+fn test_compression(addr: &str) -> TestResult {
+    // Players that do not accept compressed packets can still play with players that do
+    let a = TestClient::connect_with(addr, Compression::Deflate, test_game())?;
+    let b = TestClient::connect_with(addr, Compression::None, test_game())?;
+    a.send(NetworkEvent::FullUpdate(a.id, 0, test_player("ALICE")))?;
+    b.expect(
+        "an update from a player using compression",
+        |e| matches!(e, NetworkEvent::FullUpdate(id, ..) if *id == a.id),
+    )?;
+    b.send(NetworkEvent::FullUpdate(b.id, 0, test_player("BOB")))?;
+    a.expect(
+        "an update from a player not using compression",
+        |e| matches!(e, NetworkEvent::FullUpdate(id, ..) if *id == b.id),
+    )?;
+
+    // Check that large packets are actually compressed, which needs the raw packets
+    let mut socket = TcpStream::connect(addr).map_err(|e| e.to_string())?;
+    socket.set_read_timeout(Some(TIMEOUT)).map_err(|e| e.to_string())?;
+    let mut reader = BufReader::new(socket.try_clone().map_err(|e| e.to_string())?);
+    version::write_preamble(&mut socket).map_err(|e| e.to_string())?;
+    codec::write_compression(&mut socket, Compression::Deflate).map_err(|e| e.to_string())?;
+    game::write_game(&mut socket, &test_game()).map_err(|e| e.to_string())?;
+    version::read_preamble(&mut reader).map_err(|e| e.to_string())?;
+    let compression = codec::read_compression(&mut reader).map_err(|e| e.to_string())?;
+    game::read_verdict(&mut reader).map_err(|e| e.to_string())?;
+    account::write_login(&mut socket, None).map_err(|e| e.to_string())?;
+    account::read_answer(&mut reader).map_err(|e| e.to_string())?;
+    // Servers do not have to compress packets
+    if compression == Compression::None {
+        return Ok(());
+    }
+
+    // The server sends its rate limits once the player has joined, after which it passes on updates
+    let mut joined = false;
+    loop {
+        let mut length = [0; 4];
+        let mut packet = vec![];
+        reader
+            .read_exact(&mut length)
+            .and_then(|_| {
+                packet.resize(u32::from_le_bytes(length) as usize, 0);
+                reader.read_exact(&mut packet)
+            })
+            .map_err(|_| "the update was not compressed".to_string())?;
+
+        if !joined {
+            if let Ok(NetworkEvent::RateLimits(_)) = codec::decode_packet(&packet) {
+                joined = true;
+                a.send(NetworkEvent::FullUpdate(a.id, 0, test_player("ALICE")))?;
+            }
+        }
+        else if packet.first() == Some(&(Compression::Deflate as u8)) {
+            return match codec::decode_packet(&packet) {
+                Ok(NetworkEvent::FullUpdate(id, ..)) if id == a.id => Ok(()),
+                _ => Err("a compressed packet could not be decoded".to_string()),
+            };
+        }
+    }
+}
+
+fn test_usage_report(addr: &str) -> TestResult {
+    let a = TestClient::connect(addr)?;
+    let b = TestClient::connect(addr)?;
+
+    // Reports are only for the server, which keeps the connection open whether or not it keeps them
+    let report = UsageReport { session_secs: 60, features: vec![(Feature::Chat, 1)], crash: None };
+    a.send(NetworkEvent::UsageReport(report))?;
+    b.expect_none("a usage report", |e| matches!(e, NetworkEvent::UsageReport(_)))?;
+    a.send(NetworkEvent::Chat(a.id, 0, "Still here".to_string()))?;
+    b.expect("a chat message", |e| matches!(e, NetworkEvent::Chat(id, ..) if *id == a.id))?;
+    Ok(())
+}
+
+fn test_quit(addr: &str) -> TestResult {
+    let a = TestClient::connect(addr)?;
+    let b = TestClient::connect(addr)?;
+
+    let b_id = b.id;
+    drop(b);
+    a.expect("a quit notification", |e| matches!(e, NetworkEvent::PlayerQuit(id) if *id == b_id))?;
+    Ok(())
+}
+
+fn test_clean_quit(addr: &str) -> TestResult {
+    let a = TestClient::connect(addr)?;
+    let b = TestClient::connect(addr)?;
+    let c = TestClient::connect(addr)?;
+
+    // Leaving is announced without waiting for the connection to close, and is always for the
+    // player that sent it
+    c.send(NetworkEvent::PlayerQuit(a.id))?;
+    b.expect("a quit notification", |e| matches!(e, NetworkEvent::PlayerQuit(id) if *id == c.id))?;
+    b.expect_none("a forged quit", |e| matches!(e, NetworkEvent::PlayerQuit(id) if *id == a.id))
+}
+
+fn main() {
+    let addr = std::env::args().nth(1).unwrap_or_else(|| "localhost:8080".to_string());
+
+    let tests: &[Test] = &[
+        ("version", test_version),
+        ("join", test_join),
+        ("movement", test_movement),
+        ("movement checks", test_movement_checks),
+        ("resync", test_resync),
+        ("observe", test_observe),
+        ("lite mode", test_lite_mode),
+        ("accounts", test_accounts),
+        ("spoofed sender", test_spoofed_sender),
+        ("chat", test_chat),
+        ("battle", test_battle),
+        ("repeated request", test_repeated_request),
+        ("request cooldown", test_request_cooldown),
+        ("game compatibility", test_game_compatibility),
+        ("away message", test_away_message),
+        ("relay battle", test_relay_battle),
+        ("hall of fame", test_hall_of_fame),
+        ("paused", test_paused),
+        ("spectate", test_spectate),
+        ("safari", test_safari),
+        ("trade board", test_trade_board),
+        ("heartbeat", test_heartbeat),
+        ("latency", test_latency),
+        ("batching", test_batching),
+        ("compression", test_compression),
+        ("usage report", test_usage_report),
+        ("quit", test_quit),
+        ("clean quit", test_clean_quit),
+    ];
+
+    let mut failures = 0;
+    for (name, test) in tests {
+        match test(&addr) {
+            Ok(()) => println!("PASS {}", name),
+            Err(e) => {
+                println!("FAIL {}: {}", name, e);
+                failures += 1;
+            }
+        }
+    }
+
+    println!("{} passed, {} failed", tests.len() - failures, failures);
+    if failures != 0 {
+        process::exit(1);
+    }
+}
@@ -0,0 +1,134 @@
+//! Player accounts, which let players keep their name across sessions
+//!
+//! Players can join as guests, or log in to an account with its password or with a token that the
+//! server gave them after an earlier login. While someone is logged in, other players see them
+//! under the account's name. Guests can not use a name that has an account, so a name that has an
+//! account always belongs to the player logged in to it.
+//!
+//! Passwords are stored as salted hashes, and tokens as plain hashes since they are already random.
+//! Accounts are kept in the server's storage, where they are read when the server starts and
+//! rewritten after each change.
+use std::{collections::HashMap, io};
+
+use network_common::{
+    account::{self, Login, LoginError, LoginKind, MIN_PASSWORD_LENGTH},
+    seed,
+};
+use sha2::{Digest, Sha256};
+
+use crate::storage::{self, Storage};
+
+const STORAGE_KEY: &str = "accounts";
+
+/// How many times a password is hashed, to slow down guessing passwords from a stolen file. Logins
+/// are checked one at a time while clients are accepted, so this is kept to a few milliseconds.
+const HASH_ROUNDS: u32 = 10_000;
+
+/// The name that guests using a name with an account are shown under. It can not be registered.
+pub const GUEST_NAME: &str = "GUEST";
+
+/// The most tokens kept for each account, one for each device it was logged in from. Logging in
+/// with a password on another device after this forgets the oldest one.
+const MAX_TOKENS: usize = 8;
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct Account {
+    /// The name as it was registered
+    name: String,
+    salt: String,
+    password_hash: String,
+    token_hashes: Vec<String>,
+}
+
+pub struct Accounts {
+    /// Every account, by its name in upper case
+    accounts: HashMap<String, Account>,
+}
+
+impl Accounts {
+    /// Start from the saved accounts, if there are any
+    pub fn load(storage: &dyn Storage) -> io::Result<Accounts> {
+        let accounts = storage::load_json(storage, STORAGE_KEY)?.unwrap_or_default();
+        Ok(Accounts { accounts })
+    }
+
+    /// Whether `name` has an account. Names are compared without case.
+    pub fn is_registered(&self, name: &str) -> bool {
+        self.accounts.contains_key(&name.to_uppercase())
+    }
+
+    /// Check a login, registering the account first if asked to. Returns the account's name in
+    /// upper case and a new token for it, unless the login used a token already.
+    pub fn login(
+        &mut self,
+        login: &Login,
+        storage: &mut dyn Storage,
+    ) -> Result<(String, Option<String>), LoginError> {
+        let key = login.name.to_uppercase();
+        if login.kind == LoginKind::Register {
+            if !account::valid_name(&login.name) {
+                return Err(LoginError::InvalidName);
+            }
+            if login.secret.chars().count() < MIN_PASSWORD_LENGTH {
+                return Err(LoginError::PasswordTooShort);
+            }
+            if self.accounts.contains_key(&key) || key == GUEST_NAME {
+                return Err(LoginError::NameTaken);
+            }
+            let salt = hex(&seed::generate());
+            let password_hash = hash_password(&salt, &login.secret);
+            let account =
+                Account { name: login.name.clone(), salt, password_hash, token_hashes: vec![] };
+            self.accounts.insert(key.clone(), account);
+            println!("Registered the account: {}", login.name);
+        }
+
+        let account = self.accounts.get_mut(&key).ok_or(LoginError::WrongCredentials)?;
+        if login.kind == LoginKind::Token {
+            let token_hash = hash_token(&login.secret);
+            if !account.token_hashes.iter().any(|hash| same(hash, &token_hash)) {
+                return Err(LoginError::WrongCredentials);
+            }
+            return Ok((key, None));
+        }
+        if !same(&account.password_hash, &hash_password(&account.salt, &login.secret)) {
+            return Err(LoginError::WrongCredentials);
+        }
+
+        let token = hex(&seed::generate());
+        account.token_hashes.push(hash_token(&token));
+        if account.token_hashes.len() > MAX_TOKENS {
+            account.token_hashes.remove(0);
+        }
+        self.save(storage);
+        Ok((key, Some(token)))
+    }
+
+    fn save(&self, storage: &mut dyn Storage) {
+        if let Err(e) = storage::save_json(storage, STORAGE_KEY, &self.accounts) {
+            println!("Failed to write the accounts: {}", e);
+        }
+    }
+}
+
+fn hex(data: &[u8]) -> String {
+    data.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn hash_password(salt: &str, password: &str) -> String {
+    let mut hash = Sha256::new().chain_update(salt).chain_update(password).finalize();
+    for _ in 1..HASH_ROUNDS {
+        hash = Sha256::new().chain_update(hash).chain_update(salt).finalize();
+    }
+    hex(&hash)
+}
+
+fn hash_token(token: &str) -> String {
+    hex(&Sha256::digest(token))
+}
+
+/// Compare two hashes in the same time wherever they differ, so that the time taken to refuse a
+/// login says nothing about how close it was
+fn same(a: &str, b: &str) -> bool {
+    a.len() == b.len() && a.bytes().zip(b.bytes()).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
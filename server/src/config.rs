@@ -0,0 +1,95 @@
+//! The server's settings, read from a TOML file given with `--config FILE`
+//!
+//! Every setting is optional, and the file can be left out entirely to use the defaults. Unknown
+//! settings and values that are out of range stop the server from starting, so that a mistake in the
+//! file is not silently ignored.
+use std::{
+    fs,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    path::Path,
+};
+
+use network_common::{codec::MAX_MOTD_LENGTH, RateLimits, DEFAULT_PORT, MAX_UPDATE_RATE};
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    /// The address to listen on, for both TCP and UDP
+    pub bind_address: IpAddr,
+    pub port: u16,
+    /// The most players that can be connected at once. Every client counts until it starts
+    /// observing, so observers are turned away too while the server is full.
+    pub max_players: Option<usize>,
+    /// Shown to each player when they join
+    pub motd: Option<String>,
+    pub rates: Rates,
+}
+
+/// The slowest update rates that clients are allowed to use, in updates per second. Clients that
+/// update less often than this would appear to stutter or lag behind for other players.
+#[derive(Debug, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Rates {
+    pub min_movement_rate: u32,
+    pub min_receive_rate: u32,
+}
+
+impl Default for ServerConfig {
+    fn default() -> ServerConfig {
+        ServerConfig {
+            bind_address: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            port: DEFAULT_PORT,
+            max_players: None,
+            motd: None,
+            rates: Rates::default(),
+        }
+    }
+}
+
+impl Default for Rates {
+    fn default() -> Rates {
+        Rates { min_movement_rate: 10, min_receive_rate: 10 }
+    }
+}
+
+impl ServerConfig {
+    /// Read and check the config file at `path`
+    pub fn load(path: &Path) -> Result<ServerConfig, String> {
+        let text = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        let config: ServerConfig = toml::from_str(&text).map_err(|e| e.to_string())?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.max_players == Some(0) {
+            return Err("max_players must be at least 1".to_string());
+        }
+        if let Some(motd) = &self.motd {
+            if motd.chars().count() > MAX_MOTD_LENGTH {
+                return Err(format!("motd can be at most {} characters long", MAX_MOTD_LENGTH));
+            }
+        }
+        let rates = [
+            ("rates.min_movement_rate", self.rates.min_movement_rate),
+            ("rates.min_receive_rate", self.rates.min_receive_rate),
+        ];
+        for (name, rate) in rates {
+            if !(1..=MAX_UPDATE_RATE).contains(&rate) {
+                return Err(format!("{} must be between 1 and {}", name, MAX_UPDATE_RATE));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn bind_addr(&self) -> SocketAddr {
+        SocketAddr::new(self.bind_address, self.port)
+    }
+
+    pub fn rate_limits(&self) -> RateLimits {
+        RateLimits {
+            min_movement_rate: self.rates.min_movement_rate,
+            min_receive_rate: self.rates.min_receive_rate,
+        }
+    }
+}
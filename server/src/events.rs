@@ -0,0 +1,159 @@
+//! A record of what happened on the server, for settling disputes between players
+//!
+//! Each event is written as a single line of JSON, so the log can be read by other tools as it is
+//! written. Events older than the retention period are removed when the server starts and at the
+//! start of each day.
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, prelude::*, BufReader},
+    path::PathBuf,
+};
+
+use interface::PlayerId;
+use network_common::shared;
+
+/// How long events are kept for by default, in days
+pub const DEFAULT_RETENTION_DAYS: u64 = 30;
+
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    Join {
+        player: PlayerId,
+    },
+    Quit {
+        player: PlayerId,
+    },
+    /// A player asked for another player's party to battle
+    BattleRequest {
+        from: PlayerId,
+        to: PlayerId,
+    },
+    /// A battle request was dropped because the sender was on cooldown
+    BattleRequestRefused {
+        from: PlayerId,
+        to: PlayerId,
+    },
+    /// A player sent their party in response to a battle request
+    BattleResponse {
+        from: PlayerId,
+        to: PlayerId,
+    },
+    /// A player started a relayed battle
+    RelayBattle {
+        from: PlayerId,
+        to: PlayerId,
+    },
+    /// A player conceded a relayed battle
+    BattleForfeit {
+        from: PlayerId,
+        to: PlayerId,
+    },
+    /// A player listed a Pokemon on the trade board
+    TradeListing {
+        player: PlayerId,
+        species: u8,
+        level: u8,
+        wanted: u8,
+    },
+    /// A player offered a Pokemon for another player's listing
+    TradeOffer {
+        from: PlayerId,
+        to: PlayerId,
+    },
+    /// A player bought items from the shop, for a total price
+    ShopPurchase {
+        player: PlayerId,
+        item: u8,
+        quantity: u8,
+        price: u32,
+    },
+    HallOfFame {
+        player: PlayerId,
+        teams: u8,
+    },
+    SafariStart {
+        player: PlayerId,
+    },
+    /// A Safari Zone event ended, with the number of Pokemon caught by the leading players
+    SafariEnd {
+        leaderboard: Vec<(PlayerId, u32)>,
+    },
+}
+
+/// An event along with when it happened, in seconds since the Unix epoch
+#[derive(serde::Serialize, serde::Deserialize)]
+struct Record {
+    time: u64,
+    #[serde(flatten)]
+    event: Event,
+}
+
+pub struct EventLog {
+    path: PathBuf,
+    /// The open log file, or None if events are not being logged
+    file: Option<File>,
+    retention_days: u64,
+}
+
+impl EventLog {
+    /// Open a log, appending to it if it already exists
+    pub fn open(path: PathBuf, retention_days: u64) -> io::Result<EventLog> {
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let mut log = EventLog { path, file: Some(file), retention_days };
+        log.prune()?;
+        Ok(log)
+    }
+
+    /// A log that discards every event
+    pub fn disabled() -> EventLog {
+        EventLog { path: PathBuf::new(), file: None, retention_days: DEFAULT_RETENTION_DAYS }
+    }
+
+    pub fn write(&mut self, event: Event) {
+        let file = match &mut self.file {
+            Some(file) => file,
+            None => return,
+        };
+        let record = Record { time: shared::now_secs(), event };
+        let mut line = serde_json::to_vec(&record).unwrap();
+        line.push(b'\n');
+        if let Err(e) = file.write_all(&line) {
+            println!("Failed to write to the event log: {}", e);
+        }
+    }
+
+    /// Remove events that are older than the retention period
+    pub fn prune(&mut self) -> io::Result<()> {
+        if self.file.is_none() {
+            return Ok(());
+        }
+        let cutoff =
+            shared::now_secs().saturating_sub(self.retention_days * shared::SECONDS_PER_DAY);
+
+        let mut kept = Vec::new();
+        let mut removed = 0;
+        for line in BufReader::new(File::open(&self.path)?).lines() {
+            let line = line?;
+            match serde_json::from_str::<Record>(&line) {
+                Ok(record) if record.time < cutoff => removed += 1,
+                // Lines that can't be read are kept, so that nothing is lost by mistake
+                _ => kept.push(line),
+            }
+        }
+        if removed == 0 {
+            return Ok(());
+        }
+
+        // Write the remaining events to a new file first, so that the log is not lost if this fails
+        let tmp_path = self.path.with_extension("tmp");
+        let mut tmp = File::create(&tmp_path)?;
+        for line in &kept {
+            writeln!(tmp, "{}", line)?;
+        }
+        fs::rename(&tmp_path, &self.path)?;
+        self.file = Some(OpenOptions::new().append(true).open(&self.path)?);
+        println!("Removed {} old events from the event log", removed);
+        Ok(())
+    }
+}
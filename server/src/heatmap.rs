@@ -0,0 +1,96 @@
+//! Where players spend their time, to help plan events where players actually gather
+//!
+//! The position of every player is sampled once a second and counted separately for each map and
+//! hour of the day (in UTC). Every hour the counts are written out as greyscale PGM images, one for
+//! each map and hour (e.g. `map-12-hour-18.pgm`), where brighter tiles were occupied for longer.
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{self, prelude::*, BufWriter},
+    path::PathBuf,
+};
+
+use interface::{data::MovementData, PlayerId};
+use network_common::shared;
+
+const SECONDS_PER_HOUR: u64 = 60 * 60;
+
+pub struct Heatmap {
+    /// The directory that images are written to
+    dir: PathBuf,
+    /// The last known position of each player
+    positions: HashMap<PlayerId, MovementData>,
+    /// The number of samples at each tile, for each map and hour
+    counts: HashMap<(u8, u8), HashMap<(u8, u8), u32>>,
+    /// The hour of the day that the counts were last written out in
+    exported_hour: u8,
+}
+
+impl Heatmap {
+    pub fn new(dir: PathBuf) -> io::Result<Heatmap> {
+        fs::create_dir_all(&dir)?;
+        Ok(Heatmap {
+            dir,
+            positions: HashMap::new(),
+            counts: HashMap::new(),
+            exported_hour: current_hour(),
+        })
+    }
+
+    pub fn update(&mut self, player: PlayerId, movement_data: MovementData) {
+        self.positions.insert(player, movement_data);
+    }
+
+    pub fn remove(&mut self, player: PlayerId) {
+        self.positions.remove(&player);
+    }
+
+    /// Count the current position of every player, and write out the counts once an hour
+    pub fn sample(&mut self) {
+        let hour = current_hour();
+        for movement_data in self.positions.values() {
+            // Players in privacy mode only share which area they are in
+            if movement_data.is_hidden() {
+                continue;
+            }
+            let tiles = self.counts.entry((movement_data.map_id, hour)).or_default();
+            *tiles.entry((movement_data.map_x, movement_data.map_y)).or_insert(0) += 1;
+        }
+
+        if hour != self.exported_hour {
+            self.exported_hour = hour;
+            if let Err(e) = self.export() {
+                println!("Failed to write the heatmap: {}", e);
+            }
+        }
+    }
+
+    /// Write an image for each map and hour that players have been seen in
+    pub fn export(&self) -> io::Result<()> {
+        for (&(map_id, hour), tiles) in &self.counts {
+            let path = self.dir.join(format!("map-{}-hour-{}.pgm", map_id, hour));
+            write_image(&mut BufWriter::new(File::create(path)?), tiles)?;
+        }
+        Ok(())
+    }
+}
+
+/// Write the counts for a map as a binary PGM image that covers every tile that has been occupied
+fn write_image<W: Write>(writer: &mut W, tiles: &HashMap<(u8, u8), u32>) -> io::Result<()> {
+    let width = tiles.keys().map(|&(x, _)| x as usize + 1).max().unwrap_or(1);
+    let height = tiles.keys().map(|&(_, y)| y as usize + 1).max().unwrap_or(1);
+    let max_count = tiles.values().copied().max().unwrap_or(1);
+
+    let mut pixels = vec![0; width * height];
+    for (&(x, y), &count) in tiles {
+        pixels[y as usize * width + x as usize] = (count as u64 * 255 / max_count as u64) as u8;
+    }
+
+    write!(writer, "P5\n{} {}\n255\n", width, height)?;
+    writer.write_all(&pixels)?;
+    writer.flush()
+}
+
+fn current_hour() -> u8 {
+    (shared::now_secs() / SECONDS_PER_HOUR % 24) as u8
+}
@@ -1,140 +1,1821 @@
+mod accounts;
+mod config;
+mod events;
+mod heatmap;
+mod movement;
+mod players;
+mod progress;
+mod storage;
+mod usage;
+
 use std::{
-    collections::HashMap,
-    io::{self, BufRead, BufReader, Write},
-    net::{TcpListener, TcpStream},
+    collections::{HashMap, HashSet, VecDeque},
+    io,
+    mem::{self, Discriminant},
+    net::{Shutdown, SocketAddr, TcpListener, TcpStream, UdpSocket},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
     thread,
+    time::{Duration, Instant},
 };
 
-use interface::PlayerId;
+use accounts::{Accounts, GUEST_NAME};
+use config::ServerConfig;
+use crossbeam_channel::TryRecvError;
+use events::{Event, EventLog};
+use heatmap::Heatmap;
+use interface::{
+    data::{MovementData, PlayerData},
+    rom::{self, Generation},
+    text,
+    visibility::Visibility,
+    PlayerId,
+};
+use movement::MovementChecks;
 use network_common::{
+    account,
+    codec::{self, Compression},
+    connection::{Connection, Priority},
+    discovery,
     error::{NetworkError, NetworkResult},
-    NetworkEvent,
+    game::{self, AllowedGames, GameInfo, Verdict},
+    master,
+    poll::{self, Poller},
+    seed,
+    sequence::{self, Sequence},
+    shared::{self, SharedSeed},
+    tls::{self, ServerConfig as TlsConfig},
+    transport::Transport,
+    version, websocket, ChatId, DayCycle, NetworkEvent, SafariStatus, ShopItem, TradeListing,
+    CONNECTION_TIMEOUT, DEFAULT_PORT, LITE_UPDATE_RATE, MAX_LEADERBOARD_LENGTH, MAX_SHOP_ITEMS,
+    MAX_SNAPSHOT_PLAYERS, MAX_TRADE_LISTINGS, MIN_DAY_LENGTH,
 };
+use players::PlayerRecords;
+use progress::AccountProgress;
+use storage::{FileStorage, MemoryStorage, SqliteStorage, Storage};
+use usage::UsageStats;
+
+/// How long a player must wait before sending another battle request to the same player
+const BATTLE_REQUEST_COOLDOWN: Duration = Duration::from_secs(10);
+
+/// How long a battle request is kept open waiting for a response. Clients give up sooner than this,
+/// so requests only run out here if the requester stopped responding.
+const BATTLE_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// How long a relayed battle is counted as going on after it starts, unless it is forfeited first.
+/// Battles that are fought to the end are only seen by the two players, so the server can not tell
+/// when they finish.
+const MAX_BATTLE_LENGTH: Duration = Duration::from_secs(60 * 60);
+
+/// How long a new client has to complete the TLS handshake and send its protocol version and
+/// compression
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The most clients that can be in the middle of their handshake at once. Each one has a thread of
+/// its own, so that a slow client only holds up itself, and clients past this are turned away so
+/// that a flood of connections can not start threads without limit.
+const MAX_HANDSHAKES: usize = 64;
 
-struct Client {
+/// How long to wait before accepting connections again after failing to, which usually means
+/// that the server has run out of file descriptors for the time being
+const ACCEPT_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// How often the connection thread checks for clients that have timed out, while nothing else
+/// wakes it
+const TIMEOUT_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How much can be waiting to be sent to a client before it is switched to lite mode, since its
+/// connection can not keep up with everything
+const CONGESTED_QUEUE_LEN: usize = 256 * 1024;
+
+/// How many chat message ids to remember for each player, for dropping messages that are sent again
+const MAX_SEEN_CHATS: usize = 16;
+
+/// The name shown to clients searching the local network, unless the server is given one
+const DEFAULT_NAME: &str = "Pikemon server";
+
+/// How long a Safari Zone event lasts once it has been started
+const SAFARI_EVENT_LENGTH: Duration = Duration::from_secs(10 * 60);
+
+/// Used to send packets to a client, through the thread that serves its connection
+struct ClientStream {
     id: PlayerId,
-    client_stream: TcpStream,
-    server_sender: crossbeam_channel::Sender<NetworkEvent>,
+    connections: poll::Sender<ConnectionCommand>,
+    /// The compression agreed with the client during the handshake
+    compression: Compression,
+}
+
+/// Sent to the thread that serves every client's connection
+enum ConnectionCommand {
+    /// Start serving a client that has completed its handshake
+    Add(PlayerId, Connection),
+    /// Send an encoded packet to a client
+    Send(PlayerId, Vec<u8>, Priority),
+}
+
+/// How connections from clients are set up
+struct TransportOptions {
+    /// Compress packets for clients that accept it
+    compression: Compression,
+    /// Encrypt connections with this certificate
+    tls: Option<Arc<TlsConfig>>,
+    /// Expect clients to connect with WebSocket framing
+    websocket: bool,
+}
+
+/// Everything the server is started with, from the command line and the config file
+struct ServerContext {
+    config: ServerConfig,
+    event_log: EventLog,
+    shop: Vec<ShopItem>,
+    heatmap: Option<Heatmap>,
+    storage: Box<dyn Storage>,
+    usage_stats: Option<UsageStats>,
+    accounts: Accounts,
+    /// A second handle to the storage, for the handshake threads that check accounts
+    account_storage: Box<dyn Storage>,
+    progress: AccountProgress,
+    day_length: Option<u32>,
+    visibility: Visibility,
+    transport: TransportOptions,
+    allowed_games: AllowedGames,
+    name: String,
+    master: Option<SocketAddr>,
+}
+
+/// A client that has completed its handshakes, passed to the main thread to join
+struct NewClient {
+    id: PlayerId,
+    connection: Connection,
+    /// Identifies the client's movement updates sent over UDP
+    token: u64,
+    game: GameInfo,
+    /// The account the client logged in to, or None for a guest
+    account: Option<String>,
+}
+
+/// What a client agreed to in its handshake
+struct Handshake {
+    stream: Box<dyn Transport>,
+    compression: Compression,
+    game: GameInfo,
+    /// The account the client logged in to, or None for a guest
+    account: Option<String>,
+}
+
+/// Which clients are let in, checked by the handshake threads before they join
+struct Admission {
+    allowed_games: AllowedGames,
+    max_players: Option<usize>,
+    /// The number of players connected, kept up to date by the main thread
+    players: Arc<AtomicUsize>,
+    /// Checked against the logins that clients send, and by the main thread to keep guests from
+    /// using names that have accounts
+    accounts: Arc<Mutex<Accounts>>,
+    /// Where new accounts and tokens are saved
+    account_storage: Mutex<Box<dyn Storage>>,
+}
+
+impl Admission {
+    fn is_full(&self) -> bool {
+        matches!(self.max_players, Some(max) if self.players.load(Ordering::Relaxed) >= max)
+    }
+}
+
+/// A Safari Zone event, where players compete to catch the most Pokemon before time runs out
+struct SafariEvent {
+    ends_at: Instant,
+    catches: HashMap<PlayerId, u32>,
+}
+
+impl SafariEvent {
+    fn new() -> SafariEvent {
+        SafariEvent { ends_at: Instant::now() + SAFARI_EVENT_LENGTH, catches: HashMap::new() }
+    }
+
+    fn status(&self) -> SafariStatus {
+        let mut leaderboard: Vec<(PlayerId, u32)> =
+            self.catches.iter().map(|(&id, &count)| (id, count)).collect();
+        leaderboard.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        leaderboard.truncate(MAX_LEADERBOARD_LENGTH);
+
+        let remaining = self.ends_at.saturating_duration_since(Instant::now());
+        SafariStatus { remaining_secs: remaining.as_secs() as u32, leaderboard }
+    }
+}
+
+/// Limits how often a player can send requests to another player, so that they can't be spammed
+/// with requests faster than they can decline them
+struct RequestCooldowns {
+    /// When each kind of request was last sent, for each sender and target
+    last_sent: HashMap<(PlayerId, PlayerId, Discriminant<NetworkEvent>), Instant>,
+}
+
+impl RequestCooldowns {
+    fn new() -> RequestCooldowns {
+        RequestCooldowns { last_sent: HashMap::new() }
+    }
+
+    /// Check whether a request can be sent now, and start its cooldown if it can
+    fn allow(&mut self, from: PlayerId, to: PlayerId, request: &NetworkEvent) -> bool {
+        let key = (from, to, mem::discriminant(request));
+        match self.last_sent.get(&key) {
+            Some(sent_at) if sent_at.elapsed() < BATTLE_REQUEST_COOLDOWN => false,
+            _ => {
+                self.last_sent.insert(key, Instant::now());
+                true
+            }
+        }
+    }
+
+    /// Forget the cooldowns that have run out, or that involve a player who has left
+    fn prune(&mut self, quit: Option<PlayerId>) {
+        self.last_sent.retain(|&(from, to, _), sent_at| {
+            sent_at.elapsed() < BATTLE_REQUEST_COOLDOWN && quit != Some(from) && quit != Some(to)
+        });
+    }
+}
+
+/// Battle requests that are waiting for a response. Copies of an open request are passed on without
+/// being refused by the cooldown, so that clients can send a request again if it goes unanswered,
+/// and only the first response to each request is passed on.
+struct BattleHandshakes {
+    /// When each request was opened, for each requester and target
+    open: HashMap<(PlayerId, PlayerId), Instant>,
+    /// When each relayed battle started, for each pair of players in the order given by `pair`
+    battles: HashMap<(PlayerId, PlayerId), Instant>,
+}
+
+impl BattleHandshakes {
+    fn new() -> BattleHandshakes {
+        BattleHandshakes { open: HashMap::new(), battles: HashMap::new() }
+    }
+
+    fn is_open(&self, from: PlayerId, to: PlayerId) -> bool {
+        self.open.contains_key(&(from, to))
+    }
+
+    fn open(&mut self, from: PlayerId, to: PlayerId) {
+        self.open.insert((from, to), Instant::now());
+    }
+
+    /// Close a request once it has been answered or cancelled, returning false if it was not open
+    fn close(&mut self, from: PlayerId, to: PlayerId) -> bool {
+        self.open.remove(&(from, to)).is_some()
+    }
+
+    /// Note that a relayed battle has started between two players
+    fn start_battle(&mut self, a: PlayerId, b: PlayerId) {
+        self.battles.insert(pair(a, b), Instant::now());
+    }
+
+    /// End a relayed battle because one of the players forfeited it, returning false if there was
+    /// no such battle going on
+    fn end_battle(&mut self, a: PlayerId, b: PlayerId) -> bool {
+        self.battles.remove(&pair(a, b)).is_some()
+    }
+
+    /// Forget the requests and battles that have run out, or that involve a player who has left
+    fn prune(&mut self, quit: Option<PlayerId>) {
+        self.open.retain(|&(from, to), opened_at| {
+            opened_at.elapsed() < BATTLE_HANDSHAKE_TIMEOUT && quit != Some(from) && quit != Some(to)
+        });
+        self.battles.retain(|&(a, b), started_at| {
+            started_at.elapsed() < MAX_BATTLE_LENGTH && quit != Some(a) && quit != Some(b)
+        });
+    }
+}
+
+/// The two players in a battle, in the same order whichever of them is given first
+fn pair(a: PlayerId, b: PlayerId) -> (PlayerId, PlayerId) {
+    (a.min(b), a.max(b))
+}
+
+/// The players watching each battle, so that chat messages about a battle only reach the players
+/// involved in it. A battle's channel is closed once nobody is watching it.
+struct SpectatorChannels {
+    /// The players watching each player's battle
+    watchers: HashMap<PlayerId, HashSet<PlayerId>>,
+}
+
+impl SpectatorChannels {
+    fn new() -> SpectatorChannels {
+        SpectatorChannels { watchers: HashMap::new() }
+    }
+
+    fn join(&mut self, battle: PlayerId, watcher: PlayerId) {
+        self.watchers.entry(battle).or_default().insert(watcher);
+    }
+
+    fn leave(&mut self, battle: PlayerId, watcher: PlayerId) {
+        if let Some(watchers) = self.watchers.get_mut(&battle) {
+            watchers.remove(&watcher);
+            if watchers.is_empty() {
+                self.watchers.remove(&battle);
+            }
+        }
+    }
+
+    /// The players that a message from a member of a battle's channel should be sent to, or None if
+    /// the sender is not a member
+    fn recipients(&self, battle: PlayerId, from: PlayerId) -> Option<Vec<PlayerId>> {
+        let watchers = self.watchers.get(&battle)?;
+        if from != battle && !watchers.contains(&from) {
+            return None;
+        }
+        let members = watchers.iter().copied().chain(Some(battle));
+        Some(members.filter(|&id| id != from).collect())
+    }
+
+    /// Remove a player who has left from every channel, closing their own
+    fn prune(&mut self, quit: PlayerId) {
+        self.watchers.remove(&quit);
+        for watchers in self.watchers.values_mut() {
+            watchers.remove(&quit);
+        }
+        self.watchers.retain(|_, watchers| !watchers.is_empty());
+    }
+}
+
+/// The newest state received from each player, sent to players that ask to resynchronize
+struct PlayerStates {
+    states: HashMap<PlayerId, (Sequence, PlayerData)>,
+}
+
+impl PlayerStates {
+    fn new() -> PlayerStates {
+        PlayerStates { states: HashMap::new() }
+    }
+
+    fn update(&mut self, id: PlayerId, seq: Sequence, player_data: &PlayerData) {
+        match self.states.get_mut(&id) {
+            Some((latest, _)) if !sequence::is_newer(seq, *latest) => {}
+            _ => {
+                self.states.insert(id, (seq, player_data.clone()));
+            }
+        }
+    }
+
+    /// Apply movement to a player whose full state has already been received
+    fn update_movement(&mut self, id: PlayerId, seq: Sequence, movement_data: MovementData) {
+        if let Some((latest, player_data)) = self.states.get_mut(&id) {
+            if sequence::is_newer(seq, *latest) {
+                *latest = seq;
+                player_data.movement_data = movement_data;
+            }
+        }
+    }
+
+    /// The newest movement of a player, as an update to send to other players
+    fn movement(&self, id: PlayerId) -> Option<NetworkEvent> {
+        let (seq, player_data) = self.states.get(&id)?;
+        Some(NetworkEvent::MovementUpdate(id, *seq, player_data.movement_data))
+    }
+
+    fn position(&self, id: PlayerId) -> Option<MovementData> {
+        Some(self.states.get(&id)?.1.movement_data)
+    }
+
+    /// The state of every player other than `id`, split into snapshots small enough to send
+    fn snapshots(&self, id: PlayerId) -> Vec<NetworkEvent> {
+        let players: Vec<_> = self
+            .states
+            .iter()
+            .filter(|(&player, _)| player != id)
+            .map(|(&player, (seq, player_data))| (player, *seq, player_data.clone()))
+            .collect();
+        players
+            .chunks(MAX_SNAPSHOT_PLAYERS)
+            .map(|chunk| NetworkEvent::PlayerSnapshot(chunk.to_vec()))
+            .collect()
+    }
+
+    fn remove(&mut self, id: PlayerId) {
+        self.states.remove(&id);
+    }
+}
+
+/// The ids of the latest chat messages from each player. Clients send a message again if its
+/// acknowledgement does not arrive, which may only mean that the acknowledgement was lost, so the
+/// copies are acknowledged again without being passed on.
+struct SeenChats {
+    seen: HashMap<PlayerId, VecDeque<ChatId>>,
+}
+
+impl SeenChats {
+    fn new() -> SeenChats {
+        SeenChats { seen: HashMap::new() }
+    }
+
+    /// Whether this is the first time that the message has been received
+    fn first(&mut self, id: PlayerId, chat_id: ChatId) -> bool {
+        let seen = self.seen.entry(id).or_default();
+        if seen.contains(&chat_id) {
+            return false;
+        }
+        if seen.len() >= MAX_SEEN_CHATS {
+            seen.pop_front();
+        }
+        seen.push_back(chat_id);
+        true
+    }
+
+    fn remove(&mut self, id: PlayerId) {
+        self.seen.remove(&id);
+    }
 }
 
-fn run_server(bind_addr: &str) -> NetworkResult<()> {
-    let listener = TcpListener::bind(bind_addr)?;
+/// The UDP addresses that players send their movement from. Each player is given a random token
+/// when they join, which their datagrams must start with. Players are sent movement over UDP once
+/// they have sent their own movement that way, and over TCP until then.
+struct MovementChannels {
+    tokens: HashMap<PlayerId, u64>,
+    addrs: HashMap<PlayerId, SocketAddr>,
+}
+
+impl MovementChannels {
+    fn new() -> MovementChannels {
+        MovementChannels { tokens: HashMap::new(), addrs: HashMap::new() }
+    }
+
+    fn register(&mut self, id: PlayerId, token: u64) {
+        self.tokens.insert(id, token);
+    }
+
+    /// Check that a datagram came from the player it claims to be from, and remember where it
+    /// came from
+    fn accept(&mut self, token: u64, id: PlayerId, addr: SocketAddr) -> bool {
+        let valid = self.tokens.get(&id) == Some(&token);
+        if valid {
+            self.addrs.insert(id, addr);
+        }
+        valid
+    }
+
+    /// The token and address to send a player's movement datagrams to, if they are using UDP. This is
+    /// also the address that other players can reach them at directly.
+    fn route(&self, id: PlayerId) -> Option<(u64, SocketAddr)> {
+        Some((*self.tokens.get(&id)?, *self.addrs.get(&id)?))
+    }
+
+    fn remove(&mut self, id: PlayerId) {
+        self.tokens.remove(&id);
+        self.addrs.remove(&id);
+    }
+}
+
+fn run_server(context: ServerContext) -> NetworkResult<()> {
+    let ServerContext {
+        config,
+        mut event_log,
+        shop,
+        mut heatmap,
+        mut storage,
+        mut usage_stats,
+        accounts,
+        account_storage,
+        mut progress,
+        day_length,
+        visibility,
+        transport,
+        allowed_games,
+        name,
+        master,
+    } = context;
+    let listener = TcpListener::bind(config.bind_addr())?;
+    let udp_socket = UdpSocket::bind(config.bind_addr())?;
+    let rate_limits = config.rate_limits();
+    let player_count = Arc::new(AtomicUsize::new(0));
+    let accounts = Arc::new(Mutex::new(accounts));
+    let admission = Admission {
+        allowed_games,
+        max_players: config.max_players,
+        players: player_count.clone(),
+        accounts: accounts.clone(),
+        account_storage: Mutex::new(account_storage),
+    };
 
     let (new_client_sender, new_client_receiver) = crossbeam_channel::unbounded();
     let (packet_sender, packet_receiver) = crossbeam_channel::unbounded();
+    let (datagram_sender, datagram_receiver) = crossbeam_channel::unbounded();
+    let (probe_sender, probe_receiver) = crossbeam_channel::unbounded();
+    let (connection_sender, connection_receiver) = poll::channel()?;
 
+    thread::spawn(move || acceptor(listener, new_client_sender, transport, admission));
+    thread::spawn(move || {
+        if let Err(e) = connection_handler(connection_receiver, packet_sender) {
+            println!("Connection thread stopped: {}", e);
+        }
+    });
+    let datagram_socket = udp_socket.try_clone()?;
     thread::spawn(move || {
-        let _ = acceptor(listener, new_client_sender, packet_sender);
+        let _ = datagram_handler(datagram_socket, datagram_sender, probe_sender);
     });
 
     let mut clients = HashMap::new();
+    // The game that each player is playing, so that battles are only started between games that
+    // understand each other's battle data
+    let mut games: HashMap<PlayerId, GameInfo> = HashMap::new();
+    // The account that each logged in player is using, by its name in upper case
+    let mut logged_in: HashMap<PlayerId, String> = HashMap::new();
+    // The number of times each player has entered the Hall of Fame, if they have shared it
+    let mut hall_of_fame: HashMap<PlayerId, u8> = HashMap::new();
+    // Players whose game is paused because they have left it
+    let mut paused: HashSet<PlayerId> = HashSet::new();
+    // Clients that are only watching the server, which are not counted as players
+    let mut observers: HashSet<PlayerId> = HashSet::new();
+    // Clients in lite mode, and the players that have moved since they were last sent movement
+    let mut lite_clients: HashSet<PlayerId> = HashSet::new();
+    let mut lite_moved: HashSet<PlayerId> = HashSet::new();
+    let lite_ticker = crossbeam_channel::tick(Duration::from_secs(1) / LITE_UPDATE_RATE);
+    let mut safari_event: Option<SafariEvent> = None;
+    let mut cooldowns = RequestCooldowns::new();
+    let mut handshakes = BattleHandshakes::new();
+    let mut spectators = SpectatorChannels::new();
+    let mut movement_channels = MovementChannels::new();
+    let mut player_states = PlayerStates::new();
+    let mut seen_chats = SeenChats::new();
+    let mut movement_checks = MovementChecks::new(rate_limits.min_movement_rate);
+    let mut player_records = PlayerRecords::new();
+    // Each player can have one listing on the trade board at a time
+    let mut trade_board: HashMap<PlayerId, TradeListing> = HashMap::new();
+    // Shared effects are derived from a new seed each day
+    let server_seed = seed::generate();
+    let mut shared_seed = SharedSeed::for_day(&server_seed, shared::current_day());
+    let ticker = crossbeam_channel::tick(Duration::from_secs(1));
+    let records_ticker = crossbeam_channel::tick(players::FLUSH_INTERVAL);
+    // Servers given a master server announce themselves to it straight away, then regularly
+    let announcer = match master {
+        Some(master) => {
+            announce(&udp_socket, master);
+            crossbeam_channel::tick(master::ANNOUNCE_INTERVAL)
+        }
+        None => crossbeam_channel::never(),
+    };
     loop {
         crossbeam_channel::select! {
             recv(&packet_receiver) -> player_packet => {
                 let message = player_packet.map_err(|_| NetworkError::RecvError)?;
                 match message {
-                    NetworkEvent::FullUpdate(sender_id, _) |
-                    NetworkEvent::MovementUpdate(sender_id, _) |
-                    NetworkEvent::Chat(sender_id, _) => {
+                    NetworkEvent::MovementUpdate(sender_id, seq, movement_data) => {
+                        let generation = generation(&games, sender_id);
+                        let checked = movement_checks.check(sender_id, generation, movement_data);
+                        if let Some(movement_data) = checked {
+                            if let Some(heatmap) = &mut heatmap {
+                                heatmap.update(sender_id, movement_data);
+                            }
+                            player_states.update_movement(sender_id, seq, movement_data);
+                            lite_moved.insert(sender_id);
+                            send_movement(
+                                &mut clients,
+                                &udp_socket,
+                                &movement_channels,
+                                &lite_clients,
+                                sender_id,
+                                &NetworkEvent::MovementUpdate(sender_id, seq, movement_data),
+                            );
+                        }
+                    },
+
+                    NetworkEvent::FullUpdate(sender_id, seq, ref player_data) => {
+                        let account = logged_in.get(&sender_id);
+                        let mut player_data = match checked_name(player_data, account, &accounts) {
+                            Some(name) => PlayerData { name, ..player_data.clone() },
+                            None => player_data.clone(),
+                        };
+                        // Movement that can not be shown leaves the player where they were
+                        let generation = generation(&games, sender_id);
+                        player_data.movement_data = movement_checks
+                            .check(sender_id, generation, player_data.movement_data)
+                            .or_else(|| movement_checks.shown(sender_id))
+                            .unwrap_or(player_data.movement_data);
+                        if let Some(heatmap) = &mut heatmap {
+                            heatmap.update(sender_id, player_data.movement_data);
+                        }
+                        player_states.update(sender_id, seq, &player_data);
+                        let message = NetworkEvent::FullUpdate(sender_id, seq, player_data);
+                        for (&client_id, client_stream) in &mut clients {
+                            if client_id != sender_id {
+                                send_or_log(client_id, client_stream, &message);
+                            }
+                        }
+                    },
+
+                    NetworkEvent::WildEncounter(sender_id, ..) => {
+                        for (&client_id, client_stream) in &mut clients {
+                            if client_id != sender_id && !lite_clients.contains(&client_id) {
+                                send_or_log(client_id, client_stream, &message);
+                            }
+                        }
+                    },
+
+                    NetworkEvent::Chat(sender_id, chat_id, _) => {
+                        if seen_chats.first(sender_id, chat_id) {
+                            for (&client_id, client_stream) in &mut clients {
+                                if client_id != sender_id && !lite_clients.contains(&client_id) {
+                                    send_or_log(client_id, client_stream, &message);
+                                }
+                            }
+                        }
+                        // Let the sender know that the message was delivered
+                        if let Some(client_stream) = clients.get_mut(&sender_id) {
+                            let ack = NetworkEvent::ChatAck(chat_id);
+                            send_or_log(sender_id, client_stream, &ack);
+                        }
+                    },
+
+                    NetworkEvent::HallOfFame(sender_id, teams) => {
+                        println!("Player: {} entered the Hall of Fame", sender_id);
+                        hall_of_fame.insert(sender_id, teams);
+                        if let Some(account) = logged_in.get(&sender_id) {
+                            progress.set_hall_of_fame(account, teams, storage.as_mut());
+                        }
+                        event_log.write(Event::HallOfFame { player: sender_id, teams });
+                        for (&client_id, client_stream) in &mut clients {
+                            if client_id != sender_id {
+                                send_or_log(client_id, client_stream, &message);
+                            }
+                        }
+                    },
+
+                    NetworkEvent::PlayerPaused(sender_id, is_paused) => {
+                        if is_paused {
+                            paused.insert(sender_id);
+                        }
+                        else {
+                            paused.remove(&sender_id);
+                        }
                         for (&client_id, client_stream) in &mut clients {
                             if client_id != sender_id {
-                                send_to_client(client_stream, &message).unwrap();
+                                send_or_log(client_id, client_stream, &message);
                             }
                         }
                     },
 
+                    NetworkEvent::SafariStart(sender_id) => match &safari_event {
+                        // Only one event can run at a time, so just tell the player about it
+                        Some(event) => {
+                            if let Some(client_stream) = clients.get_mut(&sender_id) {
+                                let message = NetworkEvent::SafariUpdate(event.status());
+                                send_or_log(sender_id, client_stream, &message);
+                            }
+                        },
+                        None => {
+                            println!("Player: {} started a Safari Zone event", sender_id);
+                            event_log.write(Event::SafariStart { player: sender_id });
+                            let event = SafariEvent::new();
+                            broadcast(&mut clients, &NetworkEvent::SafariUpdate(event.status()));
+                            safari_event = Some(event);
+                        },
+                    },
+
+                    NetworkEvent::SafariCatch(sender_id, _) => {
+                        if let Some(event) = &mut safari_event {
+                            *event.catches.entry(sender_id).or_insert(0) += 1;
+                            broadcast(&mut clients, &NetworkEvent::SafariUpdate(event.status()));
+                        }
+                    },
+
                     NetworkEvent::PlayerQuit(id) => {
                         clients.remove(&id);
+                        games.remove(&id);
+                        if let Some(account) = logged_in.remove(&id) {
+                            if let Some(movement_data) = player_states.position(id) {
+                                player_records.moved(&account, movement_data);
+                            }
+                            player_records.leave(&account, storage.as_mut());
+                        }
+                        hall_of_fame.remove(&id);
+                        paused.remove(&id);
+                        if let Some(event) = &mut safari_event {
+                            event.catches.remove(&id);
+                        }
+                        cooldowns.prune(Some(id));
+                        handshakes.prune(Some(id));
+                        spectators.prune(id);
+                        movement_channels.remove(id);
+                        movement_checks.remove(id);
+                        player_states.remove(id);
+                        seen_chats.remove(id);
+                        trade_board.remove(&id);
+                        lite_clients.remove(&id);
+                        lite_moved.remove(&id);
+                        if let Some(heatmap) = &mut heatmap {
+                            heatmap.remove(id);
+                        }
                         println!("Player: {} disconnected", id);
-                        for (_, client_stream) in &mut clients {
-                            send_to_client(client_stream, &message).unwrap();
+                        event_log.write(Event::Quit { player: id });
+                        // Other players never saw observers, so are not told that they left
+                        if !observers.remove(&id) {
+                            for (&client_id, client_stream) in &mut clients {
+                                send_or_log(client_id, client_stream, &message);
+                            }
+                        }
+                        player_count.store(clients.len() - observers.len(), Ordering::Relaxed);
+                    },
+
+                    // The connection thread drops anything else that observers send, and leaving
+                    // out their game means that battle requests to them are refused
+                    NetworkEvent::Observe(id) => {
+                        println!("Player: {} is observing", id);
+                        observers.insert(id);
+                        player_count.store(clients.len() - observers.len(), Ordering::Relaxed);
+                        games.remove(&id);
+                        player_states.remove(id);
+                        movement_checks.remove(id);
+                        trade_board.remove(&id);
+                        // Movement is sent to observers over TCP, so they can not send any over UDP
+                        movement_channels.remove(id);
+                        // In case the client had already been seen as a player
+                        let quit = NetworkEvent::PlayerQuit(id);
+                        for (&client_id, client_stream) in &mut clients {
+                            if client_id != id {
+                                send_or_log(client_id, client_stream, &quit);
+                            }
+                        }
+                    },
+
+                    // Sent by the connection thread, with the client's own id
+                    NetworkEvent::LiteMode(id, on) => {
+                        println!("Player: {} lite mode {}", id, if on { "on" } else { "off" });
+                        if on {
+                            lite_clients.insert(id);
+                        }
+                        else {
+                            lite_clients.remove(&id);
+                        }
+                        forward(&mut clients, id, &message);
+                    },
+
+                    // Requests between games that can not battle each other are refused straight away
+                    NetworkEvent::BattleDataRequest(to, from)
+                        if !compatible_games(&games, from, to) =>
+                    {
+                        println!("Player: {} requested a battle with another game: {}", from, to);
+                        if let Some(client_stream) = clients.get_mut(&from) {
+                            let refusal = NetworkEvent::BattleRequestRefused(from, to);
+                            send_or_log(from, client_stream, &refusal);
+                        }
+                    },
+
+                    // New requests are dropped while on cooldown, and the sender is told so that it
+                    // does not wait for a response. Copies of an open request are passed on.
+                    NetworkEvent::BattleDataRequest(to, from) => {
+                        let repeated = handshakes.is_open(from, to);
+                        if repeated || cooldowns.allow(from, to, &message) {
+                            if !repeated {
+                                event_log.write(Event::BattleRequest { from, to });
+                            }
+                            handshakes.open(from, to);
+                            forward(&mut clients, to, &message);
+                        }
+                        else {
+                            println!("Player: {} sent too many battle requests to: {}", from, to);
+                            event_log.write(Event::BattleRequestRefused { from, to });
+                            if let Some(client_stream) = clients.get_mut(&from) {
+                                let refusal = NetworkEvent::BattleRequestRefused(from, to);
+                                send_or_log(from, client_stream, &refusal);
+                            }
+                        }
+                    },
+
+                    // Further responses are answers to copies of the same request
+                    NetworkEvent::BattleDataResponse(to, from, _) => {
+                        if handshakes.close(to, from) {
+                            event_log.write(Event::BattleResponse { from, to });
+                            forward(&mut clients, to, &message);
+                        }
+                    },
+
+                    NetworkEvent::BattleRequestCancel(to, from) => {
+                        handshakes.close(from, to);
+                        forward(&mut clients, to, &message);
+                    },
+
+                    // Answers any open battle request from the recipient, which will not get a
+                    // response
+                    NetworkEvent::AwayMessage(to, from, _) => {
+                        handshakes.close(to, from);
+                        forward(&mut clients, to, &message);
+                    },
+
+                    // Relayed battles start as soon as the battle data arrives, so it is dropped
+                    // while on cooldown
+                    NetworkEvent::RelayBattleData(to, from, _)
+                        if !compatible_games(&games, from, to) =>
+                    {
+                        println!("Player: {} requested a battle with another game: {}", from, to);
+                    },
+
+                    NetworkEvent::RelayBattleData(to, from, _)
+                        if !cooldowns.allow(from, to, &message) =>
+                    {
+                        println!("Player: {} sent too many battle requests to: {}", from, to);
+                        event_log.write(Event::BattleRequestRefused { from, to });
+                    },
+
+                    NetworkEvent::RelayBattleData(to, from, _) => {
+                        event_log.write(Event::RelayBattle { from, to });
+                        handshakes.start_battle(from, to);
+                        forward(&mut clients, to, &message);
+                        introduce_peers(&mut clients, &movement_channels, from, to);
+                    },
+
+                    // Only forfeits of a battle that the server saw start are counted in the players'
+                    // records, so that players can not give each other wins
+                    NetworkEvent::BattleForfeit(to, from) => {
+                        event_log.write(Event::BattleForfeit { from, to });
+                        if handshakes.end_battle(from, to) {
+                            if let Some(account) = logged_in.get(&from) {
+                                player_records.battle_lost(account);
+                            }
+                            if let Some(account) = logged_in.get(&to) {
+                                player_records.battle_won(account);
+                            }
+                        }
+                        forward(&mut clients, to, &message);
+                    },
+
+                    NetworkEvent::TradeListingPost(player, species, level, wanted) => {
+                        let full = trade_board.len() >= MAX_TRADE_LISTINGS;
+                        if !full || trade_board.contains_key(&player) {
+                            let listing = TradeListing { player, species, level, wanted };
+                            trade_board.insert(player, listing);
+                            if let Some(account) = logged_in.get(&player) {
+                                progress.set_listing(account, Some(listing), storage.as_mut());
+                            }
+                            event_log.write(Event::TradeListing { player, species, level, wanted });
                         }
+                        send_trade_board(&mut clients, &trade_board, player);
                     },
 
-                    NetworkEvent::BattleDataRequest(to, _) |
-                    NetworkEvent::BattleDataResponse(to, _) => {
-                        send_to_client(clients.get_mut(&to).unwrap(), &message).unwrap();
+                    NetworkEvent::TradeListingRemove(player) => {
+                        trade_board.remove(&player);
+                        if let Some(account) = logged_in.get(&player) {
+                            progress.set_listing(account, None, storage.as_mut());
+                        }
+                        send_trade_board(&mut clients, &trade_board, player);
+                    },
+
+                    NetworkEvent::TradeBoardRequest(player) => {
+                        send_trade_board(&mut clients, &trade_board, player);
+                    },
+
+                    // Offers can only be made for listings that are still on the board
+                    NetworkEvent::TradeOffer(to, from, ..) => {
+                        if trade_board.contains_key(&to) && cooldowns.allow(from, to, &message) {
+                            event_log.write(Event::TradeOffer { from, to });
+                            forward(&mut clients, to, &message);
+                        }
+                    },
+
+                    // Battle snapshots are not sent to or from lite clients, so the watcher is told that
+                    // there is nothing to watch
+                    NetworkEvent::SpectateRequest(to, from)
+                        if lite_clients.contains(&to) || lite_clients.contains(&from) =>
+                    {
+                        forward(&mut clients, from, &NetworkEvent::SpectateEnd(from, to));
+                    },
+
+                    NetworkEvent::SpectateRequest(to, from) => {
+                        spectators.join(to, from);
+                        forward(&mut clients, to, &message);
+                    },
+
+                    NetworkEvent::SpectateStop(to, from) => {
+                        spectators.leave(to, from);
+                        forward(&mut clients, to, &message);
+                    },
+
+                    // Sent by the player whose battle it is
+                    NetworkEvent::SpectateEnd(to, from) => {
+                        spectators.leave(from, to);
+                        forward(&mut clients, to, &message);
+                    },
+
+                    NetworkEvent::BattleChat(battle, from, chat_id, _) => {
+                        match spectators.recipients(battle, from) {
+                            Some(recipients) => {
+                                if seen_chats.first(from, chat_id) {
+                                    for to in recipients {
+                                        forward(&mut clients, to, &message);
+                                    }
+                                }
+                                if let Some(client_stream) = clients.get_mut(&from) {
+                                    let ack = NetworkEvent::ChatAck(chat_id);
+                                    send_or_log(from, client_stream, &ack);
+                                }
+                            },
+                            None => {
+                                println!("Player: {} is not in the battle of: {}", from, battle);
+                            },
+                        }
+                    },
+
+                    // Players pay for items in the game, so the server only checks what was bought
+                    NetworkEvent::ShopPurchase(player, item, quantity) => {
+                        match shop.iter().find(|shop_item| shop_item.item == item) {
+                            Some(shop_item) => event_log.write(Event::ShopPurchase {
+                                player,
+                                item,
+                                quantity,
+                                price: shop_item.price * quantity as u32,
+                            }),
+                            None => println!("Player: {} bought an item that is not sold", player),
+                        }
                     },
 
-                    _ => unimplemented!(),
+                    NetworkEvent::Ping(sender_id, timestamp) => {
+                        if let Some(client_stream) = clients.get_mut(&sender_id) {
+                            send_or_log(sender_id, client_stream, &NetworkEvent::Pong(timestamp));
+                        }
+                    },
+
+                    NetworkEvent::BattleSeedCommit(to, ..) |
+                    NetworkEvent::BattleSeed(to, ..) |
+                    NetworkEvent::BattleSeedReveal(to, ..) |
+                    NetworkEvent::BattleAction(to, ..) |
+                    NetworkEvent::BattleQuickChat(to, ..) |
+                    NetworkEvent::BattleRules(to, ..) |
+                    NetworkEvent::BattleItemUsed(to, _) |
+                    NetworkEvent::BattleKeepAlive(to, _) |
+                    NetworkEvent::PeerPacket(to, ..) |
+                    NetworkEvent::BattleSnapshot(to, ..) => forward(&mut clients, to, &message),
+
+                    // Snapshots can be large, so they are limited by the same cooldown as requests
+                    NetworkEvent::ResyncRequest(sender_id) => {
+                        if cooldowns.allow(sender_id, sender_id, &message) {
+                            if let Some(client_stream) = clients.get_mut(&sender_id) {
+                                for snapshot in player_states.snapshots(sender_id) {
+                                    send_or_log(sender_id, client_stream, &snapshot);
+                                }
+                            }
+                        }
+                    },
+
+                    // Reports are anonymous, so the sender is not recorded
+                    NetworkEvent::UsageReport(report) => {
+                        if let Some(usage_stats) = &mut usage_stats {
+                            usage_stats.add(report, storage.as_mut());
+                        }
+                    },
+
+                    // Only sent between players over UDP, or by the server
+                    NetworkEvent::PeerAddress(..) |
+                    NetworkEvent::PeerHello(..) |
+                    NetworkEvent::PeerAck(..) => println!("Ignoring a peer message over TCP"),
+
+                    // Anything else is dropped by the connection thread, but a client must never
+                    // be able to stop the server
+                    _ => println!("Ignoring an unexpected {} from a client", message.name()),
+                }
+            },
+
+            // Movement updates sent over UDP are handled the same as ones sent over TCP
+            recv(datagram_receiver) -> datagram => {
+                let (addr, token, message) = datagram.map_err(|_| NetworkError::RecvError)?;
+                match message {
+                    NetworkEvent::MovementUpdate(sender_id, seq, movement_data)
+                        if movement_channels.accept(token, sender_id, addr) =>
+                    {
+                        let generation = generation(&games, sender_id);
+                        let checked = movement_checks.check(sender_id, generation, movement_data);
+                        if let Some(movement_data) = checked {
+                            if let Some(heatmap) = &mut heatmap {
+                                heatmap.update(sender_id, movement_data);
+                            }
+                            player_states.update_movement(sender_id, seq, movement_data);
+                            lite_moved.insert(sender_id);
+                            send_movement(
+                                &mut clients,
+                                &udp_socket,
+                                &movement_channels,
+                                &lite_clients,
+                                sender_id,
+                                &NetworkEvent::MovementUpdate(sender_id, seq, movement_data),
+                            );
+                        }
+                    },
+                    _ => println!("Received a datagram with an unknown token from: {}", addr),
+                }
+            },
+
+            // Clients searching the local network for servers
+            recv(probe_receiver) -> addr => {
+                let addr = addr.map_err(|_| NetworkError::RecvError)?;
+                let players = clients.len() - observers.len();
+                let answer = discovery::encode_answer(&name, players as u32);
+                if let Err(e) = udp_socket.send_to(&answer, addr) {
+                    println!("Failed to answer a search from {}: {}", addr, e);
+                }
+            },
+
+            // Lite clients are only sent the newest position of each player that has moved
+            recv(lite_ticker) -> _ => {
+                for id in lite_moved.drain() {
+                    let movement = match player_states.movement(id) {
+                        Some(movement) => movement,
+                        None => continue,
+                    };
+                    for &client_id in &lite_clients {
+                        if client_id != id {
+                            forward(&mut clients, client_id, &movement);
+                        }
+                    }
+                }
+            },
+
+            // Positions are only taken from the latest update when records are written, rather than
+            // with every step
+            recv(records_ticker) -> _ => {
+                for (&id, account) in &logged_in {
+                    if let Some(movement_data) = player_states.position(id) {
+                        player_records.moved(account, movement_data);
+                    }
+                }
+                player_records.flush(storage.as_mut());
+            },
+
+            recv(announcer) -> _ => {
+                if let Some(master) = master {
+                    announce(&udp_socket, master);
                 }
             },
 
             // Handle new clients
             recv(new_client_receiver) -> packet => {
-                let (id, sender) = packet.map_err(|_| NetworkError::RecvError)?;
-                println!("New client connected, id: {}", id);
-                clients.insert(id, sender);
+                let NewClient { id, connection, token, game, account } =
+                    packet.map_err(|_| NetworkError::RecvError)?;
+                match &account {
+                    Some(account) => println!("New client connected, id: {} ({})", id, account),
+                    None => println!("New client connected, id: {}", id),
+                }
+                games.insert(id, game);
+                if let Some(account) = account {
+                    player_records.join(&account, storage.as_ref());
+                    // Other players are sent these below along with everyone else's
+                    if let Some(teams) = progress.hall_of_fame(&account) {
+                        hall_of_fame.insert(id, teams);
+                    }
+                    if let Some(listing) = progress.listing(&account, id) {
+                        if trade_board.len() < MAX_TRADE_LISTINGS {
+                            trade_board.insert(id, listing);
+                        }
+                    }
+                    logged_in.insert(id, account);
+                }
+                event_log.write(Event::Join { player: id });
+                // Packets from the client are only read once it has been added here
+                let compression = connection.compression();
+                let command = ConnectionCommand::Add(id, connection);
+                connection_sender.send(command).map_err(|_| NetworkError::SendError)?;
+                let connections = connection_sender.clone();
+                clients.insert(id, ClientStream { id, connections, compression });
+                movement_channels.register(id, token);
+                player_count.store(clients.len() - observers.len(), Ordering::Relaxed);
+
+                if let Some(client_stream) = clients.get_mut(&id) {
+                    send_or_log(id, client_stream, &NetworkEvent::RateLimits(rate_limits));
+                    send_or_log(id, client_stream, &NetworkEvent::SharedSeed(shared_seed));
+                }
+
+                // Let the new client know about players that have already entered the Hall of Fame
+                if let Some(client_stream) = clients.get_mut(&id) {
+                    for (&player_id, &teams) in &hall_of_fame {
+                        let message = NetworkEvent::HallOfFame(player_id, teams);
+                        send_or_log(id, client_stream, &message);
+                    }
+                    for &player_id in &paused {
+                        let message = NetworkEvent::PlayerPaused(player_id, true);
+                        send_or_log(id, client_stream, &message);
+                    }
+                    if let Some(event) = &safari_event {
+                        send_or_log(id, client_stream, &NetworkEvent::SafariUpdate(event.status()));
+                    }
+                    if !shop.is_empty() {
+                        send_or_log(id, client_stream, &NetworkEvent::ShopCatalog(shop.clone()));
+                    }
+                    if let Some(length_secs) = day_length {
+                        let cycle = DayCycle { server_time: shared::now_secs(), length_secs };
+                        send_or_log(id, client_stream, &NetworkEvent::DayCycle(cycle));
+                    }
+                    if visibility != Visibility::Everyone {
+                        send_or_log(id, client_stream, &NetworkEvent::Visibility(visibility));
+                    }
+                    if let Some(motd) = &config.motd {
+                        send_or_log(id, client_stream, &NetworkEvent::Motd(motd.clone()));
+                    }
+                }
 
                 // Tell connected clients that they need to send an update to the new client
-                for (_, client_stream) in &mut clients {
-                    send_to_client(client_stream, &NetworkEvent::UpdateRequest).unwrap();
+                for (&client_id, client_stream) in &mut clients {
+                    send_or_log(client_id, client_stream, &NetworkEvent::UpdateRequest);
                 }
+            },
 
+            // End the Safari Zone event once its time has run out, and start each new day
+            recv(ticker) -> _ => {
+                if let Some(heatmap) = &mut heatmap {
+                    heatmap.sample();
+                }
+                cooldowns.prune(None);
+                handshakes.prune(None);
+                let day = shared::current_day();
+                if day != shared_seed.day {
+                    if let Err(e) = event_log.prune() {
+                        println!("Failed to remove old events from the event log: {}", e);
+                    }
+                    shared_seed = SharedSeed::for_day(&server_seed, day);
+                    broadcast(&mut clients, &NetworkEvent::SharedSeed(shared_seed));
+                }
+                if matches!(&safari_event, Some(event) if event.ends_at <= Instant::now()) {
+                    let event = safari_event.take().unwrap();
+                    println!("Safari Zone event ended");
+                    event_log.write(Event::SafariEnd { leaderboard: event.status().leaderboard });
+                    broadcast(&mut clients, &NetworkEvent::SafariUpdate(event.status()));
+                }
             },
         }
     }
 }
 
-fn send_to_client(client_stream: &mut TcpStream, message: &NetworkEvent) -> io::Result<usize> {
-    let encoded_message = serde_json::to_vec(&message).unwrap();
-    client_stream.write(&encoded_message)?;
-    client_stream.write("\n".as_bytes())
+/// The generation of the game that a player is playing, if it is one that the interface supports
+fn generation(games: &HashMap<PlayerId, GameInfo>, id: PlayerId) -> Option<Generation> {
+    rom::title_generation(&games.get(&id)?.title)
+}
+
+/// Pass a message on to the player it is addressed to
+fn forward(clients: &mut HashMap<PlayerId, ClientStream>, to: PlayerId, message: &NetworkEvent) {
+    match clients.get_mut(&to) {
+        Some(client_stream) => send_or_log(to, client_stream, message),
+        None => println!("Battle message for unknown player: {}", to),
+    }
+}
+
+/// Let the players in a relayed battle know where to send battle messages to each other directly,
+/// if both of them are using UDP. Each pair is given a new token for their datagrams.
+fn introduce_peers(
+    clients: &mut HashMap<PlayerId, ClientStream>,
+    movement_channels: &MovementChannels,
+    a: PlayerId,
+    b: PlayerId,
+) {
+    let (a_addr, b_addr) = match (movement_channels.route(a), movement_channels.route(b)) {
+        (Some((_, a_addr)), Some((_, b_addr))) => (a_addr, b_addr),
+        _ => return,
+    };
+    let token = u64::from_le_bytes(seed::generate()[..8].try_into().unwrap());
+    for (to, peer, addr) in [(a, b, b_addr), (b, a, a_addr)] {
+        if let Some(client_stream) = clients.get_mut(&to) {
+            send_or_log(to, client_stream, &NetworkEvent::PeerAddress(to, peer, addr, token));
+        }
+    }
+}
+
+/// Whether two players are playing games that can battle each other
+fn compatible_games(games: &HashMap<PlayerId, GameInfo>, a: PlayerId, b: PlayerId) -> bool {
+    match (games.get(&a), games.get(&b)) {
+        (Some(a), Some(b)) => game::compatible(a, b),
+        _ => false,
+    }
+}
+
+/// Send a movement update to every other player, over UDP to players that are using it. Players in
+/// lite mode are left out, since they are sent the newest movement on a timer instead.
+fn send_movement(
+    clients: &mut HashMap<PlayerId, ClientStream>,
+    udp_socket: &UdpSocket,
+    movement_channels: &MovementChannels,
+    lite_clients: &HashSet<PlayerId>,
+    sender_id: PlayerId,
+    message: &NetworkEvent,
+) {
+    for (&client_id, client_stream) in clients {
+        if client_id == sender_id || lite_clients.contains(&client_id) {
+            continue;
+        }
+        match movement_channels.route(client_id) {
+            Some((token, addr)) => {
+                let sent = codec::encode_datagram(token, message)
+                    .and_then(|datagram| Ok(udp_socket.send_to(&datagram, addr)?));
+                if let Err(e) = sent {
+                    println!("Failed to send a datagram to player {}: {}", client_id, e);
+                }
+            }
+            None => send_or_log(client_id, client_stream, message),
+        }
+    }
+}
+
+/// Send every listing on the trade board to a player
+fn send_trade_board(
+    clients: &mut HashMap<PlayerId, ClientStream>,
+    trade_board: &HashMap<PlayerId, TradeListing>,
+    to: PlayerId,
+) {
+    let mut listings: Vec<TradeListing> = trade_board.values().copied().collect();
+    listings.sort_by_key(|listing| listing.player);
+    forward(clients, to, &NetworkEvent::TradeBoard(listings));
+}
+
+/// Send a message to every connected client
+fn broadcast(clients: &mut HashMap<PlayerId, ClientStream>, message: &NetworkEvent) {
+    for (&client_id, client_stream) in clients {
+        send_or_log(client_id, client_stream, message);
+    }
+}
+
+fn send_to_client(client_stream: &mut ClientStream, message: &NetworkEvent) -> NetworkResult<()> {
+    let encoded_message = codec::encode_packet(message, client_stream.compression)?;
+    let priority = Priority::of(message);
+    let command = ConnectionCommand::Send(client_stream.id, encoded_message, priority);
+    client_stream.connections.send(command).map_err(|_| NetworkError::SendError)
+}
+
+/// Send a message to a client, logging any failures. Failures are not fatal since the connection
+/// thread will report the disconnection separately.
+fn send_or_log(id: PlayerId, client_stream: &mut ClientStream, message: &NetworkEvent) {
+    if let Err(e) = send_to_client(client_stream, message) {
+        println!("Failed to send to player {}: {}", id, e);
+    }
 }
 
 fn acceptor(
     listener: TcpListener,
-    new_client_sender: crossbeam_channel::Sender<(u32, TcpStream)>,
-    server_sender: crossbeam_channel::Sender<NetworkEvent>,
-) -> NetworkResult<()> {
+    new_client_sender: crossbeam_channel::Sender<NewClient>,
+    transport: TransportOptions,
+    admission: Admission,
+) {
+    let transport = Arc::new(transport);
+    let admission = Arc::new(admission);
+    let handshakes = Arc::new(AtomicUsize::new(0));
     let mut next_id = 0;
 
-    for stream in listener.incoming() {
-        let mut stream = stream?;
-        if let Err(e) = send_to_client(&mut stream, &NetworkEvent::PlayerJoin(next_id)) {
-            println!("Failed to communicate with client: {}", e);
+    for socket in listener.incoming() {
+        let socket = match socket {
+            Ok(socket) => socket,
+            Err(e) => {
+                println!("Failed to accept a client: {}", e);
+                thread::sleep(ACCEPT_RETRY_DELAY);
+                continue;
+            }
+        };
+        if handshakes.load(Ordering::Relaxed) >= MAX_HANDSHAKES {
+            println!("Turned away a client, since too many are joining at once");
+            let _ = socket.shutdown(Shutdown::Both);
             continue;
         }
 
-        let client = Client {
-            id: next_id,
-            client_stream: stream.try_clone()?,
-            server_sender: server_sender.clone(),
-        };
-
+        handshakes.fetch_add(1, Ordering::Relaxed);
+        let id = next_id;
+        next_id += 1;
+        let new_client_sender = new_client_sender.clone();
+        let transport = transport.clone();
+        let admission = admission.clone();
+        let handshakes = handshakes.clone();
         thread::spawn(move || {
-            let _ = client_handler(client);
+            accept_client(id, socket, &transport, &admission, &new_client_sender);
+            handshakes.fetch_sub(1, Ordering::Relaxed);
         });
-        new_client_sender.send((next_id, stream)).map_err(|_| NetworkError::SendError)?;
+    }
+}
 
-        next_id += 1;
+/// Run the handshakes with a new client, and pass it on to the main thread if it joins
+fn accept_client(
+    id: PlayerId,
+    socket: TcpStream,
+    transport: &TransportOptions,
+    admission: &Admission,
+    new_client_sender: &crossbeam_channel::Sender<NewClient>,
+) {
+    let accepted = socket
+        .try_clone()
+        .map_err(NetworkError::from)
+        .and_then(|stream| handshake(stream, transport, admission));
+    let Handshake { stream, compression, game, account } = match accepted {
+        Ok(accepted) => accepted,
+        Err(e) => {
+            println!("Rejected a client: {}", e);
+            let _ = socket.shutdown(Shutdown::Both);
+            return;
+        }
+    };
+
+    let mut connection = match Connection::new(stream, compression) {
+        Ok(connection) => connection,
+        Err(e) => {
+            println!("Failed to communicate with client: {}", e);
+            return;
+        }
+    };
+    let token = u64::from_le_bytes(seed::generate()[..8].try_into().unwrap());
+    let joined = connection
+        .send(&NetworkEvent::PlayerJoin(id))
+        .and_then(|_| connection.send(&NetworkEvent::MovementChannel(token)));
+    if let Err(e) = joined {
+        println!("Failed to communicate with client: {}", e);
+        return;
     }
 
-    Ok(())
+    // The main thread has stopped if this fails, and the server with it
+    let _ = new_client_sender.send(NewClient { id, connection, token, game, account });
 }
 
-fn client_handler(client: Client) -> NetworkResult<()> {
-    let mut client_stream = BufReader::new(client.client_stream);
-    let mut data = String::new();
+/// Start TLS and WebSocket framing with a new client if the server uses them, then exchange
+/// protocol versions, agree on how packets sent to it are compressed, and check the game it is
+/// playing. Clients check the server's version themselves, so one that is not compatible already
+/// knows why it is disconnected, and clients playing a game that is not allowed, or joining while
+/// the server is full, are told so. Finally the client's login is checked, returning the account it
+/// logged in to, or None for a guest.
+fn handshake(
+    socket: TcpStream,
+    options: &TransportOptions,
+    admission: &Admission,
+) -> NetworkResult<Handshake> {
+    socket.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+    let mut stream: Box<dyn Transport> = match &options.tls {
+        Some(tls_config) => Box::new(tls::accept(socket, tls_config.clone())?),
+        None => Box::new(socket),
+    };
+    if options.websocket {
+        stream = Box::new(websocket::accept(stream)?);
+    }
+
+    version::write_preamble(&mut stream)?;
+    codec::write_compression(&mut stream, options.compression)?;
+    version::check(version::read_preamble(&mut stream)?)?;
+    let compression =
+        codec::agreed_compression(options.compression, codec::read_compression(&mut stream)?);
+
+    let game = game::read_game(&mut stream)?;
+    if !admission.allowed_games.allows(&game) {
+        game::write_verdict(&mut stream, Verdict::GameRejected)?;
+        println!("Turned away a client playing: {} (revision {})", game.title, game.revision);
+        return Err(NetworkError::GameRejected);
+    }
+    if admission.is_full() {
+        game::write_verdict(&mut stream, Verdict::ServerFull)?;
+        return Err(NetworkError::ServerFull);
+    }
+    game::write_verdict(&mut stream, Verdict::Allowed)?;
+
+    let login = match account::read_login(&mut stream)? {
+        Some(login) => login,
+        None => {
+            account::write_answer(&mut stream, Ok(None))?;
+            return Ok(Handshake { stream, compression, game, account: None });
+        }
+    };
+    // Other clients may be logging in at the same time, so neither lock is held while answering
+    let logged_in = {
+        let mut accounts = admission.accounts.lock().unwrap();
+        let mut account_storage = admission.account_storage.lock().unwrap();
+        accounts.login(&login, account_storage.as_mut())
+    };
+    match logged_in {
+        Ok((account, token)) => {
+            account::write_answer(&mut stream, Ok(token.as_deref()))?;
+            Ok(Handshake { stream, compression, game, account: Some(account) })
+        }
+        Err(e) => {
+            account::write_answer(&mut stream, Err(e))?;
+            println!("Refused a login to the account: {:?}", login.name);
+            Err(NetworkError::LoginRefused(e))
+        }
+    }
+}
+
+/// The name that other players see a player under, if it is not the name that they sent. Players
+/// logged in to an account are shown under its name, and guests that send a name with an account
+/// are shown as `GUEST_NAME`, so that no one can pass as someone else.
+fn checked_name(
+    player_data: &PlayerData,
+    account: Option<&String>,
+    accounts: &Mutex<Accounts>,
+) -> Option<Vec<u8>> {
+    let name = text::decode(&player_data.name).to_uppercase();
+    let shown = match account {
+        Some(account) if name != *account => account.as_str(),
+        None if accounts.lock().unwrap().is_registered(&name) => GUEST_NAME,
+        _ => return None,
+    };
+    Some(text::Encoder::new(shown).collect())
+}
+
+/// Ask the master server to list this server. It checks that the server can be reached by probing it
+/// like clients on the local network do.
+fn announce(udp_socket: &UdpSocket, master: SocketAddr) {
+    let port = udp_socket.local_addr().map_or(DEFAULT_PORT, |addr| addr.port());
+    if let Err(e) = udp_socket.send_to(&master::encode_announcement(port), master) {
+        println!("Failed to announce the server to {}: {}", master, e);
+    }
+}
+
+/// Receive movement updates sent over UDP, passing them on with the address they came from, along
+/// with the addresses of clients searching for servers
+fn datagram_handler(
+    udp_socket: UdpSocket,
+    datagram_sender: crossbeam_channel::Sender<(SocketAddr, u64, NetworkEvent)>,
+    probe_sender: crossbeam_channel::Sender<SocketAddr>,
+) -> NetworkResult<()> {
+    let mut buffer = [0; codec::MAX_DATAGRAM_SIZE];
     loop {
-        match client_stream.read_line(&mut data) {
-            Ok(_) => {
-                let packet = serde_json::from_str(&data).unwrap();
-                client.server_sender.send(packet).map_err(|_| NetworkError::SendError)?;
+        let (len, addr) = match udp_socket.recv_from(&mut buffer) {
+            Ok(received) => received,
+            Err(e) => {
+                println!("Failed to receive a datagram: {}", e);
+                continue;
             }
+        };
+        if discovery::is_probe(&buffer[..len]) {
+            probe_sender.send(addr).map_err(|_| NetworkError::SendError)?;
+            continue;
+        }
+        match codec::decode_datagram(&buffer[..len]) {
+            Ok((token, packet)) => {
+                datagram_sender.send((addr, token, packet)).map_err(|_| NetworkError::SendError)?;
+            }
+            Err(_) => println!("Received an invalid datagram from: {}", addr),
+        }
+    }
+}
 
-            Err(_) => {
-                let packet = NetworkEvent::PlayerQuit(client.id);
-                client.server_sender.send(packet).map_err(|_| NetworkError::SendError)?;
-                return Ok(());
+/// Serve every client's connection on a single thread, passing on the packets that clients send
+/// and sending them the packets queued for them. Players are reported as having quit once their
+/// connection closes or times out.
+fn connection_handler(
+    commands: poll::Receiver<ConnectionCommand>,
+    server_sender: crossbeam_channel::Sender<NetworkEvent>,
+) -> NetworkResult<()> {
+    let mut connections: HashMap<PlayerId, Connection> = HashMap::new();
+    // Connections that have asked to only watch the server
+    let mut observers: HashSet<PlayerId> = HashSet::new();
+    // Connections in lite mode, whether they asked for it or fell behind
+    let mut lite: HashSet<PlayerId> = HashSet::new();
+    let mut poller = Poller::new();
+    // The index that each connection was added to the poller at
+    let mut polled: Vec<(PlayerId, usize)> = vec![];
+    loop {
+        loop {
+            match commands.try_recv() {
+                Ok(ConnectionCommand::Add(id, connection)) => {
+                    connections.insert(id, connection);
+                }
+                Ok(ConnectionCommand::Send(id, packet, priority)) => {
+                    if let Some(connection) = connections.get_mut(&id) {
+                        connection.send_encoded(packet, priority);
+                    }
+                }
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => return Ok(()),
+            }
+        }
+
+        let mut quit = vec![];
+        for (id, index) in polled.drain(..) {
+            let connection = match connections.get_mut(&id) {
+                Some(connection) if poller.is_ready(index) => connection,
+                _ => continue,
+            };
+            let packets = match connection.receive() {
+                Ok(packets) => packets,
+                // The connection can no longer be read from
+                Err(_) => {
+                    quit.push(id);
+                    continue;
+                }
+            };
+            for packet in packets {
+                match packet {
+                    // Clients say when they are leaving, which closes the connection the same way
+                    // as if it had dropped. The id in the packet is not trusted.
+                    Ok(NetworkEvent::PlayerQuit(_)) => {
+                        quit.push(id);
+                        break;
+                    }
+                    Ok(NetworkEvent::Observe(_)) => {
+                        observers.insert(id);
+                        let packet = NetworkEvent::Observe(id);
+                        server_sender.send(packet).map_err(|_| NetworkError::SendError)?;
+                    }
+                    // Observers can only keep their connection alive and catch up on other
+                    // players, and the ids they send are replaced with their own
+                    Ok(NetworkEvent::Ping(_, timestamp)) if observers.contains(&id) => {
+                        let packet = NetworkEvent::Ping(id, timestamp);
+                        server_sender.send(packet).map_err(|_| NetworkError::SendError)?;
+                    }
+                    Ok(NetworkEvent::ResyncRequest(_)) if observers.contains(&id) => {
+                        let packet = NetworkEvent::ResyncRequest(id);
+                        server_sender.send(packet).map_err(|_| NetworkError::SendError)?;
+                    }
+                    Ok(_) if observers.contains(&id) => {}
+                    Ok(NetworkEvent::LiteMode(_, on)) => {
+                        if on {
+                            lite.insert(id);
+                        }
+                        else {
+                            lite.remove(&id);
+                        }
+                        let packet = NetworkEvent::LiteMode(id, on);
+                        server_sender.send(packet).map_err(|_| NetworkError::SendError)?;
+                    }
+                    Ok(packet) if packet.is_from_server() => {
+                        println!("Player: {} sent {}, which only servers send", id, packet.name());
+                    }
+                    // Players can only send events as themselves
+                    Ok(packet) if packet.sender().is_some_and(|sender| sender != id) => {
+                        println!("Player: {} sent {} as another player", id, packet.name());
+                    }
+                    Ok(packet) => server_sender.send(packet).map_err(|_| NetworkError::SendError)?,
+                    // Skip invalid packets, they will not be forwarded to other players
+                    Err(_) => println!("Received an invalid packet from player: {}", id),
+                }
+            }
+            if connection.is_closed() {
+                quit.push(id);
+            }
+        }
+
+        for (&id, connection) in &mut connections {
+            // Clients ping the server regularly, so one that has been silent for this long has gone
+            // away without closing its connection
+            if connection.received_at().elapsed() >= CONNECTION_TIMEOUT {
+                println!("Player: {} timed out", id);
+                quit.push(id);
+            }
+            else if connection.flush().is_err() {
+                quit.push(id);
+            }
+            else if connection.queued_len() >= CONGESTED_QUEUE_LEN && lite.insert(id) {
+                println!("Player: {} can not keep up, switching to lite mode", id);
+                let packet = NetworkEvent::LiteMode(id, true);
+                server_sender.send(packet).map_err(|_| NetworkError::SendError)?;
+            }
+        }
+
+        for id in quit {
+            if let Some(connection) = connections.remove(&id) {
+                observers.remove(&id);
+                lite.remove(&id);
+                let _ = connection.tcp().shutdown(Shutdown::Both);
+                let packet = NetworkEvent::PlayerQuit(id);
+                server_sender.send(packet).map_err(|_| NetworkError::SendError)?;
             }
         }
-        data.clear();
+
+        poller.clear();
+        poller.add(commands.waker(), false);
+        for (&id, connection) in &connections {
+            polled.push((id, poller.add(connection.tcp(), connection.wants_write())));
+        }
+        poller.wait(TIMEOUT_CHECK_INTERVAL)?;
+    }
+}
+
+/// Read the items sold by the shop from a JSON file, e.g. `[{ "item": 4, "price": 200 }]`
+fn load_shop(path: &str) -> Result<Vec<ShopItem>, String> {
+    let file = std::fs::read(path).map_err(|e| e.to_string())?;
+    let shop: Vec<ShopItem> = serde_json::from_slice(&file).map_err(|e| e.to_string())?;
+    if shop.len() > MAX_SHOP_ITEMS {
+        return Err(format!("the shop can sell at most {} items", MAX_SHOP_ITEMS));
     }
+    match shop.iter().find(|item| !codec::valid_shop_item(item)) {
+        Some(item) => Err(format!("item {} can not be sold for {}", item.item, item.price)),
+        None => Ok(shop),
+    }
+}
+
+/// Open the storage in `dir`, as a handle for the main thread and one for the handshake threads
+fn open_storage(
+    dir: &Path,
+    json_storage: bool,
+) -> io::Result<(Box<dyn Storage>, Box<dyn Storage>)> {
+    if json_storage {
+        let storage = FileStorage::open(dir)?;
+        Ok((Box::new(storage.clone()), Box::new(storage)))
+    }
+    else {
+        let storage = SqliteStorage::open(dir)?;
+        Ok((Box::new(storage.try_clone()?), Box::new(storage)))
+    }
+}
+
+/// The value given for a command line option, e.g. `--event-log events.jsonl`
+fn option_value(args: &[String], name: &str) -> Option<String> {
+    let index = args.iter().position(|arg| arg == name)?;
+    args.get(index + 1).cloned()
 }
 
 fn main() {
-    if let Err(e) = run_server("0.0.0.0:8080") {
+    let args: Vec<_> = std::env::args().skip(1).collect();
+
+    // Settings that are not given on the command line, e.g. `--config server.toml`
+    let config = match option_value(&args, "--config") {
+        Some(path) => match ServerConfig::load(Path::new(&path)) {
+            Ok(config) => config,
+            Err(e) => {
+                println!("Invalid server config in {}: {}", path, e);
+                return;
+            }
+        },
+        None => ServerConfig::default(),
+    };
+
+    // Events are only logged if a file is given for them
+    let retention_days = match option_value(&args, "--event-retention-days") {
+        Some(days) => match days.parse() {
+            Ok(days) => days,
+            Err(_) => {
+                println!("Invalid number of days to keep events for: {}", days);
+                return;
+            }
+        },
+        None => events::DEFAULT_RETENTION_DAYS,
+    };
+    let event_log = match option_value(&args, "--event-log") {
+        Some(path) => match EventLog::open(PathBuf::from(&path), retention_days) {
+            Ok(log) => log,
+            Err(e) => {
+                println!("Failed to open the event log at {}: {}", path, e);
+                return;
+            }
+        },
+        None => EventLog::disabled(),
+    };
+
+    // The shop is only opened if the server is given a list of items to sell
+    let shop = match option_value(&args, "--shop") {
+        Some(path) => match load_shop(&path) {
+            Ok(shop) => shop,
+            Err(e) => {
+                println!("Failed to load the shop from {}: {}", path, e);
+                return;
+            }
+        },
+        None => vec![],
+    };
+
+    // Player positions are only recorded if a directory is given for the heatmap
+    let heatmap = match option_value(&args, "--heatmap") {
+        Some(dir) => match Heatmap::new(PathBuf::from(&dir)) {
+            Ok(heatmap) => Some(heatmap),
+            Err(e) => {
+                println!("Failed to create the heatmap directory {}: {}", dir, e);
+                return;
+            }
+        },
+        None => None,
+    };
+
+    // Data is only kept after the server stops if it is given a directory to keep it in. Accounts
+    // are checked by the thread that accepts clients, so they are given their own handle to it.
+    let json_storage = args.iter().any(|arg| arg == "--json-storage");
+    let (storage, account_storage): (Box<dyn Storage>, Box<dyn Storage>) =
+        match option_value(&args, "--data-dir") {
+            Some(dir) => match open_storage(Path::new(&dir), json_storage) {
+                Ok(storage) => storage,
+                Err(e) => {
+                    println!("Failed to open the data directory {}: {}", dir, e);
+                    return;
+                }
+            },
+            None => (Box::new(MemoryStorage::new()), Box::new(MemoryStorage::new())),
+        };
+    let accounts = match Accounts::load(account_storage.as_ref()) {
+        Ok(accounts) => accounts,
+        Err(e) => {
+            println!("Failed to load the accounts: {}", e);
+            return;
+        }
+    };
+    let progress = match AccountProgress::load(storage.as_ref()) {
+        Ok(progress) => progress,
+        Err(e) => {
+            println!("Failed to load the progress of the accounts: {}", e);
+            return;
+        }
+    };
+
+    // Usage reports from clients are ignored unless the server is told to keep them
+    let usage_stats = if args.iter().any(|arg| arg == "--usage-stats") {
+        match UsageStats::load(storage.as_ref()) {
+            Ok(usage_stats) => Some(usage_stats),
+            Err(e) => {
+                println!("Failed to load the usage statistics: {}", e);
+                return;
+            }
+        }
+    }
+    else {
+        None
+    };
+
+    // Clients only show a day and night cycle if the server has one
+    let day_length = match option_value(&args, "--day-length") {
+        Some(minutes) => {
+            let secs = minutes.parse::<u32>().ok().and_then(|minutes| minutes.checked_mul(60));
+            match secs.filter(|&secs| secs >= MIN_DAY_LENGTH) {
+                Some(secs) => Some(secs),
+                None => {
+                    println!("Invalid day length in minutes: {}", minutes);
+                    return;
+                }
+            }
+        },
+        None => None,
+    };
+
+    // Players can be hidden from each other with `--visibility outdoors` or `--visibility friends`,
+    // on top of what each client chooses to hide
+    let visibility = match option_value(&args, "--visibility") {
+        Some(name) => {
+            let rule = Visibility::ALL.into_iter().find(|v| v.name().eq_ignore_ascii_case(&name));
+            match rule {
+                Some(rule) => rule,
+                None => {
+                    println!("Unknown visibility rule: {}", name);
+                    return;
+                }
+            }
+        },
+        None => Visibility::Everyone,
+    };
+
+    // Packets are compressed for clients that accept it, unless the server is told not to
+    let compression = if args.iter().any(|arg| arg == "--no-compression") {
+        Compression::None
+    }
+    else {
+        Compression::Deflate
+    };
+
+    // Connections are encrypted if the server is given a certificate and its private key
+    let tls_config = match (option_value(&args, "--tls-cert"), option_value(&args, "--tls-key")) {
+        (Some(cert), Some(key)) => match tls::server_config(Path::new(&cert), Path::new(&key)) {
+            Ok(tls_config) => Some(tls_config),
+            Err(e) => {
+                println!("Failed to load the TLS certificate: {}", e);
+                return;
+            }
+        },
+        (None, None) => None,
+        _ => {
+            println!("TLS needs both --tls-cert and --tls-key");
+            return;
+        }
+    };
+
+    // All clients must connect with WebSocket framing if the server is told to use it, so that it
+    // can be bridged to browsers and proxied like other HTTP traffic
+    let websocket = args.iter().any(|arg| arg == "--websocket");
+
+    // The name shown to clients searching the local network for servers
+    let name = option_value(&args, "--name").unwrap_or_else(|| DEFAULT_NAME.to_string());
+    if name.len() > discovery::MAX_NAME_LENGTH {
+        println!("The server name can be at most {} bytes long", discovery::MAX_NAME_LENGTH);
+        return;
+    }
+
+    // Servers are only listed publicly if they are given a master server to announce themselves to
+    let master = match option_value(&args, "--master") {
+        Some(addr) => match master::resolve(&addr) {
+            Ok(master) => Some(master),
+            Err(e) => {
+                println!("Failed to find the master server {}: {}", addr, e);
+                return;
+            }
+        },
+        None => None,
+    };
+
+    // Only clients playing one of these games can join, e.g. `--games "POKEMON RED,POKEMON BLUE"`.
+    // Every game is allowed if none are given, but battles are only started between games of the
+    // same generation either way.
+    let allowed_games =
+        option_value(&args, "--games").map(|list| AllowedGames::parse(&list)).unwrap_or_default();
+
+    let transport = TransportOptions { compression, tls: tls_config, websocket };
+    let result = run_server(ServerContext {
+        config,
+        event_log,
+        shop,
+        heatmap,
+        storage,
+        usage_stats,
+        accounts,
+        account_storage,
+        progress,
+        day_length,
+        visibility,
+        transport,
+        allowed_games,
+        name,
+        master,
+    });
+    if let Err(e) = result {
         println!("Server failed unexpectedly and had to close.\nReason: {}", e);
     }
 }
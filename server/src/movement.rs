@@ -0,0 +1,188 @@
+//! Checks that the movement players send could have happened in the game, so that a modified
+//! client can not teleport around other players' screens
+//!
+//! Each player can only cover so many tiles in a given time. Movement that goes further than that
+//! is moved towards where the player claims to be only as far as they could have gone, so a client
+//! that moves too fast is shown moving at the fastest possible speed. Players coming out of privacy
+//! mode are checked the same way, from where they were last shown on the map and for as long as
+//! they were hidden.
+//!
+//! Changing maps is checked separately. Warps, Fly and blacking out can move the player anywhere on
+//! the new map, and the server does not know where each warp leads, so the position on the new map
+//! is not checked. Instead the new map must exist in the game, and maps can only be changed so
+//! often: a few changes can be made in quick succession, but after that only one a second. Map
+//! changes that come too soon are dropped until one is allowed again, so a player that really does
+//! change maps quickly is only shown a little late. Walk counters out of range are already refused
+//! when packets are decoded.
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use interface::{data::MovementData, maps, rom::Generation, PlayerId};
+
+/// The most tiles a player can cover in a second. Walking covers a tile every 8 frames and the
+/// bicycle every 4, and fast forward runs 10 frames for each one, so at 60 frames a second the
+/// fastest players cover 150 tiles a second. The extra 10 allow for frames that the client runs
+/// faster to catch up after a slow one.
+const MAX_TILES_PER_SEC: f32 = 160.0;
+
+/// The shortest time between two map changes. Going through a door fades the screen out and back
+/// in, which takes more than this even with fast forward.
+const MIN_MAP_CHANGE_INTERVAL: Duration = Duration::from_millis(50);
+
+/// How many map changes can be made in quick succession, e.g. going in and straight back out of a
+/// building with fast forward
+const MAX_MAP_CHANGES: f32 = 4.0;
+
+/// How quickly map changes can be made once those are used up
+const MAP_CHANGES_PER_SEC: f32 = 1.0;
+
+struct Tracked {
+    /// The movement that other players were last shown
+    shown: MovementData,
+    /// How many tiles the player can still move, which builds up over time
+    allowance: f32,
+    last_update: Instant,
+    last_map_change: Instant,
+    /// How many map changes the player can still make, which builds up over time
+    map_changes: f32,
+    /// Where the player was last shown on their map before they went into privacy mode, and when
+    last_visible: Option<(MovementData, Instant)>,
+    /// Whether the player's last move was corrected, so that a run of them is only logged once
+    corrected: bool,
+}
+
+pub struct MovementChecks {
+    /// The most tiles that can build up, which is as far as the player can move between two
+    /// updates at the slowest update rate clients can use
+    max_allowance: f32,
+    players: HashMap<PlayerId, Tracked>,
+}
+
+impl MovementChecks {
+    pub fn new(min_movement_rate: u32) -> MovementChecks {
+        let max_allowance = MAX_TILES_PER_SEC / min_movement_rate as f32 + 1.0;
+        MovementChecks { max_allowance, players: HashMap::new() }
+    }
+
+    /// The movement to show other players in place of `movement_data`. This is the same movement
+    /// if it is possible, or as far towards it as the player could have moved if it is too far.
+    /// Returns None if the movement can not be shown at all, e.g. a map change that is too soon.
+    pub fn check(
+        &mut self,
+        id: PlayerId,
+        generation: Option<Generation>,
+        movement_data: MovementData,
+    ) -> Option<MovementData> {
+        let now = Instant::now();
+        // Players can start anywhere, since they could have saved there
+        if !self.players.contains_key(&id) {
+            let tracked = Tracked {
+                shown: movement_data,
+                allowance: self.max_allowance,
+                last_update: now,
+                last_map_change: now,
+                map_changes: MAX_MAP_CHANGES,
+                last_visible: None,
+                corrected: false,
+            };
+            self.players.insert(id, tracked);
+            return Some(movement_data);
+        }
+        let tracked = self.players.get_mut(&id).unwrap();
+
+        let elapsed = now.duration_since(tracked.last_update).as_secs_f32();
+        tracked.allowance =
+            (tracked.allowance + elapsed * MAX_TILES_PER_SEC).min(self.max_allowance);
+        tracked.map_changes =
+            (tracked.map_changes + elapsed * MAP_CHANGES_PER_SEC).min(MAX_MAP_CHANGES);
+        tracked.last_update = now;
+
+        let checked = if movement_data.map_id != tracked.shown.map_id {
+            let known_map = match generation {
+                // Map ids are only known for Gen 1
+                Some(Generation::One) => maps::map_name(movement_data.map_id).is_some(),
+                _ => true,
+            };
+            let too_soon = now.duration_since(tracked.last_map_change) < MIN_MAP_CHANGE_INTERVAL
+                || tracked.map_changes < 1.0;
+            if !known_map || too_soon {
+                None
+            }
+            else {
+                tracked.last_map_change = now;
+                tracked.map_changes -= 1.0;
+                // The player can be anywhere on the new map once they come out of privacy mode
+                tracked.last_visible = None;
+                Some(movement_data)
+            }
+        }
+        // Players in privacy mode do not share their position, so there is nothing to check
+        else if movement_data.is_hidden() {
+            if !tracked.shown.is_hidden() {
+                tracked.last_visible = Some((tracked.shown, now));
+            }
+            Some(movement_data)
+        }
+        else if tracked.shown.is_hidden() {
+            match tracked.last_visible {
+                Some((visible, hidden_at)) => {
+                    let mut allowance =
+                        now.duration_since(hidden_at).as_secs_f32() * MAX_TILES_PER_SEC;
+                    Some(step_towards(&visible, movement_data, &mut allowance))
+                }
+                // Players that were never shown on this map could have started anywhere on it
+                None => Some(movement_data),
+            }
+        }
+        else {
+            Some(step_towards(&tracked.shown, movement_data, &mut tracked.allowance))
+        };
+
+        let corrected = checked != Some(movement_data);
+        if corrected && !tracked.corrected {
+            println!("Player: {} moved in a way that the game does not allow", id);
+        }
+        tracked.corrected = corrected;
+        if let Some(shown) = checked {
+            tracked.shown = shown;
+        }
+        checked
+    }
+
+    /// The movement that other players were last shown for `id`
+    pub fn shown(&self, id: PlayerId) -> Option<MovementData> {
+        Some(self.players.get(&id)?.shown)
+    }
+
+    pub fn remove(&mut self, id: PlayerId) {
+        self.players.remove(&id);
+    }
+}
+
+/// Move from `from` towards `to` on the same map, as far as the allowance goes. Moving across first
+/// and then down is as good as any other path, since only the distance is limited.
+fn step_towards(from: &MovementData, to: MovementData, allowance: &mut f32) -> MovementData {
+    let distance = from.map_x.abs_diff(to.map_x) as f32 + from.map_y.abs_diff(to.map_y) as f32;
+    if distance <= *allowance {
+        *allowance -= distance;
+        return to;
+    }
+
+    let mut left = allowance.floor() as u8;
+    *allowance -= left as f32;
+    let mut step = |from: u8, to: u8| {
+        let moved = from.abs_diff(to).min(left);
+        left -= moved;
+        if to > from {
+            from + moved
+        }
+        else {
+            from - moved
+        }
+    };
+    let map_x = step(from.map_x, to.map_x);
+    let map_y = step(from.map_y, to.map_y);
+    MovementData { map_x, map_y, ..to }
+}
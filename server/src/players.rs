@@ -0,0 +1,154 @@
+//! Records of players with accounts, kept in the server's storage so that they outlast the server
+//!
+//! Each account has a record of where its player was last seen, how many battles it has won and
+//! lost, and when it was first and last seen. Records are read when a logged in player joins, so
+//! that their counts carry on from their last session, kept in memory while they play, and written
+//! back every `FLUSH_INTERVAL` and when they leave, so a server that stops without warning loses at
+//! most that much. A record that could not be written stays in memory until it has been. Guests
+//! have nothing to keep a record under, so they are not recorded.
+//!
+//! The position is only kept for the server's operators. Players start wherever their own save
+//! file puts them, so it is not used to place them when they join.
+//!
+//! The server only decides the result of a battle that one side forfeits. Battles fought to the end
+//! are decided by the two clients between themselves, so they are not counted.
+use std::{
+    collections::{HashMap, HashSet},
+    time::Duration,
+};
+
+use interface::data::MovementData;
+use network_common::shared;
+
+use crate::storage::{self, Storage};
+
+/// How often records that have changed are written to the storage
+pub const FLUSH_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct PlayerRecord {
+    /// The map id and coordinates that the player was last seen at
+    pub position: Option<(u8, u8, u8)>,
+    pub wins: u32,
+    pub losses: u32,
+    /// Seconds since the Unix epoch
+    pub first_seen: u64,
+    pub last_seen: u64,
+}
+
+impl PlayerRecord {
+    fn new() -> PlayerRecord {
+        let now = shared::now_secs();
+        PlayerRecord { position: None, wins: 0, losses: 0, first_seen: now, last_seen: now }
+    }
+}
+
+/// The storage key of the record of `account`
+fn storage_key(account: &str) -> String {
+    format!("player.{}", account)
+}
+
+#[derive(Default)]
+pub struct PlayerRecords {
+    /// The records of players that are playing, or that have left but could not be written yet,
+    /// by their account name in upper case
+    records: HashMap<String, PlayerRecord>,
+    /// Records that have changed since they were last written
+    dirty: HashSet<String>,
+    /// Players that have left, whose records are dropped once they have been written
+    left: HashSet<String>,
+}
+
+impl PlayerRecords {
+    pub fn new() -> PlayerRecords {
+        PlayerRecords::default()
+    }
+
+    /// Read the record of a player that has logged in to `account`, starting a new one if they
+    /// have not been seen before
+    pub fn join(&mut self, account: &str, storage: &dyn Storage) {
+        self.left.remove(account);
+        if !self.records.contains_key(account) {
+            let record = match storage::load_json(storage, &storage_key(account)) {
+                Ok(record) => record.unwrap_or_else(PlayerRecord::new),
+                Err(e) => {
+                    println!("Failed to read the record of {}: {}", account, e);
+                    PlayerRecord::new()
+                }
+            };
+            self.records.insert(account.to_string(), record);
+        }
+        if let Some(record) = self.record(account) {
+            record.last_seen = shared::now_secs();
+        }
+    }
+
+    /// Note where a player was seen, which is written with the next flush
+    pub fn moved(&mut self, account: &str, movement_data: MovementData) {
+        let position = Some((movement_data.map_id, movement_data.map_x, movement_data.map_y));
+        if self.records.get(account).is_some_and(|record| record.position != position) {
+            if let Some(record) = self.record(account) {
+                record.position = position;
+                record.last_seen = shared::now_secs();
+            }
+        }
+    }
+
+    pub fn battle_won(&mut self, account: &str) {
+        if let Some(record) = self.record(account) {
+            record.wins += 1;
+        }
+    }
+
+    pub fn battle_lost(&mut self, account: &str) {
+        if let Some(record) = self.record(account) {
+            record.losses += 1;
+        }
+    }
+
+    /// Write a player's record as they leave. It stops being kept in memory once it is written.
+    pub fn leave(&mut self, account: &str, storage: &mut dyn Storage) {
+        if let Some(record) = self.record(account) {
+            record.last_seen = shared::now_secs();
+        }
+        self.left.insert(account.to_string());
+        self.flush(storage);
+    }
+
+    /// Write every record that has changed since the last flush
+    pub fn flush(&mut self, storage: &mut dyn Storage) {
+        let records = &self.records;
+        // Records that fail are left dirty, so they are tried again with the next flush
+        self.dirty.retain(|account| {
+            let record = match records.get(account) {
+                Some(record) => record,
+                None => return false,
+            };
+            match storage::save_json(storage, &storage_key(account), record) {
+                Ok(()) => false,
+                Err(e) => {
+                    println!("Failed to write the record of {}: {}", account, e);
+                    true
+                }
+            }
+        });
+
+        let dirty = &self.dirty;
+        let records = &mut self.records;
+        self.left.retain(|account| {
+            let written = !dirty.contains(account);
+            if written {
+                records.remove(account);
+            }
+            !written
+        });
+    }
+
+    /// The record kept in memory for `account`, if the player has joined. Callers change it, so it
+    /// is marked as dirty.
+    fn record(&mut self, account: &str) -> Option<&mut PlayerRecord> {
+        let record = self.records.get_mut(account)?;
+        self.dirty.insert(account.to_string());
+        Some(record)
+    }
+}
@@ -0,0 +1,80 @@
+//! What players with accounts have shared with the server, so that it is still there when they
+//! come back
+//!
+//! This is the number of times each account has entered the Hall of Fame and the listing it has on
+//! the trade board. Both are restored when a player logs in again, including after the server
+//! restarts. Guests have nothing to keep them under, so theirs are forgotten when they leave.
+//! Progress is kept in the server's storage, where it is read when the server starts and rewritten
+//! after each change.
+use std::{collections::HashMap, io};
+
+use network_common::{PlayerId, TradeListing};
+
+use crate::storage::{self, Storage};
+
+const STORAGE_KEY: &str = "progress";
+
+/// A trade listing without the player, since players have a new id each time they join
+#[derive(Copy, Clone, serde::Serialize, serde::Deserialize)]
+struct Listing {
+    species: u8,
+    level: u8,
+    wanted: u8,
+}
+
+#[derive(Default, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+struct Progress {
+    hall_of_fame: Option<u8>,
+    listing: Option<Listing>,
+}
+
+pub struct AccountProgress {
+    /// The progress of every account that has any, by its name in upper case
+    accounts: HashMap<String, Progress>,
+}
+
+impl AccountProgress {
+    /// Start from the saved progress, if there is any
+    pub fn load(storage: &dyn Storage) -> io::Result<AccountProgress> {
+        let accounts = storage::load_json(storage, STORAGE_KEY)?.unwrap_or_default();
+        Ok(AccountProgress { accounts })
+    }
+
+    /// The number of times `account` has entered the Hall of Fame, if it has shared it
+    pub fn hall_of_fame(&self, account: &str) -> Option<u8> {
+        self.accounts.get(account)?.hall_of_fame
+    }
+
+    pub fn set_hall_of_fame(&mut self, account: &str, teams: u8, storage: &mut dyn Storage) {
+        self.update(account, storage, |progress| progress.hall_of_fame = Some(teams));
+    }
+
+    /// The listing that `account` left on the trade board, for the player now using it
+    pub fn listing(&self, account: &str, player: PlayerId) -> Option<TradeListing> {
+        let Listing { species, level, wanted } = self.accounts.get(account)?.listing?;
+        Some(TradeListing { player, species, level, wanted })
+    }
+
+    /// Keep `listing` for `account`, or forget its listing if it has been taken down
+    pub fn set_listing(
+        &mut self,
+        account: &str,
+        listing: Option<TradeListing>,
+        storage: &mut dyn Storage,
+    ) {
+        let listing = listing.map(|TradeListing { species, level, wanted, .. }| Listing {
+            species,
+            level,
+            wanted,
+        });
+        self.update(account, storage, |progress| progress.listing = listing);
+    }
+
+    fn update(&mut self, account: &str, storage: &mut dyn Storage, f: impl FnOnce(&mut Progress)) {
+        f(self.accounts.entry(account.to_string()).or_default());
+        if let Err(e) = storage::save_json(storage, STORAGE_KEY, &self.accounts) {
+            println!("Failed to write the progress of the accounts: {}", e);
+        }
+    }
+}
@@ -0,0 +1,152 @@
+//! Where the server keeps data that should outlast a single session, such as the usage totals
+//!
+//! Records are stored under a name by whichever backend the server was started with, so that
+//! features do not need to know where their data ends up. Servers given `--data-dir DIR` keep their
+//! records in an SQLite database in `DIR`, or as a JSON file for each record with `--json-storage`.
+//! Otherwise records are kept in memory, and are lost when the server stops.
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{de::DeserializeOwned, Serialize};
+
+/// The database that `SqliteStorage` keeps records in, in the data directory
+pub const DATABASE_FILE: &str = "pikemon.sqlite";
+
+/// How long a write waits for another connection to the database to finish its own
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Storage is shared with the thread that accepts clients, which checks their logins
+pub trait Storage: Send {
+    /// The record saved under `key`, if there is one
+    fn load(&self, key: &str) -> io::Result<Option<Vec<u8>>>;
+
+    /// Save a record under `key`, replacing any record already there
+    fn save(&mut self, key: &str, data: &[u8]) -> io::Result<()>;
+}
+
+/// Read a record saved with `save_json`
+pub fn load_json<T: DeserializeOwned>(storage: &dyn Storage, key: &str) -> io::Result<Option<T>> {
+    match storage.load(key)? {
+        Some(data) => Ok(Some(serde_json::from_slice(&data)?)),
+        None => Ok(None),
+    }
+}
+
+pub fn save_json<T: Serialize>(storage: &mut dyn Storage, key: &str, value: &T) -> io::Result<()> {
+    storage.save(key, &serde_json::to_vec_pretty(value)?)
+}
+
+/// Keeps records for as long as the server is running
+#[derive(Default)]
+pub struct MemoryStorage {
+    records: HashMap<String, Vec<u8>>,
+}
+
+impl MemoryStorage {
+    pub fn new() -> MemoryStorage {
+        MemoryStorage::default()
+    }
+}
+
+impl Storage for MemoryStorage {
+    fn load(&self, key: &str) -> io::Result<Option<Vec<u8>>> {
+        Ok(self.records.get(key).cloned())
+    }
+
+    fn save(&mut self, key: &str, data: &[u8]) -> io::Result<()> {
+        self.records.insert(key.to_string(), data.to_vec());
+        Ok(())
+    }
+}
+
+/// Keeps each record in its own file in a directory, e.g. `usage_stats.json`
+#[derive(Clone)]
+pub struct FileStorage {
+    dir: PathBuf,
+}
+
+impl FileStorage {
+    pub fn open(dir: &Path) -> io::Result<FileStorage> {
+        fs::create_dir_all(dir)?;
+        Ok(FileStorage { dir: dir.to_path_buf() })
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", key))
+    }
+}
+
+impl Storage for FileStorage {
+    fn load(&self, key: &str) -> io::Result<Option<Vec<u8>>> {
+        match fs::read(self.path(key)) {
+            Ok(data) => Ok(Some(data)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn save(&mut self, key: &str, data: &[u8]) -> io::Result<()> {
+        // Written to a temporary file first so that a crash can not leave a partly written record
+        let path = self.path(key);
+        let tmp_path = path.with_extension("json.tmp");
+        fs::write(&tmp_path, data)?;
+        fs::rename(&tmp_path, &path)
+    }
+}
+
+/// Keeps every record as a row of an SQLite database in a directory
+pub struct SqliteStorage {
+    dir: PathBuf,
+    connection: Connection,
+}
+
+impl SqliteStorage {
+    pub fn open(dir: &Path) -> io::Result<SqliteStorage> {
+        fs::create_dir_all(dir)?;
+        let connection = Connection::open(dir.join(DATABASE_FILE)).map_err(sql_error)?;
+        connection.busy_timeout(BUSY_TIMEOUT).map_err(sql_error)?;
+        connection
+            .execute_batch("CREATE TABLE IF NOT EXISTS records (key TEXT PRIMARY KEY, data BLOB)")
+            .map_err(sql_error)?;
+        Ok(SqliteStorage { dir: dir.to_path_buf(), connection })
+    }
+
+    /// Another connection to the same database, for another thread
+    pub fn try_clone(&self) -> io::Result<SqliteStorage> {
+        SqliteStorage::open(&self.dir)
+    }
+}
+
+impl Storage for SqliteStorage {
+    fn load(&self, key: &str) -> io::Result<Option<Vec<u8>>> {
+        let data = self
+            .connection
+            .query_row("SELECT data FROM records WHERE key = ?1", params![key], |row| row.get(0))
+            .optional()
+            .map_err(sql_error)?;
+        match data {
+            Some(data) => Ok(Some(data)),
+            // Servers used to keep records as JSON files, which are read until they are replaced
+            None => FileStorage { dir: self.dir.clone() }.load(key),
+        }
+    }
+
+    fn save(&mut self, key: &str, data: &[u8]) -> io::Result<()> {
+        self.connection
+            .execute(
+                "INSERT OR REPLACE INTO records (key, data) VALUES (?1, ?2)",
+                params![key, data],
+            )
+            .map_err(sql_error)?;
+        Ok(())
+    }
+}
+
+fn sql_error(e: rusqlite::Error) -> io::Error {
+    io::Error::other(e)
+}
@@ -0,0 +1,51 @@
+//! Totals of the anonymous usage reports sent by players that have chosen to share them, to help
+//! decide which features to work on
+//!
+//! Reports only count how long sessions last, how often each feature is used and where clients
+//! crashed. They are not linked to the player that sent them. The totals are kept in the server's
+//! storage, where they are read when the server starts and rewritten after each report.
+use std::{collections::HashMap, io};
+
+use network_common::{Feature, UsageReport};
+
+use crate::storage::{self, Storage};
+
+const STORAGE_KEY: &str = "usage_stats";
+
+#[derive(Default, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+struct Totals {
+    sessions: u64,
+    session_secs: u64,
+    /// The number of times each feature was used, across every session
+    features: HashMap<Feature, u64>,
+    /// The number of crashes at each source location
+    crashes: HashMap<String, u64>,
+}
+
+pub struct UsageStats {
+    totals: Totals,
+}
+
+impl UsageStats {
+    /// Start from the saved totals, if there are any
+    pub fn load(storage: &dyn Storage) -> io::Result<UsageStats> {
+        let totals = storage::load_json(storage, STORAGE_KEY)?.unwrap_or_default();
+        Ok(UsageStats { totals })
+    }
+
+    pub fn add(&mut self, report: UsageReport, storage: &mut dyn Storage) {
+        self.totals.sessions += 1;
+        self.totals.session_secs += report.session_secs as u64;
+        for (feature, count) in report.features {
+            *self.totals.features.entry(feature).or_insert(0) += count as u64;
+        }
+        if let Some(crash) = report.crash {
+            *self.totals.crashes.entry(crash).or_insert(0) += 1;
+        }
+
+        if let Err(e) = storage::save_json(storage, STORAGE_KEY, &self.totals) {
+            println!("Failed to write the usage statistics: {}", e);
+        }
+    }
+}
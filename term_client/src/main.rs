@@ -0,0 +1,209 @@
+//! A terminal client for Pikemon servers.
+//!
+//! Connects to a server without running the game, so that server admins can follow the chat and
+//! see who is online and where (e.g. over SSH). The client never sends any player data, so it does
+//! not appear in the game. Chat messages sent from it are shown to players as coming from an unknown
+//! player.
+//!
+//! Usage: `pikemon_term [--tls] [--websocket] [--observe] [address]`, where the address defaults to
+//! `localhost` and the port to 8080. With `--tls` the connection is encrypted, and the server's certificate must be
+//! valid for its host. Servers that use WebSocket framing need `--websocket`. With `--observe` the
+//! client only watches the server: it is not counted as a player and can not send chat messages.
+use std::{
+    collections::HashMap,
+    io::{self, BufRead},
+    process, thread,
+};
+
+use crossbeam_channel::{select, Receiver};
+use interface::{maps, text, PlayerId};
+use network_common::{
+    client::{self, Latency, NetworkManager},
+    codec::Compression,
+    game::GameInfo,
+    outbox::{Outbox, CHANNEL_CAPACITY},
+    poll,
+    simulator::NetworkConditions,
+    stats::Traffic,
+    tls,
+    transport::{self, ClientOptions},
+    ChatId, NetworkEvent,
+};
+
+const HELP: &str = "Type a message to send it to the chat, or one of the commands:
+  /players  list the players on the server and where they are
+  /help     show this message
+  /quit     disconnect from the server";
+
+fn main() {
+    let args: Vec<_> = std::env::args().skip(1).collect();
+    let addr = match args.iter().find(|arg| !arg.starts_with("--")) {
+        Some(addr) => addr.clone(),
+        None => "localhost".to_string(),
+    };
+
+    let tls = match args.iter().any(|arg| arg == "--tls").then(|| tls::client_config(None)) {
+        Some(Ok(config)) => Some(config),
+        Some(Err(e)) => {
+            println!("Failed to set up TLS: {}", e);
+            process::exit(1);
+        }
+        None => None,
+    };
+    let websocket = args.iter().any(|arg| arg == "--websocket");
+    let observe = args.iter().any(|arg| arg == "--observe");
+    let proxy = args.iter().find_map(|arg| arg.strip_prefix("--proxy=")).map(str::to_string);
+    let options = ClientOptions { tls, websocket, proxy };
+    let stream = match transport::connect(&addr, &options) {
+        Ok(stream) => stream,
+        Err(e) => {
+            println!("Failed to connect to {}: {}", addr, e);
+            process::exit(1);
+        }
+    };
+
+    let (local_update_sender, local_update_receiver) =
+        poll::bounded(CHANNEL_CAPACITY).expect("Failed to create a channel");
+    let (global_update_sender, global_update_receiver) =
+        crossbeam_channel::bounded(CHANNEL_CAPACITY);
+    let network_manager = NetworkManager {
+        socket: stream,
+        local_update_receiver,
+        global_update_sender,
+        compression: Compression::Deflate,
+        // Without a game, the server lets this client join whatever games it allows
+        game: GameInfo::default(),
+        login: None,
+        latency: Latency::default(),
+        traffic: Traffic::default(),
+        simulate: NetworkConditions::default(),
+    };
+    let id = match client::handle_network(network_manager) {
+        Ok(joined) => joined.player_id,
+        Err(e) => {
+            println!("Join handshake failed: {}", e);
+            process::exit(1);
+        }
+    };
+    println!("Connected to {}", addr);
+    println!("{}", HELP);
+
+    let mut local_updates = Outbox::new(local_update_sender);
+    if observe && local_updates.send(NetworkEvent::Observe(id)).is_err() {
+        process::exit(1);
+    }
+    let input = read_input();
+    let mut players: HashMap<PlayerId, Player> = HashMap::new();
+    // Messages are not sent again, but the server still expects each to have its own id
+    let mut next_chat_id: ChatId = 0;
+
+    loop {
+        select! {
+            recv(global_update_receiver) -> event => match event {
+                Ok(event) => handle_event(event, &mut players),
+                // The network thread stops when the server disconnects
+                Err(_) => break,
+            },
+
+            recv(input) -> line => {
+                let line = match line {
+                    Ok(line) => line,
+                    Err(_) => break,
+                };
+                match line.trim() {
+                    "" => {}
+                    "/quit" => break,
+                    "/help" => println!("{}", HELP),
+                    "/players" => list_players(&players),
+                    _ if observe => println!("* Chat messages can not be sent while observing"),
+                    msg => {
+                        let event = NetworkEvent::Chat(id, next_chat_id, msg.to_string());
+                        next_chat_id = next_chat_id.wrapping_add(1);
+                        if local_updates.send(event).is_err() {
+                            break;
+                        }
+                    }
+                }
+            },
+        }
+    }
+
+    client::disconnect(id, &mut local_updates, &global_update_receiver);
+}
+
+/// Read lines from stdin on a separate thread, so that the network can be checked while waiting
+fn read_input() -> Receiver<String> {
+    let (sender, receiver) = crossbeam_channel::unbounded();
+    thread::spawn(move || {
+        for line in io::stdin().lock().lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(_) => break,
+            };
+            if sender.send(line).is_err() {
+                break;
+            }
+        }
+    });
+    receiver
+}
+
+struct Player {
+    name: String,
+    map_id: u8,
+}
+
+fn handle_event(event: NetworkEvent, players: &mut HashMap<PlayerId, Player>) {
+    match event {
+        NetworkEvent::FullUpdate(id, _, data) => {
+            let name = text::decode(&data.name);
+            let player = Player { name: name.clone(), map_id: data.movement_data.map_id };
+            if players.insert(id, player).is_none() {
+                println!("* {} is online", name);
+            }
+        }
+
+        NetworkEvent::MovementUpdate(id, _, movement_data) => {
+            if let Some(player) = players.get_mut(&id) {
+                player.map_id = movement_data.map_id;
+            }
+        }
+
+        NetworkEvent::PlayerQuit(id) => {
+            if let Some(player) = players.remove(&id) {
+                println!("* {} left", player.name);
+            }
+        }
+
+        NetworkEvent::Chat(id, _, msg) => {
+            let name = players.get(&id).map_or("UNKNOWN", |player| player.name.as_str());
+            println!("{}: {}", name, msg);
+        }
+
+        NetworkEvent::HallOfFame(id, teams) => {
+            if let Some(player) = players.get(&id) {
+                println!("* {} entered the Hall of Fame ({} teams)", player.name, teams);
+            }
+        }
+
+        NetworkEvent::Motd(motd) => println!("* {}", motd),
+
+        // Everything else is only relevant to players running the game
+        _ => {}
+    }
+}
+
+fn list_players(players: &HashMap<PlayerId, Player>) {
+    if players.is_empty() {
+        println!("* No players are online");
+        return;
+    }
+
+    let mut players: Vec<_> = players.values().collect();
+    players.sort_by(|a, b| a.name.cmp(&b.name));
+    println!("* {} players online:", players.len());
+    for player in players {
+        let location = maps::map_name(player.map_id).unwrap_or("Unknown");
+        println!("  {:<11} {}", player.name, location);
+    }
+}